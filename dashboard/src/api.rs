@@ -2,13 +2,139 @@
 //! api.rs - API client for calling Cloudflare Workers
 //! ==============================================================================
 
+use std::fmt;
+use std::ops::Deref;
+
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use gloo_net::http::Request;
+use gloo_net::http::{Request, Response};
+use web_sys::{RequestCache, RequestMode};
 
 // Base URLs for workers
 pub const URL_SHORTENER_BASE: &str = "https://url-shortener-preview.cm-mongo-web3.workers.dev";
 pub const RATE_LIMITER_BASE: &str = "https://rate-limiter-preview.cm-mongo-web3.workers.dev";
 pub const CAPABILITY_DEMO_BASE: &str = "https://capability-demo-preview.cm-mongo-web3.workers.dev";
+pub const GATEWAY_BASE: &str = "https://gateway-preview.cm-mongo-web3.workers.dev";
+pub const EVENT_HUB_BASE: &str = "https://event-hub-preview.cm-mongo-web3.workers.dev";
+
+// Local `wrangler dev` URLs (ports match [dev] in each wrangler.toml)
+pub const URL_SHORTENER_DEV: &str = "http://localhost:8787";
+pub const RATE_LIMITER_DEV: &str = "http://localhost:8788";
+pub const CAPABILITY_DEMO_DEV: &str = "http://localhost:8789";
+pub const GATEWAY_DEV: &str = "http://localhost:8786";
+pub const EVENT_HUB_DEV: &str = "http://localhost:8792";
+
+pub const DEV_MODE_KEY: &str = "edge-demo-dev-mode";
+pub const GATEWAY_MODE_KEY: &str = "edge-demo-gateway-mode";
+
+/// Header the workers stamp on every response (and expose to the browser)
+const REQUEST_ID_HEADER: &str = "x-request-id";
+
+/// Turnstile site key for the shorten form. This is Cloudflare's always-pass
+/// test key - swap in the real widget's key alongside the worker's TURNSTILE_SECRET
+pub const TURNSTILE_SITE_KEY: &str = "1x00000000000000000000AA";
+const TURNSTILE_HEADER: &str = "X-Turnstile-Token";
+
+// ==============================================================================
+// Dev and gateway modes
+// ==============================================================================
+
+/// Apply `?dev=1` / `?gateway=1` query params (or `=0`), persisting them for later visits
+pub fn init_modes() {
+    if let Some(enabled) = query_flag("dev") {
+        set_dev_mode(enabled);
+    }
+    if let Some(enabled) = query_flag("gateway") {
+        set_gateway_mode(enabled);
+    }
+}
+
+fn query_flag(name: &str) -> Option<bool> {
+    let search = web_sys::window()
+        .and_then(|w| w.location().search().ok())
+        .unwrap_or_default();
+    
+    let value = search
+        .trim_start_matches('?')
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='));
+    
+    match value {
+        Some("1") | Some("true") => Some(true),
+        Some("0") | Some("false") => Some(false),
+        _ => None,
+    }
+}
+
+fn stored_flag(key: &str) -> bool {
+    web_sys::window()
+        .and_then(|w| w.local_storage().ok().flatten())
+        .and_then(|s| s.get_item(key).ok().flatten())
+        .is_some_and(|v| v == "1")
+}
+
+fn store_flag(key: &str, enabled: bool) {
+    if let Some(storage) = web_sys::window().and_then(|w| w.local_storage().ok().flatten()) {
+        let _ = storage.set_item(key, if enabled { "1" } else { "0" });
+    }
+}
+
+/// Whether API calls target locally running workers instead of the edge
+pub fn dev_mode() -> bool {
+    stored_flag(DEV_MODE_KEY)
+}
+
+/// Switch between local and deployed workers
+pub fn set_dev_mode(enabled: bool) {
+    store_flag(DEV_MODE_KEY, enabled);
+}
+
+/// Whether API calls go through the gateway worker instead of each worker's own host
+pub fn gateway_mode() -> bool {
+    stored_flag(GATEWAY_MODE_KEY)
+}
+
+/// Switch between the gateway and direct worker hosts
+pub fn set_gateway_mode(enabled: bool) {
+    store_flag(GATEWAY_MODE_KEY, enabled);
+}
+
+fn gateway_base() -> &'static str {
+    if dev_mode() { GATEWAY_DEV } else { GATEWAY_BASE }
+}
+
+/// Base URL for one worker - its own host, or its prefix on the gateway
+fn service_base(gateway_prefix: &str, dev: &str, live: &str) -> String {
+    if gateway_mode() {
+        format!("{}{}", gateway_base(), gateway_prefix)
+    } else if dev_mode() {
+        dev.to_string()
+    } else {
+        live.to_string()
+    }
+}
+
+fn url_shortener_base() -> String {
+    service_base("/shorten", URL_SHORTENER_DEV, URL_SHORTENER_BASE)
+}
+
+fn rate_limiter_base() -> String {
+    service_base("/limit", RATE_LIMITER_DEV, RATE_LIMITER_BASE)
+}
+
+fn capability_demo_base() -> String {
+    service_base("/capability", CAPABILITY_DEMO_DEV, CAPABILITY_DEMO_BASE)
+}
+
+/// WebSocket URL of the live event feed - on the gateway, or the event hub's own host
+pub fn live_feed_url() -> String {
+    let base = service_base("", EVENT_HUB_DEV, EVENT_HUB_BASE);
+    let ws_base = match base.strip_prefix("https://") {
+        Some(host) => format!("wss://{}", host),
+        None => base.replacen("http://", "ws://", 1),
+    };
+    format!("{}/events", ws_base)
+}
 
 // ==============================================================================
 // URL Shortener types
@@ -17,6 +143,12 @@ pub const CAPABILITY_DEMO_BASE: &str = "https://capability-demo-preview.cm-mongo
 #[derive(Debug, Clone, Serialize)]
 pub struct ShortenRequest {
     pub url: String,
+    /// custom code instead of a generated one
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// seconds until the link expires
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<u64>,
 }
 
 #[allow(dead_code)]
@@ -25,6 +157,9 @@ pub struct ShortenResponse {
     pub code: String,
     pub short_url: String,
     pub original_url: String,
+    /// expiry timestamp in ms, none = never
+    #[serde(default)]
+    pub expires_at: Option<u64>,
 }
 
 #[allow(dead_code)]
@@ -61,7 +196,7 @@ pub struct ProtectedResponse {
 #[allow(dead_code)]
 #[derive(Debug, Clone, Deserialize)]
 pub struct RateLimitedResponse {
-    pub error: String,
+    pub detail: String,
     pub retry_after_seconds: u64,
     pub limit: u32,
 }
@@ -78,86 +213,329 @@ pub struct CapabilityResult {
 }
 
 // ==============================================================================
-// API functions
+// Live feed types
 // ==============================================================================
 
-/// Shorten a URL
-pub async fn shorten_url(url: &str) -> Result<ShortenResponse, String> {
-    let body = ShortenRequest { url: url.to_string() };
+/// One event from the hub: kind is "link", "block" or "fault"
+#[allow(dead_code)]
+#[derive(Debug, Clone, Deserialize)]
+pub struct LiveEvent {
+    pub kind: String,
+    pub worker: String,
+    /// short code, client label or route
+    pub subject: String,
+    pub status: u16,
+    pub request_id: String,
+    /// unix ms
+    pub at: u64,
+}
+
+// ==============================================================================
+// Health types
+// ==============================================================================
+
+/// The gateway's consolidated status: status is "ok", "degraded" or "down"
+#[allow(dead_code)]
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthReport {
+    pub status: String,
+    /// unix ms
+    pub checked_at: u64,
+    pub checks: Vec<HealthCheck>,
+}
+
+/// One worker or binding - kind is "worker", "kv", "durable_object", "r2" or "d1"
+#[derive(Debug, Clone, Deserialize)]
+pub struct HealthCheck {
+    /// worker name, or "<worker>/<binding>"
+    pub name: String,
+    pub kind: String,
+    pub up: bool,
+    pub latency_ms: u64,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+// ==============================================================================
+// API errors
+// ==============================================================================
+
+/// A failed API call, decoded from an `application/problem+json` body
+/// (or the older `{"error": "..."}` shape) where the worker sent one
+#[derive(Debug, Clone, PartialEq)]
+pub struct ApiError {
+    /// http status, 0 when the request never reached the worker
+    pub status: u16,
+    /// machine-readable error code, e.g. `rate_limited`
+    pub code: String,
+    pub message: String,
+    /// worker request id, quoted when reporting a problem
+    pub request_id: Option<String>,
+}
+
+/// Error body fields, covering both problem+json and the legacy shape
+#[derive(Debug, Default, Deserialize)]
+struct ErrorBody {
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    title: Option<String>,
+    detail: Option<String>,
+    code: Option<String>,
+    error: Option<String>,
+    message: Option<String>,
+    request_id: Option<String>,
+}
+
+impl ApiError {
+    /// The request failed before a response arrived (offline, dns, cors, ...)
+    pub fn network(e: impl fmt::Display) -> Self {
+        Self {
+            status: 0,
+            code: "network_error".to_string(),
+            message: e.to_string(),
+            request_id: None,
+        }
+    }
     
-    let response = Request::post(&format!("{}/shorten", URL_SHORTENER_BASE))
-        .header("Content-Type", "application/json")
-        .body(serde_json::to_string(&body).unwrap())
-        .map_err(|e| e.to_string())?
-        .send()
-        .await
-        .map_err(|e| e.to_string())?;
+    /// A non-2xx response from a worker
+    async fn from_response(response: Response) -> Self {
+        let status = response.status();
+        let header_id = response.headers().get(REQUEST_ID_HEADER);
+        let text = response.text().await.unwrap_or_default();
+        
+        // plain-text error bodies become the message as-is
+        let body = serde_json::from_str::<ErrorBody>(&text).unwrap_or_else(|_| ErrorBody {
+            detail: Some(text.trim().to_string()).filter(|t| !t.is_empty()),
+            ..Default::default()
+        });
+        
+        Self::from_body(status, body, header_id)
+    }
+    
+    fn from_body(status: u16, body: ErrorBody, header_id: Option<String>) -> Self {
+        // problem+json `type` is a uri - its last segment doubles as the code
+        let type_code = body
+            .kind
+            .as_deref()
+            .and_then(|t| t.rsplit('/').next())
+            .filter(|c| !c.is_empty() && *c != "about:blank")
+            .map(str::to_string);
+        
+        Self {
+            status,
+            code: body.code.or(type_code).unwrap_or_else(|| default_code(status).to_string()),
+            message: body
+                .detail
+                .or(body.message)
+                .or(body.error)
+                .or(body.title)
+                .unwrap_or_else(|| format!("Request failed with status {}", status)),
+            request_id: body.request_id.or(header_id),
+        }
+    }
+    
+    /// A 2xx response whose body didn't match the expected shape
+    fn decode(e: impl fmt::Display) -> Self {
+        Self {
+            status: 200,
+            code: "invalid_response".to_string(),
+            message: format!("Unexpected response from worker: {}", e),
+            request_id: None,
+        }
+    }
+    
+    pub fn is_network(&self) -> bool {
+        self.status == 0
+    }
+    
+    pub fn is_rate_limited(&self) -> bool {
+        self.status == 429
+    }
     
-    // Check for rate limiting
-    if response.status() == 429 {
-        return Err("Rate limited! Please wait a minute before creating more URLs.".to_string());
+    /// Short heading for the error card
+    pub fn title(&self) -> &'static str {
+        match self.status {
+            0 => "Network Error",
+            400 => "Invalid Request",
+            404 => "Not Found",
+            409 => "Conflict",
+            429 => "Rate Limited",
+            500..=599 => "Worker Error",
+            _ => "Request Failed",
+        }
     }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+/// Same codes as the workers' shared::problem::default_code
+fn default_code(status: u16) -> &'static str {
+    match status {
+        400 => "bad_request",
+        401 => "unauthorized",
+        403 => "forbidden",
+        404 => "not_found",
+        405 => "method_not_allowed",
+        409 => "conflict",
+        413 => "payload_too_large",
+        429 => "rate_limited",
+        502 => "bad_gateway",
+        503 => "unavailable",
+        500..=599 => "internal_error",
+        _ => "http_error",
+    }
+}
+
+/// A decoded response along with the worker's request id
+#[derive(Debug, Clone)]
+pub struct Traced<T> {
+    pub value: T,
+    pub request_id: Option<String>,
+}
+
+impl<T> Deref for Traced<T> {
+    type Target = T;
     
-    response
-        .json::<ShortenResponse>()
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+/// Decode a json body, turning error statuses into an [`ApiError`]
+async fn parse_json<T: DeserializeOwned>(response: Response) -> Result<Traced<T>, ApiError> {
+    if !response.ok() {
+        return Err(ApiError::from_response(response).await);
+    }
+    let request_id = response.headers().get(REQUEST_ID_HEADER);
+    let value = response.json::<T>().await.map_err(|e| ApiError {
+        request_id: request_id.clone(),
+        ..ApiError::decode(e)
+    })?;
+    Ok(Traced { value, request_id })
+}
+
+// ==============================================================================
+// API functions
+// ==============================================================================
+
+/// Shorten a URL
+pub async fn shorten_url(
+    body: &ShortenRequest,
+    turnstile_token: Option<String>,
+) -> Result<Traced<ShortenResponse>, ApiError> {
+    let mut request = Request::post(&format!("{}/shorten", url_shortener_base()))
+        .header("Content-Type", "application/json");
+    if let Some(token) = turnstile_token {
+        request = request.header(TURNSTILE_HEADER, &token);
+    }
+    let response = request
+        .body(serde_json::to_string(body).unwrap())
+        .map_err(ApiError::network)?
+        .send()
         .await
-        .map_err(|e| e.to_string())
+        .map_err(ApiError::network)?;
+    
+    parse_json(response).await.map_err(|mut e| {
+        // friendlier wording for the errors the form can hit
+        match e.status {
+            429 => e.message = "Please wait a minute before creating more URLs.".to_string(),
+            409 => e.message = "That alias is already taken - pick another one.".to_string(),
+            403 => e.message = "The human check didn't pass - wait for it to complete and try again.".to_string(),
+            _ => {}
+        }
+        e
+    })
 }
 
 /// Get stats for a short URL
 #[allow(dead_code)]
-pub async fn get_url_stats(code: &str) -> Result<UrlStats, String> {
-    Request::get(&format!("{}/stats/{}", URL_SHORTENER_BASE, code))
+pub async fn get_url_stats(code: &str) -> Result<Traced<UrlStats>, ApiError> {
+    let response = Request::get(&format!("{}/stats/{}", url_shortener_base(), code))
         .send()
         .await
-        .map_err(|e| e.to_string())?
-        .json::<UrlStats>()
-        .await
-        .map_err(|e| e.to_string())
+        .map_err(ApiError::network)?;
+    
+    parse_json(response).await
 }
 
 /// Make a request to the protected endpoint
-pub async fn test_rate_limit() -> Result<ProtectedResponse, String> {
-    let response = Request::get(&format!("{}/api/protected", RATE_LIMITER_BASE))
+pub async fn test_rate_limit() -> Result<Traced<ProtectedResponse>, ApiError> {
+    let response = Request::get(&format!("{}/api/protected", rate_limiter_base()))
         .send()
         .await
-        .map_err(|e| e.to_string())?;
-    
-    if response.status() == 429 {
-        return Err("Rate limited (429)".to_string());
-    }
+        .map_err(ApiError::network)?;
     
-    response
-        .json::<ProtectedResponse>()
-        .await
-        .map_err(|e| e.to_string())
+    parse_json(response).await
 }
 
 /// Get rate limit status
-pub async fn get_rate_status() -> Result<RateLimitStatus, String> {
-    Request::get(&format!("{}/api/status", RATE_LIMITER_BASE))
+pub async fn get_rate_status() -> Result<Traced<RateLimitStatus>, ApiError> {
+    let response = Request::get(&format!("{}/api/status", rate_limiter_base()))
         .send()
         .await
-        .map_err(|e| e.to_string())?
-        .json::<RateLimitStatus>()
-        .await
-        .map_err(|e| e.to_string())
+        .map_err(ApiError::network)?;
+    
+    parse_json(response).await
 }
 
 /// Test a capability
-pub async fn test_capability(capability: &str) -> Result<CapabilityResult, String> {
-    let response = Request::get(&format!("{}/api/capability?test={}", CAPABILITY_DEMO_BASE, capability))
+pub async fn test_capability(capability: &str) -> Result<Traced<CapabilityResult>, ApiError> {
+    let response = Request::get(&format!("{}/api/capability?test={}", capability_demo_base(), capability))
         .send()
         .await
-        .map_err(|e| e.to_string())?;
+        .map_err(ApiError::network)?;
     
-    // Check for rate limiting
-    if response.status() == 429 {
-        return Err("429 - Rate limited".to_string());
-    }
+    parse_json(response).await
+}
+
+/// Status of every worker and its bindings - always asks the gateway,
+/// the only worker that can reach all the others
+pub async fn get_health() -> Result<Traced<HealthReport>, ApiError> {
+    let response = Request::get(&format!("{}/health/all", gateway_base()))
+        .send()
+        .await
+        .map_err(ApiError::network)?;
     
-    response
-        .json::<CapabilityResult>()
+    parse_json(response).await
+}
+
+// ==============================================================================
+// Latency probes
+// ==============================================================================
+
+/// Health endpoints of each worker (label, url), used as edge latency targets
+pub fn edge_health_urls() -> Vec<(&'static str, String)> {
+    vec![
+        ("URL Shortener", format!("{}/health", url_shortener_base())),
+        ("Rate Limiter", format!("{}/health", rate_limiter_base())),
+        ("Capability Demo", format!("{}/health", capability_demo_base())),
+        ("Gateway", format!("{}/health", gateway_base())),
+    ]
+}
+
+/// Time one round trip to `url` in milliseconds.
+/// Uses no-cors so any origin can be probed - the response is opaque,
+/// only its arrival matters - and bypasses the http cache.
+pub async fn measure_latency(url: &str) -> Result<f64, ApiError> {
+    let start = now_ms();
+    Request::get(url)
+        .mode(RequestMode::NoCors)
+        .cache(RequestCache::NoStore)
+        .send()
         .await
-        .map_err(|e| e.to_string())
+        .map_err(ApiError::network)?;
+    Ok(now_ms() - start)
+}
+
+fn now_ms() -> f64 {
+    web_sys::window()
+        .and_then(|w| w.performance())
+        .map(|p| p.now())
+        .unwrap_or_else(js_sys::Date::now)
 }
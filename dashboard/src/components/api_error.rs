@@ -0,0 +1,74 @@
+//! API error card and error boundary fallback
+
+use leptos::prelude::*;
+use crate::api::ApiError;
+
+/// Render a structured API error: title, message, code and request id
+#[component]
+pub fn ApiErrorView(error: ApiError) -> impl IntoView {
+    let (copied, set_copied) = signal(false);
+    let hint = if error.code == "network_error" {
+        Some("The worker could not be reached. The demo may have exceeded its daily request limit - try again later.")
+    } else if error.is_rate_limited() {
+        Some("Wait for the rate limit window to reset, then try again.")
+    } else {
+        None
+    };
+    
+    view! {
+        <div class="result error api-error">
+            <div class="api-error-title">
+                {error.title()}
+                {(error.status != 0).then(|| view! { <span class="api-error-status">{error.status}</span> })}
+            </div>
+            <div class="result-value">{error.message.clone()}</div>
+            {hint.map(|h| view! { <div class="api-error-hint">{h}</div> })}
+            <div class="api-error-meta">
+                <code>{error.code.clone()}</code>
+                {error.request_id.clone().map(|id| {
+                    let copy_id = id.clone();
+                    view! {
+                        <span class="api-error-request">
+                            "Request ID: "<code>{id}</code>
+                            <button
+                                class="copy-btn"
+                                title="Copy request ID"
+                                on:click=move |_| {
+                                    copy_to_clipboard(&copy_id);
+                                    set_copied.set(true);
+                                }
+                            >
+                                {move || if copied.get() { "✓ Copied" } else { "Copy" }}
+                            </button>
+                        </span>
+                    }
+                })}
+            </div>
+        </div>
+    }
+}
+
+/// `<ErrorBoundary>` fallback - renders each caught error, using the
+/// structured card for [`ApiError`]s
+pub fn error_fallback(errors: ArcRwSignal<Errors>) -> impl IntoView {
+    move || {
+        errors
+            .get()
+            .into_iter()
+            .map(|(_, e)| match e.downcast_ref::<ApiError>() {
+                Some(error) => view! { <ApiErrorView error=error.clone() /> }.into_any(),
+                None => view! {
+                    <div class="result error">
+                        <div class="result-label">"Error"</div>
+                        <div class="result-value">{e.to_string()}</div>
+                    </div>
+                }.into_any(),
+            })
+            .collect::<Vec<_>>()
+    }
+}
+
+fn copy_to_clipboard(text: &str) {
+    // fire and forget - the promise only rejects without clipboard permission
+    let _ = window().navigator().clipboard().write_text(text);
+}
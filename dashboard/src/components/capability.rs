@@ -2,6 +2,10 @@
 
 use leptos::prelude::*;
 use crate::api;
+use crate::components::error_fallback;
+use crate::keyboard;
+use crate::network::use_network_status;
+use crate::session::{use_request_log, RequestLog};
 
 #[component]
 pub fn CapabilityTab() -> impl IntoView {
@@ -12,46 +16,73 @@ pub fn CapabilityTab() -> impl IntoView {
         ("sockets".to_string(), None),
         ("subprocess".to_string(), None),
     ]);
-    let (selected, set_selected) = signal::<Option<api::CapabilityResult>>(None);
+    // detail card for the last probe, or the error that stopped it
+    let (selected, set_selected) = signal::<Option<Result<api::CapabilityResult, api::ApiError>>>(None);
     let (loading, set_loading) = signal::<Option<String>>(None);
-    let (error, set_error) = signal::<Option<String>>(None);
+    // (completed, total) while "Test All" is running
+    let (progress, set_progress) = signal::<Option<(usize, usize)>>(None);
+    let network = use_network_status();
+    let log = use_request_log();
+    
+    // escape dismisses the result detail / error card
+    keyboard::on_escape(move || set_selected.set(None));
     
     // test a capability
     let test = move |capability: String| {
         set_loading.set(Some(capability.clone()));
-        set_error.set(None);
+        set_selected.set(None);
         
         let cap = capability.clone();
         leptos::task::spawn_local(async move {
-            match api::test_capability(&cap).await {
+            match probe(log, &cap).await {
                 Ok(result) => {
-                    set_selected.set(Some(result.clone()));
-                    
-                    // update results list
-                    set_results.update(|list| {
-                        for item in list.iter_mut() {
-                            if item.0 == cap {
-                                item.1 = Some(result.clone());
-                            }
-                        }
-                    });
+                    network.report_success();
+                    set_selected.set(Some(Ok(result.clone())));
+                    record_result(set_results, &cap, result);
                 }
                 Err(e) => {
-                    // Clear selected result so error shows prominently
-                    set_selected.set(None);
-                    
-                    // Check for various error types
-                    let error_lower = e.to_lowercase();
-                    if e.contains("429") || error_lower.contains("rate") {
-                        set_error.set(Some("⏱️ Rate limited! Please wait a minute and try again.".to_string()));
-                    } else if error_lower.contains("failed to fetch") || error_lower.contains("load failed") || error_lower.contains("network") {
-                        set_error.set(Some("🌐 Service temporarily unavailable. The demo may have exceeded its daily request limit. Please try again tomorrow!".to_string()));
-                    } else {
-                        set_error.set(Some(format!("Error: {}", e)));
+                    if e.is_network() {
+                        network.report_failure();
+                    }
+                    set_selected.set(Some(Err(e)));
+                }
+            }
+            set_loading.set(None);
+            // the grid re-renders with the result, so restore focus to the same probe
+            keyboard::focus_after_render(format!("[data-cap='{}']", cap));
+        });
+    };
+    
+    // test every capability in sequence, filling the grid in one action
+    let test_all = move |_| {
+        let caps: Vec<String> = results.get_untracked().into_iter().map(|(cap, _)| cap).collect();
+        let total = caps.len();
+        
+        set_selected.set(None);
+        set_progress.set(Some((0, total)));
+        
+        leptos::task::spawn_local(async move {
+            for (i, cap) in caps.into_iter().enumerate() {
+                set_loading.set(Some(cap.clone()));
+                
+                match probe(log, &cap).await {
+                    Ok(result) => {
+                        network.report_success();
+                        record_result(set_results, &cap, result);
+                    }
+                    Err(e) => {
+                        // stop on first failure - remaining probes would hit the same limit
+                        if e.is_network() {
+                            network.report_failure();
+                        }
+                        set_selected.set(Some(Err(e)));
+                        break;
                     }
                 }
+                set_progress.set(Some((i + 1, total)));
             }
             set_loading.set(None);
+            set_progress.set(None);
         });
     };
     
@@ -62,10 +93,40 @@ pub fn CapabilityTab() -> impl IntoView {
                 "Test what Cloudflare Workers can and cannot do. Click a capability to test it live."
             </p>
             
+            <div style="display: flex; align-items: center; gap: 1rem; margin-bottom: 1rem;">
+                <button
+                    on:click=test_all
+                    disabled=move || progress.get().is_some() || loading.get().is_some()
+                >
+                    {move || if progress.get().is_some() {
+                        view! { <span class="spinner"></span> " Testing..." }.into_any()
+                    } else {
+                        view! { "▶ Test All" }.into_any()
+                    }}
+                </button>
+                
+                // per-capability progress while running
+                {move || progress.get().map(|(done, total)| {
+                    let percent = done * 100 / total.max(1);
+                    view! {
+                        <div class="progress-container" style="flex: 1; margin: 0;">
+                            <div class="progress-bar">
+                                <div class="progress-fill" style=format!("width: {}%", percent)></div>
+                            </div>
+                            <div class="progress-info">
+                                <span>{done}" / "{total}" tested"</span>
+                                <span>{move || loading.get().unwrap_or_default()}</span>
+                            </div>
+                        </div>
+                    }
+                })}
+            </div>
+            
             <div class="capability-grid">
                 {move || results.get().into_iter().map(|(cap, result)| {
                     let cap_clone = cap.clone();
                     let is_loading = loading.get().as_ref() == Some(&cap);
+                    let is_busy = progress.get().is_some();
                     let class = match &result {
                         Some(r) if r.allowed => "capability-btn allowed",
                         Some(_) => "capability-btn blocked",
@@ -75,8 +136,9 @@ pub fn CapabilityTab() -> impl IntoView {
                     view! {
                         <button 
                             class=class
+                            data-cap=cap.clone()
                             on:click=move |_| test(cap_clone.clone())
-                            disabled=is_loading
+                            disabled=is_loading || is_busy
                         >
                             {if is_loading {
                                 view! { <span class="spinner"></span> }.into_any()
@@ -93,33 +155,22 @@ pub fn CapabilityTab() -> impl IntoView {
                 }).collect::<Vec<_>>()}
             </div>
             
-            // error display (rate limit, etc)
-            {move || error.get().map(|e| view! {
-                <div class="result error" style="background: rgba(239, 68, 68, 0.1); border-color: var(--error);">
-                    <div style="display: flex; align-items: center; gap: 0.5rem;">
-                        <span style="font-size: 1.25rem;">"⏱️"</span>
-                        <div>
-                            <div style="font-weight: 600; color: var(--error);">"Rate Limited"</div>
-                            <div style="color: var(--text-secondary); font-size: 0.875rem;">{e}</div>
+            // selected result detail - errors (rate limit, etc) render through the boundary
+            <ErrorBoundary fallback=error_fallback>
+                {move || selected.get().map(|r| r.map(|result| view! {
+                    <div class=if result.allowed { "result success" } else { "result error" }>
+                        <div style="display: flex; align-items: center; gap: 0.5rem; margin-bottom: 0.5rem;">
+                            <span class=if result.allowed { "status allowed" } else { "status blocked" }>
+                                {if result.allowed { "✅ ALLOWED" } else { "❌ BLOCKED" }}
+                            </span>
+                            <strong>{result.capability}</strong>
+                        </div>
+                        <div class="result-value" style="color: var(--text-secondary);">
+                            {result.message}
                         </div>
                     </div>
-                </div>
-            })}
-            
-            // selected result detail
-            {move || selected.get().map(|result| view! {
-                <div class=if result.allowed { "result success" } else { "result error" }>
-                    <div style="display: flex; align-items: center; gap: 0.5rem; margin-bottom: 0.5rem;">
-                        <span class=if result.allowed { "status allowed" } else { "status blocked" }>
-                            {if result.allowed { "✅ ALLOWED" } else { "❌ BLOCKED" }}
-                        </span>
-                        <strong>{result.capability}</strong>
-                    </div>
-                    <div class="result-value" style="color: var(--text-secondary);">
-                        {result.message}
-                    </div>
-                </div>
-            })}
+                }))}
+            </ErrorBoundary>
         </div>
         
         <div class="card">
@@ -138,3 +189,27 @@ pub fn CapabilityTab() -> impl IntoView {
         </div>
     }
 }
+
+/// Test one capability, recording the call in the request log
+async fn probe(log: RequestLog, cap: &str) -> Result<api::CapabilityResult, api::ApiError> {
+    let result = api::test_capability(cap).await;
+    log.record("capability-demo", format!("test {}", cap), &result, |r| {
+        if r.allowed { "allowed".to_string() } else { "blocked".to_string() }
+    });
+    result.map(|r| r.value)
+}
+
+/// Store a capability result in the grid
+fn record_result(
+    set_results: WriteSignal<Vec<(String, Option<api::CapabilityResult>)>>,
+    cap: &str,
+    result: api::CapabilityResult,
+) {
+    set_results.update(|list| {
+        for item in list.iter_mut() {
+            if item.0 == cap {
+                item.1 = Some(result.clone());
+            }
+        }
+    });
+}
@@ -0,0 +1,50 @@
+//! Footer component - which build of the dashboard is running
+
+use leptos::prelude::*;
+use wasm_bindgen::JsValue;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// full commit, stamped by build.rs
+const GIT_SHA: &str = env!("DASHBOARD_GIT_SHA");
+/// unix seconds, stamped by build.rs
+const BUILT_AT: &str = env!("DASHBOARD_BUILT_AT");
+
+const REPO_URL: &str = "https://github.com/gammahazard/edge-protocol-demo";
+
+#[component]
+pub fn Footer() -> impl IntoView {
+    let short_sha = GIT_SHA.get(..7).unwrap_or(GIT_SHA);
+    
+    view! {
+        <footer class="footer">
+            <span>"Dashboard v"{VERSION}</span>
+            " · "
+            {if GIT_SHA == "unknown" {
+                view! { <code>{short_sha}</code> }.into_any()
+            } else {
+                view! {
+                    <a href=format!("{}/commit/{}", REPO_URL, GIT_SHA) target="_blank" title=GIT_SHA>
+                        <code>{short_sha}</code>
+                    </a>
+                }.into_any()
+            }}
+            " · "
+            <span>"built "{format_built_at()}</span>
+            <span class="footer-hint">" · each worker reports its own build at GET /version"</span>
+        </footer>
+    }
+}
+
+/// Build time as YYYY-MM-DD HH:MM UTC
+fn format_built_at() -> String {
+    let secs: f64 = BUILT_AT.parse().unwrap_or(0.0);
+    let date = js_sys::Date::new(&JsValue::from_f64(secs * 1000.0));
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02} UTC",
+        date.get_utc_full_year(),
+        date.get_utc_month() + 1,
+        date.get_utc_date(),
+        date.get_utc_hours(),
+        date.get_utc_minutes(),
+    )
+}
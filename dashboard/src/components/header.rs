@@ -1,16 +1,50 @@
 //! Header component
 
 use leptos::prelude::*;
+use crate::api;
 
 #[component]
 pub fn Header() -> impl IntoView {
+    let (dev, set_dev) = signal(api::dev_mode());
+    
+    // switch all API calls between deployed and `wrangler dev` workers
+    let toggle_dev = move |_| {
+        let enabled = !dev.get_untracked();
+        api::set_dev_mode(enabled);
+        set_dev.set(enabled);
+    };
+    
+    let (gateway, set_gateway) = signal(api::gateway_mode());
+    
+    // route all API calls through the gateway worker's single origin
+    let toggle_gateway = move |_| {
+        let enabled = !gateway.get_untracked();
+        api::set_gateway_mode(enabled);
+        set_gateway.set(enabled);
+    };
+    
     view! {
         <header class="header">
             <div>
                 <h1>"Edge Protocol Demo"</h1>
                 <p class="subtitle">"Cloudflare Workers + Rust WASM"</p>
             </div>
-            <span class="badge">"Live on Edge"</span>
+            <div class="header-badges">
+                <button
+                    class=move || if gateway.get() { "badge gateway" } else { "badge direct" }
+                    on:click=toggle_gateway
+                    title="Toggle between calling each worker directly and going through the gateway worker"
+                >
+                    {move || if gateway.get() { "🔀 Via Gateway" } else { "Direct" }}
+                </button>
+                <button
+                    class=move || if dev.get() { "badge dev" } else { "badge" }
+                    on:click=toggle_dev
+                    title="Toggle between deployed workers and local wrangler dev (localhost:8786-8789)"
+                >
+                    {move || if dev.get() { "🛠 Local (wrangler dev)" } else { "Live on Edge" }}
+                </button>
+            </div>
         </header>
     }
 }
@@ -0,0 +1,226 @@
+//! Edge vs origin latency comparison tab component
+
+use leptos::prelude::*;
+use crate::api;
+
+const ORIGIN_KEY: &str = "edge-demo-origin-url";
+/// single-region origin used until the viewer sets their own
+const DEFAULT_ORIGIN: &str = "https://httpbin.org/get";
+const RUN_OPTIONS: &[usize] = &[5, 10, 20];
+
+/// One comparison run - `None` when that side's request failed
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    edge: Option<f64>,
+    origin: Option<f64>,
+}
+
+/// Load the origin URL from localStorage
+fn load_origin() -> String {
+    web_sys::window()
+        .and_then(|w| w.local_storage().ok().flatten())
+        .and_then(|s| s.get_item(ORIGIN_KEY).ok().flatten())
+        .unwrap_or_else(|| DEFAULT_ORIGIN.to_string())
+}
+
+/// Save the origin URL to localStorage
+fn save_origin(url: &str) {
+    if let Some(storage) = web_sys::window().and_then(|w| w.local_storage().ok().flatten()) {
+        let _ = storage.set_item(ORIGIN_KEY, url);
+    }
+}
+
+/// Median of the successful timings
+fn median(values: &[f64]) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    let mut sorted = values.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let mid = sorted.len() / 2;
+    Some(if sorted.len().is_multiple_of(2) {
+        (sorted[mid - 1] + sorted[mid]) / 2.0
+    } else {
+        sorted[mid]
+    })
+}
+
+#[component]
+pub fn LatencyTab() -> impl IntoView {
+    let targets = api::edge_health_urls();
+    let (edge_url, set_edge_url) = signal(targets[0].1.clone());
+    let (origin_url, set_origin_url) = signal(load_origin());
+    let (runs, set_runs) = signal(RUN_OPTIONS[1]);
+    let (samples, set_samples) = signal::<Vec<Sample>>(Vec::new());
+    // (completed, total) while running
+    let (progress, set_progress) = signal::<Option<(usize, usize)>>(None);
+    
+    // fire the same probe at both targets, alternating so neither side
+    // consistently benefits from a warmed-up connection
+    let run = move |_| {
+        let edge = edge_url.get_untracked();
+        let origin = origin_url.get_untracked().trim().to_string();
+        let total = runs.get_untracked();
+        save_origin(&origin);
+        
+        set_samples.set(Vec::new());
+        set_progress.set(Some((0, total)));
+        
+        leptos::task::spawn_local(async move {
+            for i in 0..total {
+                let (edge_ms, origin_ms) = if i % 2 == 0 {
+                    let e = api::measure_latency(&edge).await.ok();
+                    (e, api::measure_latency(&origin).await.ok())
+                } else {
+                    let o = api::measure_latency(&origin).await.ok();
+                    (api::measure_latency(&edge).await.ok(), o)
+                };
+                
+                // stop quietly if the tab was closed mid-run
+                if set_samples.try_update(|s| s.push(Sample { edge: edge_ms, origin: origin_ms })).is_none() {
+                    return;
+                }
+                set_progress.set(Some((i + 1, total)));
+            }
+            set_progress.set(None);
+        });
+    };
+    
+    // summary stats: (edge median, origin median, failed requests)
+    let summary = move || {
+        let samples = samples.get();
+        let edge: Vec<f64> = samples.iter().filter_map(|s| s.edge).collect();
+        let origin: Vec<f64> = samples.iter().filter_map(|s| s.origin).collect();
+        let failed = samples.len() * 2 - edge.len() - origin.len();
+        (median(&edge), median(&origin), failed)
+    };
+    
+    view! {
+        <div class="card">
+            <h2>"📡 Edge vs Origin"</h2>
+            <p style="color: var(--text-secondary); margin-bottom: 1rem; font-size: 0.875rem;">
+                "Time the same request against an edge worker and a single-region origin, measured from your browser."
+            </p>
+            
+            <div class="input-group">
+                <select on:change=move |ev| set_edge_url.set(event_target_value(&ev))>
+                    {targets.into_iter().map(|(label, url)| view! {
+                        <option value=url>{format!("Edge: {}", label)}</option>
+                    }).collect::<Vec<_>>()}
+                </select>
+                <select on:change=move |ev| set_runs.set(event_target_value(&ev).parse().unwrap_or(RUN_OPTIONS[1]))>
+                    {RUN_OPTIONS.iter().map(|n| view! {
+                        <option value=n.to_string() selected=move || runs.get() == *n>
+                            {format!("{} runs", n)}
+                        </option>
+                    }).collect::<Vec<_>>()}
+                </select>
+            </div>
+            
+            <div class="input-group">
+                <input
+                    type="text"
+                    placeholder="Origin URL, e.g. https://api.example.com/health"
+                    prop:value=move || origin_url.get()
+                    on:input=move |ev| set_origin_url.set(event_target_value(&ev))
+                />
+                <button
+                    on:click=run
+                    disabled=move || progress.get().is_some() || origin_url.get().trim().is_empty()
+                >
+                    {move || if progress.get().is_some() {
+                        view! { <span class="spinner"></span> " Running..." }.into_any()
+                    } else {
+                        view! { "▶ Compare" }.into_any()
+                    }}
+                </button>
+            </div>
+            
+            {move || progress.get().map(|(done, total)| view! {
+                <div class="progress-container">
+                    <div class="progress-bar">
+                        <div class="progress-fill" style=format!("width: {}%", done * 100 / total.max(1))></div>
+                    </div>
+                    <div class="progress-info">
+                        <span>{done}" / "{total}" runs"</span>
+                    </div>
+                </div>
+            })}
+            
+            // side-by-side bars per run, scaled to the slowest request
+            {move || {
+                let samples = samples.get();
+                if samples.is_empty() {
+                    return None;
+                }
+                let max = samples
+                    .iter()
+                    .flat_map(|s| [s.edge, s.origin])
+                    .flatten()
+                    .fold(1.0_f64, f64::max);
+                let bar = move |ms: Option<f64>, class: &'static str| match ms {
+                    Some(ms) => view! {
+                        <div
+                            class=format!("latency-bar {}", class)
+                            style=format!("height: {:.0}%", ms / max * 100.0)
+                            title=format!("{:.0} ms", ms)
+                        ></div>
+                    }.into_any(),
+                    None => view! {
+                        <div class=format!("latency-bar {} failed", class) title="request failed"></div>
+                    }.into_any(),
+                };
+                
+                Some(view! {
+                    <div class="latency-chart">
+                        {samples.into_iter().map(|s| view! {
+                            <div class="latency-run">
+                                {bar(s.edge, "edge")}
+                                {bar(s.origin, "origin")}
+                            </div>
+                        }).collect::<Vec<_>>()}
+                    </div>
+                    <div class="latency-legend">
+                        <span><span class="latency-swatch edge"></span>"Edge"</span>
+                        <span><span class="latency-swatch origin"></span>"Origin"</span>
+                        <span>{format!("max {:.0} ms", max)}</span>
+                    </div>
+                })
+            }}
+            
+            {move || {
+                let (edge, origin, failed) = summary();
+                (edge.is_some() || origin.is_some()).then(|| {
+                    let fmt = |ms: Option<f64>| ms.map(|ms| format!("{:.0} ms", ms)).unwrap_or_else(|| "-".to_string());
+                    let verdict = match (edge, origin) {
+                        (Some(e), Some(o)) if e > 0.0 => format!("Edge median is {:.1}× {} than origin", (o / e).max(e / o), if e <= o { "faster" } else { "slower" }),
+                        _ => "Not enough successful requests to compare".to_string(),
+                    };
+                    view! {
+                        <div class="result success">
+                            <div class="result-label">"Median latency"</div>
+                            <div class="result-value">
+                                "Edge: "{fmt(edge)}" · Origin: "{fmt(origin)}
+                            </div>
+                            <div class="result-value" style="margin-top: 0.5rem;">{verdict}</div>
+                            {(failed > 0).then(|| view! {
+                                <div class="result-label" style="margin: 0.5rem 0 0;">
+                                    {format!("{} request(s) failed and were left out", failed)}
+                                </div>
+                            })}
+                        </div>
+                    }
+                })
+            }}
+        </div>
+        
+        <div class="card">
+            <h2>"Reading the Chart"</h2>
+            <p style="color: var(--text-secondary); font-size: 0.875rem; line-height: 1.8;">
+                "Workers run in the Cloudflare location closest to you, while the origin answers from wherever it is hosted. "
+                "The first run of each side includes DNS and TLS setup, so compare the medians rather than single bars. "
+                "Probes use "<code>"no-cors"</code>" requests, so any public URL works as an origin."
+            </p>
+        </div>
+    }
+}
@@ -0,0 +1,150 @@
+//! Live event feed - new links, rate-limit blocks and faults from every worker
+
+use std::time::Duration;
+
+use leptos::prelude::*;
+use wasm_bindgen::prelude::*;
+use web_sys::{MessageEvent, WebSocket};
+use crate::api::{self, LiveEvent};
+
+/// events kept on screen, newest first
+const MAX_EVENTS: usize = 20;
+/// wait before reopening a dropped connection
+const RECONNECT_SECS: u64 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum FeedStatus {
+    Connecting,
+    Live,
+    Closed,
+}
+
+/// Socket handle shared by the connect/reconnect closures
+#[derive(Clone, Copy)]
+struct Feed {
+    socket: StoredValue<Option<WebSocket>, LocalStorage>,
+    set_status: WriteSignal<FeedStatus>,
+    set_events: WriteSignal<Vec<LiveEvent>>,
+}
+
+impl Feed {
+    fn connect(self) {
+        let Ok(ws) = WebSocket::new(&api::live_feed_url()) else {
+            self.set_status.set(FeedStatus::Closed);
+            return;
+        };
+        self.set_status.set(FeedStatus::Connecting);
+        
+        // the socket outlives this call, so its handlers are handed to js for good
+        let on_open = Closure::<dyn Fn()>::new(move || {
+            let _ = self.set_status.try_set(FeedStatus::Live);
+        });
+        let on_message = Closure::<dyn Fn(MessageEvent)>::new(move |ev: MessageEvent| {
+            let Some(text) = ev.data().as_string() else {
+                return;
+            };
+            if let Ok(event) = serde_json::from_str::<LiveEvent>(&text) {
+                self.set_events.try_update(|events| {
+                    events.insert(0, event);
+                    events.truncate(MAX_EVENTS);
+                });
+            }
+        });
+        let on_close = Closure::<dyn Fn()>::new(move || {
+            // unmounted - nothing to reconnect
+            if self.set_status.try_set(FeedStatus::Closed).is_some() {
+                return;
+            }
+            set_timeout(move || self.reconnect(), Duration::from_secs(RECONNECT_SECS));
+        });
+        ws.set_onopen(Some(on_open.into_js_value().unchecked_ref()));
+        ws.set_onmessage(Some(on_message.into_js_value().unchecked_ref()));
+        ws.set_onclose(Some(on_close.into_js_value().unchecked_ref()));
+        self.socket.set_value(Some(ws));
+    }
+    
+    /// Reopen the feed unless the panel is gone or a newer socket already took over
+    fn reconnect(self) {
+        let open = self.socket.try_with_value(|ws| {
+            ws.as_ref().is_some_and(|ws| ws.ready_state() <= WebSocket::OPEN)
+        });
+        if open == Some(false) {
+            self.connect();
+        }
+    }
+}
+
+#[component]
+pub fn LiveFeed() -> impl IntoView {
+    let (status, set_status) = signal(FeedStatus::Connecting);
+    let (events, set_events) = signal::<Vec<LiveEvent>>(Vec::new());
+    let feed = Feed {
+        socket: StoredValue::new_local(None),
+        set_status,
+        set_events,
+    };
+    
+    feed.connect();
+    on_cleanup(move || {
+        if let Some(Some(ws)) = feed.socket.try_get_value() {
+            ws.set_onclose(None);
+            let _ = ws.close();
+        }
+    });
+    
+    view! {
+        <div class="card">
+            <div style="display: flex; justify-content: space-between; align-items: center; margin-bottom: 1rem;">
+                <h2 style="margin: 0;">"📡 Live Feed"</h2>
+                <span class=move || match status.get() {
+                    FeedStatus::Live => "feed-status live",
+                    FeedStatus::Connecting => "feed-status",
+                    FeedStatus::Closed => "feed-status closed",
+                }>
+                    {move || match status.get() {
+                        FeedStatus::Live => "● live",
+                        FeedStatus::Connecting => "○ connecting",
+                        FeedStatus::Closed => "○ reconnecting",
+                    }}
+                </span>
+            </div>
+            
+            {move || {
+                let events = events.get();
+                if events.is_empty() {
+                    return view! {
+                        <p class="storage-disclaimer">"New links, rate-limit blocks and errors from every worker show up here as they happen."</p>
+                    }.into_any();
+                }
+                
+                view! {
+                    <ul class="request-log">
+                        {events.into_iter().map(|e| view! {
+                            <li class=if e.kind == "link" { "ok" } else { "failed" }>
+                                <span class="request-log-time">{format_clock(e.at)}</span>
+                                <span class="request-log-action">{e.worker}" · "{describe(&e.kind)}</span>
+                                <span class="request-log-detail">{e.subject}</span>
+                                <code>{e.request_id}</code>
+                            </li>
+                        }).collect::<Vec<_>>()}
+                    </ul>
+                }.into_any()
+            }}
+        </div>
+    }
+}
+
+fn describe(kind: &str) -> &'static str {
+    match kind {
+        "link" => "new link",
+        "block" => "rate limited",
+        "fault" => "server error",
+        _ => "event",
+    }
+}
+
+/// Format a timestamp (ms) as local HH:MM:SS
+fn format_clock(at: u64) -> String {
+    let date = js_sys::Date::new(&JsValue::from_f64(at as f64));
+    format!("{:02}:{:02}:{:02}", date.get_hours(), date.get_minutes(), date.get_seconds())
+}
@@ -7,9 +7,25 @@ mod tabs;
 mod url_shortener;
 mod rate_limiter;
 mod capability;
+mod latency;
+mod session_panel;
+mod live_feed;
+mod offline_banner;
+mod api_error;
+mod turnstile;
+mod footer;
+mod status_strip;
 
 pub use header::Header;
 pub use tabs::TabNav;
 pub use url_shortener::UrlShortenerTab;
 pub use rate_limiter::RateLimiterTab;
 pub use capability::CapabilityTab;
+pub use latency::LatencyTab;
+pub use session_panel::SessionPanel;
+pub use live_feed::LiveFeed;
+pub use offline_banner::OfflineBanner;
+pub use api_error::error_fallback;
+pub use turnstile::{TurnstileToken, TurnstileWidget};
+pub use footer::Footer;
+pub use status_strip::StatusStrip;
@@ -0,0 +1,26 @@
+//! Offline banner component
+
+use leptos::prelude::*;
+use crate::network::use_network_status;
+
+#[component]
+pub fn OfflineBanner() -> impl IntoView {
+    let network = use_network_status();
+    
+    view! {
+        <Show when=move || !network.online.get()>
+            <div class="offline-banner">
+                <span>
+                    "📡 You're offline - the workers can't be reached."
+                    {move || {
+                        let count = network.pending.get().len();
+                        (count > 0).then(|| format!(" {} action(s) queued.", count))
+                    }}
+                </span>
+                <button class="secondary" on:click=move |_| network.replay()>
+                    "↻ Retry now"
+                </button>
+            </div>
+        </Show>
+    }
+}
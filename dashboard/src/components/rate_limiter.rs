@@ -1,12 +1,91 @@
 //! Rate Limiter tab component
 
+use std::time::Duration;
+
 use leptos::prelude::*;
 use crate::api;
+use crate::components::error_fallback;
+use crate::keyboard;
+use crate::network::use_network_status;
+use crate::session::use_request_log;
+
+// auto-refresh polling intervals (seconds)
+const ACTIVE_POLL_SECS: u64 = 5;
+const IDLE_POLL_MIN_SECS: u64 = 10;
+const IDLE_POLL_MAX_SECS: u64 = 120;
+
+/// Delay before the next status poll, plus the idle delay to use after it.
+/// Polls tightly while a window is counting down and backs off exponentially
+/// when idle, so leaving auto-refresh on doesn't burn the daily request budget.
+fn next_poll_delay(window_active: bool, idle_delay: u64) -> (u64, u64) {
+    if window_active {
+        (ACTIVE_POLL_SECS, IDLE_POLL_MIN_SECS)
+    } else {
+        (idle_delay, (idle_delay * 2).min(IDLE_POLL_MAX_SECS))
+    }
+}
+
+/// Signals the status auto-refresh loop reads and writes
+#[derive(Clone, Copy)]
+struct StatusPoller {
+    /// bumped on every restart so superseded loops stop
+    generation: StoredValue<u32>,
+    idle_delay: StoredValue<u64>,
+    enabled: ReadSignal<bool>,
+    has_active_window: ReadSignal<bool>,
+    set_has_active_window: WriteSignal<bool>,
+    set_countdown: WriteSignal<u64>,
+    set_status: WriteSignal<Option<api::RateLimitStatus>>,
+}
+
+impl StatusPoller {
+    /// (Re)start polling after `delay_secs`, cancelling any poll already scheduled
+    fn restart(self, delay_secs: u64) {
+        let Some(generation) = self.generation.try_update_value(|g| {
+            *g += 1;
+            *g
+        }) else {
+            return;
+        };
+        self.idle_delay.set_value(IDLE_POLL_MIN_SECS);
+        self.schedule(generation, delay_secs);
+    }
+    
+    fn schedule(self, generation: u32, delay_secs: u64) {
+        set_timeout(move || self.poll(generation), Duration::from_secs(delay_secs));
+    }
+    
+    fn poll(self, generation: u32) {
+        // stop if disabled, superseded by a newer loop, or the tab unmounted
+        if self.generation.try_get_value() != Some(generation)
+            || self.enabled.try_get_untracked() != Some(true)
+        {
+            return;
+        }
+        
+        leptos::task::spawn_local(async move {
+            if let Ok(s) = api::get_rate_status().await {
+                let active = s.requests_made > 0 && s.reset_in_seconds > 0;
+                self.set_countdown.set(s.reset_in_seconds);
+                self.set_has_active_window.set(active);
+                self.set_status.set(Some(s.value));
+            }
+            
+            let active = self.has_active_window.try_get_untracked().unwrap_or(false);
+            let Some(idle) = self.idle_delay.try_get_value() else {
+                return;
+            };
+            let (delay, next_idle) = next_poll_delay(active, idle);
+            self.idle_delay.set_value(next_idle);
+            self.schedule(generation, delay);
+        });
+    }
+}
 
 #[component]
 pub fn RateLimiterTab() -> impl IntoView {
     let (status, set_status) = signal::<Option<api::RateLimitStatus>>(None);
-    let (last_response, set_last_response) = signal::<Option<String>>(None);
+    let (last_response, set_last_response) = signal::<Option<Result<String, api::ApiError>>>(None);
     let (loading, set_loading) = signal(false);
     let (rate_limited, set_rate_limited) = signal(false);
     
@@ -14,9 +93,23 @@ pub fn RateLimiterTab() -> impl IntoView {
     let (countdown, set_countdown) = signal::<u64>(0);
     // track if user has an active rate limit window
     let (has_active_window, set_has_active_window) = signal(false);
+    let network = use_network_status();
+    let log = use_request_log();
+    
+    // optional status auto-refresh (off by default)
+    let (auto_refresh, set_auto_refresh) = signal(false);
+    let poller = StatusPoller {
+        generation: StoredValue::new(0),
+        idle_delay: StoredValue::new(IDLE_POLL_MIN_SECS),
+        enabled: auto_refresh,
+        has_active_window,
+        set_has_active_window,
+        set_countdown,
+        set_status,
+    };
     
     // Note: We don't fetch status on mount to save API requests.
-    // Status will populate on first "Send Request" click.
+    // Status will populate on first "Send Request" click, or via auto-refresh.
     
     // live countdown timer - uses try_get to safely handle disposed signals
     Effect::new(move || {
@@ -70,23 +163,29 @@ pub fn RateLimiterTab() -> impl IntoView {
         closure.forget();
     });
     
+    // escape dismisses the last response
+    keyboard::on_escape(move || set_last_response.set(None));
+    
     // test request action
     let test_request = move |_| {
         set_loading.set(true);
+        // clear first so a repeated error replaces, rather than joins, the old one
+        set_last_response.set(None);
         
         leptos::task::spawn_local(async move {
-            match api::test_rate_limit().await {
+            let result = api::test_rate_limit().await;
+            log.record("rate-limiter", "GET /api/protected", &result, |r| r.message.clone());
+            match result {
                 Ok(resp) => {
-                    set_last_response.set(Some(format!("✅ {}", resp.message)));
+                    network.report_success();
+                    set_last_response.set(Some(Ok(format!("✅ {}", resp.message))));
                     set_rate_limited.set(false);
                 }
                 Err(e) => {
-                    let error_lower = e.to_lowercase();
-                    if error_lower.contains("failed to fetch") || error_lower.contains("load failed") || error_lower.contains("network") {
-                        set_last_response.set(Some("🌐 Service unavailable - daily request limit may be exceeded. Try again tomorrow!".to_string()));
-                    } else {
-                        set_last_response.set(Some(format!("❌ {}", e)));
+                    if e.is_network() {
+                        network.report_failure();
                     }
+                    set_last_response.set(Some(Err(e)));
                     set_rate_limited.set(true);
                 }
             }
@@ -95,10 +194,17 @@ pub fn RateLimiterTab() -> impl IntoView {
             if let Ok(s) = api::get_rate_status().await {
                 set_countdown.set(s.reset_in_seconds);
                 set_has_active_window.set(true);
-                set_status.set(Some(s));
+                set_status.set(Some(s.value));
+            }
+            
+            // a new window just started - poll at the tight interval again
+            if auto_refresh.get_untracked() {
+                poller.restart(ACTIVE_POLL_SECS);
             }
             
             set_loading.set(false);
+            // the button was disabled while loading, which drops focus
+            keyboard::focus_after_render("#send-request".to_string());
         });
     };
     
@@ -110,6 +216,7 @@ pub fn RateLimiterTab() -> impl IntoView {
             </p>
             
             <button 
+                id="send-request"
                 on:click=test_request
                 disabled=move || loading.get()
                 class=move || if rate_limited.get() { "danger" } else { "" }
@@ -121,6 +228,30 @@ pub fn RateLimiterTab() -> impl IntoView {
                 }}
             </button>
             
+            <label class="toggle">
+                <input
+                    type="checkbox"
+                    prop:checked=move || auto_refresh.get()
+                    on:change=move |ev| {
+                        let enabled = event_target_checked(&ev);
+                        set_auto_refresh.set(enabled);
+                        if enabled {
+                            poller.restart(0);
+                        }
+                    }
+                />
+                " Auto-refresh status"
+                <span class="toggle-hint">
+                    {move || auto_refresh.get().then(|| {
+                        if has_active_window.get() {
+                            format!(" - every {}s while a window is active", ACTIVE_POLL_SECS)
+                        } else {
+                            format!(" - backing off up to {}s while idle", IDLE_POLL_MAX_SECS)
+                        }
+                    })}
+                </span>
+            </label>
+            
             // progress bar with live countdown
             {move || status.get().map(|s| {
                 let percent = (s.requests_remaining as f32 / s.limit as f32 * 100.0) as u32;
@@ -152,12 +283,14 @@ pub fn RateLimiterTab() -> impl IntoView {
                 }
             })}
             
-            // last response
-            {move || last_response.get().map(|resp| view! {
-                <div class=if rate_limited.get() { "result error" } else { "result success" }>
-                    <div class="result-value">{resp}</div>
-                </div>
-            })}
+            // last response - errors render through the boundary
+            <ErrorBoundary fallback=error_fallback>
+                {move || last_response.get().map(|r| r.map(|resp| view! {
+                    <div class="result success">
+                        <div class="result-value">{resp}</div>
+                    </div>
+                }))}
+            </ErrorBoundary>
         </div>
         
         <div class="card">
@@ -0,0 +1,116 @@
+//! Request log and session share/restore component
+
+use leptos::prelude::*;
+use wasm_bindgen::JsCast;
+use wasm_bindgen_futures::JsFuture;
+use crate::session::{self, use_request_log};
+
+/// log rows shown before the list is cut off
+const VISIBLE_ENTRIES: usize = 10;
+
+#[component]
+pub fn SessionPanel() -> impl IntoView {
+    let log = use_request_log();
+    let (status, set_status) = signal::<Option<Result<String, String>>>(None);
+    
+    let copy_link = move |_| {
+        let link = session::share_link();
+        let _ = window().navigator().clipboard().write_text(&link);
+        set_status.set(Some(Ok(format!("Share link copied ({} characters)", link.len()))));
+    };
+    
+    // fill in the href as the link is clicked so the download is always current
+    let download = move |ev: leptos::ev::MouseEvent| {
+        if let Some(anchor) = ev.current_target().and_then(|t| t.dyn_into::<web_sys::Element>().ok()) {
+            let json = session::export_json();
+            let href = format!(
+                "data:application/json;charset=utf-8,{}",
+                js_sys::encode_uri_component(&json)
+            );
+            let _ = anchor.set_attribute("href", &href);
+        }
+    };
+    
+    let load = move |ev: leptos::ev::Event| {
+        let input = event_target::<web_sys::HtmlInputElement>(&ev);
+        let Some(file) = input.files().and_then(|f| f.get(0)) else {
+            return;
+        };
+        
+        leptos::task::spawn_local(async move {
+            let text = JsFuture::from(file.text())
+                .await
+                .ok()
+                .and_then(|v| v.as_string())
+                .unwrap_or_default();
+            
+            match session::import_json(&text) {
+                // tabs read their state on mount - reload to pick up the restored session
+                Ok(()) => {
+                    let _ = window().location().reload();
+                }
+                Err(e) => set_status.set(Some(Err(e))),
+            }
+        });
+    };
+    
+    view! {
+        <div class="card">
+            <div style="display: flex; justify-content: space-between; align-items: center; margin-bottom: 1rem; gap: 0.5rem; flex-wrap: wrap;">
+                <h2 style="margin: 0;">"🧾 Session"</h2>
+                <div class="session-actions">
+                    <button class="secondary" on:click=copy_link>"🔗 Copy share link"</button>
+                    <a class="button secondary" download="edge-demo-session.json" href="#" on:click=download>
+                        "⬇ Download"
+                    </a>
+                    <label class="button secondary">
+                        "⬆ Load"
+                        <input type="file" accept="application/json,.json" on:change=load hidden />
+                    </label>
+                </div>
+            </div>
+            
+            {move || status.get().map(|s| match s {
+                Ok(msg) => view! { <p class="session-status">{msg}</p> }.into_any(),
+                Err(e) => view! { <p class="field-error">{e}</p> }.into_any(),
+            })}
+            
+            {move || {
+                let entries = log.entries.get();
+                if entries.is_empty() {
+                    return view! {
+                        <p class="storage-disclaimer">"No requests yet - calls made from any tab show up here."</p>
+                    }.into_any();
+                }
+                let hidden = entries.len().saturating_sub(VISIBLE_ENTRIES);
+                
+                view! {
+                    <ul class="request-log">
+                        {entries.into_iter().take(VISIBLE_ENTRIES).map(|e| view! {
+                            <li class=if e.ok { "ok" } else { "failed" }>
+                                <span class="request-log-time">{format_clock(e.at)}</span>
+                                <span class="request-log-action">{e.service}" · "{e.action}</span>
+                                <span class="request-log-detail">{e.detail}</span>
+                                {e.request_id.map(|id| view! { <code>{id}</code> })}
+                            </li>
+                        }).collect::<Vec<_>>()}
+                    </ul>
+                    <div style="display: flex; justify-content: space-between; align-items: center; margin-top: 0.75rem;">
+                        <span class="storage-disclaimer" style="margin: 0;">
+                            {if hidden > 0 { format!("+{} older", hidden) } else { "Share links include this log".to_string() }}
+                        </span>
+                        <button class="secondary" style="padding: 0.5rem 1rem; font-size: 0.75rem;" on:click=move |_| log.clear()>
+                            "Clear log"
+                        </button>
+                    </div>
+                }.into_any()
+            }}
+        </div>
+    }
+}
+
+/// Format a timestamp (seconds) as local HH:MM:SS
+fn format_clock(at: u64) -> String {
+    let date = js_sys::Date::new(&wasm_bindgen::JsValue::from_f64(at as f64 * 1000.0));
+    format!("{:02}:{:02}:{:02}", date.get_hours(), date.get_minutes(), date.get_seconds())
+}
@@ -0,0 +1,97 @@
+//! Status strip - one pill per worker from the gateway's /health/all
+
+use std::time::Duration;
+
+use leptos::prelude::*;
+use crate::api::{self, HealthCheck, HealthReport};
+
+/// how often the strip re-checks while the page is open
+const REFRESH_SECS: u64 = 60;
+
+#[component]
+pub fn StatusStrip() -> impl IntoView {
+    let (report, set_report) = signal::<Option<HealthReport>>(None);
+    let (failed, set_failed) = signal(false);
+    let (loading, set_loading) = signal(false);
+    
+    let refresh = move || {
+        if loading.get_untracked() {
+            return;
+        }
+        set_loading.set(true);
+        leptos::task::spawn_local(async move {
+            let result = api::get_health().await;
+            // the strip may have unmounted while the check ran
+            if set_loading.try_set(false).is_some() {
+                return;
+            }
+            match result {
+                Ok(r) => {
+                    set_report.set(Some(r.value));
+                    set_failed.set(false);
+                }
+                Err(_) => set_failed.set(true),
+            }
+        });
+    };
+    
+    refresh();
+    if let Ok(handle) = set_interval_with_handle(refresh, Duration::from_secs(REFRESH_SECS)) {
+        on_cleanup(move || handle.clear());
+    }
+    
+    view! {
+        <div class="status-strip">
+            <button
+                class="status-overall"
+                on:click=move |_| refresh()
+                title="Check every worker and its bindings again (gateway /health/all)"
+            >
+                {move || match (failed.get(), report.get().map(|r| r.status)) {
+                    (true, _) => "○ gateway unreachable".to_string(),
+                    (_, Some(status)) => format!("{} {}", if status == "ok" { "●" } else { "◐" }, status),
+                    (_, None) => "○ checking".to_string(),
+                }}
+            </button>
+            {move || report.get().map(|r| workers(&r.checks).into_iter().map(|(worker, deps)| {
+                let healthy = worker.up && deps.iter().all(|d| d.up);
+                let class = if !worker.up { "status-pill down" } else if healthy { "status-pill up" } else { "status-pill degraded" };
+                view! {
+                    <span class=class title=describe(&worker, &deps)>
+                        {worker.name.clone()}
+                        <small>{format!(" {}ms", worker.latency_ms)}</small>
+                    </span>
+                }
+            }).collect::<Vec<_>>())}
+        </div>
+    }
+}
+
+/// Group the flat check list into (worker, its binding checks)
+fn workers(checks: &[HealthCheck]) -> Vec<(HealthCheck, Vec<HealthCheck>)> {
+    checks
+        .iter()
+        .filter(|c| c.kind == "worker")
+        .map(|w| {
+            let prefix = format!("{}/", w.name);
+            let deps = checks.iter().filter(|c| c.name.starts_with(&prefix)).cloned().collect();
+            (w.clone(), deps)
+        })
+        .collect()
+}
+
+/// Hover text: one line per binding with its latency or error
+fn describe(worker: &HealthCheck, deps: &[HealthCheck]) -> String {
+    let mut lines = vec![match &worker.error {
+        Some(e) => format!("{}: {}", worker.name, e),
+        None => format!("{}: {}ms", worker.name, worker.latency_ms),
+    }];
+    lines.extend(deps.iter().map(|d| {
+        let binding = d.name.rsplit('/').next().unwrap_or(&d.name);
+        match &d.error {
+            Some(e) => format!("✗ {} ({}) - {}", binding, d.kind, e),
+            None => format!("✓ {} ({}) {}ms", binding, d.kind, d.latency_ms),
+        }
+    }));
+    lines.join("\n")
+}
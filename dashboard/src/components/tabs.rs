@@ -12,21 +12,31 @@ pub fn TabNav(
             <button
                 class=move || if active_tab.get() == 0 { "tab active" } else { "tab" }
                 on:click=move |_| set_active_tab.set(0)
+                title="Shortcut: 1"
             >
                 "🔗 URL Shortener"
             </button>
             <button
                 class=move || if active_tab.get() == 1 { "tab active" } else { "tab" }
                 on:click=move |_| set_active_tab.set(1)
+                title="Shortcut: 2"
             >
                 "⏱️ Rate Limiter"
             </button>
             <button
                 class=move || if active_tab.get() == 2 { "tab active" } else { "tab" }
                 on:click=move |_| set_active_tab.set(2)
+                title="Shortcut: 3"
             >
                 "🔒 Capabilities"
             </button>
+            <button
+                class=move || if active_tab.get() == 3 { "tab active" } else { "tab" }
+                on:click=move |_| set_active_tab.set(3)
+                title="Shortcut: 4"
+            >
+                "📡 Latency"
+            </button>
         </div>
     }
 }
@@ -0,0 +1,94 @@
+//! Cloudflare Turnstile widget for write forms
+//!
+//! The api.js script (loaded in index.html with `render=explicit`) exposes
+//! `window.turnstile`; the widget is rendered into our own div so Leptos owns
+//! its lifetime. If the script was blocked the widget silently doesn't render
+//! and the worker's 403 explains what went wrong.
+
+use leptos::prelude::*;
+use wasm_bindgen::prelude::*;
+use wasm_bindgen::JsCast;
+use crate::api;
+
+/// Token state shared between the widget and the form submitting it
+#[derive(Clone, Copy)]
+pub struct TurnstileToken {
+    token: RwSignal<Option<String>>,
+    widget_id: StoredValue<Option<JsValue>, LocalStorage>,
+}
+
+impl TurnstileToken {
+    pub fn new() -> Self {
+        Self {
+            token: RwSignal::new(None),
+            widget_id: StoredValue::new_local(None),
+        }
+    }
+    
+    /// Take the current token - they're single-use, so the widget is reset
+    /// to fetch a fresh one for the next submission
+    pub fn take(&self) -> Option<String> {
+        let token = self.token.try_update(Option::take).flatten();
+        if let (Some(turnstile), Some(Some(id))) = (turnstile(), self.widget_id.try_get_value()) {
+            let _ = call(&turnstile, "reset", &[id]);
+        }
+        token
+    }
+}
+
+/// `window.turnstile`, if the script loaded
+fn turnstile() -> Option<JsValue> {
+    js_sys::Reflect::get(&window(), &"turnstile".into())
+        .ok()
+        .filter(|t| !t.is_undefined())
+}
+
+/// Call `turnstile.<method>(...args)`
+fn call(turnstile: &JsValue, method: &str, args: &[JsValue]) -> Result<JsValue, JsValue> {
+    let function: js_sys::Function = js_sys::Reflect::get(turnstile, &method.into())?.dyn_into()?;
+    function.apply(turnstile, &args.iter().collect::<js_sys::Array>())
+}
+
+/// `{ sitekey, theme, callback, "expired-callback" }`
+fn render_options(token: RwSignal<Option<String>>) -> Result<JsValue, JsValue> {
+    let options = js_sys::Object::new();
+    let set = |key: &str, value: &JsValue| js_sys::Reflect::set(&options, &key.into(), value);
+    set("sitekey", &api::TURNSTILE_SITE_KEY.into())?;
+    set("theme", &"dark".into())?;
+    
+    // the widget outlives nothing but its own div, so the closures are handed to js for good
+    let on_token = Closure::<dyn Fn(String)>::new(move |t: String| {
+        let _ = token.try_set(Some(t));
+    });
+    let on_expired = Closure::<dyn Fn()>::new(move || {
+        let _ = token.try_set(None);
+    });
+    set("callback", &on_token.into_js_value())?;
+    set("expired-callback", &on_expired.into_js_value())?;
+    Ok(options.into())
+}
+
+#[component]
+pub fn TurnstileWidget(token: TurnstileToken) -> impl IntoView {
+    let container = NodeRef::<leptos::html::Div>::new();
+    
+    Effect::new(move || {
+        let (Some(el), Some(turnstile)) = (container.get(), turnstile()) else {
+            return;
+        };
+        let rendered = render_options(token.token)
+            .and_then(|options| call(&turnstile, "render", &[el.into(), options]));
+        match rendered {
+            Ok(id) => token.widget_id.set_value(Some(id)),
+            Err(e) => leptos::logging::warn!("turnstile render failed: {:?}", e),
+        }
+    });
+    
+    on_cleanup(move || {
+        if let (Some(turnstile), Some(Some(id))) = (turnstile(), token.widget_id.try_get_value()) {
+            let _ = call(&turnstile, "remove", &[id]);
+        }
+    });
+    
+    view! { <div class="turnstile" node_ref=container></div> }
+}
@@ -2,10 +2,23 @@
 
 use leptos::prelude::*;
 use crate::api;
+use crate::components::{error_fallback, TurnstileToken, TurnstileWidget};
+use crate::keyboard;
+use crate::network::{use_network_status, NetworkStatus};
+use crate::session::{use_request_log, RequestLog};
 use serde::{Deserialize, Serialize};
 
 const STORAGE_KEY: &str = "edge-demo-shortened-urls";
 
+/// Expiry choices offered in the form (label, seconds - 0 means never)
+const EXPIRY_OPTIONS: &[(&str, u64)] = &[
+    ("Never expires", 0),
+    ("1 hour", 3600),
+    ("1 day", 86400),
+    ("7 days", 7 * 86400),
+    ("30 days", 30 * 86400),
+];
+
 /// Stored URL entry for localStorage
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct StoredUrl {
@@ -14,6 +27,9 @@ struct StoredUrl {
     original_url: String,
     created_at: u64,
     clicks: u64,
+    /// expiry timestamp in seconds, none = never
+    #[serde(default)]
+    expires_at: Option<u64>,
 }
 
 /// Load URLs from localStorage
@@ -62,29 +78,145 @@ fn format_relative_time(timestamp: u64) -> String {
     }
 }
 
+/// Format time left until expiry
+fn format_expiry(expires_at: Option<u64>, now: u64) -> String {
+    let Some(expires_at) = expires_at else {
+        return "Never".to_string();
+    };
+    let left = expires_at.saturating_sub(now);
+    
+    if left == 0 {
+        "Expired".to_string()
+    } else if left < 3600 {
+        format!("in {}m", left.div_ceil(60))
+    } else if left < 86400 {
+        format!("in {}h {}m", left / 3600, (left % 3600) / 60)
+    } else {
+        format!("in {}d {}h", left / 86400, (left % 86400) / 3600)
+    }
+}
+
+/// Client-side mirror of the worker's alias rules (3-32 chars, letters/digits/-/_)
+fn validate_alias(alias: &str) -> Result<(), String> {
+    if alias.is_empty() {
+        return Ok(());
+    }
+    if alias.len() < 3 || alias.len() > 32 {
+        return Err("Alias must be 3-32 characters".to_string());
+    }
+    if !alias.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("Alias may only contain letters, digits, '-' and '_'".to_string());
+    }
+    if ["shorten", "stats", "health"].contains(&alias.to_ascii_lowercase().as_str()) {
+        return Err("That alias is reserved".to_string());
+    }
+    Ok(())
+}
+
+/// Add a URL to the history, persisting it even if the tab has since unmounted
+fn remember_url(set_stored_urls: WriteSignal<Vec<StoredUrl>>, new_url: StoredUrl) {
+    fn insert(urls: &mut Vec<StoredUrl>, new_url: StoredUrl) {
+        // avoid duplicates
+        if !urls.iter().any(|u| u.code == new_url.code) {
+            urls.insert(0, new_url);
+            save_stored_urls(urls);
+        }
+    }
+    
+    let pending = new_url.clone();
+    if set_stored_urls.try_update(|urls| insert(urls, pending)).is_none() {
+        // signal disposed (e.g. replayed from the offline queue on another tab)
+        let mut urls = load_stored_urls();
+        insert(&mut urls, new_url);
+    }
+}
+
+/// Signals the shorten flow writes to
+#[derive(Clone, Copy)]
+struct ShortenHandles {
+    set_result: WriteSignal<Option<Result<api::ShortenResponse, api::ApiError>>>,
+    set_loading: WriteSignal<bool>,
+    set_stored_urls: WriteSignal<Vec<StoredUrl>>,
+    network: NetworkStatus,
+    log: RequestLog,
+    turnstile: TurnstileToken,
+}
+
+/// Queue a shorten request until connectivity returns
+fn queue_shorten(req: api::ShortenRequest, h: ShortenHandles) {
+    h.set_result.set(Some(Err(api::ApiError {
+        status: 0,
+        code: "offline_queued".to_string(),
+        message: "📡 Offline - request queued and will be sent when you're back online.".to_string(),
+        request_id: None,
+    })));
+    h.network.enqueue(format!("Shorten {}", req.url), move || {
+        h.set_loading.set(true);
+        leptos::task::spawn_local(run_shorten(req, h));
+    });
+}
+
+/// Shorten a URL, queueing it for replay if the network is down
+async fn run_shorten(req: api::ShortenRequest, h: ShortenHandles) {
+    h.set_result.set(None);
+    // read at send time, so replayed requests use whatever token is current then
+    let result = api::shorten_url(&req, h.turnstile.take()).await;
+    // queued retries are logged once they actually go out
+    if !result.as_ref().is_err_and(|e| e.is_network()) {
+        h.log.record("url-shortener", "POST /shorten", &result, |r| r.short_url.clone());
+    }
+    match result {
+        Ok(resp) => {
+            h.network.report_success();
+            remember_url(h.set_stored_urls, StoredUrl {
+                code: resp.code.clone(),
+                short_url: resp.short_url.clone(),
+                original_url: resp.original_url.clone(),
+                created_at: now_timestamp(),
+                clicks: 0,
+                expires_at: resp.expires_at.map(|ms| ms / 1000),
+            });
+            h.set_result.set(Some(Ok(resp.value)));
+        }
+        Err(e) if e.is_network() => {
+            h.network.report_failure();
+            queue_shorten(req, h);
+        }
+        Err(e) => h.set_result.set(Some(Err(e))),
+    }
+    h.set_loading.set(false);
+    
+    // hand focus back to the input for the next url
+    keyboard::focus_after_render("#url-input".to_string());
+}
+
 #[component]
 pub fn UrlShortenerTab() -> impl IntoView {
     let (url_input, set_url_input) = signal(String::new());
-    let (result, set_result) = signal::<Option<Result<api::ShortenResponse, String>>>(None);
+    let (alias_input, set_alias_input) = signal(String::new());
+    let (expiry_secs, set_expiry_secs) = signal(0u64);
+    let alias_error = move || validate_alias(alias_input.get().trim()).err();
+    let (result, set_result) = signal::<Option<Result<api::ShortenResponse, api::ApiError>>>(None);
     let (loading, set_loading) = signal(false);
     let (stored_urls, set_stored_urls) = signal(load_stored_urls());
+    let network = use_network_status();
+    let log = use_request_log();
+    let turnstile = TurnstileToken::new();
+    let handles = ShortenHandles { set_result, set_loading, set_stored_urls, network, log, turnstile };
     
     // refresh stats for all stored URLs
     let refresh_stats = move || {
         let urls = stored_urls.get();
         for url in urls.iter() {
             let code = url.code.clone();
-            leptos::task::spawn_local({
-                let set_stored_urls = set_stored_urls.clone();
-                async move {
-                    if let Ok(stats) = api::get_url_stats(&code).await {
-                        set_stored_urls.update(|urls| {
-                            if let Some(url) = urls.iter_mut().find(|u| u.code == code) {
-                                url.clicks = stats.clicks;
-                            }
-                            save_stored_urls(urls);
-                        });
-                    }
+            leptos::task::spawn_local(async move {
+                if let Ok(stats) = api::get_url_stats(&code).await {
+                    set_stored_urls.update(|urls| {
+                        if let Some(url) = urls.iter_mut().find(|u| u.code == code) {
+                            url.clicks = stats.clicks;
+                        }
+                        save_stored_urls(urls);
+                    });
                 }
             });
         }
@@ -95,41 +227,44 @@ pub fn UrlShortenerTab() -> impl IntoView {
         refresh_stats();
     });
     
-    // shorten action
-    let shorten = move |_| {
+    // tick the clock so expiry countdowns stay current
+    let (now, set_now) = signal(now_timestamp());
+    if let Ok(handle) = set_interval_with_handle(
+        move || set_now.set(now_timestamp()),
+        std::time::Duration::from_secs(30),
+    ) {
+        on_cleanup(move || handle.clear());
+    }
+    
+    // escape dismisses the result toast
+    keyboard::on_escape(move || set_result.set(None));
+    
+    // shorten action (form submit, so Enter in the input works)
+    let shorten = move |ev: leptos::ev::SubmitEvent| {
+        ev.prevent_default();
         let url = url_input.get();
-        if url.is_empty() {
+        if url.is_empty() || alias_error().is_some() {
             return;
         }
         
-        set_loading.set(true);
+        let alias = alias_input.get().trim().to_string();
+        let expires_in = expiry_secs.get();
+        let req = api::ShortenRequest {
+            url,
+            alias: (!alias.is_empty()).then_some(alias),
+            expires_in: (expires_in > 0).then_some(expires_in),
+        };
+        
         set_result.set(None);
         
-        leptos::task::spawn_local(async move {
-            let res = api::shorten_url(&url).await;
-            
-            // if successful, add to stored URLs
-            if let Ok(ref resp) = res {
-                let new_url = StoredUrl {
-                    code: resp.code.clone(),
-                    short_url: resp.short_url.clone(),
-                    original_url: resp.original_url.clone(),
-                    created_at: now_timestamp(),
-                    clicks: 0,
-                };
-                
-                set_stored_urls.update(|urls| {
-                    // avoid duplicates
-                    if !urls.iter().any(|u| u.code == new_url.code) {
-                        urls.insert(0, new_url);
-                        save_stored_urls(urls);
-                    }
-                });
-            }
-            
-            set_result.set(Some(res));
-            set_loading.set(false);
-        });
+        // don't bother hitting the network when the browser knows it's offline
+        if !network.online.get_untracked() {
+            queue_shorten(req, handles);
+            return;
+        }
+        
+        set_loading.set(true);
+        leptos::task::spawn_local(run_shorten(req, handles));
     };
     
     // delete a stored URL
@@ -147,28 +282,56 @@ pub fn UrlShortenerTab() -> impl IntoView {
                 "Create short URLs using Workers KV storage at the edge."
             </p>
             
-            <div class="input-group">
-                <input
-                    type="text"
-                    placeholder="https://example.com/very/long/url"
-                    prop:value=move || url_input.get()
-                    on:input=move |ev| set_url_input.set(event_target_value(&ev))
-                />
-                <button 
-                    on:click=shorten
-                    disabled=move || loading.get() || url_input.get().is_empty()
-                >
-                    {move || if loading.get() {
-                        view! { <span class="spinner"></span> " Shortening..." }.into_any()
-                    } else {
-                        view! { "Shorten" }.into_any()
-                    }}
-                </button>
-            </div>
+            <form on:submit=shorten>
+                <div class="input-group">
+                    <input
+                        id="url-input"
+                        type="text"
+                        placeholder="https://example.com/very/long/url"
+                        prop:value=move || url_input.get()
+                        on:input=move |ev| set_url_input.set(event_target_value(&ev))
+                    />
+                    <button 
+                        type="submit"
+                        disabled=move || loading.get() || url_input.get().is_empty() || alias_error().is_some()
+                    >
+                        {move || if loading.get() {
+                            view! { <span class="spinner"></span> " Shortening..." }.into_any()
+                        } else {
+                            view! { "Shorten" }.into_any()
+                        }}
+                    </button>
+                </div>
+                
+                // optional custom alias + expiry
+                <div class="input-group">
+                    <input
+                        type="text"
+                        placeholder="Custom alias (optional)"
+                        prop:value=move || alias_input.get()
+                        on:input=move |ev| set_alias_input.set(event_target_value(&ev))
+                    />
+                    <select
+                        on:change=move |ev| set_expiry_secs.set(event_target_value(&ev).parse().unwrap_or(0))
+                    >
+                        {EXPIRY_OPTIONS.iter().map(|(label, secs)| view! {
+                            <option value=secs.to_string() selected=move || expiry_secs.get() == *secs>
+                                {*label}
+                            </option>
+                        }).collect::<Vec<_>>()}
+                    </select>
+                </div>
+                
+                <TurnstileWidget token=turnstile />
+            </form>
+            
+            {move || alias_error().map(|e| view! {
+                <p class="field-error">{e}</p>
+            })}
             
-            // result display - simple toast since table shows details
-            {move || result.get().map(|r| match r {
-                Ok(resp) => {
+            // result display - simple toast since table shows details, errors go through the boundary
+            <ErrorBoundary fallback=error_fallback>
+                {move || result.get().map(|r| r.map(|resp| {
                     let short_url = resp.short_url.clone();
                     let short_url_display = short_url.clone();
                     
@@ -182,15 +345,9 @@ pub fn UrlShortenerTab() -> impl IntoView {
                                 </a>
                             </div>
                         </div>
-                    }.into_any()
-                },
-                Err(e) => view! {
-                    <div class="result error">
-                        <div class="result-label">"Error"</div>
-                        <div class="result-value">{e}</div>
-                    </div>
-                }.into_any(),
-            })}
+                    }
+                }))}
+            </ErrorBoundary>
         </div>
         
         // URL History table
@@ -220,6 +377,7 @@ pub fn UrlShortenerTab() -> impl IntoView {
                                         <th>"Original"</th>
                                         <th>"Clicks"</th>
                                         <th>"Created"</th>
+                                        <th>"Expires"</th>
                                         <th></th>
                                     </tr>
                                 </thead>
@@ -234,6 +392,8 @@ pub fn UrlShortenerTab() -> impl IntoView {
                                         };
                                         let clicks = url.clicks;
                                         let created = format_relative_time(url.created_at);
+                                        let expires_at = url.expires_at;
+                                        let expired = move || expires_at.is_some_and(|t| t <= now.get());
                                         let code_for_delete = url.code.clone();
                                         
                                         view! {
@@ -252,6 +412,11 @@ pub fn UrlShortenerTab() -> impl IntoView {
                                                 <td style="color: var(--text-secondary); font-size: 0.8rem;">
                                                     {created}
                                                 </td>
+                                                <td
+                                                    class=move || if expired() { "expiry expired" } else { "expiry" }
+                                                >
+                                                    {move || format_expiry(expires_at, now.get())}
+                                                </td>
                                                 <td>
                                                     <button 
                                                         class="delete-btn"
@@ -0,0 +1,54 @@
+//! ==============================================================================
+//! keyboard.rs - Keyboard shortcuts and focus helpers
+//! ==============================================================================
+//!
+//! shortcuts:
+//!     1-4     switch tabs (ignored while typing in a field)
+//!     Enter   submits the focused form (native <form> behaviour)
+//!     Escape  dismisses result cards and error toasts
+//!
+//! ==============================================================================
+
+use leptos::prelude::*;
+use wasm_bindgen::JsCast;
+
+/// Whether the key event comes from a text field, where shortcuts must not fire
+fn is_typing(ev: &web_sys::KeyboardEvent) -> bool {
+    ev.target()
+        .and_then(|t| t.dyn_into::<web_sys::Element>().ok())
+        .is_some_and(|el| matches!(el.tag_name().as_str(), "INPUT" | "TEXTAREA" | "SELECT"))
+}
+
+/// Map a key press to a tab index (`1` → 0, `2` → 1, ...)
+pub fn tab_shortcut(ev: &web_sys::KeyboardEvent, tab_count: usize) -> Option<usize> {
+    if ev.ctrl_key() || ev.meta_key() || ev.alt_key() || is_typing(ev) {
+        return None;
+    }
+    let digit = ev.key().parse::<usize>().ok()?;
+    (1..=tab_count).contains(&digit).then(|| digit - 1)
+}
+
+/// Run `dismiss` when Escape is pressed, for as long as the calling component is mounted
+pub fn on_escape(dismiss: impl Fn() + 'static) {
+    let handle = window_event_listener(leptos::ev::keydown, move |ev| {
+        if ev.key() == "Escape" {
+            dismiss();
+        }
+    });
+    on_cleanup(move || handle.remove());
+}
+
+/// Focus an element once the pending DOM updates have been applied
+/// (e.g. a button that was disabled while its request was in flight)
+pub fn focus_after_render(selector: String) {
+    request_animation_frame(move || {
+        if let Some(el) = document()
+            .query_selector(&selector)
+            .ok()
+            .flatten()
+            .and_then(|el| el.dyn_into::<web_sys::HtmlElement>().ok())
+        {
+            let _ = el.focus();
+        }
+    });
+}
@@ -5,12 +5,17 @@
 //! purpose:
 //!     leptos wasm dashboard for interacting with cloudflare workers.
 //!     provides visual interface to test url shortener, rate limiter,
-//!     and capability explorer.
+//!     capability explorer and edge-vs-origin latency comparison.
 //!
 //! architecture:
 //!     - leptos csr (client-side rendering)
 //!     - compiled to wasm, runs in browser
 //!     - calls worker apis via fetch
+//!     - live feed of events from every worker over a websocket
+//!     - status strip of every worker and binding (gateway /health/all)
+//!     - queues writes while offline and replays them on reconnect
+//!     - logs api calls; sessions can be exported and shared as links
+//!     - footer names the build (version, commit, build time - build.rs)
 //!     - hosted on cloudflare pages
 //!
 //! ==============================================================================
@@ -20,8 +25,11 @@ use wasm_bindgen::prelude::*;
 
 mod api;
 mod components;
+mod keyboard;
+mod network;
+mod session;
 
-use components::{Header, TabNav, UrlShortenerTab, RateLimiterTab, CapabilityTab};
+use components::{Header, Footer, OfflineBanner, StatusStrip, SessionPanel, LiveFeed, TabNav, UrlShortenerTab, RateLimiterTab, CapabilityTab, LatencyTab};
 
 // ==============================================================================
 // main entry point
@@ -30,6 +38,10 @@ use components::{Header, TabNav, UrlShortenerTab, RateLimiterTab, CapabilityTab}
 #[wasm_bindgen(start)]
 pub fn main() {
     console_error_panic_hook::set_once();
+    // honour ?dev=1 / ?gateway=1 before any component reads the API base urls
+    api::init_modes();
+    // a shared #session= link restores its state before the tabs load it
+    session::restore_from_url();
     mount_to_body(App);
 }
 
@@ -42,8 +54,22 @@ fn App() -> impl IntoView {
     // track active tab
     let (active_tab, set_active_tab) = signal(0usize);
     
+    // connectivity tracking + offline retry queue
+    network::provide_network_status();
+    // api call history, shown in the session panel
+    session::provide_request_log();
+    
+    // number keys switch tabs
+    let _ = window_event_listener(leptos::ev::keydown, move |ev| {
+        if let Some(tab) = keyboard::tab_shortcut(&ev, 4) {
+            set_active_tab.set(tab);
+        }
+    });
+    
     view! {
         <Header />
+        <OfflineBanner />
+        <StatusStrip />
         <div class="container">
             <TabNav active_tab=active_tab set_active_tab=set_active_tab />
             
@@ -58,6 +84,14 @@ fn App() -> impl IntoView {
             <Show when=move || active_tab.get() == 2>
                 <CapabilityTab />
             </Show>
+            
+            <Show when=move || active_tab.get() == 3>
+                <LatencyTab />
+            </Show>
+            
+            <LiveFeed />
+            <SessionPanel />
         </div>
+        <Footer />
     }
 }
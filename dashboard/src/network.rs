@@ -0,0 +1,90 @@
+//! ==============================================================================
+//! network.rs - Connectivity tracking and offline retry queue
+//! ==============================================================================
+//!
+//! purpose:
+//!     tracks whether the workers are reachable (navigator.onLine plus
+//!     failed fetches) and holds write actions that could not be sent,
+//!     replaying them once connectivity returns.
+//!
+//! design:
+//!     the queue lives in memory only - it is a convenience for flaky
+//!     connections, not durable storage. wasm is single-threaded, so the
+//!     queued closures are kept in a thread-local rather than a signal.
+//!
+//! ==============================================================================
+
+use std::cell::RefCell;
+
+use leptos::prelude::*;
+
+thread_local! {
+    static QUEUE: RefCell<Vec<Box<dyn FnOnce()>>> = RefCell::new(Vec::new());
+}
+
+/// Connectivity state shared through context
+#[derive(Debug, Clone, Copy)]
+pub struct NetworkStatus {
+    pub online: ReadSignal<bool>,
+    set_online: WriteSignal<bool>,
+    /// labels of queued actions, for display
+    pub pending: ReadSignal<Vec<String>>,
+    set_pending: WriteSignal<Vec<String>>,
+}
+
+impl NetworkStatus {
+    /// Record a fetch that failed at the network layer
+    pub fn report_failure(&self) {
+        self.set_online.set(false);
+    }
+
+    /// Record a successful fetch - connectivity is back, so flush the queue
+    pub fn report_success(&self) {
+        if !self.online.get_untracked() {
+            self.set_online.set(true);
+            self.replay();
+        }
+    }
+
+    /// Queue a write action to run when connectivity returns
+    pub fn enqueue(&self, label: impl Into<String>, action: impl FnOnce() + 'static) {
+        QUEUE.with(|q| q.borrow_mut().push(Box::new(action)));
+        self.set_pending.update(|p| p.push(label.into()));
+    }
+
+    /// Run every queued action (each one re-queues itself if it fails again)
+    pub fn replay(&self) {
+        let actions: Vec<_> = QUEUE.with(|q| q.borrow_mut().drain(..).collect());
+        self.set_pending.set(Vec::new());
+
+        for action in actions {
+            action();
+        }
+    }
+}
+
+/// Create the network status, hook up browser events and provide it as context
+pub fn provide_network_status() -> NetworkStatus {
+    let initial = window().navigator().on_line();
+    let (online, set_online) = signal(initial);
+    let (pending, set_pending) = signal(Vec::<String>::new());
+
+    let status = NetworkStatus { online, set_online, pending, set_pending };
+
+    // app-lifetime listeners - the handles are never removed
+    let _ = window_event_listener(leptos::ev::online, move |_| {
+        status.set_online.set(true);
+        status.replay();
+    });
+    let _ = window_event_listener(leptos::ev::offline, move |_| {
+        status.set_online.set(false);
+    });
+
+    provide_context(status);
+    status
+}
+
+/// Get the network status provided by the app
+pub fn use_network_status() -> NetworkStatus {
+    expect_context::<NetworkStatus>()
+}
@@ -0,0 +1,237 @@
+//! ==============================================================================
+//! session.rs - Request log and shareable session snapshots
+//! ==============================================================================
+//!
+//! purpose:
+//!     keeps a short log of the api calls made from the dashboard and
+//!     packs the demo state (persisted tab state + request log) into a
+//!     compact json snapshot that can be downloaded or shared as a link.
+//!
+//! format:
+//!     {"v":1,"at":<secs>,"storage":{"edge-demo-...":"..."},"log":[...]}
+//!     links carry it base64url-encoded in the fragment: #session=<blob>
+//!     the fragment never reaches a server, so shared runs stay client-side.
+//!
+//! ==============================================================================
+
+use std::collections::BTreeMap;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use leptos::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::api::{self, ApiError, Traced};
+
+const LOG_KEY: &str = "edge-demo-request-log";
+/// only keys with this prefix are exported or restored
+const STATE_PREFIX: &str = "edge-demo-";
+const FRAGMENT_PREFIX: &str = "#session=";
+const SNAPSHOT_VERSION: u32 = 1;
+/// log entries kept - also bounds the size of share links
+const MAX_LOG_ENTRIES: usize = 25;
+
+// ==============================================================================
+// Request log
+// ==============================================================================
+
+/// One api call made from the dashboard
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LogEntry {
+    /// timestamp in seconds
+    pub at: u64,
+    pub service: String,
+    pub action: String,
+    pub ok: bool,
+    /// result summary or error message
+    pub detail: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+}
+
+/// Request log shared through context
+#[derive(Debug, Clone, Copy)]
+pub struct RequestLog {
+    pub entries: ReadSignal<Vec<LogEntry>>,
+    set_entries: WriteSignal<Vec<LogEntry>>,
+}
+
+impl RequestLog {
+    /// Record the outcome of an api call, newest first
+    pub fn record<T>(
+        &self,
+        service: &str,
+        action: impl Into<String>,
+        result: &Result<Traced<T>, ApiError>,
+        summary: impl FnOnce(&T) -> String,
+    ) {
+        let entry = match result {
+            Ok(value) => LogEntry {
+                at: now_secs(),
+                service: service.to_string(),
+                action: action.into(),
+                ok: true,
+                detail: summary(&value.value),
+                request_id: value.request_id.clone(),
+            },
+            Err(e) => LogEntry {
+                at: now_secs(),
+                service: service.to_string(),
+                action: action.into(),
+                ok: false,
+                detail: e.to_string(),
+                request_id: e.request_id.clone(),
+            },
+        };
+        
+        // the tab may have unmounted while the request was in flight
+        let _ = self.set_entries.try_update(|entries| {
+            entries.insert(0, entry);
+            entries.truncate(MAX_LOG_ENTRIES);
+            save_log(entries);
+        });
+    }
+    
+    pub fn clear(&self) {
+        self.set_entries.set(Vec::new());
+        save_log(&[]);
+    }
+}
+
+/// Create the request log from localStorage and provide it as context
+pub fn provide_request_log() -> RequestLog {
+    let (entries, set_entries) = signal(load_log());
+    let log = RequestLog { entries, set_entries };
+    provide_context(log);
+    log
+}
+
+/// Get the request log provided by the app
+pub fn use_request_log() -> RequestLog {
+    expect_context::<RequestLog>()
+}
+
+// ==============================================================================
+// Snapshots
+// ==============================================================================
+
+/// Everything needed to reproduce a demo session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SessionSnapshot {
+    #[serde(rename = "v")]
+    version: u32,
+    #[serde(rename = "at")]
+    exported_at: u64,
+    /// persisted tab state (url history, latency origin, ...)
+    storage: BTreeMap<String, String>,
+    log: Vec<LogEntry>,
+}
+
+/// Serialize the current session to json
+pub fn export_json() -> String {
+    let mut storage = BTreeMap::new();
+    if let Some(s) = local_storage() {
+        for i in 0..s.length().unwrap_or(0) {
+            let Some(key) = s.key(i).ok().flatten() else {
+                continue;
+            };
+            if is_shared_key(&key) {
+                if let Some(value) = s.get_item(&key).ok().flatten() {
+                    storage.insert(key, value);
+                }
+            }
+        }
+    }
+    
+    let snapshot = SessionSnapshot {
+        version: SNAPSHOT_VERSION,
+        exported_at: now_secs(),
+        storage,
+        log: load_log(),
+    };
+    serde_json::to_string(&snapshot).unwrap_or_default()
+}
+
+/// Build a link that restores the current session when opened
+pub fn share_link() -> String {
+    let location = window().location();
+    format!(
+        "{}{}{}{}",
+        location.origin().unwrap_or_default(),
+        location.pathname().unwrap_or_default(),
+        FRAGMENT_PREFIX,
+        URL_SAFE_NO_PAD.encode(export_json()),
+    )
+}
+
+/// Restore a session from exported json, replacing the persisted state.
+/// Tabs read their state on mount, so callers reload afterwards.
+pub fn import_json(json: &str) -> Result<(), String> {
+    let snapshot: SessionSnapshot =
+        serde_json::from_str(json).map_err(|e| format!("Not a session file: {}", e))?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(format!("Unsupported session version {}", snapshot.version));
+    }
+    
+    let storage = local_storage().ok_or("localStorage is unavailable")?;
+    for (key, value) in snapshot.storage.iter().filter(|(k, _)| is_shared_key(k)) {
+        let _ = storage.set_item(key, value);
+    }
+    let mut log = snapshot.log;
+    log.truncate(MAX_LOG_ENTRIES);
+    save_log(&log);
+    Ok(())
+}
+
+/// Apply a `#session=...` fragment, then drop it so a refresh doesn't re-import
+pub fn restore_from_url() {
+    let location = window().location();
+    let hash = location.hash().unwrap_or_default();
+    let Some(blob) = hash.strip_prefix(FRAGMENT_PREFIX) else {
+        return;
+    };
+    
+    let restored = URL_SAFE_NO_PAD
+        .decode(blob)
+        .map_err(|e| e.to_string())
+        .and_then(|bytes| String::from_utf8(bytes).map_err(|e| e.to_string()))
+        .and_then(|json| import_json(&json));
+    if let Err(e) = restored {
+        leptos::logging::warn!("ignoring shared session: {}", e);
+    }
+    let _ = location.set_hash("");
+}
+
+/// Persisted state that belongs in a snapshot - the log travels separately
+/// and dev/gateway mode are properties of the viewer's setup, not the session
+fn is_shared_key(key: &str) -> bool {
+    key.starts_with(STATE_PREFIX)
+        && key != LOG_KEY
+        && key != api::DEV_MODE_KEY
+        && key != api::GATEWAY_MODE_KEY
+}
+
+// ==============================================================================
+// Storage helpers
+// ==============================================================================
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window().and_then(|w| w.local_storage().ok().flatten())
+}
+
+fn load_log() -> Vec<LogEntry> {
+    local_storage()
+        .and_then(|s| s.get_item(LOG_KEY).ok().flatten())
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+fn save_log(entries: &[LogEntry]) {
+    if let (Some(storage), Ok(json)) = (local_storage(), serde_json::to_string(entries)) {
+        let _ = storage.set_item(LOG_KEY, &json);
+    }
+}
+
+fn now_secs() -> u64 {
+    (js_sys::Date::now() / 1000.0) as u64
+}
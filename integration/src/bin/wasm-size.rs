@@ -0,0 +1,88 @@
+//! ==============================================================================
+//! wasm-size.rs - measure built worker bundles against their size budgets
+//! ==============================================================================
+//!
+//! purpose:
+//!     ci builds every worker with worker-build, then runs this to fail the
+//!     pr when a bundle outgrows its budget in shared::size_budget.
+//!
+//! usage:
+//!     cargo run -p integration-tests --bin wasm-size [-- --json] [worker ...]
+//!     without names, every directory under workers/ is measured. a worker
+//!     that hasn't been built fails the run rather than passing unseen.
+//!     exit status 1 when anything is over budget, unbudgeted or unbuilt.
+//!
+//! ==============================================================================
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use shared::size_budget::SizeReport;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let json = args.iter().any(|a| a == "--json");
+    let workers_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("..").join("workers");
+    let names: Vec<String> = match args.iter().filter(|a| !a.starts_with("--")).cloned().collect::<Vec<_>>() {
+        names if !names.is_empty() => names,
+        _ => worker_dirs(&workers_dir),
+    };
+    
+    let mut measured = Vec::new();
+    let mut unbuilt = Vec::new();
+    for name in &names {
+        match bundle_bytes(&workers_dir.join(name).join("build")) {
+            Some(bytes) => measured.push((name.as_str(), bytes)),
+            None => unbuilt.push(name.as_str()),
+        }
+    }
+    
+    let report = SizeReport::new(&measured);
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report).expect("report serializes"));
+    } else {
+        println!("{}", report.table());
+    }
+    for name in &unbuilt {
+        eprintln!("{}: no .wasm under build/ - run worker-build --release in workers/{}", name, name);
+    }
+    if report.within_budget && unbuilt.is_empty() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// every worker crate, by directory name
+fn worker_dirs(workers_dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = fs::read_dir(workers_dir)
+        .map(|entries| {
+            entries
+                .filter_map(|e| e.ok())
+                .filter(|e| e.path().join("Cargo.toml").is_file())
+                .filter_map(|e| e.file_name().into_string().ok())
+                .collect()
+        })
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+/// total size of the .wasm files worker-build left under `dir` - one in
+/// practice, but its layout has moved between versions
+fn bundle_bytes(dir: &Path) -> Option<u64> {
+    let mut pending: Vec<PathBuf> = vec![dir.to_path_buf()];
+    let mut total = None;
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).ok()?.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            if path.is_dir() {
+                pending.push(path);
+            } else if path.extension().is_some_and(|ext| ext == "wasm") {
+                *total.get_or_insert(0) += entry.metadata().ok()?.len();
+            }
+        }
+    }
+    total
+}
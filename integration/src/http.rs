@@ -0,0 +1,194 @@
+//! ==============================================================================
+//! http.rs - just enough http/1.0 client to talk to wrangler dev
+//! ==============================================================================
+//!
+//! purpose:
+//!     sends one request per connection over a plain tcp socket and reads
+//!     the response to eof. redirects are not followed, so tests see the
+//!     301s and 304s the workers send.
+//!
+//! design rationale:
+//!     std only - the harness stays buildable offline and adds nothing to
+//!     the lockfile. http/1.0 with "Connection: close" means the body ends
+//!     at eof; chunked bodies are still decoded in case a proxy upgrades.
+//!
+//! ==============================================================================
+
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+/// longest a single request may take, first compile aside
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+#[derive(Debug, Clone)]
+pub struct Request {
+    pub method: &'static str,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn new(method: &'static str, path: &str) -> Self {
+        Self { method, path: path.to_string(), headers: Vec::new(), body: Vec::new() }
+    }
+    
+    pub fn get(path: &str) -> Self {
+        Self::new("GET", path)
+    }
+    
+    pub fn post(path: &str, body: impl Into<Vec<u8>>) -> Self {
+        Self { body: body.into(), ..Self::new("POST", path) }
+    }
+    
+    pub fn options(path: &str) -> Self {
+        Self::new("OPTIONS", path)
+    }
+    
+    pub fn delete(path: &str) -> Self {
+        Self::new("DELETE", path)
+    }
+    
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+    
+    /// the bytes on the wire
+    pub fn encode(&self, host: &str) -> Vec<u8> {
+        let mut head = format!("{} {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n", self.method, self.path, host);
+        for (name, value) in &self.headers {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        if !self.body.is_empty() || self.method == "POST" {
+            head.push_str(&format!("Content-Length: {}\r\n", self.body.len()));
+        }
+        head.push_str("\r\n");
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(&self.body);
+        bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    /// first header named `name`, case-insensitively
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+    
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+    
+    /// the body as json - panics with the body when it isn't
+    pub fn json(&self) -> serde_json::Value {
+        serde_json::from_slice(&self.body)
+            .unwrap_or_else(|e| panic!("{} response is not json ({}): {}", self.status, e, self.text()))
+    }
+}
+
+// ==============================================================================
+// client
+// ==============================================================================
+
+/// send `req` to 127.0.0.1:`port`
+pub fn send(port: u16, req: &Request) -> io::Result<Response> {
+    let host = format!("127.0.0.1:{}", port);
+    let mut stream = TcpStream::connect(&host)?;
+    stream.set_read_timeout(Some(IO_TIMEOUT))?;
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    stream.write_all(&req.encode(&host))?;
+    
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw)?;
+    parse_response(&raw).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed http response"))
+}
+
+/// split a raw response into status, headers and (de-chunked) body
+pub fn parse_response(raw: &[u8]) -> Option<Response> {
+    let split = raw.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let head = std::str::from_utf8(&raw[..split]).ok()?;
+    let mut lines = head.split("\r\n");
+    
+    // "HTTP/1.1 200 OK"
+    let status = lines.next()?.split_whitespace().nth(1)?.parse().ok()?;
+    let headers: Vec<(String, String)> = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(n, v)| (n.trim().to_string(), v.trim().to_string()))
+        .collect();
+    
+    let mut response = Response { status, headers, body: raw[split + 4..].to_vec() };
+    if response.header("Transfer-Encoding").is_some_and(|te| te.eq_ignore_ascii_case("chunked")) {
+        response.body = dechunk(&response.body)?;
+    }
+    Some(response)
+}
+
+fn dechunk(mut data: &[u8]) -> Option<Vec<u8>> {
+    let mut body = Vec::new();
+    loop {
+        let line_end = data.windows(2).position(|w| w == b"\r\n")?;
+        let size_field = std::str::from_utf8(&data[..line_end]).ok()?;
+        // chunk extensions after ';' carry nothing we need
+        let size = usize::from_str_radix(size_field.split(';').next()?.trim(), 16).ok()?;
+        data = &data[line_end + 2..];
+        if size == 0 {
+            return Some(body);
+        }
+        body.extend_from_slice(data.get(..size)?);
+        data = data.get(size + 2..)?;
+    }
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_encode_request() {
+        let req = Request::post("/shorten", r#"{"url":"https://example.com"}"#).header("Origin", "https://dash.example");
+        let wire = String::from_utf8(req.encode("127.0.0.1:18787")).unwrap();
+        assert!(wire.starts_with("POST /shorten HTTP/1.0\r\nHost: 127.0.0.1:18787\r\n"));
+        assert!(wire.contains("Origin: https://dash.example\r\n"));
+        assert!(wire.contains("Content-Length: 29\r\n\r\n{\"url\""));
+        
+        // bodyless requests don't announce a length
+        assert!(!String::from_utf8(Request::get("/health").encode("h")).unwrap().contains("Content-Length"));
+    }
+    
+    #[test]
+    fn test_parse_response() {
+        let raw = b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 42\r\ncontent-type: application/problem+json\r\n\r\n{\"status\":429}";
+        let resp = parse_response(raw).unwrap();
+        assert_eq!(resp.status, 429);
+        assert_eq!(resp.header("retry-after"), Some("42"));
+        assert_eq!(resp.header("Content-Type"), Some("application/problem+json"));
+        assert_eq!(resp.json()["status"], 429);
+        
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\n").is_none());
+        assert!(parse_response(b"garbage\r\n\r\n").is_none());
+    }
+    
+    #[test]
+    fn test_parse_chunked_response() {
+        let raw = b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n4\r\nedge\r\n6;x=y\r\n-proto\r\n0\r\n\r\n";
+        assert_eq!(parse_response(raw).unwrap().text(), "edge-proto");
+        assert!(parse_response(b"HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\nzz\r\n").is_none());
+    }
+}
@@ -0,0 +1,176 @@
+//! ==============================================================================
+//! lib.rs - integration test harness: real workers under wrangler dev
+//! ==============================================================================
+//!
+//! purpose:
+//!     the crates' unit tests only reach pure functions. the suites in
+//!     integration/tests start the actual workers with `wrangler dev`
+//!     (workerd, with miniflare's local kv, durable objects, d1 and queues)
+//!     and drive their http endpoints - routing, kv round trips, cors,
+//!     429s and malformed bodies.
+//!
+//! relationships:
+//!     - runs: workers/<name> via `wrangler dev` (their own wrangler.toml)
+//!     - used by: integration/tests/*.rs
+//!
+//! running:
+//!     cargo test -p integration-tests -- --ignored
+//!     needs wrangler on PATH (or WRANGLER=/path/to/wrangler) and the
+//!     wasm32-unknown-unknown target - the first start of each worker runs
+//!     its [build] command, so allow a few minutes.
+//!
+//! design:
+//!     each suite is one #[test] that starts its workers, runs named checks
+//!     in order and reports every failure at the end - a failing check
+//!     doesn't skip the rest, and the workers are always stopped. state
+//!     lives in a fresh --persist-to directory per start, so every run
+//!     begins with empty kv. service bindings (the gateway) find the other
+//!     workers through wrangler's local dev registry.
+//!
+//! ==============================================================================
+
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod http;
+
+pub use http::{Request, Response};
+
+/// default time a worker gets to build and answer /health
+const STARTUP_SECS: u64 = 300;
+/// inspector ports sit this far above the worker's own port
+const INSPECTOR_OFFSET: u16 = 1000;
+
+// ==============================================================================
+// workers
+// ==============================================================================
+
+/// one worker running under `wrangler dev`, stopped on drop
+pub struct Worker {
+    pub name: &'static str,
+    pub port: u16,
+    child: Child,
+    state_dir: PathBuf,
+}
+
+impl Worker {
+    /// start workers/`name` on `port`, with `vars` overriding its [vars],
+    /// and wait until GET /health answers
+    pub fn start(name: &'static str, port: u16, vars: &[(&str, &str)]) -> Worker {
+        let root = workspace_root();
+        let state_dir = root.join("target").join("integration").join(format!("{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&state_dir);
+        fs::create_dir_all(&state_dir).expect("create state dir");
+        let log = fs::File::create(state_dir.join("wrangler.log")).expect("create wrangler log");
+        
+        let wrangler = std::env::var("WRANGLER").unwrap_or_else(|_| "wrangler".to_string());
+        let mut command = Command::new(wrangler);
+        command
+            .current_dir(root.join("workers").join(name))
+            .args(["dev", "--ip", "127.0.0.1"])
+            .args(["--port", &port.to_string()])
+            .args(["--inspector-port", &(port + INSPECTOR_OFFSET).to_string()])
+            .arg("--persist-to")
+            .arg(state_dir.join("state"));
+        for (key, value) in vars {
+            command.args(["--var", &format!("{}:{}", key, value)]);
+        }
+        let child = command
+            .stdin(Stdio::null())
+            .stdout(log.try_clone().expect("clone log handle"))
+            .stderr(log)
+            .spawn()
+            .unwrap_or_else(|e| panic!("can't run wrangler for {} ({}) - is it installed?", name, e));
+        
+        let mut worker = Worker { name, port, child, state_dir };
+        worker.wait_ready();
+        worker
+    }
+    
+    fn wait_ready(&mut self) {
+        let secs = std::env::var("EDGE_IT_STARTUP_SECS").ok().and_then(|s| s.parse().ok()).unwrap_or(STARTUP_SECS);
+        let deadline = Instant::now() + Duration::from_secs(secs);
+        while Instant::now() < deadline {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                panic!("{} exited early ({})\n{}", self.name, status, self.log_tail());
+            }
+            if http::send(self.port, &Request::get("/health")).is_ok_and(|r| r.status == 200) {
+                return;
+            }
+            thread::sleep(Duration::from_millis(500));
+        }
+        panic!("{} didn't answer /health within {}s\n{}", self.name, secs, self.log_tail());
+    }
+    
+    /// send a request, panicking if the worker can't be reached
+    pub fn send(&self, req: Request) -> Response {
+        http::send(self.port, &req).unwrap_or_else(|e| panic!("{} {} on {}: {}", req.method, req.path, self.name, e))
+    }
+    
+    pub fn get(&self, path: &str) -> Response {
+        self.send(Request::get(path))
+    }
+    
+    /// the last lines wrangler printed, for failure messages
+    pub fn log_tail(&self) -> String {
+        let log = fs::read_to_string(self.state_dir.join("wrangler.log")).unwrap_or_default();
+        let lines: Vec<&str> = log.lines().collect();
+        lines[lines.len().saturating_sub(40)..].join("\n")
+    }
+}
+
+impl Drop for Worker {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+        if !thread::panicking() {
+            let _ = fs::remove_dir_all(&self.state_dir);
+        }
+    }
+}
+
+fn workspace_root() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).parent().expect("integration/ sits in the workspace").to_path_buf()
+}
+
+// ==============================================================================
+// checks
+// ==============================================================================
+
+/// a named check against a suite's workers
+pub type Check<C> = (&'static str, fn(&C));
+
+/// run every check in order, then fail with the names of the ones that panicked
+pub fn run_checks<C>(ctx: &C, checks: &[Check<C>]) {
+    let mut failed = Vec::new();
+    for (name, check) in checks {
+        match panic::catch_unwind(AssertUnwindSafe(|| check(ctx))) {
+            Ok(()) => println!("check {} ... ok", name),
+            Err(_) => {
+                println!("check {} ... FAILED", name);
+                failed.push(*name);
+            }
+        }
+    }
+    assert!(failed.is_empty(), "failed checks: {}", failed.join(", "));
+}
+
+/// an error response in the workers' problem+json shape, with the request id
+/// repeated from the header
+pub fn assert_problem(resp: &Response, status: u16) {
+    assert_eq!(resp.status, status, "body: {}", resp.text());
+    assert_eq!(resp.header("Content-Type"), Some("application/problem+json"));
+    let body = resp.json();
+    assert_eq!(body["status"], status);
+    let request_id = resp.header("X-Request-Id").expect("X-Request-Id header");
+    assert_eq!(body["request_id"], request_id);
+}
+
+/// a made-up client address, so checks don't share rate-limit counters
+pub fn client_ip(n: u8) -> String {
+    format!("203.0.113.{}", n)
+}
@@ -0,0 +1,76 @@
+//! capability-demo under wrangler dev - see integration/src/lib.rs
+
+use integration_tests::{assert_problem, client_ip, run_checks, Request, Response, Worker};
+
+const PORT: u16 = 18789;
+
+#[test]
+#[ignore = "starts wrangler dev - cargo test -p integration-tests -- --ignored"]
+fn capability_demo() {
+    let worker = Worker::start("capability-demo", PORT, &[("RATE_LIMIT", "100")]);
+    run_checks(&worker, &[
+        ("allowed capabilities", allowed),
+        ("blocked capabilities", blocked),
+        ("unknown capability", unknown_capability),
+        ("capability list revalidates", capability_list),
+        ("unknown paths", unknown_path),
+        ("cors", cors),
+        ("health and version", health),
+    ]);
+}
+
+fn capability(worker: &Worker, test: &str) -> Response {
+    let req = Request::get(&format!("/api/capability?test={}", test)).header("CF-Connecting-IP", &client_ip(1));
+    worker.send(req)
+}
+
+fn allowed(worker: &Worker) {
+    // the fetch test goes out to example.com - offline it fails, but still counts as allowed
+    let resp = capability(worker, "fetch");
+    assert_eq!(resp.status, 200, "body: {}", resp.text());
+    assert_eq!(resp.json()["allowed"], true);
+}
+
+fn blocked(worker: &Worker) {
+    for test in ["filesystem", "sockets", "subprocess"] {
+        let resp = capability(worker, test);
+        assert_eq!(resp.status, 200, "{}: {}", test, resp.text());
+        assert_eq!(resp.json()["allowed"], false, "{}", test);
+    }
+}
+
+fn unknown_capability(worker: &Worker) {
+    assert_problem(&capability(worker, "teleport"), 400);
+    assert_problem(&worker.get("/api/capability"), 400);
+}
+
+fn capability_list(worker: &Worker) {
+    let list = worker.get("/api/capabilities");
+    assert_eq!(list.status, 200, "body: {}", list.text());
+    assert_eq!(list.json().as_array().map(Vec::len), Some(5));
+    let etag = list.header("ETag").expect("capabilities carry an etag");
+    assert_eq!(worker.send(Request::get("/api/capabilities").header("If-None-Match", etag)).status, 304);
+}
+
+fn unknown_path(worker: &Worker) {
+    assert_problem(&worker.get("/api/nothing-here"), 404);
+}
+
+fn cors(worker: &Worker) {
+    let preflight = worker.send(
+        Request::options("/api/capability")
+            .header("Origin", "https://dash.example")
+            .header("Access-Control-Request-Method", "GET"),
+    );
+    assert!(preflight.status < 300, "status {}", preflight.status);
+    assert_eq!(preflight.header("Access-Control-Allow-Origin"), Some("*"));
+    
+    let error = worker.send(Request::get("/api/capability?test=teleport").header("Origin", "https://dash.example"));
+    assert_eq!(error.header("Access-Control-Allow-Origin"), Some("*"));
+}
+
+fn health(worker: &Worker) {
+    assert_eq!(worker.get("/health").text(), "ok");
+    assert_eq!(worker.get("/version").json()["worker"], "capability-demo");
+    assert_eq!(worker.get("/health/deps").json()["status"], "ok");
+}
@@ -0,0 +1,108 @@
+//! gateway under wrangler dev, with the workers it proxies to - see
+//! integration/src/lib.rs
+
+use integration_tests::{assert_problem, client_ip, run_checks, Request, Response, Worker};
+
+const PORT: u16 = 18786;
+/// gateway-wide limit, small enough for the last check to reach
+const RATE_LIMIT: u32 = 12;
+
+/// the gateway plus the workers behind its service bindings - they register
+/// with wrangler's dev registry and must outlive the gateway, so they're
+/// declared (and dropped) after it
+struct Stack {
+    gateway: Worker,
+    _upstreams: Vec<Worker>,
+}
+
+#[test]
+#[ignore = "starts wrangler dev - cargo test -p integration-tests -- --ignored"]
+fn gateway() {
+    let upstreams = vec![
+        Worker::start("url-shortener", 18787, &[]),
+        Worker::start("rate-limiter", 18788, &[]),
+        Worker::start("capability-demo", 18789, &[]),
+    ];
+    let limit = RATE_LIMIT.to_string();
+    let gateway = Worker::start("gateway", PORT, &[("RATE_LIMIT", &limit), ("RATE_WINDOW_SECONDS", "600")]);
+    let stack = Stack { gateway, _upstreams: upstreams };
+    run_checks(&stack, &[
+        ("proxies by prefix", proxies),
+        ("shorten through the gateway", shorten),
+        ("preflights stay at the gateway", preflight),
+        ("unknown prefixes", unknown_prefix),
+        ("aggregated health", health_all),
+        // last - it spends the client's whole window
+        ("gateway-wide rate limit", rate_limit),
+    ]);
+}
+
+fn via(stack: &Stack, req: Request) -> Response {
+    stack.gateway.send(req.header("CF-Connecting-IP", &client_ip(1)))
+}
+
+fn proxies(stack: &Stack) {
+    let status = via(stack, Request::get("/limit/api/status").header("X-Request-Id", "it-gateway-1"));
+    assert_eq!(status.status, 200, "body: {}", status.text());
+    assert!(status.json()["limit"].is_u64());
+    // the id is forwarded, so the worker behind answers with the same one
+    assert_eq!(status.header("X-Request-Id"), Some("it-gateway-1"));
+    
+    let capabilities = via(stack, Request::get("/capability"));
+    assert_eq!(capabilities.status, 200, "body: {}", capabilities.text());
+    assert!(capabilities.json().is_array());
+}
+
+fn shorten(stack: &Stack) {
+    let resp = via(stack, Request::post("/shorten", r#"{"url": "https://example.com/via-gateway"}"#));
+    assert_eq!(resp.status, 200, "body: {}", resp.text());
+    let body = resp.json();
+    let code = body["code"].as_str().expect("code");
+    // links handed out through the gateway live under its prefix
+    assert!(body["short_url"].as_str().unwrap().ends_with(&format!("/shorten/{}", code)), "{}", body);
+    
+    let redirect = via(stack, Request::get(&format!("/shorten/{}", code)));
+    assert_eq!(redirect.header("Location"), Some("https://example.com/via-gateway"));
+}
+
+fn preflight(stack: &Stack) {
+    let resp = via(
+        stack,
+        Request::options("/shorten")
+            .header("Origin", "https://dash.example")
+            .header("Access-Control-Request-Method", "POST"),
+    );
+    assert!(resp.status < 300, "status {}", resp.status);
+    assert!(resp.header("Access-Control-Allow-Methods").unwrap_or_default().contains("POST"));
+    assert!(resp.header("Access-Control-Allow-Origin").is_some());
+}
+
+fn unknown_prefix(stack: &Stack) {
+    assert_problem(&via(stack, Request::get("/vote/results")), 404);
+    assert_problem(&via(stack, Request::get("/shortened")), 404);
+}
+
+fn health_all(stack: &Stack) {
+    let resp = via(stack, Request::get("/health/all"));
+    assert_eq!(resp.status, 200, "body: {}", resp.text());
+    let report = resp.json();
+    let checks = report["checks"].as_array().expect("checks");
+    let worker_up = |name: &str| {
+        checks.iter().find(|c| c["name"] == name && c["kind"] == "worker").map(|c| c["up"] == true)
+    };
+    for running in ["gateway", "url-shortener", "rate-limiter", "capability-demo"] {
+        assert_eq!(worker_up(running), Some(true), "{} in {}", running, report);
+    }
+    // capture-archive, event-hub and log-collector aren't started here
+    assert_eq!(worker_up("event-hub"), Some(false), "{}", report);
+    assert_eq!(report["status"], "degraded");
+}
+
+fn rate_limit(stack: &Stack) {
+    let blocked = (0..=RATE_LIMIT)
+        .map(|_| stack.gateway.send(Request::get("/limit/api/status").header("CF-Connecting-IP", &client_ip(9))))
+        .find(|r| r.status != 200)
+        .expect("no 429 within the gateway limit");
+    assert_problem(&blocked, 429);
+    assert!(blocked.header("Retry-After").is_some());
+}
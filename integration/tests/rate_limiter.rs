@@ -0,0 +1,100 @@
+//! rate-limiter under wrangler dev - see integration/src/lib.rs
+
+use integration_tests::{assert_problem, run_checks, Request, Response, Worker};
+
+const PORT: u16 = 18788;
+/// small enough that a check spends a whole window in a few requests
+const RATE_LIMIT: u32 = 3;
+
+#[test]
+#[ignore = "starts wrangler dev - cargo test -p integration-tests -- --ignored"]
+fn rate_limiter() {
+    let limit = RATE_LIMIT.to_string();
+    let worker = Worker::start("rate-limiter", PORT, &[("RATE_LIMIT", &limit), ("RATE_WINDOW_SECONDS", "600")]);
+    run_checks(&worker, &[
+        ("allowed requests count down", counts_down),
+        ("429 past the limit", blocks_past_limit),
+        ("status doesn't consume", status_is_free),
+        ("admin reset needs credentials", admin_reset_unauthenticated),
+        ("cors", cors),
+        ("health and version", health),
+    ]);
+}
+
+/// the limiter keys clients by X-API-Key first, so every check picks its own
+fn protected(worker: &Worker, key: &str) -> Response {
+    worker.send(Request::get("/api/protected").header("X-API-Key", key))
+}
+
+fn counts_down(worker: &Worker) {
+    for remaining in (0..RATE_LIMIT).rev() {
+        let resp = protected(worker, "it-countdown");
+        assert_eq!(resp.status, 200, "body: {}", resp.text());
+        assert_eq!(resp.header("X-RateLimit-Limit"), Some(RATE_LIMIT.to_string().as_str()));
+        assert_eq!(resp.header("X-RateLimit-Remaining"), Some(remaining.to_string().as_str()));
+        assert!(resp.json()["message"].is_string());
+    }
+}
+
+fn blocks_past_limit(worker: &Worker) {
+    for _ in 0..RATE_LIMIT {
+        assert_eq!(protected(worker, "it-blocked").status, 200);
+    }
+    let blocked = protected(worker, "it-blocked");
+    assert_problem(&blocked, 429);
+    assert_eq!(blocked.header("X-RateLimit-Remaining"), Some("0"));
+    let retry: u64 = blocked.header("Retry-After").expect("Retry-After").parse().expect("Retry-After seconds");
+    assert!(retry > 0 && retry <= 600, "Retry-After {}", retry);
+    assert_eq!(blocked.json()["limit"], RATE_LIMIT);
+    
+    // other clients are untouched
+    assert_eq!(protected(worker, "it-bystander").status, 200);
+}
+
+fn status_is_free(worker: &Worker) {
+    let status = |etag: Option<&str>| {
+        let req = Request::get("/api/status").header("X-API-Key", "it-status");
+        worker.send(match etag {
+            Some(etag) => req.header("If-None-Match", etag),
+            None => req,
+        })
+    };
+    let first = status(None);
+    assert_eq!(first.status, 200, "body: {}", first.text());
+    assert_eq!(first.json()["requests_made"], 0);
+    assert_eq!(status(None).json()["requests_made"], 0);
+    
+    // reset_in_seconds ticks, so a 304 needs the same second - retry a few times
+    let revalidated = (0..5).any(|_| {
+        let fresh = status(None);
+        let etag = fresh.header("ETag").expect("status carries an etag").to_string();
+        status(Some(&etag)).status == 304
+    });
+    assert!(revalidated, "no 304 for a matching If-None-Match");
+}
+
+fn admin_reset_unauthenticated(worker: &Worker) {
+    assert_problem(&worker.send(Request::post("/admin/reset?client=key:it-blocked", "")), 401);
+    let bad = Request::post("/admin/reset?client=key:it-blocked", "").header("Authorization", "Bearer not-a-token");
+    assert_problem(&worker.send(bad), 401);
+}
+
+fn cors(worker: &Worker) {
+    let preflight = worker.send(
+        Request::options("/api/protected")
+            .header("Origin", "https://dash.example")
+            .header("Access-Control-Request-Method", "GET"),
+    );
+    assert!(preflight.status < 300, "status {}", preflight.status);
+    assert_eq!(preflight.header("Access-Control-Allow-Origin"), Some("*"));
+    // the dashboard reads the counters, so they must be exposed
+    let exposed = preflight.header("Access-Control-Expose-Headers").unwrap_or_default();
+    assert!(exposed.contains("X-RateLimit-Remaining"), "exposed: {}", exposed);
+}
+
+fn health(worker: &Worker) {
+    assert_eq!(worker.get("/health").text(), "ok");
+    assert_eq!(worker.get("/version").json()["worker"], "rate-limiter");
+    let deps = worker.get("/health/deps").json();
+    assert!(deps["checks"].as_array().expect("checks").iter().any(|c| c["name"] == "RATES" && c["up"] == true), "{}", deps);
+}
@@ -0,0 +1,120 @@
+//! url-shortener under wrangler dev - see integration/src/lib.rs
+
+use integration_tests::{assert_problem, client_ip, run_checks, Request, Worker};
+
+const PORT: u16 = 18787;
+/// small enough for the rate limit check to reach quickly
+const RATE_LIMIT: u32 = 10;
+
+#[test]
+#[ignore = "starts wrangler dev - cargo test -p integration-tests -- --ignored"]
+fn url_shortener() {
+    let limit = RATE_LIMIT.to_string();
+    let worker = Worker::start("url-shortener", PORT, &[("RATE_LIMIT", &limit), ("RATE_WINDOW_SECONDS", "600")]);
+    run_checks(&worker, &[
+        ("shorten, redirect and stats", shorten_round_trip),
+        ("custom alias conflicts", alias_conflict),
+        ("malformed bodies", malformed_bodies),
+        ("unknown codes", unknown_code),
+        ("cors", cors),
+        ("request ids", request_ids),
+        ("health, version and dependencies", health),
+        // last - it spends a client's whole window
+        ("rate limit", rate_limit),
+    ]);
+}
+
+fn shorten(worker: &Worker, client: u8, body: &str) -> integration_tests::Response {
+    worker.send(Request::post("/shorten", body).header("CF-Connecting-IP", &client_ip(client)))
+}
+
+fn shorten_round_trip(worker: &Worker) {
+    let resp = shorten(worker, 1, r#"{"url": "https://example.com/long/path"}"#);
+    assert_eq!(resp.status, 200, "body: {}", resp.text());
+    let body = resp.json();
+    let code = body["code"].as_str().expect("code");
+    assert_eq!(body["original_url"], "https://example.com/long/path");
+    assert!(body["short_url"].as_str().unwrap().ends_with(&format!("/{}", code)));
+    
+    let redirect = worker.get(&format!("/{}", code));
+    assert!((300..400).contains(&redirect.status), "status {}", redirect.status);
+    assert_eq!(redirect.header("Location"), Some("https://example.com/long/path"));
+    
+    let stats = worker.get(&format!("/stats/{}", code));
+    assert_eq!(stats.status, 200, "body: {}", stats.text());
+    assert_eq!(stats.json()["code"], code);
+    // the click went to the queue, and no consumer runs here - nothing changed
+    let etag = stats.header("ETag").expect("stats carry an etag");
+    let again = worker.send(Request::get(&format!("/stats/{}", code)).header("If-None-Match", etag));
+    assert_eq!(again.status, 304);
+}
+
+fn alias_conflict(worker: &Worker) {
+    let body = r#"{"url": "https://example.com", "alias": "it-alias"}"#;
+    let first = shorten(worker, 2, body);
+    assert_eq!(first.status, 200, "body: {}", first.text());
+    assert_eq!(first.json()["code"], "it-alias");
+    assert_problem(&shorten(worker, 2, body), 409);
+}
+
+fn malformed_bodies(worker: &Worker) {
+    assert_problem(&shorten(worker, 3, "{not json"), 400);
+    assert_problem(&shorten(worker, 3, r#"{"url": "ftp://example.com"}"#), 400);
+    assert_problem(&shorten(worker, 3, r#"{"url": "https://example.com", "alias": "no spaces"}"#), 400);
+    assert_problem(&shorten(worker, 3, r#"{"url": "https://example.com", "expires_in": 1}"#), 400);
+    // over the 4 KiB cap, refused before parsing
+    let huge = format!(r#"{{"url": "https://example.com/{}"}}"#, "a".repeat(5000));
+    assert_problem(&shorten(worker, 3, &huge), 413);
+}
+
+fn unknown_code(worker: &Worker) {
+    assert_problem(&worker.get("/doesnotexist"), 404);
+    assert_problem(&worker.get("/stats/doesnotexist"), 404);
+}
+
+fn cors(worker: &Worker) {
+    let preflight = worker.send(
+        Request::options("/shorten")
+            .header("Origin", "https://dash.example")
+            .header("Access-Control-Request-Method", "POST"),
+    );
+    assert!(preflight.status < 300, "status {}", preflight.status);
+    assert_eq!(preflight.header("Access-Control-Allow-Origin"), Some("*"));
+    assert!(preflight.header("Access-Control-Allow-Methods").unwrap_or_default().contains("POST"));
+    
+    // errors carry cors too, or the dashboard couldn't read them
+    let error = worker.send(Request::get("/doesnotexist").header("Origin", "https://dash.example"));
+    assert_eq!(error.header("Access-Control-Allow-Origin"), Some("*"));
+}
+
+fn request_ids(worker: &Worker) {
+    let adopted = worker.send(Request::get("/doesnotexist").header("X-Request-Id", "it-trace-1"));
+    assert_eq!(adopted.header("X-Request-Id"), Some("it-trace-1"));
+    assert_eq!(adopted.json()["request_id"], "it-trace-1");
+    
+    // unsafe ids are replaced, not echoed
+    let minted = worker.send(Request::get("/doesnotexist").header("X-Request-Id", "<script>"));
+    let id = minted.header("X-Request-Id").expect("X-Request-Id");
+    assert_ne!(id, "<script>");
+}
+
+fn health(worker: &Worker) {
+    assert_eq!(worker.get("/health").text(), "ok");
+    let version = worker.get("/version").json();
+    assert_eq!(version["worker"], "url-shortener");
+    assert!(version["git_sha"].is_string());
+    
+    let deps = worker.get("/health/deps").json();
+    let checks = deps["checks"].as_array().expect("checks");
+    assert!(checks.iter().any(|c| c["name"] == "URLS" && c["kind"] == "kv" && c["up"] == true), "{}", deps);
+}
+
+fn rate_limit(worker: &Worker) {
+    // local dev may not keep our CF-Connecting-IP, in which case the other
+    // checks already spent part of the window - either way it runs out
+    let blocked = (0..=RATE_LIMIT)
+        .map(|_| shorten(worker, 9, r#"{"url": "https://example.com"}"#))
+        .find(|r| r.status != 200)
+        .expect("no 429 within the limit");
+    assert_problem(&blocked, 429);
+}
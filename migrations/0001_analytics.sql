@@ -0,0 +1,36 @@
+-- ==============================================================================
+-- 0001_analytics.sql - analytics events and click totals
+-- ==============================================================================
+--
+-- applied with: wrangler d1 migrations apply edge-demo --remote
+-- (from workers/analytics-consumer, whose wrangler.toml points here)
+--
+-- queues deliver at least once, so an event is unique per (request_id, kind)
+-- and the consumer's INSERT OR IGNORE drops redeliveries. click totals are
+-- kept by a trigger, so they only move when an event is actually stored.
+--
+-- ==============================================================================
+
+CREATE TABLE IF NOT EXISTS events (
+    request_id TEXT NOT NULL,
+    kind TEXT NOT NULL,          -- click | denial | fault
+    worker TEXT NOT NULL,
+    subject TEXT NOT NULL,       -- short code, client id or route
+    status INTEGER NOT NULL,
+    at INTEGER NOT NULL,         -- unix ms
+    PRIMARY KEY (request_id, kind)
+);
+
+CREATE INDEX IF NOT EXISTS events_by_kind_time ON events (kind, at);
+
+CREATE TABLE IF NOT EXISTS link_clicks (
+    code TEXT PRIMARY KEY,
+    clicks INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE TRIGGER IF NOT EXISTS count_click AFTER INSERT ON events
+WHEN NEW.kind = 'click'
+BEGIN
+    INSERT INTO link_clicks (code, clicks) VALUES (NEW.subject, 1)
+    ON CONFLICT (code) DO UPDATE SET clicks = clicks + 1;
+END;
@@ -0,0 +1,20 @@
+-- ==============================================================================
+-- 0002_links.sql - url shortener links (d1 link store)
+-- ==============================================================================
+--
+-- only used when the url-shortener has a LINKS_DB binding (shared::storage).
+-- expires_at and created_at are unix ms like the kv entries; expired rows
+-- are removed by the shortener's daily cron. clicks holds counts from before
+-- the analytics pipeline - newer ones are in link_clicks (see 0001).
+--
+-- ==============================================================================
+
+CREATE TABLE IF NOT EXISTS links (
+    code TEXT PRIMARY KEY,
+    original_url TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    clicks INTEGER NOT NULL DEFAULT 0,
+    expires_at INTEGER              -- null = never
+);
+
+CREATE INDEX IF NOT EXISTS links_by_expiry ON links (expires_at) WHERE expires_at IS NOT NULL;
@@ -0,0 +1,49 @@
+//! ==============================================================================
+//! build.rs - stamp build info into the shared crate
+//! ==============================================================================
+//!
+//! purpose:
+//!     records the git commit and build time as compile-time env vars, read
+//!     back by src/build_info.rs for every worker's GET /version.
+//!
+//! sources (first one set wins):
+//!     EDGE_GIT_SHA        GITHUB_SHA (ci), `git rev-parse HEAD`, "unknown"
+//!     EDGE_BUILT_AT       SOURCE_DATE_EPOCH (reproducible builds), now
+//!                         - unix seconds
+//!
+//! ==============================================================================
+
+use std::path::Path;
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+fn main() {
+    let sha = std::env::var("GITHUB_SHA")
+        .ok()
+        .or_else(|| git(&["rev-parse", "HEAD"]))
+        .unwrap_or_else(|| "unknown".to_string());
+    let built_at = std::env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+    
+    println!("cargo:rustc-env=EDGE_GIT_SHA={}", sha);
+    println!("cargo:rustc-env=EDGE_BUILT_AT={}", built_at);
+    
+    // rebuild when the checked-out commit moves, not on every source edit
+    println!("cargo:rerun-if-env-changed=GITHUB_SHA");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        let head = Path::new(&git_dir).join("HEAD");
+        println!("cargo:rerun-if-changed={}", head.display());
+        if let Some(branch) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            println!("cargo:rerun-if-changed={}", Path::new(&git_dir).join(branch).display());
+        }
+    }
+}
+
+fn git(args: &[&str]) -> Option<String> {
+    let out = Command::new("git").args(args).output().ok()?;
+    let text = String::from_utf8(out.stdout).ok()?.trim().to_string();
+    (out.status.success() && !text.is_empty()).then_some(text)
+}
@@ -0,0 +1,241 @@
+//! ==============================================================================
+//! abuse.rs - noticing clients that keep getting turned away
+//! ==============================================================================
+//!
+//! purpose:
+//!     one 429 is a client that misjudged its rate; a hundred in ten minutes
+//!     is a scraper or an attack. the rate limiter counts each client's
+//!     refusals, and when they reach ABUSE_THRESHOLD within
+//!     ABUSE_WINDOW_SECONDS it raises an "abuse_threshold" notification
+//!     (shared::notify) with the client's details.
+//!
+//! relationships:
+//!     - used by: rate-limiter (/api/protected and proxied paths)
+//!     - uses: notify (the notification), analytics::client_label (api keys
+//!       only by prefix), the kv namespace the worker passes in (RATES)
+//!
+//! configuration (rate-limiter):
+//!     var ABUSE_THRESHOLD        429s per window that raise a notification -
+//!                                0 turns counting off
+//!     var ABUSE_WINDOW_SECONDS   that window, DEFAULT_WINDOW_SECONDS when unset
+//!
+//! counting:
+//!     a fixed window per client under "abuse:<client id>", expiring with the
+//!     window. like the kv rate counter it's read and written without a lock,
+//!     so racing refusals can undercount. the notification goes out on the
+//!     refusal that brings the count to the threshold - once per client and
+//!     window, so an attack that keeps going is reported again every window.
+//!
+//! ==============================================================================
+
+use serde::{Deserialize, Serialize};
+
+use crate::decisions::Outcome;
+use crate::notify::Notification;
+
+/// env var with the refusals that make a client abusive
+pub const THRESHOLD_VAR: &str = "ABUSE_THRESHOLD";
+/// env var with the window they're counted over
+pub const WINDOW_VAR: &str = "ABUSE_WINDOW_SECONDS";
+/// ten minutes
+pub const DEFAULT_WINDOW_SECONDS: u64 = 600;
+/// kv prefix of the refusal counters in RATES
+pub const ABUSE_KEY_PREFIX: &str = "abuse:";
+/// Notification::event of a crossed threshold
+pub const EVENT: &str = "abuse_threshold";
+
+/// kv refuses shorter ttls
+const MIN_TTL_SECS: u64 = 60;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// so many refusals within so many seconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Threshold {
+    pub refusals: u32,
+    pub window_seconds: u64,
+}
+
+impl Threshold {
+    /// from ABUSE_THRESHOLD and ABUSE_WINDOW_SECONDS - none (off) while the
+    /// threshold is 0 or not a number; a bad window falls back to the default
+    pub fn parse(refusals: &str, window_seconds: &str) -> Option<Self> {
+        let refusals = refusals.trim().parse::<u32>().ok().filter(|r| *r > 0)?;
+        let window_seconds = window_seconds.trim().parse::<u64>().ok().filter(|w| *w > 0).unwrap_or(DEFAULT_WINDOW_SECONDS);
+        Some(Self { refusals, window_seconds })
+    }
+}
+
+/// one client's refusals in the current window, as stored in kv
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Strikes {
+    pub count: u32,
+    /// unix seconds
+    pub window_start: u64,
+}
+
+impl Strikes {
+    /// `stored` plus one refusal at `now` - a fresh window once its own is over
+    pub fn add(stored: Option<Self>, now: u64, window_seconds: u64) -> Self {
+        match stored {
+            Some(s) if now < s.window_start + window_seconds => Self { count: s.count.saturating_add(1), ..s },
+            _ => Self { count: 1, window_start: now },
+        }
+    }
+    
+    /// whether this refusal is the one that reaches `threshold`
+    pub fn crossed(&self, threshold: Threshold) -> bool {
+        self.count == threshold.refusals
+    }
+    
+    /// kv ttl at `now` - the rest of the window
+    pub fn ttl(&self, now: u64, window_seconds: u64) -> u64 {
+        (self.window_start + window_seconds).saturating_sub(now).max(MIN_TTL_SECS)
+    }
+}
+
+/// kv key of `client_id`'s refusals
+pub fn kv_key(client_id: &str) -> String {
+    format!("{}{}", ABUSE_KEY_PREFIX, client_id)
+}
+
+/// what a security team gets about an abusive client - the notification's
+/// "details"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Incident {
+    /// the ip, or an api key's prefix
+    pub client: String,
+    pub refusals: u32,
+    pub window_seconds: u64,
+    /// the refusal that crossed the threshold
+    pub outcome: Outcome,
+    pub route: String,
+    pub colo: String,
+}
+
+impl Incident {
+    pub fn new(client_id: &str, threshold: Threshold, outcome: Outcome, route: &str, colo: &str) -> Self {
+        Self {
+            client: crate::analytics::client_label(client_id),
+            refusals: threshold.refusals,
+            window_seconds: threshold.window_seconds,
+            outcome,
+            route: route.to_string(),
+            colo: colo.to_string(),
+        }
+    }
+    
+    /// "ip:192.0.2.1 got 100 refusals in 10 minutes (last: rate_limited on /api/protected)"
+    pub fn text(&self) -> String {
+        let window = match self.window_seconds {
+            s if s % 60 == 0 => format!("{} minutes", s / 60),
+            s => format!("{} seconds", s),
+        };
+        format!(
+            "{} got {} refusals in {} (last: {} on {})",
+            self.client,
+            self.refusals,
+            window,
+            self.outcome.as_str(),
+            self.route
+        )
+    }
+    
+    pub fn notification(&self, worker: &str, request_id: &str, at: u64) -> Notification {
+        Notification {
+            event: EVENT.to_string(),
+            worker: worker.to_string(),
+            text: self.text(),
+            details: serde_json::to_value(self).unwrap_or_default(),
+            request_id: request_id.to_string(),
+            at,
+        }
+    }
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// ABUSE_THRESHOLD and ABUSE_WINDOW_SECONDS - none when counting is off
+#[cfg(feature = "worker")]
+pub fn configured(env: &worker::Env) -> Option<Threshold> {
+    let var = |name: &str| env.var(name).map(|v| v.to_string()).unwrap_or_default();
+    Threshold::parse(&var(THRESHOLD_VAR), &var(WINDOW_VAR))
+}
+
+/// count a refusal for `client_id` - true when it's the one that reaches
+/// the threshold
+#[cfg(feature = "worker")]
+pub async fn strike(kv: &worker::kv::KvStore, client_id: &str, threshold: Threshold, now: u64) -> worker::Result<bool> {
+    let key = kv_key(client_id);
+    crate::metrics::record_kv_op("get");
+    let stored = kv.get(&key).json::<Strikes>().await?;
+    let strikes = Strikes::add(stored, now, threshold.window_seconds);
+    crate::metrics::record_kv_op("put");
+    kv.put(&key, serde_json::to_string(&strikes)?)?
+        .expiration_ttl(strikes.ttl(now, threshold.window_seconds))
+        .execute()
+        .await?;
+    Ok(strikes.crossed(threshold))
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const TEN_MINUTES: Threshold = Threshold { refusals: 3, window_seconds: 600 };
+    
+    #[test]
+    fn test_parse_threshold() {
+        assert_eq!(Threshold::parse("100", "600"), Some(Threshold { refusals: 100, window_seconds: 600 }));
+        assert_eq!(Threshold::parse("100", ""), Some(Threshold { refusals: 100, window_seconds: DEFAULT_WINDOW_SECONDS }));
+        assert_eq!(Threshold::parse("100", "0"), Some(Threshold { refusals: 100, window_seconds: DEFAULT_WINDOW_SECONDS }));
+        assert_eq!(Threshold::parse("0", "600"), None);
+        assert_eq!(Threshold::parse("", "600"), None);
+        assert_eq!(Threshold::parse("lots", "600"), None);
+    }
+    
+    #[test]
+    fn test_crosses_once_per_window() {
+        let mut strikes = None;
+        let crossed: Vec<bool> = (0..5)
+            .map(|i| {
+                let s = Strikes::add(strikes, 1000 + i, TEN_MINUTES.window_seconds);
+                strikes = Some(s);
+                s.crossed(TEN_MINUTES)
+            })
+            .collect();
+        assert_eq!(crossed, [false, false, true, false, false]);
+        assert_eq!(strikes.unwrap().ttl(1004, 600), 596);
+        
+        // the next window starts over - and can cross again
+        let fresh = Strikes::add(strikes, 1600, 600);
+        assert_eq!(fresh, Strikes { count: 1, window_start: 1600 });
+        assert_eq!(fresh.ttl(2190, 600), 60);
+        assert_eq!(kv_key("ip:192.0.2.1"), "abuse:ip:192.0.2.1");
+    }
+    
+    #[test]
+    fn test_incident_notification() {
+        let incident = Incident::new("key:rl_9f2c0000", TEN_MINUTES, Outcome::RateLimited, "/api/protected", "LAX");
+        // api keys never leave the worker whole
+        assert_eq!(incident.client, "key:rl_9f2...");
+        assert_eq!(incident.text(), "key:rl_9f2... got 3 refusals in 10 minutes (last: rate_limited on /api/protected)");
+        
+        let n = incident.notification("rate-limiter", "18bcfe56800-deadbeef", 42);
+        assert_eq!(n.event, EVENT);
+        assert_eq!(n.details["outcome"], "rate_limited");
+        assert_eq!(n.details["colo"], "LAX");
+        assert_eq!(n.details["window_seconds"], 600);
+        
+        let odd = Incident::new("ip:192.0.2.1", Threshold { refusals: 5, window_seconds: 90 }, Outcome::QuotaExceeded, "/", "AMS");
+        assert!(odd.text().starts_with("ip:192.0.2.1 got 5 refusals in 90 seconds"));
+    }
+}
@@ -0,0 +1,338 @@
+//! ==============================================================================
+//! alerting.rs - templated alert emails through mailchannels
+//! ==============================================================================
+//!
+//! purpose:
+//!     critical events email the operators instead of waiting for someone to
+//!     look at the dashboard. each alert is rendered from a plain-text
+//!     template and posted to the mailchannels send api from the worker.
+//!
+//! relationships:
+//!     - used by: gateway (cron - the aggregated health check, shared::health)
+//!     - uses: workers kv namespace "AUTH" for the throttle keys
+//!     - calls: mailchannels (MAILCHANNELS_URL)
+//!
+//! configuration (per worker):
+//!     var ALERT_EMAIL_TO            comma-separated recipients - empty turns
+//!                                   alerting off, so `wrangler dev` sends nothing
+//!     var ALERT_EMAIL_FROM          sender; its domain needs the mailchannels
+//!                                   domain lockdown TXT record for this account
+//!     secret MAILCHANNELS_API_KEY   sent as X-Api-Key when set
+//!
+//! throttling:
+//!     one email per event type per window (AlertKind::throttle_secs). a sent
+//!     alert writes "alert:throttle:<kind>" with that ttl, and while the key
+//!     exists the same kind is only logged. a failed send writes nothing, so
+//!     the next run tries again.
+//!
+//! events:
+//!     health-down / health-degraded - every check or only some failing in
+//!     the gateway's /health/all
+//!
+//! ==============================================================================
+
+use serde::Serialize;
+
+use crate::health::{HealthReport, HealthStatus};
+
+pub const MAILCHANNELS_URL: &str = "https://api.mailchannels.net/tx/v1/send";
+/// kv key prefix of the per-kind throttle markers
+pub const THROTTLE_KEY_PREFIX: &str = "alert:throttle:";
+/// failing checks listed in one email before the rest are summarized
+const MAX_DETAIL_LINES: usize = 20;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AlertKind {
+    /// every check in the aggregated health report failed
+    HealthDown,
+    /// some checks failed
+    HealthDegraded,
+}
+
+impl AlertKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::HealthDown => "health-down",
+            Self::HealthDegraded => "health-degraded",
+        }
+    }
+    
+    /// shortest gap between two emails of this kind - also the kv ttl, so
+    /// never under kv's 60 second minimum
+    pub fn throttle_secs(&self) -> u64 {
+        match self {
+            Self::HealthDown => 15 * 60,
+            Self::HealthDegraded => 60 * 60,
+        }
+    }
+    
+    pub fn throttle_key(&self) -> String {
+        format!("{}{}", THROTTLE_KEY_PREFIX, self.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Alert {
+    pub kind: AlertKind,
+    /// one line - becomes the email subject
+    pub summary: String,
+    /// one line each in the body
+    pub details: Vec<String>,
+    /// unix ms
+    pub at: u64,
+}
+
+impl Alert {
+    /// the alert for a health report, none while everything is up
+    pub fn from_health(report: &HealthReport) -> Option<Self> {
+        let kind = match report.status {
+            HealthStatus::Ok => return None,
+            HealthStatus::Down => AlertKind::HealthDown,
+            HealthStatus::Degraded => AlertKind::HealthDegraded,
+        };
+        let failing: Vec<_> = report.checks.iter().filter(|c| !c.up).collect();
+        let mut details: Vec<String> = failing
+            .iter()
+            .take(MAX_DETAIL_LINES)
+            .map(|c| format!("{} ({}) - {}", c.name, c.kind.as_str(), c.error.as_deref().unwrap_or("down")))
+            .collect();
+        if failing.len() > MAX_DETAIL_LINES {
+            details.push(format!("... and {} more", failing.len() - MAX_DETAIL_LINES));
+        }
+        Some(Self {
+            kind,
+            summary: format!("{} of {} health checks failing", failing.len(), report.checks.len()),
+            details,
+            at: report.checked_at,
+        })
+    }
+}
+
+/// who gets the emails, from the worker's vars
+#[derive(Debug, Clone, PartialEq)]
+pub struct AlertConfig {
+    pub to: Vec<String>,
+    pub from: String,
+}
+
+impl AlertConfig {
+    /// none (alerting off) unless there's a sender and at least one recipient
+    pub fn parse(to: &str, from: &str) -> Option<Self> {
+        let looks_like_address = |s: &str| s.len() > 2 && s.contains('@') && !s.contains(char::is_whitespace);
+        let to: Vec<String> = to.split(',').map(str::trim).filter(|s| looks_like_address(s)).map(String::from).collect();
+        let from = from.trim();
+        (!to.is_empty() && looks_like_address(from)).then(|| Self { to, from: from.to_string() })
+    }
+}
+
+/// what happened to an alert
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delivery {
+    Sent,
+    /// the same kind went out within its throttle window
+    Throttled,
+    /// ALERT_EMAIL_TO / ALERT_EMAIL_FROM unset
+    NotConfigured,
+}
+
+// ==============================================================================
+// templates
+// ==============================================================================
+
+/// (subject, plain-text body) - `environment` is the worker's ENVIRONMENT var
+pub fn render(alert: &Alert, environment: &str) -> (String, String) {
+    let subject = format!("[edge-protocol-demo {}] {}: {}", environment, alert.kind.as_str(), alert.summary);
+    let mut body = format!(
+        "{}\n\nenvironment: {}\nevent: {}\nat: {} (unix ms)\n",
+        alert.summary,
+        environment,
+        alert.kind.as_str(),
+        alert.at
+    );
+    if !alert.details.is_empty() {
+        body.push('\n');
+        for line in &alert.details {
+            body.push_str(&format!("  - {}\n", line));
+        }
+    }
+    body.push_str(&format!(
+        "\nfurther {} alerts are held for {} minutes.\n",
+        alert.kind.as_str(),
+        alert.kind.throttle_secs() / 60
+    ));
+    (subject, body)
+}
+
+#[derive(Debug, Serialize)]
+struct Address<'a> {
+    email: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    name: Option<&'a str>,
+}
+
+#[derive(Debug, Serialize)]
+struct Personalization<'a> {
+    to: Vec<Address<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Content<'a> {
+    #[serde(rename = "type")]
+    kind: &'a str,
+    value: &'a str,
+}
+
+/// body for POST MAILCHANNELS_URL
+#[derive(Debug, Serialize)]
+pub struct SendRequest<'a> {
+    personalizations: Vec<Personalization<'a>>,
+    from: Address<'a>,
+    subject: &'a str,
+    content: Vec<Content<'a>>,
+}
+
+impl<'a> SendRequest<'a> {
+    pub fn new(config: &'a AlertConfig, subject: &'a str, body: &'a str) -> Self {
+        Self {
+            personalizations: vec![Personalization {
+                to: config.to.iter().map(|email| Address { email, name: None }).collect(),
+            }],
+            from: Address { email: &config.from, name: Some("edge-protocol-demo alerts") },
+            subject,
+            content: vec![Content { kind: "text/plain", value: body }],
+        }
+    }
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// email `alert` unless alerting is off or its kind is throttled
+#[cfg(feature = "worker")]
+pub async fn send(env: &worker::Env, alert: &Alert) -> worker::Result<Delivery> {
+    use worker::*;
+    
+    let var = |name: &str| env.var(name).map(|v| v.to_string()).unwrap_or_default();
+    let Some(config) = AlertConfig::parse(&var("ALERT_EMAIL_TO"), &var("ALERT_EMAIL_FROM")) else {
+        console_log!("alerting off - {}: {}", alert.kind.as_str(), alert.summary);
+        return Ok(Delivery::NotConfigured);
+    };
+    
+    let kv = env.kv(crate::auth_guard::AUTH_KV_BINDING)?;
+    let throttle_key = alert.kind.throttle_key();
+    crate::metrics::record_kv_op("get");
+    if kv.get(&throttle_key).text().await?.is_some() {
+        console_log!("{} throttled: {}", alert.kind.as_str(), alert.summary);
+        return Ok(Delivery::Throttled);
+    }
+    
+    let environment = env.var("ENVIRONMENT").map(|v| v.to_string()).unwrap_or_else(|_| "production".to_string());
+    let (subject, body) = render(alert, &environment);
+    let payload = serde_json::to_string(&SendRequest::new(&config, &subject, &body))?;
+    let headers = Headers::new();
+    headers.set("Content-Type", "application/json")?;
+    if let Ok(key) = env.secret("MAILCHANNELS_API_KEY") {
+        headers.set("X-Api-Key", &key.to_string())?;
+    }
+    let init = RequestInit {
+        method: Method::Post,
+        headers,
+        body: Some(payload.into()),
+        ..RequestInit::default()
+    };
+    let mut resp = Fetch::Request(Request::new_with_init(MAILCHANNELS_URL, &init)?).send().await?;
+    if !(200..300).contains(&resp.status_code()) {
+        let detail = resp.text().await.unwrap_or_default();
+        return Err(Error::from(format!("mailchannels answered {}: {}", resp.status_code(), detail)));
+    }
+    
+    crate::metrics::record_kv_op("put");
+    kv.put(&throttle_key, alert.at.to_string())?
+        .expiration_ttl(alert.kind.throttle_secs())
+        .execute()
+        .await?;
+    console_log!("alert sent to {} recipient(s): {}", config.to.len(), subject);
+    Ok(Delivery::Sent)
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::health::{Check, CheckKind};
+    
+    fn check(name: &str, up: bool) -> Check {
+        let error = (!up).then(|| "timed out".to_string());
+        Check { name: name.to_string(), kind: CheckKind::R2, up, latency_ms: 5, error }
+    }
+    
+    #[test]
+    fn test_alert_from_health() {
+        assert_eq!(Alert::from_health(&HealthReport::new(vec![check("gateway", true)], 1)), None);
+        
+        let degraded = HealthReport::new(vec![check("gateway", true), check("capture-archive/CAPTURES", false)], 1_767_225_600_000);
+        let alert = Alert::from_health(&degraded).unwrap();
+        assert_eq!(alert.kind, AlertKind::HealthDegraded);
+        assert_eq!(alert.summary, "1 of 2 health checks failing");
+        assert_eq!(alert.details, ["capture-archive/CAPTURES (r2) - timed out"]);
+        assert_eq!(alert.at, 1_767_225_600_000);
+        
+        let down = HealthReport::new(vec![check("gateway", false)], 1);
+        assert_eq!(Alert::from_health(&down).unwrap().kind, AlertKind::HealthDown);
+        
+        // long outages don't turn into long emails
+        let many = HealthReport::new((0..30).map(|i| check(&format!("w{}", i), false)).collect(), 1);
+        let details = Alert::from_health(&many).unwrap().details;
+        assert_eq!(details.len(), MAX_DETAIL_LINES + 1);
+        assert_eq!(details.last().unwrap(), "... and 10 more");
+    }
+    
+    #[test]
+    fn test_config_parse() {
+        let config = AlertConfig::parse(" ops@example.com, ,oncall@example.com ", "alerts@example.com").unwrap();
+        assert_eq!(config.to, ["ops@example.com", "oncall@example.com"]);
+        assert_eq!(AlertConfig::parse("", "alerts@example.com"), None);
+        assert_eq!(AlertConfig::parse("ops@example.com", ""), None);
+        assert_eq!(AlertConfig::parse("not an address", "alerts@example.com"), None);
+    }
+    
+    #[test]
+    fn test_throttle_keys_per_kind() {
+        assert_eq!(AlertKind::HealthDown.throttle_key(), "alert:throttle:health-down");
+        assert_ne!(AlertKind::HealthDown.throttle_key(), AlertKind::HealthDegraded.throttle_key());
+        // an outage re-alerts sooner than a partial failure
+        assert!(AlertKind::HealthDown.throttle_secs() < AlertKind::HealthDegraded.throttle_secs());
+        // kv refuses expirations under a minute
+        assert!(AlertKind::HealthDown.throttle_secs() >= 60);
+    }
+    
+    #[test]
+    fn test_render_and_send_request() {
+        let alert = Alert {
+            kind: AlertKind::HealthDegraded,
+            summary: "1 of 2 health checks failing".to_string(),
+            details: vec!["event-hub (worker) - timed out".to_string()],
+            at: 42,
+        };
+        let (subject, body) = render(&alert, "preview");
+        assert_eq!(subject, "[edge-protocol-demo preview] health-degraded: 1 of 2 health checks failing");
+        assert!(body.contains("  - event-hub (worker) - timed out\n"));
+        assert!(body.contains("held for 60 minutes"));
+        
+        let config = AlertConfig::parse("ops@example.com", "alerts@example.com").unwrap();
+        let json = serde_json::to_value(SendRequest::new(&config, &subject, &body)).unwrap();
+        assert_eq!(json["personalizations"][0]["to"][0]["email"], "ops@example.com");
+        assert!(json["personalizations"][0]["to"][0].get("name").is_none());
+        assert_eq!(json["from"]["email"], "alerts@example.com");
+        assert_eq!(json["content"][0]["type"], "text/plain");
+        assert_eq!(json["subject"], subject);
+    }
+}
@@ -0,0 +1,246 @@
+//! ==============================================================================
+//! analytics.rs - analytics events sent through cloudflare queues
+//! ==============================================================================
+//!
+//! purpose:
+//!     clicks, rate-limit denials and 5xx faults are recorded as events and
+//!     sent to a queue after the response is on its way. the
+//!     analytics-consumer worker batches them into d1, so the request path
+//!     no longer writes to kv (the redirect used to rewrite its link entry
+//!     on every click).
+//!
+//! relationships:
+//!     - used by: url-shortener (clicks, denials, faults), rate-limiter
+//!       (denials, faults) - producers on binding ANALYTICS
+//!     - used by: workers/analytics-consumer (the queue consumer)
+//!     - uses: d1 database ANALYTICS_DB (schema in /migrations)
+//!
+//! design:
+//!     like metrics, events go into a per-isolate buffer first - handlers
+//!     only push to a vec - and fetch sends the buffer with ctx.wait_until.
+//!     queues deliver at least once, so the consumer inserts each event
+//!     keyed by (request_id, kind) and ignores repeats; click totals are
+//!     kept by a trigger on that insert.
+//!
+//! ==============================================================================
+
+use std::cell::RefCell;
+
+use serde::{Deserialize, Serialize};
+
+/// queue producer binding in the producers' wrangler.toml
+pub const QUEUE_BINDING: &str = "ANALYTICS";
+/// d1 binding of the analytics database
+pub const DB_BINDING: &str = "ANALYTICS_DB";
+/// events an isolate holds while the queue is unreachable; older ones are dropped
+const MAX_PENDING: usize = 1000;
+
+/// insert one event, ignoring redeliveries
+pub const INSERT_EVENT_SQL: &str =
+    "INSERT OR IGNORE INTO events (request_id, kind, worker, subject, status, at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+/// click total for one short code
+pub const SELECT_CLICKS_SQL: &str = "SELECT clicks FROM link_clicks WHERE code = ?1";
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    /// a short link was followed
+    Click,
+    /// a client was rate limited
+    Denial,
+    /// a request ended in a 5xx
+    Fault,
+}
+
+impl EventKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Click => "click",
+            Self::Denial => "denial",
+            Self::Fault => "fault",
+        }
+    }
+}
+
+/// one analytics event, the queue message body
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AnalyticsEvent {
+    pub kind: EventKind,
+    pub worker: String,
+    /// what it's about: the short code (click), client id (denial) or route (fault)
+    pub subject: String,
+    pub status: u16,
+    pub request_id: String,
+    /// unix ms
+    pub at: u64,
+}
+
+impl AnalyticsEvent {
+    pub fn click(worker: &str, code: &str, request_id: &str, at: u64) -> Self {
+        Self::new(EventKind::Click, worker, code, 301, request_id, at)
+    }
+    
+    /// api keys in `client_id` ("key:<key>") are cut to a short prefix
+    pub fn denial(worker: &str, client_id: &str, request_id: &str, at: u64) -> Self {
+        Self::new(EventKind::Denial, worker, &client_label(client_id), 429, request_id, at)
+    }
+    
+    pub fn fault(worker: &str, route: &str, status: u16, request_id: &str, at: u64) -> Self {
+        Self::new(EventKind::Fault, worker, route, status, request_id, at)
+    }
+    
+    fn new(kind: EventKind, worker: &str, subject: &str, status: u16, request_id: &str, at: u64) -> Self {
+        Self {
+            kind,
+            worker: worker.to_string(),
+            subject: subject.to_string(),
+            status,
+            request_id: request_id.to_string(),
+            at,
+        }
+    }
+}
+
+/// a client id safe to store: ips as they are, api keys only by prefix
+pub fn client_label(client_id: &str) -> String {
+    match client_id.strip_prefix("key:") {
+        Some(key) => format!("key:{}...", key.chars().take(6).collect::<String>()),
+        None => client_id.to_string(),
+    }
+}
+
+// ==============================================================================
+// per-isolate buffer
+// ==============================================================================
+
+thread_local! {
+    static PENDING: RefCell<Vec<AnalyticsEvent>> = const { RefCell::new(Vec::new()) };
+}
+
+/// buffer an event for the next send
+pub fn record(event: AnalyticsEvent) {
+    PENDING.with(|p| push_capped(&mut p.borrow_mut(), vec![event]));
+}
+
+/// take everything buffered so far, leaving the buffer empty
+pub fn take_pending() -> Vec<AnalyticsEvent> {
+    PENDING.with(|p| std::mem::take(&mut *p.borrow_mut()))
+}
+
+/// put events back after a failed send, ahead of anything recorded since
+pub fn restore_pending(events: Vec<AnalyticsEvent>) {
+    PENDING.with(|p| {
+        let mut pending = p.borrow_mut();
+        let newer = std::mem::take(&mut *pending);
+        push_capped(&mut pending, events);
+        push_capped(&mut pending, newer);
+    });
+}
+
+fn push_capped(pending: &mut Vec<AnalyticsEvent>, events: Vec<AnalyticsEvent>) {
+    pending.extend(events);
+    if pending.len() > MAX_PENDING {
+        let excess = pending.len() - MAX_PENDING;
+        pending.drain(..excess);
+    }
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// send this isolate's buffered events in the background. without a queue
+/// binding (plain `wrangler dev`) they're dropped
+#[cfg(feature = "queue")]
+pub fn flush(env: &worker::Env, ctx: &worker::Context) {
+    let events = take_pending();
+    if events.is_empty() {
+        return;
+    }
+    let queue = match env.queue(QUEUE_BINDING) {
+        Ok(queue) => queue,
+        Err(_) => {
+            worker::console_warn!("no {} queue binding - dropping {} analytics events", QUEUE_BINDING, events.len());
+            return;
+        }
+    };
+    
+    ctx.wait_until(async move {
+        if let Err(e) = queue.send_batch(events.clone()).await {
+            worker::console_warn!("analytics send failed: {}", e);
+            restore_pending(events);
+        }
+    });
+}
+
+/// clicks counted so far for `code`, none when the database isn't reachable
+#[cfg(feature = "d1")]
+pub async fn click_count(env: &worker::Env, code: &str) -> Option<u64> {
+    let db = env.d1(DB_BINDING).ok()?;
+    let statement = db.prepare(SELECT_CLICKS_SQL).bind(&[code.into()]).ok()?;
+    match statement.first::<f64>(Some("clicks")).await {
+        Ok(clicks) => Some(clicks.unwrap_or(0.0) as u64),
+        Err(e) => {
+            worker::console_warn!("click count for {} failed: {}", code, e);
+            None
+        }
+    }
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_event_message_shape() {
+        let event = AnalyticsEvent::click("url-shortener", "abc123", "18bcfe56800-deadbeef", 1_700_000_000_000);
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "kind": "click",
+                "worker": "url-shortener",
+                "subject": "abc123",
+                "status": 301,
+                "request_id": "18bcfe56800-deadbeef",
+                "at": 1_700_000_000_000u64
+            })
+        );
+        assert_eq!(EventKind::Denial.as_str(), "denial");
+    }
+    
+    #[test]
+    fn test_denials_dont_store_api_keys() {
+        let event = AnalyticsEvent::denial("rate-limiter", "key:sk_live_0123456789", "id", 0);
+        assert_eq!(event.subject, "key:sk_liv...");
+        assert_eq!(client_label("ip:203.0.113.7"), "ip:203.0.113.7");
+    }
+    
+    #[test]
+    fn test_buffer_keeps_order_and_cap() {
+        take_pending();
+        record(AnalyticsEvent::denial("rate-limiter", "ip:1.2.3.4", "a", 1));
+        let failed = take_pending();
+        record(AnalyticsEvent::fault("rate-limiter", "/api/status", 500, "b", 2));
+        
+        // a failed send goes back in front of newer events
+        restore_pending(failed);
+        let pending = take_pending();
+        assert_eq!(pending.iter().map(|e| e.request_id.as_str()).collect::<Vec<_>>(), ["a", "b"]);
+        
+        // past the cap the oldest events go
+        for i in 0..MAX_PENDING + 5 {
+            record(AnalyticsEvent::click("url-shortener", "x", &i.to_string(), 0));
+        }
+        let pending = take_pending();
+        assert_eq!(pending.len(), MAX_PENDING);
+        assert_eq!(pending[0].request_id, "5");
+    }
+}
@@ -0,0 +1,471 @@
+//! ==============================================================================
+//! auth.rs - credential parsing and checks for privileged worker routes
+//! ==============================================================================
+//!
+//! purpose:
+//!     the pure half of the auth layer: pulling credentials out of request
+//!     headers, building the string an hmac signature covers, and parsing
+//!     and checking cloudflare access jwts. the crypto itself runs on
+//!     webcrypto in auth_guard.rs.
+//!
+//! relationships:
+//!     - used by: auth_guard (worker side, behind the "worker" feature)
+//!
+//! credentials (first one present wins):
+//!     Authorization: Bearer <api key>
+//!         looked up in kv by sha-256 hash, never stored in the clear
+//!     X-Auth-Timestamp: <unix secs> + X-Auth-Signature: <hex hmac-sha256>
+//!         signature over signing_payload(). with X-Auth-Key-Id: <id> the
+//!         secret is that key's, from kv "auth:hmac:<id>"; without it the
+//!         worker's AUTH_HMAC_SECRET. each signature is accepted once.
+//!     Cf-Access-Jwt-Assertion: <jwt>
+//!         set by cloudflare access in front of the worker, RS256-signed
+//!
+//! ==============================================================================
+
+use std::fmt;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+
+pub const TIMESTAMP_HEADER: &str = "X-Auth-Timestamp";
+pub const SIGNATURE_HEADER: &str = "X-Auth-Signature";
+/// names the per-key secret a request was signed with
+pub const KEY_ID_HEADER: &str = "X-Auth-Key-Id";
+pub const ACCESS_JWT_HEADER: &str = "Cf-Access-Jwt-Assertion";
+/// how far a signed request's timestamp may drift from the worker's clock
+pub const MAX_CLOCK_SKEW_SECS: u64 = 300;
+/// how long a used signature is remembered - the whole window it could be
+/// replayed in, either side of now
+pub const REPLAY_TTL_SECS: u64 = 2 * MAX_CLOCK_SKEW_SECS;
+/// scope needed for the workers' admin routes
+pub const ADMIN_SCOPE: &str = "admin";
+/// scope needed to scrape /metrics
+pub const METRICS_SCOPE: &str = "metrics";
+/// scope needed to upload captures to the archive
+pub const CAPTURES_SCOPE: &str = "captures";
+/// scope a signed caller needs to create links without a turnstile pass
+pub const SHORTEN_SCOPE: &str = "shorten";
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// how a caller proved who they are
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthMethod {
+    ApiKey,
+    Hmac,
+    Access,
+}
+
+/// an authenticated caller
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Principal {
+    /// api key name, "hmac", or the access user's email
+    pub subject: String,
+    pub method: AuthMethod,
+    pub scopes: Vec<String>,
+}
+
+impl Principal {
+    pub fn allows(&self, scope: &str) -> bool {
+        self.scopes.iter().any(|s| s == scope || s == "*")
+    }
+}
+
+/// what kv stores for an api key, under "auth:key:<sha-256 hex>"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ApiKeyRecord {
+    pub name: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+/// what kv stores for a signing key, under "auth:hmac:<key id>" - hmac
+/// needs the secret itself, so unlike api keys it can't be hashed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HmacKeyRecord {
+    pub name: String,
+    pub secret: String,
+    #[serde(default)]
+    pub scopes: Vec<String>,
+}
+
+/// credentials found on a request, before verification
+#[derive(Debug, Clone, PartialEq)]
+pub enum Credential {
+    ApiKey(String),
+    /// `key_id` picks a per-key secret; none means the shared one
+    Hmac { timestamp: u64, signature: Vec<u8>, key_id: Option<String> },
+    Access(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuthError {
+    /// no credential on the request
+    Missing,
+    /// credential present but malformed, unknown or with a bad signature
+    Invalid(&'static str),
+    /// authenticated, but without the scope the route needs
+    Forbidden,
+}
+
+impl AuthError {
+    pub fn status(&self) -> u16 {
+        match self {
+            AuthError::Forbidden => 403,
+            _ => 401,
+        }
+    }
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Missing => write!(f, "authentication required"),
+            AuthError::Invalid(why) => write!(f, "invalid credentials: {}", why),
+            AuthError::Forbidden => write!(f, "not allowed for this credential"),
+        }
+    }
+}
+
+// ==============================================================================
+// credential extraction
+// ==============================================================================
+
+/// pick the credential out of the relevant headers (each `None` if absent)
+pub fn credential(
+    authorization: Option<&str>,
+    timestamp: Option<&str>,
+    signature: Option<&str>,
+    key_id: Option<&str>,
+    access_jwt: Option<&str>,
+) -> Result<Credential, AuthError> {
+    if let Some(value) = authorization {
+        let key = value
+            .strip_prefix("Bearer ")
+            .map(str::trim)
+            .filter(|k| !k.is_empty())
+            .ok_or(AuthError::Invalid("expected a bearer token"))?;
+        return Ok(Credential::ApiKey(key.to_string()));
+    }
+    
+    match (timestamp, signature) {
+        (Some(ts), Some(sig)) => {
+            let timestamp = ts.trim().parse().map_err(|_| AuthError::Invalid("bad timestamp"))?;
+            let signature = hex_decode(sig.trim()).ok_or(AuthError::Invalid("signature is not hex"))?;
+            let key_id = match key_id.map(str::trim) {
+                Some(id) if valid_key_id(id) => Some(id.to_string()),
+                Some(_) => return Err(AuthError::Invalid("bad key id")),
+                None => None,
+            };
+            return Ok(Credential::Hmac { timestamp, signature, key_id });
+        }
+        (Some(_), None) | (None, Some(_)) => {
+            return Err(AuthError::Invalid("signature and timestamp go together"));
+        }
+        (None, None) => {}
+    }
+    
+    match access_jwt {
+        Some(jwt) => Ok(Credential::Access(jwt.to_string())),
+        None => Err(AuthError::Missing),
+    }
+}
+
+/// what an hmac signature covers: method, path with query, timestamp and body
+pub fn signing_payload(method: &str, path_and_query: &str, timestamp: u64, body: &[u8]) -> Vec<u8> {
+    let mut payload = format!("{}\n{}\n{}\n", method.to_ascii_uppercase(), path_and_query, timestamp).into_bytes();
+    payload.extend_from_slice(body);
+    payload
+}
+
+/// reject signed requests from too far in the past (replays) or future
+pub fn timestamp_fresh(timestamp: u64, now: u64) -> bool {
+    timestamp.abs_diff(now) <= MAX_CLOCK_SKEW_SECS
+}
+
+/// key ids end up in kv keys - keep them short and plain
+pub fn valid_key_id(id: &str) -> bool {
+    (1..=64).contains(&id.len()) && id.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+}
+
+/// kv key of a signing key record
+pub fn hmac_key_kv_key(key_id: &str) -> String {
+    format!("auth:hmac:{}", key_id)
+}
+
+/// kv key marking a signature as used. the signature already covers the
+/// timestamp, so two valid requests never share one
+pub fn replay_kv_key(signature: &[u8]) -> String {
+    format!("auth:replay:{}", hex_encode(signature))
+}
+
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn hex_decode(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|b| u8::from_str_radix(b, 16).ok()))
+        .collect()
+}
+
+// ==============================================================================
+// cloudflare access jwts
+// ==============================================================================
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct JwtHeader {
+    pub alg: String,
+    #[serde(default)]
+    pub kid: Option<String>,
+}
+
+/// access sends `aud` as an array, other issuers as a string
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum Audience {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Audience {
+    fn contains(&self, aud: &str) -> bool {
+        match self {
+            Audience::One(a) => a == aud,
+            Audience::Many(all) => all.iter().any(|a| a == aud),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct JwtClaims {
+    pub aud: Audience,
+    pub iss: String,
+    pub exp: u64,
+    #[serde(default)]
+    pub nbf: Option<u64>,
+    #[serde(default)]
+    pub email: Option<String>,
+    #[serde(default)]
+    pub sub: Option<String>,
+}
+
+/// a decoded (not yet verified) jwt
+#[derive(Debug, Clone, PartialEq)]
+pub struct Jwt {
+    pub header: JwtHeader,
+    pub claims: JwtClaims,
+    /// "<header>.<payload>" - the bytes the signature covers
+    pub signing_input: String,
+    pub signature: Vec<u8>,
+}
+
+impl Jwt {
+    pub fn parse(token: &str) -> Result<Jwt, AuthError> {
+        let mut parts = token.split('.');
+        let (Some(header), Some(payload), Some(signature), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(AuthError::Invalid("jwt must have three parts"));
+        };
+        
+        let decode = |part: &str| URL_SAFE_NO_PAD.decode(part).map_err(|_| AuthError::Invalid("jwt is not base64url"));
+        let header: JwtHeader =
+            serde_json::from_slice(&decode(header)?).map_err(|_| AuthError::Invalid("bad jwt header"))?;
+        let claims: JwtClaims =
+            serde_json::from_slice(&decode(payload)?).map_err(|_| AuthError::Invalid("bad jwt claims"))?;
+        
+        Ok(Jwt {
+            header,
+            claims,
+            signing_input: token[..token.len() - signature.len() - 1].to_string(),
+            signature: decode(signature)?,
+        })
+    }
+    
+    /// everything but the signature: algorithm, audience, issuer and lifetime
+    pub fn check_claims(&self, audience: &str, team_domain: &str, now: u64) -> Result<(), AuthError> {
+        if self.header.alg != "RS256" {
+            return Err(AuthError::Invalid("unsupported jwt algorithm"));
+        }
+        if !self.claims.aud.contains(audience) {
+            return Err(AuthError::Invalid("jwt audience mismatch"));
+        }
+        if self.claims.iss.trim_end_matches('/') != access_issuer(team_domain) {
+            return Err(AuthError::Invalid("jwt issuer mismatch"));
+        }
+        if now >= self.claims.exp {
+            return Err(AuthError::Invalid("jwt expired"));
+        }
+        if self.claims.nbf.is_some_and(|nbf| now < nbf) {
+            return Err(AuthError::Invalid("jwt not yet valid"));
+        }
+        Ok(())
+    }
+}
+
+/// "myteam.cloudflareaccess.com" -> "https://myteam.cloudflareaccess.com"
+pub fn access_issuer(team_domain: &str) -> String {
+    let domain = team_domain.trim_end_matches('/');
+    if domain.starts_with("https://") {
+        domain.to_string()
+    } else {
+        format!("https://{}", domain)
+    }
+}
+
+/// where access publishes the team's signing keys
+pub fn access_certs_url(team_domain: &str) -> String {
+    format!("{}/cdn-cgi/access/certs", access_issuer(team_domain))
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn jwt(header: serde_json::Value, claims: serde_json::Value) -> String {
+        format!(
+            "{}.{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string()),
+            URL_SAFE_NO_PAD.encode(b"sig"),
+        )
+    }
+    
+    fn access_jwt(exp: u64) -> Jwt {
+        Jwt::parse(&jwt(
+            serde_json::json!({ "alg": "RS256", "kid": "k1" }),
+            serde_json::json!({
+                "aud": ["app-aud"],
+                "iss": "https://team.cloudflareaccess.com",
+                "exp": exp,
+                "nbf": 1_000,
+                "email": "ops@example.com",
+            }),
+        ))
+        .unwrap()
+    }
+    
+    #[test]
+    fn test_credential_precedence() {
+        assert_eq!(
+            credential(Some("Bearer k"), Some("1"), Some("00"), None, Some("jwt")),
+            Ok(Credential::ApiKey("k".to_string()))
+        );
+        assert_eq!(
+            credential(None, Some("17"), Some("beef"), None, Some("jwt")),
+            Ok(Credential::Hmac { timestamp: 17, signature: vec![0xbe, 0xef], key_id: None })
+        );
+        assert_eq!(
+            credential(None, Some("17"), Some("beef"), Some("billing-sync"), None),
+            Ok(Credential::Hmac { timestamp: 17, signature: vec![0xbe, 0xef], key_id: Some("billing-sync".to_string()) })
+        );
+        assert_eq!(credential(None, None, None, None, Some("jwt")), Ok(Credential::Access("jwt".to_string())));
+        assert_eq!(credential(None, None, None, None, None), Err(AuthError::Missing));
+    }
+    
+    #[test]
+    fn test_credential_rejects_malformed() {
+        assert!(matches!(credential(Some("Basic abc"), None, None, None, None), Err(AuthError::Invalid(_))));
+        assert!(matches!(credential(Some("Bearer  "), None, None, None, None), Err(AuthError::Invalid(_))));
+        assert!(matches!(credential(None, Some("soon"), Some("00"), None, None), Err(AuthError::Invalid(_))));
+        assert!(matches!(credential(None, Some("1"), Some("xyz"), None, None), Err(AuthError::Invalid(_))));
+        assert!(matches!(credential(None, Some("1"), None, None, None), Err(AuthError::Invalid(_))));
+        assert!(matches!(credential(None, Some("1"), Some("00"), Some("a:b"), None), Err(AuthError::Invalid(_))));
+    }
+    
+    #[test]
+    fn test_signing_payload() {
+        let payload = signing_payload("post", "/admin/reset?client=ip:1.2.3.4", 1700000000, b"{}");
+        assert_eq!(payload, b"POST\n/admin/reset?client=ip:1.2.3.4\n1700000000\n{}".to_vec());
+    }
+    
+    #[test]
+    fn test_timestamp_freshness() {
+        assert!(timestamp_fresh(1_000, 1_000));
+        assert!(timestamp_fresh(1_000 - MAX_CLOCK_SKEW_SECS, 1_000));
+        assert!(timestamp_fresh(1_000 + MAX_CLOCK_SKEW_SECS, 1_000));
+        assert!(!timestamp_fresh(1_000 - MAX_CLOCK_SKEW_SECS - 1, 1_000));
+    }
+    
+    #[test]
+    fn test_key_ids_and_replay_keys() {
+        assert!(valid_key_id("billing-sync_2"));
+        assert!(!valid_key_id(""));
+        assert!(!valid_key_id("a/b"));
+        assert!(!valid_key_id(&"k".repeat(65)));
+        assert_eq!(hmac_key_kv_key("ci"), "auth:hmac:ci");
+        assert_eq!(replay_kv_key(&[0xbe, 0xef]), "auth:replay:beef");
+        // kv rejects ttls under a minute
+        const { assert!(REPLAY_TTL_SECS >= 60) };
+    }
+    
+    #[test]
+    fn test_hex_round_trip() {
+        assert_eq!(hex_encode(&[0, 15, 255]), "000fff");
+        assert_eq!(hex_decode("000fff"), Some(vec![0, 15, 255]));
+        assert_eq!(hex_decode("abc"), None);
+        assert_eq!(hex_decode("zz"), None);
+    }
+    
+    #[test]
+    fn test_principal_scopes() {
+        let p = Principal { subject: "ci".into(), method: AuthMethod::ApiKey, scopes: vec!["admin".into()] };
+        assert!(p.allows(ADMIN_SCOPE));
+        assert!(!p.allows("metrics"));
+        let all = Principal { scopes: vec!["*".into()], ..p };
+        assert!(all.allows("metrics"));
+    }
+    
+    #[test]
+    fn test_jwt_parse() {
+        let jwt = access_jwt(5_000);
+        assert_eq!(jwt.header.kid.as_deref(), Some("k1"));
+        assert_eq!(jwt.claims.email.as_deref(), Some("ops@example.com"));
+        assert_eq!(jwt.signature, b"sig".to_vec());
+        assert_eq!(jwt.signing_input.matches('.').count(), 1);
+        
+        assert!(Jwt::parse("a.b").is_err());
+        assert!(Jwt::parse("a.b.c.d").is_err());
+        assert!(Jwt::parse("!!.??.**").is_err());
+    }
+    
+    #[test]
+    fn test_jwt_claims() {
+        let jwt = access_jwt(5_000);
+        assert_eq!(jwt.check_claims("app-aud", "team.cloudflareaccess.com", 2_000), Ok(()));
+        assert_eq!(jwt.check_claims("app-aud", "https://team.cloudflareaccess.com/", 2_000), Ok(()));
+        assert!(jwt.check_claims("other-aud", "team.cloudflareaccess.com", 2_000).is_err());
+        assert!(jwt.check_claims("app-aud", "evil.cloudflareaccess.com", 2_000).is_err());
+        assert!(jwt.check_claims("app-aud", "team.cloudflareaccess.com", 5_000).is_err());
+        assert!(jwt.check_claims("app-aud", "team.cloudflareaccess.com", 999).is_err());
+    }
+    
+    #[test]
+    fn test_jwt_rejects_other_algorithms() {
+        let token = jwt(
+            serde_json::json!({ "alg": "none" }),
+            serde_json::json!({ "aud": "app-aud", "iss": "https://team.cloudflareaccess.com", "exp": 5_000 }),
+        );
+        let jwt = Jwt::parse(&token).unwrap();
+        assert!(jwt.check_claims("app-aud", "team.cloudflareaccess.com", 2_000).is_err());
+    }
+    
+    #[test]
+    fn test_access_urls() {
+        assert_eq!(access_certs_url("team.cloudflareaccess.com"), "https://team.cloudflareaccess.com/cdn-cgi/access/certs");
+    }
+}
@@ -0,0 +1,333 @@
+//! ==============================================================================
+//! auth_guard.rs - verifies credentials on privileged worker routes
+//! ==============================================================================
+//!
+//! purpose:
+//!     the worker half of the auth layer: looks up api keys in kv, checks
+//!     hmac signatures and cloudflare access jwts with webcrypto, and
+//!     answers "who is this and may they do that".
+//!
+//! relationships:
+//!     - uses: auth (credential parsing, jwt claims, signing payload)
+//!     - used by: admin and /metrics routes of every worker
+//!     - used by: url-shortener (signed machine callers on POST /shorten)
+//!     - used by: capture-archive (upload scope, sha256_hex for content ids)
+//!     - used by: tiers (hashed rate-limiter key records, random_hex for new keys)
+//!     - used by: notify (hmac_sha256_hex signs webhook bodies)
+//!
+//! configuration (per worker, all optional - unconfigured methods reject):
+//!     kv binding AUTH               api key records under "auth:key:<sha-256 hex>",
+//!                                   signing keys under "auth:hmac:<key id>",
+//!                                   used signatures under "auth:replay:<hex>"
+//!     secret AUTH_HMAC_SECRET       shared secret for signed requests without a key id
+//!     vars ACCESS_TEAM_DOMAIN +     e.g. "myteam.cloudflareaccess.com" and the
+//!          ACCESS_AUD               access application's audience tag
+//!
+//! replays:
+//!     a verified signature is remembered in kv for REPLAY_TTL_SECS and
+//!     refused after that. kv is eventually consistent, so a replay sent to
+//!     another colo within a few seconds can still get through - the
+//!     timestamp window bounds how long that stays possible.
+//!
+//! ==============================================================================
+
+use std::cell::RefCell;
+
+use serde::Deserialize;
+use worker::js_sys::{Array, Function, Object, Promise, Reflect, Uint8Array};
+use worker::wasm_bindgen::{JsCast, JsValue};
+use worker::wasm_bindgen_futures::JsFuture;
+use worker::*;
+
+use crate::auth::{self, AuthError, AuthMethod, Credential, Jwt, Principal};
+
+pub const AUTH_KV_BINDING: &str = "AUTH";
+const API_KEY_PREFIX: &str = "auth:key:";
+/// how long an isolate reuses access signing keys before refetching
+const CERTS_TTL_MS: f64 = 60.0 * 60.0 * 1000.0;
+
+thread_local! {
+    // (fetched at ms, jwks keys)
+    static ACCESS_CERTS: RefCell<Option<(f64, Vec<serde_json::Value>)>> = const { RefCell::new(None) };
+}
+
+#[derive(Deserialize)]
+struct Jwks {
+    keys: Vec<serde_json::Value>,
+}
+
+// ==============================================================================
+// entry points
+// ==============================================================================
+
+/// authenticate the caller and check they hold `scope`.
+/// `body` is the raw request body (empty for bodyless routes) - signed
+/// requests cover it, so handlers read it before calling this.
+/// the outer error is infrastructure failure, the inner one the verdict.
+pub async fn require(
+    req: &Request,
+    env: &Env,
+    body: &[u8],
+    scope: &str,
+) -> Result<std::result::Result<Principal, AuthError>> {
+    Ok(match authenticate(req, env, body).await? {
+        Ok(principal) if principal.allows(scope) => Ok(principal),
+        Ok(principal) => {
+            console_warn!("{} ({:?}) lacks scope {}", principal.subject, principal.method, scope);
+            Err(AuthError::Forbidden)
+        }
+        Err(e) => Err(e),
+    })
+}
+
+/// work out who sent the request
+pub async fn authenticate(
+    req: &Request,
+    env: &Env,
+    body: &[u8],
+) -> Result<std::result::Result<Principal, AuthError>> {
+    let headers = req.headers();
+    let header = |name: &str| headers.get(name).ok().flatten();
+    let credential = match auth::credential(
+        header("Authorization").as_deref(),
+        header(auth::TIMESTAMP_HEADER).as_deref(),
+        header(auth::SIGNATURE_HEADER).as_deref(),
+        header(auth::KEY_ID_HEADER).as_deref(),
+        header(auth::ACCESS_JWT_HEADER).as_deref(),
+    ) {
+        Ok(c) => c,
+        Err(e) => return Ok(Err(e)),
+    };
+    
+    match credential {
+        Credential::ApiKey(key) => verify_api_key(env, &key).await,
+        Credential::Hmac { timestamp, signature, key_id } => {
+            verify_hmac(req, env, body, timestamp, &signature, key_id.as_deref()).await
+        }
+        Credential::Access(token) => verify_access(env, &token).await,
+    }
+}
+
+/// for routes open to anonymous callers that also take signed requests:
+/// `None` when the request isn't signed, otherwise the verdict on the
+/// signature and `scope`. other credential kinds are ignored here.
+pub async fn verify_signature(
+    req: &Request,
+    env: &Env,
+    body: &[u8],
+    scope: &str,
+) -> Result<Option<std::result::Result<Principal, AuthError>>> {
+    let headers = req.headers();
+    if headers.get(auth::TIMESTAMP_HEADER)?.is_none() && headers.get(auth::SIGNATURE_HEADER)?.is_none() {
+        return Ok(None);
+    }
+    // a signed request can't also carry a bearer token - credential() would pick that first
+    if headers.get("Authorization")?.is_some() {
+        return Ok(Some(Err(AuthError::Invalid("send either a signature or a bearer token"))));
+    }
+    Ok(Some(require(req, env, body, scope).await?))
+}
+
+// ==============================================================================
+// verifiers
+// ==============================================================================
+
+async fn verify_api_key(env: &Env, key: &str) -> Result<std::result::Result<Principal, AuthError>> {
+    let Ok(kv) = env.kv(AUTH_KV_BINDING) else {
+        return Ok(Err(AuthError::Invalid("api keys are not configured")));
+    };
+    
+    // only the hash is stored, so a kv dump doesn't leak usable keys
+    let hash = sha256_hex(key.as_bytes()).await?;
+    
+    crate::metrics::record_kv_op("get");
+    let record: Option<auth::ApiKeyRecord> = kv.get(&format!("{}{}", API_KEY_PREFIX, hash)).json().await?;
+    Ok(match record {
+        Some(record) => Ok(Principal { subject: record.name, method: AuthMethod::ApiKey, scopes: record.scopes }),
+        None => Err(AuthError::Invalid("unknown api key")),
+    })
+}
+
+async fn verify_hmac(
+    req: &Request,
+    env: &Env,
+    body: &[u8],
+    timestamp: u64,
+    signature: &[u8],
+    key_id: Option<&str>,
+) -> Result<std::result::Result<Principal, AuthError>> {
+    // (secret, who signed, their scopes)
+    let signer = match key_id {
+        Some(id) => {
+            let Ok(kv) = env.kv(AUTH_KV_BINDING) else {
+                return Ok(Err(AuthError::Invalid("signing keys are not configured")));
+            };
+            crate::metrics::record_kv_op("get");
+            match kv.get(&auth::hmac_key_kv_key(id)).json::<auth::HmacKeyRecord>().await? {
+                Some(record) => (record.secret, record.name, record.scopes),
+                None => return Ok(Err(AuthError::Invalid("unknown signing key"))),
+            }
+        }
+        None => match env.secret("AUTH_HMAC_SECRET") {
+            Ok(secret) => (secret.to_string(), "hmac".to_string(), vec!["*".to_string()]),
+            Err(_) => return Ok(Err(AuthError::Invalid("signed requests are not configured"))),
+        },
+    };
+    let (secret, subject, scopes) = signer;
+    if !auth::timestamp_fresh(timestamp, js_sys::Date::now() as u64 / 1000) {
+        return Ok(Err(AuthError::Invalid("signature timestamp out of range")));
+    }
+    
+    // callers sign the path they sent - behind the gateway that includes its prefix
+    let url = req.url()?;
+    let prefix = req
+        .headers()
+        .get(crate::FORWARDED_PREFIX_HEADER)?
+        .and_then(|p| crate::accept_forwarded_prefix(&p).map(str::to_string))
+        .unwrap_or_default();
+    let path = match url.query() {
+        Some(q) => format!("{}{}?{}", prefix, url.path(), q),
+        None => format!("{}{}", prefix, url.path()),
+    };
+    let payload = auth::signing_payload(&req.method().to_string(), &path, timestamp, body);
+    
+    let algorithm = algorithm("HMAC")?;
+    let key = subtle_call(
+        "importKey",
+        &["raw".into(), bytes(secret.as_bytes()), algorithm, false.into(), usages("verify")],
+    )
+    .await?;
+    let valid = subtle_call("verify", &["HMAC".into(), key, bytes(signature), bytes(&payload)]).await?;
+    
+    if valid.as_bool() != Some(true) {
+        return Ok(Err(AuthError::Invalid("bad signature")));
+    }
+    if !claim_signature(env, signature).await? {
+        return Ok(Err(AuthError::Invalid("signature already used")));
+    }
+    Ok(Ok(Principal { subject, method: AuthMethod::Hmac, scopes }))
+}
+
+/// record a verified signature as used; false if it already was. without
+/// the AUTH namespace only the timestamp window guards against replays
+async fn claim_signature(env: &Env, signature: &[u8]) -> Result<bool> {
+    let Ok(kv) = env.kv(AUTH_KV_BINDING) else {
+        return Ok(true);
+    };
+    let key = auth::replay_kv_key(signature);
+    crate::metrics::record_kv_op("get");
+    if kv.get(&key).text().await?.is_some() {
+        return Ok(false);
+    }
+    crate::metrics::record_kv_op("put");
+    kv.put(&key, "1")?.expiration_ttl(auth::REPLAY_TTL_SECS).execute().await?;
+    Ok(true)
+}
+
+async fn verify_access(env: &Env, token: &str) -> Result<std::result::Result<Principal, AuthError>> {
+    let (Ok(team), Ok(aud)) = (env.var("ACCESS_TEAM_DOMAIN"), env.var("ACCESS_AUD")) else {
+        return Ok(Err(AuthError::Invalid("access is not configured")));
+    };
+    let (team, aud) = (team.to_string(), aud.to_string());
+    if team.is_empty() || aud.is_empty() {
+        return Ok(Err(AuthError::Invalid("access is not configured")));
+    }
+    
+    let jwt = match Jwt::parse(token) {
+        Ok(jwt) => jwt,
+        Err(e) => return Ok(Err(e)),
+    };
+    if let Err(e) = jwt.check_claims(&aud, &team, js_sys::Date::now() as u64 / 1000) {
+        return Ok(Err(e));
+    }
+    
+    let Some(jwk) = signing_key(&team, jwt.header.kid.as_deref()).await? else {
+        return Ok(Err(AuthError::Invalid("unknown jwt signing key")));
+    };
+    let algorithm = algorithm("RSASSA-PKCS1-v1_5")?;
+    let jwk = js_sys::JSON::parse(&jwk.to_string())?;
+    let key = subtle_call("importKey", &["jwk".into(), jwk, algorithm.clone(), false.into(), usages("verify")]).await?;
+    let valid = subtle_call(
+        "verify",
+        &[algorithm, key, bytes(&jwt.signature), bytes(jwt.signing_input.as_bytes())],
+    )
+    .await?;
+    
+    Ok(if valid.as_bool() == Some(true) {
+        let subject = jwt.claims.email.or(jwt.claims.sub).unwrap_or_else(|| "access".to_string());
+        Ok(Principal { subject, method: AuthMethod::Access, scopes: vec!["*".to_string()] })
+    } else {
+        Err(AuthError::Invalid("bad jwt signature"))
+    })
+}
+
+/// the team's jwk matching `kid`, from the isolate cache or freshly fetched
+async fn signing_key(team: &str, kid: Option<&str>) -> Result<Option<serde_json::Value>> {
+    let now = js_sys::Date::now();
+    let cached = ACCESS_CERTS.with(|c| {
+        c.borrow().as_ref().filter(|(at, _)| now - at < CERTS_TTL_MS).map(|(_, keys)| keys.clone())
+    });
+    let keys = match cached {
+        Some(keys) => keys,
+        None => {
+            let url = Url::parse(&auth::access_certs_url(team))?;
+            let jwks: Jwks = Fetch::Url(url).send().await?.json().await?;
+            ACCESS_CERTS.with(|c| *c.borrow_mut() = Some((now, jwks.keys.clone())));
+            jwks.keys
+        }
+    };
+    
+    Ok(keys.into_iter().find(|k| kid.is_none() || k.get("kid").and_then(|v| v.as_str()) == kid))
+}
+
+// ==============================================================================
+// webcrypto helpers
+// ==============================================================================
+
+/// lowercase hex sha-256 of `data`
+pub async fn sha256_hex(data: &[u8]) -> Result<String> {
+    let digest = subtle_call("digest", &["SHA-256".into(), bytes(data)]).await?;
+    Ok(auth::hex_encode(&Uint8Array::new(&digest).to_vec()))
+}
+
+/// lowercase hex hmac-sha256 of `data` under `secret` - for signing
+/// outgoing requests (shared::notify)
+pub async fn hmac_sha256_hex(secret: &[u8], data: &[u8]) -> Result<String> {
+    let key = subtle_call("importKey", &["raw".into(), bytes(secret), algorithm("HMAC")?, false.into(), usages("sign")]).await?;
+    let signature = subtle_call("sign", &["HMAC".into(), key, bytes(data)]).await?;
+    Ok(auth::hex_encode(&Uint8Array::new(&signature).to_vec()))
+}
+
+/// `len` bytes from `crypto.getRandomValues` as lowercase hex - for secrets
+pub fn random_hex(len: usize) -> Result<String> {
+    let crypto = Reflect::get(&js_sys::global(), &"crypto".into())?;
+    let function: Function = Reflect::get(&crypto, &"getRandomValues".into())?.dyn_into()?;
+    let buffer = Uint8Array::new_with_length(len as u32);
+    function.call1(&crypto, &buffer)?;
+    Ok(auth::hex_encode(&buffer.to_vec()))
+}
+
+/// call `crypto.subtle.<method>(...args)` and await the promise
+async fn subtle_call(method: &str, args: &[JsValue]) -> Result<JsValue> {
+    let crypto = Reflect::get(&js_sys::global(), &"crypto".into())?;
+    let subtle = Reflect::get(&crypto, &"subtle".into())?;
+    let function: Function = Reflect::get(&subtle, &method.into())?.dyn_into()?;
+    let promise: Promise = function.apply(&subtle, &args.iter().collect::<Array>())?.dyn_into()?;
+    Ok(JsFuture::from(promise).await?)
+}
+
+/// `{ name, hash: "SHA-256" }`
+fn algorithm(name: &str) -> Result<JsValue> {
+    let algorithm = Object::new();
+    Reflect::set(&algorithm, &"name".into(), &name.into())?;
+    Reflect::set(&algorithm, &"hash".into(), &"SHA-256".into())?;
+    Ok(algorithm.into())
+}
+
+fn usages(usage: &str) -> JsValue {
+    Array::of1(&usage.into()).into()
+}
+
+fn bytes(data: &[u8]) -> JsValue {
+    Uint8Array::from(data).into()
+}
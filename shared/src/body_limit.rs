@@ -0,0 +1,150 @@
+//! ==============================================================================
+//! body_limit.rs - per-route request body size limits
+//! ==============================================================================
+//!
+//! purpose:
+//!     keeps multi-megabyte payloads away from json parsing. a route reads its
+//!     body through read_body with its own limit: a too-big Content-Length
+//!     is refused without reading anything, and bodies that lie about (or
+//!     omit) their length are cut off as soon as the stream passes the limit.
+//!
+//! relationships:
+//!     - used by: url-shortener (POST /shorten), rate-limiter (POST /admin/reset),
+//!       gateway (proxied bodies)
+//!     - uses: problem (the 413 body)
+//!
+//! 413 body:
+//!     problem+json with code "payload_too_large" plus "max_bytes" (the
+//!     route's limit) and "content_length" when the client declared one
+//!
+//! ==============================================================================
+
+use crate::problem::Problem;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// a body over its route's limit
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BodyTooLarge {
+    pub limit: usize,
+    /// the declared Content-Length, none when the stream ran over
+    pub declared: Option<u64>,
+}
+
+impl BodyTooLarge {
+    pub fn problem(&self) -> Problem {
+        let problem = Problem::new(413, &format!("request body is larger than {} bytes", self.limit))
+            .with_extension("max_bytes", self.limit as u64);
+        match self.declared {
+            Some(n) => problem.with_extension("content_length", n),
+            None => problem,
+        }
+    }
+}
+
+/// refuse up front when the client declares a body over `limit`.
+/// a missing or unparsable header isn't an error - the stream check catches it
+pub fn check_declared(content_length: Option<&str>, limit: usize) -> Result<(), BodyTooLarge> {
+    match content_length.and_then(|v| v.trim().parse::<u64>().ok()) {
+        Some(n) if n > limit as u64 => Err(BodyTooLarge { limit, declared: Some(n) }),
+        _ => Ok(()),
+    }
+}
+
+/// body bytes collected chunk by chunk, refusing to grow past the limit
+#[derive(Debug)]
+pub struct LimitedBody {
+    bytes: Vec<u8>,
+    limit: usize,
+}
+
+impl LimitedBody {
+    pub fn new(limit: usize) -> Self {
+        Self { bytes: Vec::new(), limit }
+    }
+    
+    pub fn push(&mut self, chunk: &[u8]) -> Result<(), BodyTooLarge> {
+        if self.bytes.len() + chunk.len() > self.limit {
+            return Err(BodyTooLarge { limit: self.limit, declared: None });
+        }
+        self.bytes.extend_from_slice(chunk);
+        Ok(())
+    }
+    
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// read the request body, stopping at `limit` bytes.
+/// the outer error is infrastructure failure, the inner one the verdict.
+#[cfg(feature = "worker")]
+pub async fn read_body(
+    req: &mut worker::Request,
+    limit: usize,
+) -> worker::Result<std::result::Result<Vec<u8>, BodyTooLarge>> {
+    use futures_util::StreamExt;
+    
+    if let Err(e) = check_declared(req.headers().get("Content-Length")?.as_deref(), limit) {
+        return Ok(Err(e));
+    }
+    
+    // bodyless requests have no stream to read
+    let Ok(mut stream) = req.stream() else {
+        return Ok(Ok(Vec::new()));
+    };
+    let mut body = LimitedBody::new(limit);
+    while let Some(chunk) = stream.next().await {
+        if let Err(e) = body.push(&chunk?) {
+            return Ok(Err(e));
+        }
+    }
+    Ok(Ok(body.into_bytes()))
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_declared_length() {
+        assert!(check_declared(Some("1024"), 1024).is_ok());
+        assert_eq!(check_declared(Some("1025"), 1024), Err(BodyTooLarge { limit: 1024, declared: Some(1025) }));
+        // the stream check handles these
+        assert!(check_declared(None, 1024).is_ok());
+        assert!(check_declared(Some("lots"), 1024).is_ok());
+    }
+    
+    #[test]
+    fn test_stream_stops_at_limit() {
+        let mut body = LimitedBody::new(8);
+        assert!(body.push(b"1234").is_ok());
+        assert!(body.push(b"5678").is_ok());
+        assert_eq!(body.push(b"9"), Err(BodyTooLarge { limit: 8, declared: None }));
+        assert_eq!(body.into_bytes(), b"12345678");
+    }
+    
+    #[test]
+    fn test_413_problem() {
+        let json: serde_json::Value =
+            serde_json::from_str(&BodyTooLarge { limit: 4096, declared: Some(5_000_000) }.problem().to_json()).unwrap();
+        assert_eq!(json["status"], 413);
+        assert_eq!(json["type"], "tag:edge-protocol-demo,2025:problems/payload_too_large");
+        assert_eq!(json["max_bytes"], 4096);
+        assert_eq!(json["content_length"], 5_000_000);
+        
+        let streamed: serde_json::Value =
+            serde_json::from_str(&BodyTooLarge { limit: 4096, declared: None }.problem().to_json()).unwrap();
+        assert!(streamed.get("content_length").is_none());
+    }
+}
@@ -0,0 +1,88 @@
+//! ==============================================================================
+//! build_info.rs - which build a worker is running
+//! ==============================================================================
+//!
+//! purpose:
+//!     every worker answers GET /version with its crate version, the git
+//!     commit and build time stamped by shared/build.rs, and the shared
+//!     crate's enabled cargo features - so an issue report can name the
+//!     exact deployment it hit. it also carries the worker's wasm size
+//!     budget (shared::size_budget), the number ci held the bundle to.
+//!
+//! relationships:
+//!     - used by: every worker with a fetch handler (GET /version)
+//!     - stamped by: shared/build.rs (EDGE_GIT_SHA, EDGE_BUILT_AT)
+//!
+//! response:
+//!     {"worker": "url-shortener", "version": "0.1.0", "git_sha": "1a2b3c...",
+//!      "built_at": 1767225600, "features": ["worker", "queue", "d1"],
+//!      "size_budget_bytes": 1126400}
+//!
+//! ==============================================================================
+
+use serde::Serialize;
+
+/// full commit the workspace was built from, "unknown" outside a checkout
+pub const GIT_SHA: &str = env!("EDGE_GIT_SHA");
+/// unix seconds
+const BUILT_AT: &str = env!("EDGE_BUILT_AT");
+
+/// cargo features this build of the shared crate was compiled with
+pub const FEATURES: &[&str] = &[
+    #[cfg(feature = "worker")]
+    "worker",
+    #[cfg(feature = "queue")]
+    "queue",
+    #[cfg(feature = "d1")]
+    "d1",
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct BuildInfo {
+    pub worker: &'static str,
+    /// the worker crate's version
+    pub version: &'static str,
+    pub git_sha: &'static str,
+    /// unix seconds
+    pub built_at: u64,
+    pub features: &'static [&'static str],
+    /// max raw wasm bytes for this worker's bundle
+    pub size_budget_bytes: Option<u64>,
+}
+
+impl BuildInfo {
+    /// `version` is the caller's env!("CARGO_PKG_VERSION") - expanded here it
+    /// would always be the shared crate's
+    pub fn new(worker: &'static str, version: &'static str) -> Self {
+        Self {
+            worker,
+            version,
+            git_sha: GIT_SHA,
+            built_at: BUILT_AT.parse().unwrap_or(0),
+            features: FEATURES,
+            size_budget_bytes: crate::size_budget::budget(worker),
+        }
+    }
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_build_info_shape() {
+        let info = BuildInfo::new("url-shortener", "1.2.3");
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["worker"], "url-shortener");
+        assert_eq!(json["version"], "1.2.3");
+        assert_eq!(json["git_sha"], GIT_SHA);
+        assert!(json["built_at"].is_u64());
+        assert!(json["features"].is_array());
+        assert_eq!(json["size_budget_bytes"], crate::size_budget::budget("url-shortener").unwrap());
+        assert!(BuildInfo::new("unknown-worker", "1.2.3").size_budget_bytes.is_none());
+    }
+}
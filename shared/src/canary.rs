@@ -0,0 +1,334 @@
+//! ==============================================================================
+//! canary.rs - stable/canary traffic split for the gateway's backends
+//! ==============================================================================
+//!
+//! purpose:
+//!     each worker behind the gateway can run a second, canary deployment
+//!     next to the stable one. the gateway sends a share of clients (or any
+//!     request that asks) to the canary, counts responses per version, and
+//!     GET /canary compares the error rates - so a larger change can go to
+//!     5% of clients first and be pulled before everyone sees it.
+//!
+//! relationships:
+//!     - used by: gateway (choosing the service binding, GET /canary)
+//!     - uses: workers kv namespace AUTH, key "canary:config" - re-read
+//!       every 30 seconds, like shared::flags
+//!     - uses: metrics (record_upstream) and metrics_store for the counts
+//!
+//! document (json, one entry per backend worker):
+//!     {"url-shortener": {"percent": 10}, "rate-limiter": {"percent": 0}}
+//!     a worker left out gets no canary traffic. the canary is a separate
+//!     worker bound as <BINDING>_CANARY; without that binding everything
+//!     goes to stable whatever the document says.
+//!
+//! choosing:
+//!     X-Canary: canary | stable   forces the version for one request (as
+//!                                 long as the canary is bound)
+//!     otherwise                   the client's bucket (shared::flags::bucket
+//!                                 of "canary:<worker>" and the client id)
+//!                                 below percent goes to the canary - a client
+//!                                 stays on one side while the split holds
+//!     responses say which version answered in X-Upstream-Version.
+//!
+//! comparison:
+//!     errors are 5xx answers (and failed subrequests, counted as 502).
+//!     the totals run from when the metrics object was created, so they
+//!     mix canary releases - compare shortly after a rollout. a verdict
+//!     needs MIN_REQUESTS on both sides.
+//!
+//! ==============================================================================
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// kv key of the split document in the AUTH namespace
+pub const CANARY_KV_KEY: &str = "canary:config";
+/// request header forcing a version
+pub const FORCE_HEADER: &str = "X-Canary";
+/// response header naming the version that answered
+pub const VERSION_HEADER: &str = "X-Upstream-Version";
+/// service binding suffix of a worker's canary
+pub const BINDING_SUFFIX: &str = "_CANARY";
+/// requests each version needs before the comparison gives a verdict
+pub const MIN_REQUESTS: u64 = 50;
+/// how much higher the canary's error rate may be before it's "worse"
+pub const MAX_ERROR_RATE_DELTA: f64 = 0.01;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Version {
+    Stable,
+    Canary,
+}
+
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Stable => "stable",
+            Self::Canary => "canary",
+        }
+    }
+    
+    /// an X-Canary header value
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "stable" => Some(Self::Stable),
+            "canary" => Some(Self::Canary),
+            _ => None,
+        }
+    }
+}
+
+/// one worker's split
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Split {
+    /// percent of clients sent to the canary, 0-100
+    #[serde(default)]
+    pub percent: u8,
+}
+
+/// the split document
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CanaryConfig {
+    splits: BTreeMap<String, Split>,
+}
+
+impl CanaryConfig {
+    pub fn parse(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+    
+    /// share of clients `upstream` sends to its canary, 0 when not listed
+    pub fn percent(&self, upstream: &str) -> u8 {
+        self.splits.get(upstream).map_or(0, |s| s.percent.min(100))
+    }
+    
+    /// which version of `upstream` serves this request
+    pub fn choose(&self, upstream: &str, client_id: &str, forced: Option<&str>, canary_bound: bool) -> Version {
+        if !canary_bound {
+            return Version::Stable;
+        }
+        if let Some(version) = forced.and_then(Version::parse) {
+            return version;
+        }
+        let bucket = crate::flags::bucket(&format!("canary:{}", upstream), client_id);
+        if bucket < self.percent(upstream) {
+            Version::Canary
+        } else {
+            Version::Stable
+        }
+    }
+}
+
+/// the service binding for `version` of a worker bound as `binding`
+pub fn binding(binding: &str, version: Version) -> String {
+    match version {
+        Version::Stable => binding.to_string(),
+        Version::Canary => format!("{}{}", binding, BINDING_SUFFIX),
+    }
+}
+
+/// one version's counts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct VersionStats {
+    pub requests: u64,
+    /// 5xx answers
+    pub errors: u64,
+    /// errors / requests, 0 without requests
+    pub error_rate: f64,
+}
+
+impl VersionStats {
+    fn add(&mut self, status: u16, n: u64) {
+        self.requests += n;
+        if status >= 500 {
+            self.errors += n;
+        }
+        self.error_rate = if self.requests == 0 { 0.0 } else { self.errors as f64 / self.requests as f64 };
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Verdict {
+    /// fewer than MIN_REQUESTS on one side
+    TooFewRequests,
+    /// the canary's error rate is within MAX_ERROR_RATE_DELTA of stable
+    Healthy,
+    /// the canary errs noticeably more - roll it back
+    Worse,
+}
+
+/// stable vs canary for one backend worker
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Comparison {
+    pub upstream: String,
+    /// the current split
+    pub percent: u8,
+    pub stable: VersionStats,
+    pub canary: VersionStats,
+    pub verdict: Verdict,
+}
+
+/// GET /canary document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CanaryReport {
+    /// unix ms
+    pub checked_at: u64,
+    pub upstreams: Vec<Comparison>,
+}
+
+/// compare the versions of every worker in `upstreams`, from metrics'
+/// upstream_requests counts ("<upstream> <version> <status>")
+pub fn compare(upstreams: &[&str], config: &CanaryConfig, counts: &BTreeMap<String, u64>) -> Vec<Comparison> {
+    upstreams
+        .iter()
+        .map(|upstream| {
+            let mut stable = VersionStats::default();
+            let mut canary = VersionStats::default();
+            for (key, n) in counts {
+                let mut parts = key.splitn(3, ' ');
+                if parts.next() != Some(upstream) {
+                    continue;
+                }
+                let version = parts.next().and_then(Version::parse);
+                let Some(status) = parts.next().and_then(|s| s.parse::<u16>().ok()) else {
+                    continue;
+                };
+                match version {
+                    Some(Version::Stable) => stable.add(status, *n),
+                    Some(Version::Canary) => canary.add(status, *n),
+                    None => {}
+                }
+            }
+            let verdict = if stable.requests < MIN_REQUESTS || canary.requests < MIN_REQUESTS {
+                Verdict::TooFewRequests
+            } else if canary.error_rate - stable.error_rate > MAX_ERROR_RATE_DELTA {
+                Verdict::Worse
+            } else {
+                Verdict::Healthy
+            };
+            Comparison { upstream: upstream.to_string(), percent: config.percent(upstream), stable, canary, verdict }
+        })
+        .collect()
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// how long an isolate keeps the document before reading kv again
+#[cfg(feature = "worker")]
+const CANARY_TTL_MS: f64 = 30_000.0;
+
+#[cfg(feature = "worker")]
+thread_local! {
+    // (read at ms, config)
+    static CONFIG: std::cell::RefCell<Option<(f64, CanaryConfig)>> = const { std::cell::RefCell::new(None) };
+}
+
+/// the current split - cached per isolate, no canary traffic when kv has no
+/// document or it doesn't parse
+#[cfg(feature = "worker")]
+pub async fn load(env: &worker::Env) -> CanaryConfig {
+    let now = worker::js_sys::Date::now();
+    let cached = CONFIG.with(|c| c.borrow().as_ref().filter(|(at, _)| now - at < CANARY_TTL_MS).map(|(_, c)| c.clone()));
+    if let Some(config) = cached {
+        return config;
+    }
+    
+    let mut document = None;
+    if let Ok(kv) = env.kv(crate::auth_guard::AUTH_KV_BINDING) {
+        crate::metrics::record_kv_op("get");
+        document = kv.get(CANARY_KV_KEY).text().await.ok().flatten();
+    }
+    let config = match document.as_deref().map(CanaryConfig::parse) {
+        Some(Ok(config)) => config,
+        Some(Err(e)) => {
+            worker::console_warn!("ignoring invalid {}: {}", CANARY_KV_KEY, e);
+            CanaryConfig::default()
+        }
+        None => CanaryConfig::default(),
+    };
+    
+    CONFIG.with(|c| *c.borrow_mut() = Some((now, config.clone())));
+    config
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn clients() -> Vec<String> {
+        (0..1000).map(|i| format!("ip:10.0.{}.{}", i / 256, i % 256)).collect()
+    }
+    
+    #[test]
+    fn test_split_by_percent() {
+        let config = CanaryConfig::parse(r#"{"url-shortener": {"percent": 20}}"#).unwrap();
+        let canary = clients().iter().filter(|c| config.choose("url-shortener", c, None, true) == Version::Canary).count();
+        assert!((120..280).contains(&canary), "{} of 1000 clients", canary);
+        
+        // sticky per client, and unlisted workers get nothing
+        let client = "ip:203.0.113.7";
+        assert_eq!(config.choose("url-shortener", client, None, true), config.choose("url-shortener", client, None, true));
+        assert!(clients().iter().all(|c| config.choose("rate-limiter", c, None, true) == Version::Stable));
+        
+        let all = CanaryConfig::parse(r#"{"url-shortener": {"percent": 250}}"#).unwrap();
+        assert_eq!(all.percent("url-shortener"), 100);
+        assert!(clients().iter().all(|c| all.choose("url-shortener", c, None, true) == Version::Canary));
+    }
+    
+    #[test]
+    fn test_header_and_binding_override() {
+        let none = CanaryConfig::default();
+        assert_eq!(none.choose("gateway", "ip:1", Some("Canary"), true), Version::Canary);
+        assert_eq!(none.choose("gateway", "ip:1", Some("nonsense"), true), Version::Stable);
+        
+        // without a canary binding nothing can reach it
+        let all = CanaryConfig::parse(r#"{"gateway": {"percent": 100}}"#).unwrap();
+        assert_eq!(all.choose("gateway", "ip:1", Some("canary"), false), Version::Stable);
+        assert_eq!(all.choose("gateway", "ip:1", Some("stable"), true), Version::Stable);
+        
+        assert_eq!(binding("URL_SHORTENER", Version::Stable), "URL_SHORTENER");
+        assert_eq!(binding("URL_SHORTENER", Version::Canary), "URL_SHORTENER_CANARY");
+    }
+    
+    #[test]
+    fn test_compare_versions() {
+        let mut counts = BTreeMap::new();
+        counts.insert("url-shortener stable 200".to_string(), 990);
+        counts.insert("url-shortener stable 500".to_string(), 10);
+        counts.insert("url-shortener canary 200".to_string(), 90);
+        counts.insert("url-shortener canary 502".to_string(), 10);
+        counts.insert("rate-limiter stable 429".to_string(), 60);
+        counts.insert("rate-limiter canary 200".to_string(), 3);
+        let config = CanaryConfig::parse(r#"{"url-shortener": {"percent": 10}}"#).unwrap();
+        
+        let report = compare(&["url-shortener", "rate-limiter", "capability-demo"], &config, &counts);
+        let shortener = &report[0];
+        assert_eq!(shortener.percent, 10);
+        assert_eq!((shortener.stable.requests, shortener.stable.errors), (1000, 10));
+        assert_eq!((shortener.canary.requests, shortener.canary.errors), (100, 10));
+        assert!((shortener.canary.error_rate - 0.1).abs() < 1e-9);
+        assert_eq!(shortener.verdict, Verdict::Worse);
+        
+        // 429s aren't errors, and three canary requests say nothing yet
+        assert_eq!(report[1].stable.errors, 0);
+        assert_eq!(report[1].verdict, Verdict::TooFewRequests);
+        assert_eq!(report[2].stable, VersionStats::default());
+        
+        counts.insert("url-shortener canary 200".to_string(), 990);
+        assert_eq!(compare(&["url-shortener"], &config, &counts)[0].verdict, Verdict::Healthy);
+    }
+}
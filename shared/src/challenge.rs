@@ -0,0 +1,141 @@
+//! ==============================================================================
+//! challenge.rs - a turnstile challenge for rate-limited clients
+//! ==============================================================================
+//!
+//! purpose:
+//!     a person who hits the limit can prove they're human and carry on; a
+//!     bot can't. the 429 carries a challenge, the client solves the
+//!     turnstile widget and posts its token to /api/verify, and gets back a
+//!     bypass token that skips the limit for a while.
+//!
+//! relationships:
+//!     - used by: rate-limiter (429 bodies, POST /api/verify, /api/protected)
+//!     - uses: turnstile (siteverify), auth_guard (random tokens, sha-256)
+//!     - storage: the kv namespace the worker passes in, keys
+//!       "bypass:<sha-256 hex of the token>" expiring after BYPASS_TTL_SECS
+//!
+//! configuration (rate-limiter):
+//!     var TURNSTILE_SITE_KEY    the widget's site key - empty turns challenges off
+//!     secret TURNSTILE_SECRET   needed too: without it /api/verify refuses,
+//!                               rather than handing out bypasses unchecked
+//!
+//! bypass tokens:
+//!     "bp_" and 32 random hex bytes, sent back in BYPASS_HEADER. a token is
+//!     bound to the client that solved the challenge - another ip or api
+//!     key presenting it is counted as usual.
+//!
+//! ==============================================================================
+
+use serde::{Deserialize, Serialize};
+
+/// header clients send a bypass token in
+pub const BYPASS_HEADER: &str = "X-Bypass-Token";
+/// where clients post the solved challenge
+pub const VERIFY_PATH: &str = "/api/verify";
+/// how long a bypass lasts
+pub const BYPASS_TTL_SECS: u64 = 600;
+/// kv prefix of bypass records
+pub const BYPASS_KEY_PREFIX: &str = "bypass:";
+/// prefix of issued bypass tokens
+pub const BYPASS_TOKEN_PREFIX: &str = "bp_";
+/// random bytes in a bypass token
+#[cfg(feature = "worker")]
+const BYPASS_TOKEN_BYTES: usize = 32;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// the "challenge" member of a 429 body
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Challenge {
+    /// always "turnstile"
+    pub provider: String,
+    pub site_key: String,
+    /// post the widget's token here in X-Turnstile-Token
+    pub verify_url: String,
+}
+
+impl Challenge {
+    pub fn turnstile(site_key: &str) -> Self {
+        Self { provider: "turnstile".to_string(), site_key: site_key.to_string(), verify_url: VERIFY_PATH.to_string() }
+    }
+}
+
+/// POST /api/verify response
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bypass {
+    pub bypass_token: String,
+    pub expires_in_seconds: u64,
+}
+
+/// kv key of a bypass record for a token's sha-256 hex
+pub fn bypass_kv_key(token_hash: &str) -> String {
+    format!("{}{}", BYPASS_KEY_PREFIX, token_hash)
+}
+
+/// the token from the header, if it's shaped like one we issued
+pub fn bypass_token(header: Option<&str>) -> Option<&str> {
+    header.map(str::trim).filter(|t| {
+        t.strip_prefix(BYPASS_TOKEN_PREFIX).is_some_and(|hex| hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+    })
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// the challenge to offer - none when TURNSTILE_SITE_KEY is unset or empty
+#[cfg(feature = "worker")]
+pub fn offer(env: &worker::Env) -> Option<Challenge> {
+    let site_key = env.var("TURNSTILE_SITE_KEY").ok()?.to_string();
+    (!site_key.trim().is_empty()).then(|| Challenge::turnstile(site_key.trim()))
+}
+
+/// a new bypass token for `client_id`, stored in `kv` until it expires
+#[cfg(feature = "worker")]
+pub async fn issue(kv: &worker::kv::KvStore, client_id: &str) -> worker::Result<Bypass> {
+    let token = format!("{}{}", BYPASS_TOKEN_PREFIX, crate::auth_guard::random_hex(BYPASS_TOKEN_BYTES)?);
+    let key = bypass_kv_key(&crate::auth_guard::sha256_hex(token.as_bytes()).await?);
+    crate::metrics::record_kv_op("put");
+    kv.put(&key, client_id)?.expiration_ttl(BYPASS_TTL_SECS).execute().await?;
+    Ok(Bypass { bypass_token: token, expires_in_seconds: BYPASS_TTL_SECS })
+}
+
+/// whether the request's bypass token was issued to `client_id` and hasn't expired
+#[cfg(feature = "worker")]
+pub async fn bypassed(req: &worker::Request, kv: &worker::kv::KvStore, client_id: &str) -> worker::Result<bool> {
+    let header = req.headers().get(BYPASS_HEADER)?;
+    let Some(token) = bypass_token(header.as_deref()) else {
+        return Ok(false);
+    };
+    let key = bypass_kv_key(&crate::auth_guard::sha256_hex(token.as_bytes()).await?);
+    crate::metrics::record_kv_op("get");
+    Ok(kv.get(&key).text().await?.as_deref() == Some(client_id))
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_challenge_body() {
+        let challenge = serde_json::to_value(Challenge::turnstile("0x4AAA")).unwrap();
+        assert_eq!(challenge, serde_json::json!({"provider": "turnstile", "site_key": "0x4AAA", "verify_url": "/api/verify"}));
+    }
+    
+    #[test]
+    fn test_bypass_token_shape() {
+        let token = format!("bp_{}", "ab".repeat(32));
+        assert_eq!(bypass_token(Some(&format!(" {} ", token))), Some(token.as_str()));
+        assert_eq!(bypass_token(Some(&format!("rl_{}", "ab".repeat(32)))), None);
+        assert_eq!(bypass_token(Some("bp_abc")), None);
+        assert_eq!(bypass_token(Some(&format!("bp_{}", "zz".repeat(32)))), None);
+        assert_eq!(bypass_token(None), None);
+        assert_eq!(bypass_kv_key("ab12"), "bypass:ab12");
+    }
+}
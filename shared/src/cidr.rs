@@ -0,0 +1,184 @@
+//! ==============================================================================
+//! cidr.rs - ip addresses and cidr ranges
+//! ==============================================================================
+//!
+//! purpose:
+//!     matches client ips against entries like "203.0.113.7",
+//!     "198.51.100.0/24" or "2001:db8::/32". a bare address is a range of
+//!     one.
+//!
+//! relationships:
+//!     - used by: ip_lists (allow and deny entries)
+//!
+//! matching:
+//!     ipv4 ranges only match ipv4 addresses and ipv6 ranges ipv6 ones,
+//!     except that ipv4-mapped ipv6 addresses ("::ffff:203.0.113.7") count
+//!     as the ipv4 address they carry. host bits after the prefix are
+//!     cleared when parsing, so "198.51.100.9/24" is "198.51.100.0/24".
+//!
+//! ==============================================================================
+
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// an address range - serialized as its text form
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// the range's first address
+    pub fn network(&self) -> IpAddr {
+        self.network
+    }
+    
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+    
+    /// whether `ip` falls in the range
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => u32::from(ip) & v4_mask(self.prefix) == u32::from(net),
+            (IpAddr::V6(net), IpAddr::V6(ip)) => u128::from(ip) & v6_mask(self.prefix) == u128::from(net),
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+        let ip: IpAddr = addr.parse().map_err(|_| format!("invalid ip address: {:?}", addr))?;
+        // a mapped address written as a range is still an ipv6 range - only
+        // bare addresses are canonicalized
+        let ip = if prefix.is_none() { ip.to_canonical() } else { ip };
+        let max = if ip.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(p) => p.parse::<u8>().ok().filter(|p| *p <= max).ok_or_else(|| format!("invalid prefix length: {:?}", p))?,
+            None => max,
+        };
+        let network = match ip {
+            IpAddr::V4(ip) => IpAddr::V4((u32::from(ip) & v4_mask(prefix)).into()),
+            IpAddr::V6(ip) => IpAddr::V6((u128::from(ip) & v6_mask(prefix)).into()),
+        };
+        Ok(Self { network, prefix })
+    }
+}
+
+impl TryFrom<String> for Cidr {
+    type Error = String;
+    
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl From<Cidr> for String {
+    fn from(cidr: Cidr) -> Self {
+        cidr.to_string()
+    }
+}
+
+/// single addresses print without a prefix
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let max = if self.network.is_ipv4() { 32 } else { 128 };
+        if self.prefix == max {
+            write!(f, "{}", self.network)
+        } else {
+            write!(f, "{}/{}", self.network, self.prefix)
+        }
+    }
+}
+
+fn v4_mask(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0)
+}
+
+fn v6_mask(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0)
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+    
+    fn cidr(s: &str) -> Cidr {
+        s.parse().unwrap()
+    }
+    
+    #[test]
+    fn test_ipv4_ranges() {
+        let range = cidr("198.51.100.0/24");
+        assert!(range.contains(ip("198.51.100.0")));
+        assert!(range.contains(ip("198.51.100.255")));
+        assert!(!range.contains(ip("198.51.101.0")));
+        
+        let single = cidr("203.0.113.7");
+        assert_eq!(single.prefix(), 32);
+        assert!(single.contains(ip("203.0.113.7")));
+        assert!(!single.contains(ip("203.0.113.8")));
+        
+        // /0 is everything of its family
+        assert!(cidr("0.0.0.0/0").contains(ip("8.8.8.8")));
+        assert!(!cidr("0.0.0.0/0").contains(ip("2001:db8::1")));
+    }
+    
+    #[test]
+    fn test_ipv6_ranges() {
+        let range = cidr("2001:db8::/32");
+        assert!(range.contains(ip("2001:db8:ffff::1")));
+        assert!(!range.contains(ip("2001:db9::1")));
+        assert!(!range.contains(ip("198.51.100.1")));
+        assert!(cidr("::/0").contains(ip("2001:db8::1")));
+    }
+    
+    #[test]
+    fn test_mapped_ipv4() {
+        // a client reported as ::ffff:a.b.c.d matches ipv4 entries
+        assert!(cidr("198.51.100.0/24").contains(ip("::ffff:198.51.100.9")));
+        assert_eq!(cidr("::ffff:203.0.113.7"), cidr("203.0.113.7"));
+    }
+    
+    #[test]
+    fn test_parse_and_display() {
+        assert_eq!(cidr("198.51.100.9/24").to_string(), "198.51.100.0/24");
+        assert_eq!(cidr(" 203.0.113.7 ").to_string(), "203.0.113.7");
+        assert_eq!(cidr("203.0.113.7/32").to_string(), "203.0.113.7");
+        assert_eq!(cidr("2001:db8::1/32").to_string(), "2001:db8::/32");
+        
+        assert!("198.51.100.0/33".parse::<Cidr>().is_err());
+        assert!("2001:db8::/129".parse::<Cidr>().is_err());
+        assert!("198.51.100.0/".parse::<Cidr>().is_err());
+        assert!("example.com".parse::<Cidr>().is_err());
+        
+        let json = serde_json::to_string(&cidr("198.51.100.0/24")).unwrap();
+        assert_eq!(json, "\"198.51.100.0/24\"");
+        assert!(serde_json::from_str::<Cidr>("\"nope\"").is_err());
+    }
+}
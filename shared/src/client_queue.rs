@@ -0,0 +1,207 @@
+//! ==============================================================================
+//! client_queue.rs - one read-modify-write per client at a time, per isolate
+//! ==============================================================================
+//!
+//! purpose:
+//!     an isolate serves many requests at once, and the kv counters are read,
+//!     changed and written back with awaits in between. parallel requests
+//!     from one client used to all read count 9 and all pass, and cached
+//!     counters synced at once overwrote each other's hits. now each
+//!     waits for its turn: the next request for a client starts reading
+//!     only once the one before has written.
+//!
+//! relationships:
+//!     - used by: rate-limiter (quota counters, and the kv, kv_cached and
+//!       token bucket counters - held from the first read to the last write)
+//!
+//! limits:
+//!     the line is per isolate. requests for the same client in another
+//!     isolate or colo still race through kv, which is also only eventually
+//!     consistent between colos - LIMITER_BACKEND = "durable_object" is the
+//!     exact backend. a request dropped while waiting gives up its place.
+//!
+//! ==============================================================================
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll, Waker};
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// one client's line - dropped once nobody is in it
+#[derive(Debug, Default)]
+struct Line {
+    /// the ticket the next arrival gets
+    next: u64,
+    /// the ticket whose turn it is
+    serving: u64,
+    /// wakers of tickets that have been polled and are waiting
+    waiting: HashMap<u64, Waker>,
+    /// tickets given up before their turn came - skipped
+    abandoned: HashSet<u64>,
+}
+
+thread_local! {
+    static LINES: RefCell<HashMap<String, Line>> = RefCell::new(HashMap::new());
+}
+
+/// a place in `key`'s line - resolves to the Turn
+#[derive(Debug)]
+pub struct Waiting {
+    /// none once the turn was handed out
+    key: Option<String>,
+    ticket: u64,
+}
+
+/// `key`'s turn - the next caller gets its own when this is dropped
+#[derive(Debug)]
+pub struct Turn {
+    key: String,
+}
+
+/// get in `key`'s line; await it for the turn
+pub fn turn(key: &str) -> Waiting {
+    let ticket = LINES.with(|l| {
+        let mut lines = l.borrow_mut();
+        let line = lines.entry(key.to_string()).or_default();
+        line.next += 1;
+        line.next - 1
+    });
+    Waiting { key: Some(key.to_string()), ticket }
+}
+
+impl Future for Waiting {
+    type Output = Turn;
+    
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Turn> {
+        let ticket = self.ticket;
+        let Some(key) = self.key.as_deref() else {
+            panic!("client_queue::Waiting polled after its turn came");
+        };
+        let ready = LINES.with(|l| {
+            let mut lines = l.borrow_mut();
+            let line = lines.entry(key.to_string()).or_default();
+            if line.serving == ticket {
+                line.waiting.remove(&ticket);
+                true
+            } else {
+                line.waiting.insert(ticket, cx.waker().clone());
+                false
+            }
+        });
+        if ready {
+            Poll::Ready(Turn { key: self.key.take().unwrap_or_default() })
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+impl Drop for Waiting {
+    /// leaving the line before the turn came
+    fn drop(&mut self) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        let ticket = self.ticket;
+        let next = LINES.with(|l| {
+            let mut lines = l.borrow_mut();
+            let line = lines.get_mut(&key)?;
+            line.waiting.remove(&ticket);
+            if line.serving == ticket {
+                advance(&mut lines, &key)
+            } else {
+                line.abandoned.insert(ticket);
+                None
+            }
+        });
+        if let Some(waker) = next {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for Turn {
+    fn drop(&mut self) {
+        // woken outside the borrow - a waker may poll right away
+        if let Some(waker) = LINES.with(|l| advance(&mut l.borrow_mut(), &self.key)) {
+            waker.wake();
+        }
+    }
+}
+
+/// hand `key`'s turn to the next ticket still in line, and return its
+/// waker if it's already waiting
+fn advance(lines: &mut HashMap<String, Line>, key: &str) -> Option<Waker> {
+    let line = lines.get_mut(key)?;
+    line.serving += 1;
+    while line.abandoned.remove(&line.serving) {
+        line.serving += 1;
+    }
+    if line.serving == line.next {
+        lines.remove(key);
+        return None;
+    }
+    line.waiting.remove(&line.serving)
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn poll(waiting: &mut Waiting) -> Option<Turn> {
+        let mut cx = Context::from_waker(Waker::noop());
+        match Pin::new(waiting).poll(&mut cx) {
+            Poll::Ready(turn) => Some(turn),
+            Poll::Pending => None,
+        }
+    }
+    
+    #[test]
+    fn test_one_at_a_time_in_order() {
+        let mut first = turn("ip:192.0.2.1");
+        let mut second = turn("ip:192.0.2.1");
+        let mut third = turn("ip:192.0.2.1");
+        // another client doesn't wait behind them
+        let mut other = turn("ip:192.0.2.2");
+        assert!(poll(&mut other).is_some());
+        
+        assert!(poll(&mut third).is_none());
+        assert!(poll(&mut second).is_none());
+        let held = poll(&mut first).unwrap();
+        assert!(poll(&mut second).is_none());
+        drop(held);
+        assert!(poll(&mut third).is_none());
+        let held = poll(&mut second).unwrap();
+        drop(held);
+        drop(poll(&mut third).unwrap());
+        
+        assert!(!LINES.with(|l| l.borrow().contains_key("ip:192.0.2.1")));
+    }
+    
+    #[test]
+    fn test_leaving_the_line_skips_the_ticket() {
+        let mut first = turn("key:abc");
+        let second = turn("key:abc");
+        let mut third = turn("key:abc");
+        let held = poll(&mut first).unwrap();
+        drop(second);
+        drop(held);
+        drop(poll(&mut third).unwrap());
+        
+        // leaving when it was already your turn passes it on too
+        let first = turn("key:abc");
+        let mut second = turn("key:abc");
+        drop(first);
+        drop(poll(&mut second).unwrap());
+        assert!(!LINES.with(|l| l.borrow().contains_key("key:abc")));
+    }
+}
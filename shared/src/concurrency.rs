@@ -0,0 +1,241 @@
+//! ==============================================================================
+//! concurrency.rs - in-flight request limits in a durable object
+//! ==============================================================================
+//!
+//! purpose:
+//!     a window limit doesn't stop one client holding dozens of slow
+//!     requests open at once - against a slow origin, that parallelism is
+//!     what hurts. this caps the requests a client may have in flight, next
+//!     to the per-window limit.
+//!
+//! relationships:
+//!     - used by: rate-limiter (MAX_CONCURRENT > 0)
+//!     - binding: CONCURRENCY -> class ConcurrencyLimiter, exported by
+//!       workers/rate-limiter
+//!
+//! protocol (internal, worker -> object named by the client id):
+//!     POST /acquire?cap=4      take a slot if fewer than cap are held; Slot json
+//!     POST /release?lease=7    give slot 7 back
+//!     GET  /                   {"in_flight": n}
+//!
+//! leases:
+//!     every slot is a lease that expires after LEASE_TTL_SECS, so a worker
+//!     that dies before releasing only holds it that long. a request
+//!     running longer than that stops counting. the object handles one
+//!     request at a time, so two acquires can't both take the last slot.
+//!
+//! ==============================================================================
+
+use serde::{Deserialize, Serialize};
+
+/// binding name of the concurrency durable object
+pub const CONCURRENCY_BINDING: &str = "CONCURRENCY";
+/// env var with the per-client cap - unset or 0 turns the limiter off
+pub const CAP_VAR: &str = "MAX_CONCURRENT";
+/// how long a slot is held without a release
+pub const LEASE_TTL_SECS: u64 = 30;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// the outcome of an acquire
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Slot {
+    /// to hand back on release - none when the cap was reached
+    pub lease: Option<u64>,
+    /// slots held, this one included
+    pub in_flight: u32,
+    pub cap: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Lease {
+    id: u64,
+    /// unix ms
+    expires_at: u64,
+}
+
+/// one client's held slots
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Leases {
+    next_id: u64,
+    held: Vec<Lease>,
+}
+
+impl Leases {
+    /// take a slot at `now_ms` if fewer than `cap` are held
+    pub fn acquire(&mut self, now_ms: u64, cap: u32, ttl_ms: u64) -> Slot {
+        self.prune(now_ms);
+        let lease = if (self.held.len() as u64) < cap as u64 {
+            self.next_id += 1;
+            self.held.push(Lease { id: self.next_id, expires_at: now_ms + ttl_ms });
+            Some(self.next_id)
+        } else {
+            None
+        };
+        Slot { lease, in_flight: self.held.len() as u32, cap }
+    }
+    
+    /// give a slot back; false when it had expired or was never held
+    pub fn release(&mut self, id: u64) -> bool {
+        let before = self.held.len();
+        self.held.retain(|l| l.id != id);
+        self.held.len() != before
+    }
+    
+    /// slots held at `now_ms`
+    pub fn in_flight(&mut self, now_ms: u64) -> u32 {
+        self.prune(now_ms);
+        self.held.len() as u32
+    }
+    
+    /// when the last held slot expires (unix ms)
+    pub fn expires_at(&self) -> Option<u64> {
+        self.held.iter().map(|l| l.expires_at).max()
+    }
+    
+    fn prune(&mut self, now_ms: u64) {
+        self.held.retain(|l| l.expires_at > now_ms);
+    }
+}
+
+// ==============================================================================
+// durable object
+// ==============================================================================
+
+#[cfg(feature = "worker")]
+const LEASES_KEY: &str = "leases";
+/// object the health probe reads - never holds a slot
+#[cfg(feature = "worker")]
+const PROBE_NAME: &str = "health:probe";
+/// the limiter as a GET /health/deps dependency
+#[cfg(feature = "worker")]
+pub const HEALTH_DEPENDENCY: crate::health::Dependency = crate::health::Dependency::DurableObject(CONCURRENCY_BINDING, PROBE_NAME);
+
+#[cfg(feature = "worker")]
+#[worker::durable_object]
+pub struct ConcurrencyLimiter {
+    state: worker::State,
+}
+
+#[cfg(feature = "worker")]
+impl worker::DurableObject for ConcurrencyLimiter {
+    fn new(state: worker::State, _env: worker::Env) -> Self {
+        Self { state }
+    }
+    
+    async fn fetch(&self, req: worker::Request) -> worker::Result<worker::Response> {
+        use worker::{Method, Response};
+        
+        let storage = self.state.storage();
+        let url = req.url()?;
+        let param = |name: &str| url.query_pairs().find(|(k, _)| k == name).and_then(|(_, v)| v.parse::<u64>().ok());
+        let now = worker::js_sys::Date::now() as u64;
+        let mut leases: Leases = storage.get(LEASES_KEY).await?.unwrap_or_default();
+        
+        match (req.method(), url.path()) {
+            (Method::Post, "/acquire") => {
+                let Some(cap) = param("cap") else {
+                    return Response::error("missing cap", 400);
+                };
+                let slot = leases.acquire(now, cap.min(u32::MAX as u64) as u32, LEASE_TTL_SECS * 1000);
+                if slot.lease.is_some() {
+                    storage.put(LEASES_KEY, &leases).await?;
+                    // every acquire pushes the alarm to the newest lease's expiry
+                    storage.set_alarm(std::time::Duration::from_secs(LEASE_TTL_SECS)).await?;
+                }
+                Response::from_json(&slot)
+            }
+            (Method::Post, "/release") => {
+                let Some(lease) = param("lease") else {
+                    return Response::error("missing lease", 400);
+                };
+                if leases.release(lease) {
+                    storage.put(LEASES_KEY, &leases).await?;
+                }
+                Response::empty()
+            }
+            (Method::Get, _) => Response::from_json(&serde_json::json!({ "in_flight": leases.in_flight(now) })),
+            _ => Response::error("not found", 404),
+        }
+    }
+    
+    /// the newest lease has expired - nothing is in flight any more
+    async fn alarm(&self) -> worker::Result<worker::Response> {
+        self.state.storage().delete_all().await?;
+        worker::Response::empty()
+    }
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// the per-client cap MAX_CONCURRENT sets - none when unset or 0
+#[cfg(feature = "worker")]
+pub fn cap(env: &worker::Env) -> Option<u32> {
+    let cap = env.var(CAP_VAR).ok()?.to_string().trim().parse::<u32>().ok()?;
+    (cap > 0).then_some(cap)
+}
+
+#[cfg(feature = "worker")]
+async fn call(env: &worker::Env, client_id: &str, path_and_query: &str) -> worker::Result<worker::Response> {
+    let url = format!("https://concurrency.internal{}", path_and_query);
+    let init = worker::RequestInit { method: worker::Method::Post, ..worker::RequestInit::default() };
+    let req = worker::Request::new_with_init(&url, &init)?;
+    env.durable_object(CONCURRENCY_BINDING)?.id_from_name(client_id)?.get_stub()?.fetch_with_request(req).await
+}
+
+/// take one of `client_id`'s `cap` slots
+#[cfg(feature = "worker")]
+pub async fn acquire(env: &worker::Env, client_id: &str, cap: u32) -> worker::Result<Slot> {
+    call(env, client_id, &format!("/acquire?cap={}", cap)).await?.json().await
+}
+
+/// give a slot back once its request is done
+#[cfg(feature = "worker")]
+pub async fn release(env: &worker::Env, client_id: &str, lease: u64) -> worker::Result<()> {
+    call(env, client_id, &format!("/release?lease={}", lease)).await.map(|_| ())
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const TTL: u64 = 30_000;
+    
+    #[test]
+    fn test_cap_and_release() {
+        let mut leases = Leases::default();
+        let first = leases.acquire(0, 2, TTL);
+        let second = leases.acquire(1, 2, TTL);
+        assert_eq!((first.lease, second.lease), (Some(1), Some(2)));
+        assert_eq!(second.in_flight, 2);
+        
+        let refused = leases.acquire(2, 2, TTL);
+        assert_eq!(refused, Slot { lease: None, in_flight: 2, cap: 2 });
+        
+        assert!(leases.release(1));
+        assert!(!leases.release(1));
+        assert_eq!(leases.acquire(3, 2, TTL).lease, Some(3));
+    }
+    
+    #[test]
+    fn test_leases_expire() {
+        let mut leases = Leases::default();
+        leases.acquire(0, 1, TTL);
+        assert!(leases.acquire(TTL - 1, 1, TTL).lease.is_none());
+        // an unreleased slot frees itself
+        assert_eq!(leases.in_flight(TTL), 0);
+        assert!(leases.acquire(TTL, 1, TTL).lease.is_some());
+        assert_eq!(leases.expires_at(), Some(2 * TTL));
+        
+        // a zero cap refuses everything
+        assert!(Leases::default().acquire(0, 0, TTL).lease.is_none());
+    }
+}
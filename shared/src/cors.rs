@@ -0,0 +1,261 @@
+//! ==============================================================================
+//! cors.rs - shared cors origin policy for every worker
+//! ==============================================================================
+//!
+//! purpose:
+//!     decides which browser origins may read a worker's responses. the
+//!     default stays `*` so the public demo keeps working, but deployers can
+//!     lock the workers to their own dashboard without patching each one.
+//!
+//! relationships:
+//!     - used by: every worker's fetch entry point (applied to the final response)
+//!
+//! configuration (per worker, first match wins):
+//!     kv key "cors:allowed-origins"   in the AUTH namespace, shared by all
+//!                                     workers - re-read every minute
+//!     var CORS_ALLOWED_ORIGINS        in wrangler.toml
+//!     (neither set)                   "*"
+//!
+//! allowlist entries (comma separated):
+//!     *                               any origin (sends a literal `*`)
+//!     https://dash.example.com        exactly this origin
+//!     https://*.pages.dev             any subdomain, e.g. preview deployments
+//!
+//! design rationale:
+//!     allowed origins are echoed back with `Vary: Origin` so caches keep
+//!     per-origin copies; anything else gets no allow-origin header at all
+//!     and the browser blocks the read.
+//!
+//! ==============================================================================
+
+pub const ALLOWED_ORIGINS_VAR: &str = "CORS_ALLOWED_ORIGINS";
+pub const ALLOWED_ORIGINS_KV_KEY: &str = "cors:allowed-origins";
+
+// ==============================================================================
+// policy
+// ==============================================================================
+
+/// one allowlist entry
+#[derive(Debug, Clone, PartialEq)]
+pub enum OriginPattern {
+    Any,
+    Exact(String),
+    /// `https://*.example.com` - scheme plus the suffix after the `*`
+    Subdomain { scheme: String, suffix: String },
+}
+
+impl OriginPattern {
+    /// parse one entry, `None` for something that can't be an origin
+    pub fn parse(entry: &str) -> Option<Self> {
+        let entry = entry.trim().trim_end_matches('/').to_ascii_lowercase();
+        if entry == "*" {
+            return Some(Self::Any);
+        }
+        let (scheme, rest) = entry.split_once("://")?;
+        if !matches!(scheme, "http" | "https") || rest.is_empty() || rest.contains('/') {
+            return None;
+        }
+        match rest.strip_prefix("*.") {
+            Some(suffix) if !suffix.is_empty() && !suffix.contains('*') => Some(Self::Subdomain {
+                scheme: scheme.to_string(),
+                suffix: format!(".{}", suffix),
+            }),
+            Some(_) => None,
+            None if rest.contains('*') => None,
+            None => Some(Self::Exact(entry)),
+        }
+    }
+    
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            Self::Any => true,
+            Self::Exact(o) => o == origin,
+            Self::Subdomain { scheme, suffix } => origin
+                .strip_prefix(scheme.as_str())
+                .and_then(|o| o.strip_prefix("://"))
+                .and_then(|host| host.strip_suffix(suffix.as_str()))
+                .is_some_and(|sub| {
+                    !sub.is_empty()
+                        && sub.split('.').all(|label| {
+                            !label.is_empty() && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+                        })
+                }),
+        }
+    }
+}
+
+/// the allowlist a worker answers with
+#[derive(Debug, Clone, PartialEq)]
+pub struct CorsPolicy {
+    pub patterns: Vec<OriginPattern>,
+}
+
+impl Default for CorsPolicy {
+    /// no configuration - any origin, as before the allowlist existed
+    fn default() -> Self {
+        Self { patterns: vec![OriginPattern::Any] }
+    }
+}
+
+impl CorsPolicy {
+    /// parse a comma separated allowlist, skipping (and returning) bad entries
+    pub fn parse(list: &str) -> (Self, Vec<String>) {
+        let mut patterns = Vec::new();
+        let mut rejected = Vec::new();
+        for entry in list.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            match OriginPattern::parse(entry) {
+                Some(p) => patterns.push(p),
+                None => rejected.push(entry.to_string()),
+            }
+        }
+        (Self { patterns }, rejected)
+    }
+    
+    fn allows_any(&self) -> bool {
+        self.patterns.contains(&OriginPattern::Any)
+    }
+    
+    /// value for Access-Control-Allow-Origin, `None` when the origin isn't allowed
+    pub fn allow_origin(&self, origin: Option<&str>) -> Option<String> {
+        if self.allows_any() {
+            return Some("*".to_string());
+        }
+        let origin = origin?.trim().to_ascii_lowercase();
+        self.patterns.iter().any(|p| p.matches(&origin)).then_some(origin)
+    }
+    
+    /// cors headers for a response to `origin`. the answer depends on the
+    /// origin unless everything is allowed, so caches are told to vary on it
+    pub fn headers(&self, origin: Option<&str>) -> Vec<(&'static str, String)> {
+        let mut headers = Vec::new();
+        if let Some(allowed) = self.allow_origin(origin) {
+            headers.push(("Access-Control-Allow-Origin", allowed));
+        }
+        if !self.allows_any() {
+            headers.push(("Vary", "Origin".to_string()));
+        }
+        headers
+    }
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// how long an isolate reuses the kv allowlist before rereading it
+#[cfg(feature = "worker")]
+const POLICY_TTL_MS: f64 = 60_000.0;
+
+#[cfg(feature = "worker")]
+thread_local! {
+    // (read at ms, policy)
+    static POLICY: std::cell::RefCell<Option<(f64, CorsPolicy)>> = const { std::cell::RefCell::new(None) };
+}
+
+/// this worker's policy: kv override, then the var, then allow-all
+#[cfg(feature = "worker")]
+pub async fn policy(env: &worker::Env) -> CorsPolicy {
+    let now = worker::js_sys::Date::now();
+    let cached = POLICY.with(|p| p.borrow().as_ref().filter(|(at, _)| now - at < POLICY_TTL_MS).map(|(_, p)| p.clone()));
+    if let Some(policy) = cached {
+        return policy;
+    }
+    
+    let mut list = None;
+    if let Ok(kv) = env.kv(crate::auth_guard::AUTH_KV_BINDING) {
+        crate::metrics::record_kv_op("get");
+        list = kv.get(ALLOWED_ORIGINS_KV_KEY).text().await.ok().flatten();
+    }
+    let list = list.or_else(|| env.var(ALLOWED_ORIGINS_VAR).ok().map(|v| v.to_string()));
+    let policy = match list {
+        Some(list) if !list.trim().is_empty() => {
+            let (policy, rejected) = CorsPolicy::parse(&list);
+            if !rejected.is_empty() {
+                worker::console_warn!("ignoring invalid cors origins: {}", rejected.join(", "));
+            }
+            policy
+        }
+        _ => CorsPolicy::default(),
+    };
+    
+    POLICY.with(|p| *p.borrow_mut() = Some((now, policy.clone())));
+    policy
+}
+
+/// replace whatever allow-origin the handler set with the policy's answer
+#[cfg(feature = "worker")]
+pub fn apply(policy: &CorsPolicy, origin: Option<&str>, headers: &worker::Headers) {
+    let _ = headers.delete("Access-Control-Allow-Origin");
+    for (name, value) in policy.headers(origin) {
+        let _ = headers.append(name, &value);
+    }
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn policy(list: &str) -> CorsPolicy {
+        CorsPolicy::parse(list).0
+    }
+    
+    #[test]
+    fn test_default_allows_any_origin() {
+        let p = CorsPolicy::default();
+        assert_eq!(p.allow_origin(Some("https://anything.example")), Some("*".to_string()));
+        assert_eq!(p.allow_origin(None), Some("*".to_string()));
+        // `*` doesn't depend on the origin, so no vary
+        assert_eq!(p.headers(None), vec![("Access-Control-Allow-Origin", "*".to_string())]);
+    }
+    
+    #[test]
+    fn test_exact_origins_are_echoed() {
+        let p = policy("https://dash.example.com, http://localhost:8080");
+        assert_eq!(p.allow_origin(Some("https://dash.example.com")), Some("https://dash.example.com".to_string()));
+        assert_eq!(p.allow_origin(Some("http://localhost:8080")), Some("http://localhost:8080".to_string()));
+        assert_eq!(p.allow_origin(Some("http://dash.example.com")), None);
+        assert_eq!(p.allow_origin(Some("https://dash.example.com.evil.io")), None);
+        assert_eq!(p.allow_origin(None), None);
+    }
+    
+    #[test]
+    fn test_wildcard_subdomains() {
+        let p = policy("https://*.pages.dev");
+        assert!(p.allow_origin(Some("https://edge-demo.pages.dev")).is_some());
+        assert!(p.allow_origin(Some("https://abc123.edge-demo.pages.dev")).is_some());
+        // the bare domain, lookalikes and other schemes don't match
+        assert!(p.allow_origin(Some("https://pages.dev")).is_none());
+        assert!(p.allow_origin(Some("https://evilpages.dev")).is_none());
+        assert!(p.allow_origin(Some("http://edge-demo.pages.dev")).is_none());
+        assert!(p.allow_origin(Some("https://a..pages.dev")).is_none());
+        assert!(p.allow_origin(Some("https://x/y.pages.dev")).is_none());
+    }
+    
+    #[test]
+    fn test_headers_vary_on_origin() {
+        let p = policy("https://dash.example.com");
+        assert_eq!(
+            p.headers(Some("https://dash.example.com")),
+            vec![
+                ("Access-Control-Allow-Origin", "https://dash.example.com".to_string()),
+                ("Vary", "Origin".to_string()),
+            ]
+        );
+        // refused origins still vary, so a cached refusal isn't served to the dashboard
+        assert_eq!(p.headers(Some("https://other.example")), vec![("Vary", "Origin".to_string())]);
+    }
+    
+    #[test]
+    fn test_parse_rejects_bad_entries() {
+        let (p, rejected) = CorsPolicy::parse("https://ok.example, ftp://x.example, https://a*.example, example.com, https://x.example/path,");
+        assert_eq!(p.patterns, vec![OriginPattern::Exact("https://ok.example".to_string())]);
+        assert_eq!(rejected.len(), 4);
+        
+        // trailing slashes and case are normalised
+        assert_eq!(OriginPattern::parse("HTTPS://Dash.Example.com/"), Some(OriginPattern::Exact("https://dash.example.com".to_string())));
+    }
+}
@@ -0,0 +1,187 @@
+//! ==============================================================================
+//! counter_cache.rs - rate counters cached in the isolate
+//! ==============================================================================
+//!
+//! purpose:
+//!     the kv backend reads a client's counter on every request and writes
+//!     it on every allowed one. a hot client - usually one already over its
+//!     limit - costs a kv read per request. with LIMITER_BACKEND =
+//!     "kv_cached" the isolate keeps each counter for a moment, answers
+//!     from memory and writes its hits back in batches.
+//!
+//! relationships:
+//!     - used by: rate-limiter (Backend::KvCached), which owns the kv format
+//!
+//! sync rules:
+//!     a counter read from kv is trusted for MAX_AGE_MS. while it is,
+//!     blocked requests cost nothing and allowed ones are counted locally.
+//!     the isolate syncs - reads kv, adds its pending hits, writes the sum -
+//!     once FLUSH_EVERY hits are pending or the counter is too old.
+//!
+//! trade-off:
+//!     isolates don't see each other's pending hits, so every isolate
+//!     serving a client may let up to FLUSH_EVERY - 1 extra requests through
+//!     per window (and two syncing at once can lose a batch, as with the
+//!     plain kv backend). a client over its limit stays blocked without
+//!     any kv traffic until the counter goes stale.
+//!
+//! ==============================================================================
+
+/// how long a counter read from kv is trusted
+pub const MAX_AGE_MS: u64 = 1_000;
+/// pending hits that force a sync
+pub const FLUSH_EVERY: u32 = 10;
+/// counters an isolate keeps before making room
+pub const MAX_ENTRIES: usize = 10_000;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// one client's counter as this isolate knows it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LocalCounter {
+    /// unix seconds, as in the kv record
+    pub window_start: u64,
+    /// hits in the window - kv's count at the last sync plus pending
+    pub count: u32,
+    /// hits counted here but not written to kv yet
+    pub pending: u32,
+    /// unix ms of the last sync
+    pub synced_at_ms: u64,
+}
+
+impl LocalCounter {
+    /// the counter after a sync: `stored` is kv's (count, window_start) if it
+    /// holds a record, `local` this isolate's previous counter. pending hits
+    /// still in a current window are added to kv's count
+    pub fn synced(stored: Option<(u32, u64)>, local: Option<&LocalCounter>, now_ms: u64, window_seconds: u64) -> Self {
+        let now = now_ms / 1000;
+        let current = |start: u64| now.saturating_sub(start) < window_seconds;
+        let (count, window_start) = match stored {
+            Some((count, start)) if current(start) => (count, start),
+            _ => (0, now),
+        };
+        let pending = local.filter(|l| current(l.window_start)).map_or(0, |l| l.pending);
+        Self { window_start, count: count.saturating_add(pending), pending: 0, synced_at_ms: now_ms }
+    }
+    
+    /// whether the counter can still be answered from memory
+    pub fn fresh(&self, now_ms: u64, window_seconds: u64) -> bool {
+        now_ms.saturating_sub(self.synced_at_ms) < MAX_AGE_MS && (now_ms / 1000).saturating_sub(self.window_start) < window_seconds
+    }
+    
+    /// count a hit if there's room; whether it was allowed
+    pub fn hit(&mut self, limit: u32) -> bool {
+        if self.count >= limit {
+            return false;
+        }
+        self.count += 1;
+        self.pending += 1;
+        true
+    }
+    
+    /// whether the pending hits should go to kv now
+    pub fn needs_flush(&self, now_ms: u64) -> bool {
+        self.pending >= FLUSH_EVERY || (self.pending > 0 && now_ms.saturating_sub(self.synced_at_ms) >= MAX_AGE_MS)
+    }
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+#[cfg(feature = "worker")]
+thread_local! {
+    static COUNTERS: std::cell::RefCell<std::collections::HashMap<String, LocalCounter>> = std::cell::RefCell::new(std::collections::HashMap::new());
+}
+
+/// this isolate's counter for `client_id`, fresh or not
+#[cfg(feature = "worker")]
+pub fn get(client_id: &str) -> Option<LocalCounter> {
+    COUNTERS.with(|c| c.borrow().get(client_id).copied())
+}
+
+/// keep `counter` for `client_id`. a full cache first drops counters with
+/// nothing pending, then - if that wasn't enough - everything
+#[cfg(feature = "worker")]
+pub fn put(client_id: &str, counter: LocalCounter) {
+    COUNTERS.with(|c| {
+        let mut counters = c.borrow_mut();
+        if counters.len() >= MAX_ENTRIES && !counters.contains_key(client_id) {
+            counters.retain(|_, l| l.pending > 0);
+            if counters.len() >= MAX_ENTRIES {
+                counters.clear();
+            }
+        }
+        counters.insert(client_id.to_string(), counter);
+    });
+}
+
+/// forget `client_id` here (admin reset) - other isolates keep theirs until stale
+#[cfg(feature = "worker")]
+pub fn forget(client_id: &str) {
+    COUNTERS.with(|c| c.borrow_mut().remove(client_id));
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const NOW: u64 = 1_000_000;
+    
+    #[test]
+    fn test_counts_locally_until_flush() {
+        let mut counter = LocalCounter::synced(Some((2, 990)), None, NOW, 60);
+        assert_eq!((counter.count, counter.window_start), (2, 990));
+        assert!(counter.fresh(NOW + 999, 60));
+        assert!(!counter.fresh(NOW + MAX_AGE_MS, 60));
+        
+        for _ in 0..FLUSH_EVERY - 1 {
+            assert!(counter.hit(100));
+            assert!(!counter.needs_flush(NOW));
+        }
+        assert!(counter.hit(100));
+        assert!(counter.needs_flush(NOW));
+        assert_eq!((counter.count, counter.pending), (2 + FLUSH_EVERY, FLUSH_EVERY));
+        
+        // a few pending hits go out once the counter is stale
+        let mut counter = LocalCounter::synced(None, None, NOW, 60);
+        counter.hit(100);
+        assert!(!counter.needs_flush(NOW + 10));
+        assert!(counter.needs_flush(NOW + MAX_AGE_MS));
+    }
+    
+    #[test]
+    fn test_blocks_from_memory() {
+        let mut counter = LocalCounter::synced(Some((3, 990)), None, NOW, 60);
+        assert!(!counter.hit(3));
+        assert_eq!(counter.pending, 0);
+        // nothing to write, so a blocked client never forces a sync
+        assert!(!counter.needs_flush(NOW + 10 * MAX_AGE_MS));
+    }
+    
+    #[test]
+    fn test_sync_merges_pending_hits() {
+        let mut local = LocalCounter::synced(Some((1, 990)), None, NOW, 60);
+        local.hit(100);
+        local.hit(100);
+        // another isolate added 4 meanwhile
+        let synced = LocalCounter::synced(Some((5, 990)), Some(&local), NOW + 500, 60);
+        assert_eq!((synced.count, synced.pending, synced.window_start), (7, 0, 990));
+        
+        // kv's window is over: a new one starts, still carrying hits made in a current window
+        let mut local = LocalCounter::synced(None, None, 1_050_000, 60);
+        local.hit(100);
+        let synced = LocalCounter::synced(Some((9, 980)), Some(&local), 1_050_500, 60);
+        assert_eq!((synced.count, synced.window_start), (1, 1_050));
+        
+        // hits from a window that has ended are dropped
+        let synced = LocalCounter::synced(None, Some(&local), 1_200_000, 60);
+        assert_eq!(synced.count, 0);
+    }
+}
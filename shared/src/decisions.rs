@@ -0,0 +1,298 @@
+//! ==============================================================================
+//! decisions.rs - rate limiter decisions in workers analytics engine
+//! ==============================================================================
+//!
+//! purpose:
+//!     /metrics shows how many 429s happened, not who caused them or when.
+//!     every allowed or blocked request becomes an analytics engine data
+//!     point, and /api/analytics/summary queries them back, so an attack
+//!     shows up as a shape over hours - which clients, which colos.
+//!
+//! relationships:
+//!     - used by: rate-limiter (/api/protected, GET /api/analytics/summary)
+//!     - uses: mode (whether a refusal was returned or held back)
+//!     - binding: analytics engine dataset DECISIONS
+//!     - reads: the analytics engine sql api, with var CF_ACCOUNT_ID and
+//!       secret ANALYTICS_API_TOKEN (account analytics: read)
+//!
+//! data point layout (dataset "rate_limiter_decisions"):
+//!     index1   client hash - first 16 hex of sha-256 of the client id, so
+//!              ips and api keys never leave the worker
+//!     blob1    outcome (Outcome::as_str)
+//!     blob2    route - the metrics label, so proxied paths share "other"
+//!     blob3    colo
+//!     blob4    mode (Mode::as_str) - "shadow" when a refusal wasn't returned;
+//!              points written before modes existed have none and count as
+//!              "enforce"
+//!     double1  1 - queries sum _sample_interval instead, which stays right
+//!              when analytics engine samples
+//!
+//! ==============================================================================
+
+use serde::{Deserialize, Serialize};
+
+use crate::mode::Mode;
+
+/// analytics engine binding in the rate limiter's wrangler.toml
+pub const DATASET_BINDING: &str = "DECISIONS";
+/// the dataset the binding writes to - what the sql names
+pub const DATASET: &str = "rate_limiter_decisions";
+/// the summary looks back at most a week
+pub const MAX_HOURS: u32 = 168;
+/// clients listed in a summary
+pub const TOP_CLIENTS: u32 = 10;
+/// hex chars of the client hash
+pub const CLIENT_HASH_LEN: usize = 16;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// what happened to a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Outcome {
+    /// counted and within the limit
+    Allowed,
+    /// served without counting - allowlisted ip or a bypass token
+    Bypassed,
+    /// 429, window full
+    RateLimited,
+    /// 429, too many in flight
+    ConcurrencyLimited,
+    /// 429, the day's or month's quota is spent
+    QuotaExceeded,
+    /// 403 or 401 - denied ip, revoked or unknown key
+    Denied,
+}
+
+impl Outcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Allowed => "allowed",
+            Self::Bypassed => "bypassed",
+            Self::RateLimited => "rate_limited",
+            Self::ConcurrencyLimited => "concurrency_limited",
+            Self::QuotaExceeded => "quota_exceeded",
+            Self::Denied => "denied",
+        }
+    }
+    
+    /// a 429 - what shadow mode holds back
+    pub fn limited(&self) -> bool {
+        matches!(self, Self::RateLimited | Self::ConcurrencyLimited | Self::QuotaExceeded)
+    }
+}
+
+/// one hour of one outcome in one mode
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bucket {
+    /// "2026-01-01 13:00:00", utc
+    pub hour: String,
+    pub outcome: String,
+    /// "shadow" for refusals that were only recorded
+    pub mode: String,
+    pub requests: u64,
+}
+
+/// a client that was turned away often - or, in shadow mode, would have been
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlockedClient {
+    pub client: String,
+    pub colo: String,
+    pub mode: String,
+    pub blocked: u64,
+}
+
+/// GET /api/analytics/summary
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Summary {
+    pub hours: u32,
+    pub timeline: Vec<Bucket>,
+    pub top_blocked_clients: Vec<BlockedClient>,
+}
+
+/// `?hours=` clamped to 1..=MAX_HOURS, 24 when missing or not a number
+pub fn hours(param: Option<&str>) -> u32 {
+    param.and_then(|h| h.parse::<u32>().ok()).unwrap_or(24).clamp(1, MAX_HOURS)
+}
+
+/// requests per hour, outcome and mode over the last `hours`
+pub fn timeline_sql(hours: u32) -> String {
+    format!(
+        "SELECT toStartOfInterval(timestamp, INTERVAL '1' HOUR) AS hour, blob1 AS outcome, blob4 AS mode, SUM(_sample_interval) AS requests \
+         FROM {} WHERE timestamp > NOW() - INTERVAL '{}' HOUR \
+         GROUP BY hour, outcome, mode ORDER BY hour, outcome, mode",
+        DATASET,
+        hours.clamp(1, MAX_HOURS),
+    )
+}
+
+/// the clients with the most blocked requests over the last `hours`
+pub fn top_blocked_sql(hours: u32) -> String {
+    format!(
+        "SELECT index1 AS client, blob3 AS colo, blob4 AS mode, SUM(_sample_interval) AS blocked \
+         FROM {} WHERE timestamp > NOW() - INTERVAL '{}' HOUR AND blob1 IN ('rate_limited', 'concurrency_limited', 'quota_exceeded', 'denied') \
+         GROUP BY client, colo, mode ORDER BY blocked DESC LIMIT {}",
+        DATASET,
+        hours.clamp(1, MAX_HOURS),
+        TOP_CLIENTS,
+    )
+}
+
+/// the "data" rows of a sql api response
+pub fn rows(json: &str) -> Result<Vec<serde_json::Value>, String> {
+    #[derive(Deserialize)]
+    struct SqlResponse {
+        data: Vec<serde_json::Value>,
+    }
+    serde_json::from_str::<SqlResponse>(json).map(|r| r.data).map_err(|e| e.to_string())
+}
+
+/// the timeline from its query's rows
+pub fn timeline(rows: &[serde_json::Value]) -> Vec<Bucket> {
+    rows.iter()
+        .map(|r| Bucket { hour: text(&r["hour"]), outcome: text(&r["outcome"]), mode: mode(&r["mode"]), requests: count(&r["requests"]) })
+        .collect()
+}
+
+/// the top blocked clients from their query's rows
+pub fn top_blocked(rows: &[serde_json::Value]) -> Vec<BlockedClient> {
+    rows.iter()
+        .map(|r| BlockedClient { client: text(&r["client"]), colo: text(&r["colo"]), mode: mode(&r["mode"]), blocked: count(&r["blocked"]) })
+        .collect()
+}
+
+fn text(value: &serde_json::Value) -> String {
+    value.as_str().unwrap_or_default().to_string()
+}
+
+/// blob4, with points from before modes counted as enforced
+fn mode(value: &serde_json::Value) -> String {
+    match value.as_str() {
+        Some(mode) if !mode.is_empty() => mode.to_string(),
+        _ => Mode::Enforce.as_str().to_string(),
+    }
+}
+
+/// sums come back as json numbers or, for 64-bit ones, strings
+fn count(value: &serde_json::Value) -> u64 {
+    match value {
+        serde_json::Value::String(s) => s.parse::<f64>().map(|n| n as u64).unwrap_or(0),
+        v => v.as_f64().map(|n| n as u64).unwrap_or(0),
+    }
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// write one decision - skipped when the dataset isn't bound (wrangler dev)
+#[cfg(feature = "worker")]
+pub async fn record(env: &worker::Env, client_id: &str, route: &str, colo: &str, outcome: Outcome, mode: Mode) {
+    let Ok(dataset) = env.analytics_engine(DATASET_BINDING) else {
+        return;
+    };
+    let hash = match crate::auth_guard::sha256_hex(client_id.as_bytes()).await {
+        Ok(hash) => hash[..CLIENT_HASH_LEN].to_string(),
+        Err(e) => {
+            worker::console_warn!("hashing client failed: {}", e);
+            return;
+        }
+    };
+    let written = worker::AnalyticsEngineDataPointBuilder::new()
+        .indexes([hash.as_str()].as_slice())
+        .add_blob(outcome.as_str())
+        .add_blob(route)
+        .add_blob(colo)
+        .add_blob(mode.as_str())
+        .add_double(1.0)
+        .write_to(&dataset);
+    if let Err(e) = written {
+        worker::console_warn!("analytics engine write failed: {}", e);
+    }
+}
+
+/// run `sql` against the sql api and return its rows
+#[cfg(feature = "worker")]
+async fn query(env: &worker::Env, sql: String) -> worker::Result<Vec<serde_json::Value>> {
+    use worker::{Fetch, Headers, Method, Request, RequestInit};
+    
+    let account = env.var("CF_ACCOUNT_ID")?.to_string();
+    let token = env.secret("ANALYTICS_API_TOKEN")?.to_string();
+    let url = format!("https://api.cloudflare.com/client/v4/accounts/{}/analytics_engine/sql", account);
+    let headers = Headers::new();
+    headers.set("Authorization", &format!("Bearer {}", token))?;
+    let init = RequestInit { method: Method::Post, headers, body: Some(sql.into()), ..RequestInit::default() };
+    
+    let mut resp = Fetch::Request(Request::new_with_init(&url, &init)?).send().await?;
+    let body = resp.text().await?;
+    if resp.status_code() != 200 {
+        return Err(worker::Error::from(format!("sql api status {}: {}", resp.status_code(), body)));
+    }
+    rows(&body).map_err(worker::Error::from)
+}
+
+/// the last `hours` of decisions
+#[cfg(feature = "worker")]
+pub async fn summary(env: &worker::Env, hours: u32) -> worker::Result<Summary> {
+    let timeline_rows = query(env, timeline_sql(hours)).await?;
+    let blocked_rows = query(env, top_blocked_sql(hours)).await?;
+    Ok(Summary { hours, timeline: timeline(&timeline_rows), top_blocked_clients: top_blocked(&blocked_rows) })
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_hours_param() {
+        assert_eq!(hours(None), 24);
+        assert_eq!(hours(Some("6")), 6);
+        assert_eq!(hours(Some("0")), 1);
+        assert_eq!(hours(Some("9999")), MAX_HOURS);
+        // only ever a number reaches the sql
+        assert_eq!(hours(Some("1; DROP")), 24);
+    }
+    
+    #[test]
+    fn test_sql() {
+        let sql = timeline_sql(6);
+        assert!(sql.contains("FROM rate_limiter_decisions"));
+        assert!(sql.contains("INTERVAL '6' HOUR"));
+        assert!(sql.contains("GROUP BY hour, outcome, mode"));
+        let sql = top_blocked_sql(1000);
+        assert!(sql.contains("INTERVAL '168' HOUR"));
+        assert!(sql.contains("LIMIT 10"));
+        assert!(sql.contains("'quota_exceeded'"));
+    }
+    
+    #[test]
+    fn test_parse_rows() {
+        let body = r#"{"meta": [], "data": [
+            {"hour": "2026-01-01 13:00:00", "outcome": "rate_limited", "mode": "shadow", "requests": "420"},
+            {"hour": "2026-01-01 13:00:00", "outcome": "allowed", "mode": "", "requests": 37}
+        ], "rows": 2}"#;
+        let buckets = timeline(&rows(body).unwrap());
+        assert_eq!(
+            buckets[0],
+            Bucket { hour: "2026-01-01 13:00:00".to_string(), outcome: "rate_limited".to_string(), mode: "shadow".to_string(), requests: 420 }
+        );
+        assert_eq!(buckets[1].requests, 37);
+        // written before modes existed
+        assert_eq!(buckets[1].mode, "enforce");
+        
+        let clients = top_blocked(&rows(r#"{"data": [{"client": "9f86d081884c7d65", "colo": "LAX", "mode": "enforce", "blocked": 12.0}]}"#).unwrap());
+        assert_eq!(
+            clients,
+            vec![BlockedClient { client: "9f86d081884c7d65".to_string(), colo: "LAX".to_string(), mode: "enforce".to_string(), blocked: 12 }]
+        );
+        
+        assert!(rows(r#"{"errors": ["bad"]}"#).is_err());
+        assert_eq!(serde_json::to_value(Outcome::ConcurrencyLimited).unwrap(), Outcome::ConcurrencyLimited.as_str());
+    }
+}
@@ -0,0 +1,101 @@
+//! ==============================================================================
+//! etag.rs - etags and conditional GETs for read endpoints
+//! ==============================================================================
+//!
+//! purpose:
+//!     the dashboard polls stats and status endpoints whose answers rarely
+//!     change between polls. responses carry an etag derived from the body;
+//!     once the browser's cached copy goes stale it revalidates with
+//!     If-None-Match and gets an empty 304 when nothing moved.
+//!
+//! relationships:
+//!     - used by: url-shortener (/stats/:code), rate-limiter (/api/status),
+//!       capability-demo (/api/capabilities)
+//!
+//! design rationale:
+//!     the tag is a hash of the serialized body, so handlers don't need to
+//!     track versions - they build the response as before and the helper
+//!     decides between 200 and 304. comparison is weak (W/ prefixes are
+//!     ignored) since cloudflare weakens strong etags when it compresses.
+//!
+//! ==============================================================================
+
+// ==============================================================================
+// etags
+// ==============================================================================
+
+/// strong etag for a response body: a quoted 64-bit fnv-1a hash
+pub fn etag(body: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in body {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("\"{:016x}\"", hash)
+}
+
+/// whether an If-None-Match header matches `etag` - the client's copy is current
+pub fn not_modified(if_none_match: Option<&str>, etag: &str) -> bool {
+    let Some(header) = if_none_match else {
+        return false;
+    };
+    let etag = weak(etag);
+    header
+        .split(',')
+        .map(str::trim)
+        .any(|candidate| candidate == "*" || weak(candidate) == etag)
+}
+
+fn weak(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// a json response with an etag, or an empty 304 if the client already has it
+#[cfg(feature = "worker")]
+pub fn json_response(req: &worker::Request, json: String, cache_control: &str) -> worker::Result<worker::Response> {
+    let tag = etag(json.as_bytes());
+    let headers = worker::Headers::new();
+    headers.set("ETag", &tag)?;
+    headers.set("Cache-Control", cache_control)?;
+    
+    if not_modified(req.headers().get("If-None-Match")?.as_deref(), &tag) {
+        return Ok(worker::Response::empty()?.with_status(304).with_headers(headers));
+    }
+    headers.set("Content-Type", "application/json")?;
+    Ok(worker::Response::ok(json)?.with_headers(headers))
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_etag_is_stable_and_quoted() {
+        let a = etag(br#"{"clicks":42}"#);
+        assert_eq!(a, etag(br#"{"clicks":42}"#));
+        assert_ne!(a, etag(br#"{"clicks":43}"#));
+        assert!(a.starts_with('"') && a.ends_with('"'));
+        assert_eq!(a.len(), 18);
+    }
+    
+    #[test]
+    fn test_not_modified() {
+        let tag = etag(b"body");
+        assert!(not_modified(Some(&tag), &tag));
+        // weakened by the edge, or in a list
+        assert!(not_modified(Some(&format!("W/{}", tag)), &tag));
+        assert!(not_modified(Some(&format!("\"other\", {}", tag)), &tag));
+        assert!(not_modified(Some("*"), &tag));
+        
+        assert!(!not_modified(Some("\"other\""), &tag));
+        assert!(!not_modified(None, &tag));
+    }
+}
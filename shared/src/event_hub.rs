@@ -0,0 +1,317 @@
+//! ==============================================================================
+//! event_hub.rs - live event feed for the dashboard
+//! ==============================================================================
+//!
+//! purpose:
+//!     one durable object every worker publishes notable events to - a new
+//!     short link, a rate-limit block, a 5xx - and that dashboards hold a
+//!     websocket open to. the ui gets a single live feed across all demos
+//!     instead of polling each worker.
+//!
+//! relationships:
+//!     - used by: url-shortener (links, blocks, faults), rate-limiter
+//!       (blocks, faults), capability-demo and capture-archive (faults),
+//!       gateway (blocks, faults)
+//!     - binding: EVENTS -> class EventHub, exported by workers/event-hub;
+//!       the other workers bind it with script_name = "event-hub"
+//!     - subscribe: GET /events on the event-hub worker or the gateway
+//!     - read by: dashboard live feed panel
+//!
+//! protocol (internal, worker -> object):
+//!     POST /  body: [LiveEvent] json, stored in the backlog and broadcast
+//!     GET  /  websocket upgrade; the backlog is sent first, then every new
+//!             event as one json text frame. "ping" is answered with "pong"
+//!
+//! design:
+//!     the object accepts sockets through the hibernation api, so it can be
+//!     evicted between events without dropping subscribers. workers buffer
+//!     events per isolate like analytics and send them with ctx.wait_until;
+//!     the feed is best effort, so a failed send is dropped, not retried.
+//!
+//! ==============================================================================
+
+use std::cell::RefCell;
+
+use serde::{Deserialize, Serialize};
+
+/// binding name every worker uses for the event hub
+pub const EVENTS_BINDING: &str = "EVENTS";
+/// events a new subscriber is sent before live ones
+pub const BACKLOG_SIZE: usize = 25;
+/// events an isolate holds between sends; older ones are dropped
+const MAX_PENDING: usize = 100;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LiveKind {
+    /// a short link was created
+    Link,
+    /// a client was rate limited
+    Block,
+    /// a request ended in a 5xx
+    Fault,
+}
+
+/// one feed entry, as sent to the object and on to subscribers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LiveEvent {
+    pub kind: LiveKind,
+    pub worker: String,
+    /// the short code (link), client label (block) or route (fault)
+    pub subject: String,
+    pub status: u16,
+    pub request_id: String,
+    /// unix ms
+    pub at: u64,
+}
+
+impl LiveEvent {
+    pub fn link(worker: &str, code: &str, request_id: &str, at: u64) -> Self {
+        Self::new(LiveKind::Link, worker, code, 200, request_id, at)
+    }
+    
+    /// api keys in `client_id` are cut to a short prefix, as in analytics
+    pub fn block(worker: &str, client_id: &str, request_id: &str, at: u64) -> Self {
+        let label = crate::analytics::client_label(client_id);
+        Self::new(LiveKind::Block, worker, &label, 429, request_id, at)
+    }
+    
+    pub fn fault(worker: &str, route: &str, status: u16, request_id: &str, at: u64) -> Self {
+        Self::new(LiveKind::Fault, worker, route, status, request_id, at)
+    }
+    
+    fn new(kind: LiveKind, worker: &str, subject: &str, status: u16, request_id: &str, at: u64) -> Self {
+        Self {
+            kind,
+            worker: worker.to_string(),
+            subject: subject.to_string(),
+            status,
+            request_id: request_id.to_string(),
+            at,
+        }
+    }
+}
+
+/// append `events` to the stored backlog, keeping the newest BACKLOG_SIZE
+pub fn extend_backlog(backlog: &mut Vec<LiveEvent>, events: &[LiveEvent]) {
+    backlog.extend_from_slice(events);
+    if backlog.len() > BACKLOG_SIZE {
+        let excess = backlog.len() - BACKLOG_SIZE;
+        backlog.drain(..excess);
+    }
+}
+
+// ==============================================================================
+// per-isolate buffer
+// ==============================================================================
+
+thread_local! {
+    static PENDING: RefCell<Vec<LiveEvent>> = const { RefCell::new(Vec::new()) };
+}
+
+/// buffer an event for the next publish
+pub fn record(event: LiveEvent) {
+    PENDING.with(|p| {
+        let mut pending = p.borrow_mut();
+        pending.push(event);
+        if pending.len() > MAX_PENDING {
+            let excess = pending.len() - MAX_PENDING;
+            pending.drain(..excess);
+        }
+    });
+}
+
+/// take everything buffered so far, leaving the buffer empty
+pub fn take_pending() -> Vec<LiveEvent> {
+    PENDING.with(|p| std::mem::take(&mut *p.borrow_mut()))
+}
+
+// ==============================================================================
+// durable object
+// ==============================================================================
+
+/// storage key of the backlog inside the object
+#[cfg(feature = "worker")]
+const BACKLOG_KEY: &str = "backlog";
+/// the one hub every worker talks to
+#[cfg(feature = "worker")]
+const HUB_NAME: &str = "hub";
+/// the hub as a GET /health/deps dependency
+#[cfg(feature = "worker")]
+pub const HEALTH_DEPENDENCY: crate::health::Dependency = crate::health::Dependency::DurableObject(EVENTS_BINDING, HUB_NAME);
+
+#[cfg(feature = "worker")]
+#[worker::durable_object]
+pub struct EventHub {
+    state: worker::State,
+}
+
+#[cfg(feature = "worker")]
+impl worker::DurableObject for EventHub {
+    fn new(state: worker::State, _env: worker::Env) -> Self {
+        Self { state }
+    }
+    
+    async fn fetch(&self, mut req: worker::Request) -> worker::Result<worker::Response> {
+        use worker::{Method, Response, WebSocketPair};
+        
+        let storage = self.state.storage();
+        let mut backlog: Vec<LiveEvent> = storage.get(BACKLOG_KEY).await?.unwrap_or_default();
+        
+        if req.method() == Method::Post {
+            let events: Vec<LiveEvent> = req.json().await?;
+            extend_backlog(&mut backlog, &events);
+            storage.put(BACKLOG_KEY, &backlog).await?;
+            
+            let frames = events.iter().map(serde_json::to_string).collect::<Result<Vec<_>, _>>()?;
+            for socket in self.state.get_websockets() {
+                for frame in &frames {
+                    // a socket that's closing is cleaned up by websocket_close
+                    let _ = socket.send_with_str(frame);
+                }
+            }
+            return Response::empty();
+        }
+        
+        let upgrade = req.headers().get("Upgrade")?;
+        if !upgrade.is_some_and(|u| u.eq_ignore_ascii_case("websocket")) {
+            return Response::error("expected a websocket upgrade", 426);
+        }
+        
+        let pair = WebSocketPair::new()?;
+        self.state.accept_web_socket(&pair.server);
+        for event in &backlog {
+            pair.server.send_with_str(serde_json::to_string(event)?)?;
+        }
+        Response::from_websocket(pair.client)
+    }
+    
+    async fn websocket_message(
+        &self,
+        ws: worker::WebSocket,
+        message: worker::WebSocketIncomingMessage,
+    ) -> worker::Result<()> {
+        // subscribers only listen - a ping keeps idle proxies from closing the socket
+        if let worker::WebSocketIncomingMessage::String(text) = message {
+            if text == "ping" {
+                ws.send_with_str("pong")?;
+            }
+        }
+        Ok(())
+    }
+    
+    async fn websocket_close(
+        &self,
+        ws: worker::WebSocket,
+        code: usize,
+        reason: String,
+        _was_clean: bool,
+    ) -> worker::Result<()> {
+        ws.close(Some(code as u16), Some(reason))
+    }
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// publish this isolate's buffered events in the background. without an
+/// EVENTS binding (plain `wrangler dev`) they're dropped
+#[cfg(feature = "worker")]
+pub fn flush(env: &worker::Env, ctx: &worker::Context) {
+    let events = take_pending();
+    if events.is_empty() {
+        return;
+    }
+    let stub = match stub(env) {
+        Ok(stub) => stub,
+        Err(_) => return,
+    };
+    
+    ctx.wait_until(async move {
+        let result = async {
+            let init = worker::RequestInit {
+                method: worker::Method::Post,
+                body: Some(serde_json::to_string(&events)?.into()),
+                ..worker::RequestInit::default()
+            };
+            let req = worker::Request::new_with_init("https://events.internal/", &init)?;
+            stub.fetch_with_request(req).await
+        }
+        .await;
+        if let Err(e) = result {
+            worker::console_warn!("live event publish failed, dropping {} events: {}", events.len(), e);
+        }
+    });
+}
+
+/// GET /events - hand a subscriber's websocket upgrade to the hub.
+/// browsers can't send custom headers on a websocket, so instead of cors
+/// the origin is checked against the worker's cors policy
+#[cfg(feature = "worker")]
+pub async fn subscribe(
+    req: worker::Request,
+    env: &worker::Env,
+    request_id: &str,
+) -> worker::Result<worker::Response> {
+    use crate::problem::Problem;
+    
+    let upgrade = req.headers().get("Upgrade")?;
+    if !upgrade.is_some_and(|u| u.eq_ignore_ascii_case("websocket")) {
+        return Problem::new(426, "expected a websocket upgrade").with_request_id(request_id).into_response();
+    }
+    let origin = req.headers().get("Origin")?;
+    if origin.is_some() && crate::cors::policy(env).await.allow_origin(origin.as_deref()).is_none() {
+        return Problem::new(403, "origin not allowed").with_request_id(request_id).into_response();
+    }
+    stub(env)?.fetch_with_request(req).await
+}
+
+#[cfg(feature = "worker")]
+fn stub(env: &worker::Env) -> worker::Result<worker::Stub> {
+    env.durable_object(EVENTS_BINDING)?
+        .id_from_name(HUB_NAME)?
+        .get_stub()
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_live_event_shape() {
+        let event = LiveEvent::block("rate-limiter", "key:sk_live_0123456789", "18bcfe56800-deadbeef", 1_700_000_000_000);
+        assert_eq!(
+            serde_json::to_value(&event).unwrap(),
+            serde_json::json!({
+                "kind": "block",
+                "worker": "rate-limiter",
+                "subject": "key:sk_liv...",
+                "status": 429,
+                "request_id": "18bcfe56800-deadbeef",
+                "at": 1_700_000_000_000u64
+            })
+        );
+    }
+    
+    #[test]
+    fn test_backlog_keeps_newest() {
+        let mut backlog = Vec::new();
+        let events: Vec<LiveEvent> = (0..BACKLOG_SIZE + 3)
+            .map(|i| LiveEvent::link("url-shortener", "abc123", &i.to_string(), i as u64))
+            .collect();
+        extend_backlog(&mut backlog, &events[..2]);
+        extend_backlog(&mut backlog, &events[2..]);
+        assert_eq!(backlog.len(), BACKLOG_SIZE);
+        assert_eq!(backlog[0].request_id, "3");
+        assert_eq!(backlog.last().unwrap().request_id, (BACKLOG_SIZE + 2).to_string());
+    }
+}
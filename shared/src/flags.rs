@@ -0,0 +1,229 @@
+//! ==============================================================================
+//! flags.rs - feature flags read from kv
+//! ==============================================================================
+//!
+//! purpose:
+//!     lets the newer, riskier paths - the analytics queue, the live event
+//!     hub, durable object metrics, d1 link storage - be switched off or
+//!     rolled out to a share of clients without a redeploy.
+//!
+//! relationships:
+//!     - used by: url-shortener, rate-limiter, capability-demo,
+//!       capture-archive, gateway (fetch evaluates the flags once per request)
+//!     - uses: workers kv namespace AUTH, key "flags:config" - shared by all
+//!       workers, re-read every 30 seconds
+//!
+//! document (json, every field optional):
+//!     {
+//!       "analytics-queue": {"enabled": true, "rollout": 25},
+//!       "d1-links": {"enabled": false}
+//!     }
+//!     a flag missing from the document (or the whole document missing)
+//!     keeps its default - see DEFAULTS. rollout is the percentage of
+//!     clients that get the feature, 100 when left out.
+//!
+//! design:
+//!     a client's bucket is a hash of the flag name and client id, so the
+//!     same client always lands on the same side of a rollout and each flag
+//!     picks a different slice of clients. switches that can't be split per
+//!     client - where links are stored - only look at "enabled".
+//!
+//! ==============================================================================
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+/// kv key of the flags document in the AUTH namespace
+pub const FLAGS_KV_KEY: &str = "flags:config";
+
+/// clicks, denials and faults go to the analytics queue
+pub const ANALYTICS_QUEUE: &str = "analytics-queue";
+/// links, blocks and faults go to the live event hub
+pub const LIVE_EVENTS: &str = "live-events";
+/// isolates merge their counters into the metrics durable object
+pub const DURABLE_METRICS: &str = "durable-metrics";
+/// the url shortener uses d1 when LINKS_DB is bound (whole-worker switch)
+pub const D1_LINKS: &str = "d1-links";
+/// the rate limiter sends the ietf RateLimit-* headers next to X-RateLimit-*
+pub const RATELIMIT_HEADERS: &str = "ratelimit-headers";
+
+/// (flag, on by default) - everything already shipped stays on until the
+/// document says otherwise
+pub const DEFAULTS: &[(&str, bool)] = &[
+    (ANALYTICS_QUEUE, true),
+    (LIVE_EVENTS, true),
+    (DURABLE_METRICS, true),
+    (D1_LINKS, true),
+    (RATELIMIT_HEADERS, false),
+];
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// one flag's settings
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Flag {
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// percent of clients, 0-100
+    #[serde(default = "default_rollout")]
+    pub rollout: u8,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+fn default_rollout() -> u8 {
+    100
+}
+
+/// the flags document
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Flags {
+    flags: BTreeMap<String, Flag>,
+}
+
+impl Flags {
+    pub fn parse(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+    
+    /// the document's settings for `name`, or its default
+    pub fn flag(&self, name: &str) -> Flag {
+        self.flags.get(name).copied().unwrap_or_else(|| {
+            let enabled = DEFAULTS.iter().find(|(n, _)| *n == name).is_some_and(|(_, on)| *on);
+            Flag { enabled, rollout: 100 }
+        })
+    }
+    
+    /// whether the flag is switched on at all, ignoring the rollout
+    pub fn enabled(&self, name: &str) -> bool {
+        self.flag(name).enabled
+    }
+    
+    /// whether `client_id` gets the feature
+    pub fn on_for(&self, name: &str, client_id: &str) -> bool {
+        let flag = self.flag(name);
+        flag.enabled && bucket(name, client_id) < flag.rollout.min(100)
+    }
+    
+    /// these flags evaluated for one client
+    pub fn for_client(&self, client_id: &str) -> ClientFlags {
+        ClientFlags { flags: self.clone(), client_id: client_id.to_string() }
+    }
+}
+
+/// flags bound to the client of one request
+#[derive(Debug, Clone, Default)]
+pub struct ClientFlags {
+    flags: Flags,
+    client_id: String,
+}
+
+impl ClientFlags {
+    pub fn on(&self, name: &str) -> bool {
+        self.flags.on_for(name, &self.client_id)
+    }
+    
+    /// the whole-worker switch, ignoring the rollout
+    pub fn enabled(&self, name: &str) -> bool {
+        self.flags.enabled(name)
+    }
+}
+
+/// a client's rollout bucket for a flag, 0-99 (fnv-1a)
+pub fn bucket(name: &str, client_id: &str) -> u8 {
+    let mut hash: u32 = 0x811c_9dc5;
+    for byte in name.bytes().chain([b':']).chain(client_id.bytes()) {
+        hash ^= byte as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    (hash % 100) as u8
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// how long an isolate keeps the document before reading kv again
+#[cfg(feature = "worker")]
+const FLAGS_TTL_MS: f64 = 30_000.0;
+
+#[cfg(feature = "worker")]
+thread_local! {
+    // (read at ms, flags)
+    static FLAGS: std::cell::RefCell<Option<(f64, Flags)>> = const { std::cell::RefCell::new(None) };
+}
+
+/// the current flags - cached per isolate, defaults when kv has no document
+/// or it doesn't parse
+#[cfg(feature = "worker")]
+pub async fn load(env: &worker::Env) -> Flags {
+    let now = worker::js_sys::Date::now();
+    let cached = FLAGS.with(|f| f.borrow().as_ref().filter(|(at, _)| now - at < FLAGS_TTL_MS).map(|(_, f)| f.clone()));
+    if let Some(flags) = cached {
+        return flags;
+    }
+    
+    let mut document = None;
+    if let Ok(kv) = env.kv(crate::auth_guard::AUTH_KV_BINDING) {
+        crate::metrics::record_kv_op("get");
+        document = kv.get(FLAGS_KV_KEY).text().await.ok().flatten();
+    }
+    let flags = match document.as_deref().map(Flags::parse) {
+        Some(Ok(flags)) => flags,
+        Some(Err(e)) => {
+            worker::console_warn!("ignoring invalid {}: {}", FLAGS_KV_KEY, e);
+            Flags::default()
+        }
+        None => Flags::default(),
+    };
+    
+    FLAGS.with(|f| *f.borrow_mut() = Some((now, flags.clone())));
+    flags
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_missing_flags_keep_defaults() {
+        let flags = Flags::parse(r#"{"d1-links": {"enabled": false}}"#).unwrap();
+        assert!(!flags.enabled(D1_LINKS));
+        assert!(flags.on_for(ANALYTICS_QUEUE, "ip:203.0.113.7"));
+        // unknown flags are off unless the document turns them on
+        assert!(!Flags::default().enabled("new-feature"));
+        assert!(Flags::parse(r#"{"new-feature": {}}"#).unwrap().enabled("new-feature"));
+    }
+    
+    #[test]
+    fn test_rollout_splits_clients() {
+        let flags = Flags::parse(r#"{"live-events": {"rollout": 30}}"#).unwrap();
+        let clients: Vec<String> = (0..1000).map(|i| format!("ip:10.0.{}.{}", i / 256, i % 256)).collect();
+        let on = clients.iter().filter(|c| flags.on_for(LIVE_EVENTS, c)).count();
+        assert!((200..400).contains(&on), "{} of 1000 clients", on);
+        
+        // stable per client, and the ends of the range are exact
+        assert_eq!(flags.on_for(LIVE_EVENTS, &clients[0]), flags.for_client(&clients[0]).on(LIVE_EVENTS));
+        let none = Flags::parse(r#"{"live-events": {"rollout": 0}}"#).unwrap();
+        assert!(clients.iter().all(|c| !none.on_for(LIVE_EVENTS, c)));
+        let off = Flags::parse(r#"{"live-events": {"enabled": false, "rollout": 100}}"#).unwrap();
+        assert!(!off.on_for(LIVE_EVENTS, &clients[0]));
+    }
+    
+    #[test]
+    fn test_bucket_depends_on_flag() {
+        let buckets: Vec<u8> = DEFAULTS.iter().map(|(name, _)| bucket(name, "ip:203.0.113.7")).collect();
+        assert!(buckets.iter().all(|b| *b < 100));
+        assert!(buckets.windows(2).any(|w| w[0] != w[1]));
+    }
+}
@@ -0,0 +1,308 @@
+//! ==============================================================================
+//! health.rs - dependency checks and the aggregated status document
+//! ==============================================================================
+//!
+//! purpose:
+//!     GET /health only says a worker answered. GET /health/deps also probes
+//!     the worker's own kv, durable object, r2 and d1 bindings, and the
+//!     gateway's GET /health/all calls every worker's /health/deps at once
+//!     and merges them into one document for the dashboard's status strip.
+//!
+//! relationships:
+//!     - used by: every worker with a fetch handler (GET /health/deps)
+//!     - used by: gateway (GET /health/all, over its service bindings)
+//!
+//! document:
+//!     {"status": "ok" | "degraded" | "down", "checked_at": 1767225600000,
+//!      "checks": [{"name": "url-shortener", "kind": "worker", "up": true, "latency_ms": 12},
+//!                 {"name": "url-shortener/URLS", "kind": "kv", "up": true, "latency_ms": 4},
+//!                 {"name": "capture-archive/CAPTURES", "kind": "r2", "up": false,
+//!                  "latency_ms": 2000, "error": "timed out"}]}
+//!
+//! design:
+//!     probes only read - a kv get, an r2 head, a d1 "SELECT 1", a GET to the
+//!     durable object - so checking never changes state. queue producers
+//!     can't be probed without sending a message, so they aren't listed.
+//!     every probe gives up after PROBE_TIMEOUT_MS and counts as down.
+//!
+//! ==============================================================================
+
+use serde::{Deserialize, Serialize};
+
+/// per-worker dependency report
+pub const DEPS_PATH: &str = "/health/deps";
+/// longest a single binding probe may take
+pub const PROBE_TIMEOUT_MS: u64 = 2_000;
+/// longest the gateway waits for a worker's whole report - past its probes' own timeout
+pub const WORKER_TIMEOUT_MS: u64 = PROBE_TIMEOUT_MS + 1_000;
+/// key the kv and r2 probes read - never written
+#[cfg(feature = "worker")]
+const PROBE_KEY: &str = "health:probe";
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckKind {
+    Worker,
+    Kv,
+    DurableObject,
+    R2,
+    D1,
+}
+
+impl CheckKind {
+    /// the name used in the json document
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Worker => "worker",
+            Self::Kv => "kv",
+            Self::DurableObject => "durable_object",
+            Self::R2 => "r2",
+            Self::D1 => "d1",
+        }
+    }
+}
+
+/// a binding a worker probes for GET /health/deps
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Dependency {
+    Kv(&'static str),
+    /// (binding, object name) - the object the worker really uses
+    DurableObject(&'static str, &'static str),
+    R2(&'static str),
+    D1(&'static str),
+}
+
+impl Dependency {
+    pub fn binding(&self) -> &'static str {
+        match self {
+            Self::Kv(b) | Self::DurableObject(b, _) | Self::R2(b) | Self::D1(b) => b,
+        }
+    }
+    
+    pub fn kind(&self) -> CheckKind {
+        match self {
+            Self::Kv(_) => CheckKind::Kv,
+            Self::DurableObject(..) => CheckKind::DurableObject,
+            Self::R2(_) => CheckKind::R2,
+            Self::D1(_) => CheckKind::D1,
+        }
+    }
+}
+
+/// one probe's outcome
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Check {
+    pub name: String,
+    pub kind: CheckKind,
+    pub up: bool,
+    pub latency_ms: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthStatus {
+    /// every check passed
+    Ok,
+    /// some checks failed
+    Degraded,
+    /// every check failed
+    Down,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HealthReport {
+    pub status: HealthStatus,
+    /// unix ms
+    pub checked_at: u64,
+    pub checks: Vec<Check>,
+}
+
+impl HealthReport {
+    pub fn new(checks: Vec<Check>, checked_at: u64) -> Self {
+        let up = checks.iter().filter(|c| c.up).count();
+        let status = if up == checks.len() {
+            HealthStatus::Ok
+        } else if up == 0 {
+            HealthStatus::Down
+        } else {
+            HealthStatus::Degraded
+        };
+        Self { status, checked_at, checks }
+    }
+}
+
+/// the gateway's checks for one worker: the call itself, then the worker's
+/// own dependencies named "<worker>/<binding>"
+pub fn worker_checks(worker: &str, latency_ms: u64, report: Result<HealthReport, String>) -> Vec<Check> {
+    let (up, error, deps) = match report {
+        Ok(report) => (true, None, report.checks),
+        Err(e) => (false, Some(e), Vec::new()),
+    };
+    let mut checks = vec![Check { name: worker.to_string(), kind: CheckKind::Worker, up, latency_ms, error }];
+    checks.extend(deps.into_iter().map(|dep| Check { name: format!("{}/{}", worker, dep.name), ..dep }));
+    checks
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// run `probe`, giving up after `timeout_ms`; returns (result, latency ms)
+#[cfg(feature = "worker")]
+async fn timed<T>(probe: impl std::future::Future<Output = worker::Result<T>>, timeout_ms: u64) -> (Result<T, String>, u64) {
+    use futures_util::future::{select, Either};
+    
+    let started = worker::js_sys::Date::now();
+    let timeout = worker::Delay::from(std::time::Duration::from_millis(timeout_ms));
+    let result = match select(std::pin::pin!(probe), timeout).await {
+        Either::Left((Ok(value), _)) => Ok(value),
+        Either::Left((Err(e), _)) => Err(e.to_string()),
+        Either::Right(_) => Err("timed out".to_string()),
+    };
+    (result, (worker::js_sys::Date::now() - started) as u64)
+}
+
+#[cfg(feature = "worker")]
+async fn probe(env: &worker::Env, dep: Dependency) -> worker::Result<()> {
+    match dep {
+        Dependency::Kv(binding) => {
+            crate::metrics::record_kv_op("get");
+            env.kv(binding)?.get(PROBE_KEY).text().await?;
+        }
+        Dependency::DurableObject(binding, name) => {
+            let resp = env.durable_object(binding)?.id_from_name(name)?.get_stub()?
+                .fetch_with_str("https://health.internal/").await?;
+            // any answer below 500 means the object ran - the event hub
+            // turns a plain GET away with 426
+            if resp.status_code() >= 500 {
+                return Err(worker::Error::from(format!("status {}", resp.status_code())));
+            }
+        }
+        Dependency::R2(binding) => {
+            env.bucket(binding)?.head(PROBE_KEY).await?;
+        }
+        #[cfg(feature = "d1")]
+        Dependency::D1(binding) => {
+            env.d1(binding)?.prepare("SELECT 1").first::<serde_json::Value>(None).await?;
+        }
+        // a worker built without d1 has no way to reach the binding
+        #[cfg(not(feature = "d1"))]
+        Dependency::D1(_) => return Err(worker::Error::from("built without the d1 feature")),
+    }
+    Ok(())
+}
+
+/// probe every dependency at once
+#[cfg(feature = "worker")]
+async fn probe_all(env: &worker::Env, deps: &[Dependency]) -> Vec<Check> {
+    futures_util::future::join_all(deps.iter().map(|dep| async move {
+        let (result, latency_ms) = timed(probe(env, *dep), PROBE_TIMEOUT_MS).await;
+        Check {
+            name: dep.binding().to_string(),
+            kind: dep.kind(),
+            up: result.is_ok(),
+            latency_ms,
+            error: result.err(),
+        }
+    }))
+    .await
+}
+
+/// a worker's report over its service binding
+#[cfg(feature = "worker")]
+async fn fetch_report(env: &worker::Env, binding: &str) -> worker::Result<HealthReport> {
+    let url = format!("https://health.internal{}", DEPS_PATH);
+    let mut resp = env.service(binding)?.fetch(url, None).await?;
+    match resp.json::<HealthReport>().await {
+        Ok(report) => Ok(report),
+        Err(_) => Err(worker::Error::from(format!("status {}", resp.status_code()))),
+    }
+}
+
+/// the calling worker's own bindings plus every (name, service binding)
+/// worker's report, all at once - GET /health/all on the gateway
+#[cfg(feature = "worker")]
+pub async fn aggregate(env: &worker::Env, name: &str, deps: &[Dependency], services: &[(&str, &str)]) -> HealthReport {
+    let own = async {
+        let started = worker::js_sys::Date::now();
+        let checks = probe_all(env, deps).await;
+        let latency_ms = (worker::js_sys::Date::now() - started) as u64;
+        worker_checks(name, latency_ms, Ok(HealthReport::new(checks, 0)))
+    };
+    let others = futures_util::future::join_all(services.iter().map(|(worker, binding)| async move {
+        let (report, latency_ms) = timed(fetch_report(env, binding), WORKER_TIMEOUT_MS).await;
+        worker_checks(worker, latency_ms, report)
+    }));
+    let (own, others) = futures_util::future::join(own, others).await;
+    let checks = own.into_iter().chain(others.into_iter().flatten()).collect();
+    HealthReport::new(checks, worker::js_sys::Date::now() as u64)
+}
+
+/// GET /health/deps - this worker's dependencies
+#[cfg(feature = "worker")]
+pub async fn deps_response(env: &worker::Env, deps: &[Dependency]) -> worker::Result<worker::Response> {
+    report_response(&HealthReport::new(probe_all(env, deps).await, worker::js_sys::Date::now() as u64))
+}
+
+/// a report as an uncached json response - always a 200, so the error
+/// pipeline (shared::problem) leaves the document alone; "status" says how it went
+#[cfg(feature = "worker")]
+pub fn report_response(report: &HealthReport) -> worker::Result<worker::Response> {
+    let resp = worker::Response::from_json(report)?;
+    resp.headers().set("Cache-Control", "no-store")?;
+    Ok(resp)
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn check(name: &str, up: bool) -> Check {
+        Check { name: name.to_string(), kind: CheckKind::Kv, up, latency_ms: 3, error: None }
+    }
+    
+    #[test]
+    fn test_overall_status() {
+        assert_eq!(HealthReport::new(vec![check("A", true), check("B", true)], 0).status, HealthStatus::Ok);
+        assert_eq!(HealthReport::new(vec![check("A", true), check("B", false)], 0).status, HealthStatus::Degraded);
+        assert_eq!(HealthReport::new(vec![check("A", false)], 0).status, HealthStatus::Down);
+        // a worker without bindings is healthy if it answers
+        assert_eq!(HealthReport::new(Vec::new(), 0).status, HealthStatus::Ok);
+    }
+    
+    #[test]
+    fn test_worker_checks_prefix_dependencies() {
+        let report = HealthReport::new(vec![check("URLS", true), check("AUTH", false)], 0);
+        let checks = worker_checks("url-shortener", 12, Ok(report));
+        let names: Vec<&str> = checks.iter().map(|c| c.name.as_str()).collect();
+        assert_eq!(names, ["url-shortener", "url-shortener/URLS", "url-shortener/AUTH"]);
+        assert_eq!(checks[0].kind, CheckKind::Worker);
+        assert!(checks[0].up && !checks[2].up);
+        
+        let unreachable = worker_checks("gateway", 2000, Err("timed out".to_string()));
+        assert_eq!(unreachable.len(), 1);
+        assert_eq!(unreachable[0].error.as_deref(), Some("timed out"));
+    }
+    
+    #[test]
+    fn test_report_json_shape() {
+        let report = HealthReport::new(vec![check("URLS", true)], 1_767_225_600_000);
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["status"], "ok");
+        assert_eq!(json["checks"][0]["kind"], "kv");
+        assert_eq!(json["checks"][0]["kind"], CheckKind::Kv.as_str());
+        assert!(json["checks"][0].get("error").is_none());
+        let back: HealthReport = serde_json::from_value(json).unwrap();
+        assert_eq!(back, report);
+    }
+}
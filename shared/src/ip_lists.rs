@@ -0,0 +1,224 @@
+//! ==============================================================================
+//! ip_lists.rs - ip allow and deny lists read from kv
+//! ==============================================================================
+//!
+//! purpose:
+//!     lets an operator shut out an abusive address or range at once, and
+//!     let trusted ones (monitoring, partners' egress) past the rate limit,
+//!     without a redeploy.
+//!
+//! relationships:
+//!     - used by: rate-limiter (/api/protected, GET/POST/DELETE /admin/ip-lists)
+//!     - uses: cidr (entries), workers kv namespace AUTH, key
+//!       "ip-lists:config" - re-read every 30 seconds, like shared::flags
+//!
+//! document (json, both fields optional):
+//!     {"allow": ["192.0.2.10"], "deny": ["198.51.100.0/24", "2001:db8::/32"]}
+//!
+//! precedence:
+//!     deny wins - an address on both lists is denied. everything on
+//!     neither list is counted as usual.
+//!
+//! ==============================================================================
+
+use std::net::IpAddr;
+
+use serde::{Deserialize, Serialize};
+
+use crate::cidr::Cidr;
+
+/// kv key of the lists document in the AUTH namespace
+pub const IP_LISTS_KV_KEY: &str = "ip-lists:config";
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum List {
+    Allow,
+    Deny,
+}
+
+impl List {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "allow" => Some(Self::Allow),
+            "deny" => Some(Self::Deny),
+            _ => None,
+        }
+    }
+}
+
+/// where an address stands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Listing {
+    /// refused with 403 before anything is counted
+    Denied,
+    /// let through without counting
+    Allowed,
+    Unlisted,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct IpLists {
+    #[serde(default)]
+    pub allow: Vec<Cidr>,
+    #[serde(default)]
+    pub deny: Vec<Cidr>,
+}
+
+impl IpLists {
+    pub fn parse(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| e.to_string())
+    }
+    
+    pub fn listing(&self, ip: IpAddr) -> Listing {
+        if self.deny.iter().any(|c| c.contains(ip)) {
+            Listing::Denied
+        } else if self.allow.iter().any(|c| c.contains(ip)) {
+            Listing::Allowed
+        } else {
+            Listing::Unlisted
+        }
+    }
+    
+    /// add `entry` to `list`; false when it's already there
+    pub fn add(&mut self, list: List, entry: Cidr) -> bool {
+        let entries = self.entries_mut(list);
+        if entries.contains(&entry) {
+            return false;
+        }
+        entries.push(entry);
+        true
+    }
+    
+    /// take `entry` off `list`; false when it wasn't there
+    pub fn remove(&mut self, list: List, entry: Cidr) -> bool {
+        let entries = self.entries_mut(list);
+        let before = entries.len();
+        entries.retain(|c| *c != entry);
+        entries.len() != before
+    }
+    
+    fn entries_mut(&mut self, list: List) -> &mut Vec<Cidr> {
+        match list {
+            List::Allow => &mut self.allow,
+            List::Deny => &mut self.deny,
+        }
+    }
+}
+
+/// POST /admin/ip-lists body
+#[derive(Debug, Clone, Deserialize)]
+pub struct ListEntry {
+    pub list: List,
+    /// an address or a cidr range
+    pub entry: Cidr,
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// how long an isolate keeps the document before reading kv again
+#[cfg(feature = "worker")]
+const IP_LISTS_TTL_MS: f64 = 30_000.0;
+
+#[cfg(feature = "worker")]
+thread_local! {
+    // (read at ms, lists)
+    static IP_LISTS: std::cell::RefCell<Option<(f64, IpLists)>> = const { std::cell::RefCell::new(None) };
+}
+
+/// the current lists - cached per isolate, empty when kv has no document
+/// or it doesn't parse
+#[cfg(feature = "worker")]
+pub async fn load(env: &worker::Env) -> IpLists {
+    let now = worker::js_sys::Date::now();
+    let cached = IP_LISTS.with(|l| l.borrow().as_ref().filter(|(at, _)| now - at < IP_LISTS_TTL_MS).map(|(_, l)| l.clone()));
+    if let Some(lists) = cached {
+        return lists;
+    }
+    
+    let lists = stored(env).await.unwrap_or_else(|e| {
+        worker::console_warn!("ignoring {}: {}", IP_LISTS_KV_KEY, e);
+        IpLists::default()
+    });
+    IP_LISTS.with(|l| *l.borrow_mut() = Some((now, lists.clone())));
+    lists
+}
+
+/// the document in kv, read past the isolate cache
+#[cfg(feature = "worker")]
+pub async fn stored(env: &worker::Env) -> worker::Result<IpLists> {
+    crate::metrics::record_kv_op("get");
+    match env.kv(crate::auth_guard::AUTH_KV_BINDING)?.get(IP_LISTS_KV_KEY).text().await? {
+        Some(json) => IpLists::parse(&json).map_err(worker::Error::from),
+        None => Ok(IpLists::default()),
+    }
+}
+
+/// replace the document - this isolate applies it at once, others within
+/// the cache ttl
+#[cfg(feature = "worker")]
+pub async fn save(env: &worker::Env, lists: &IpLists) -> worker::Result<()> {
+    crate::metrics::record_kv_op("put");
+    env.kv(crate::auth_guard::AUTH_KV_BINDING)?.put(IP_LISTS_KV_KEY, serde_json::to_string(lists)?)?.execute().await?;
+    IP_LISTS.with(|l| *l.borrow_mut() = Some((worker::js_sys::Date::now(), lists.clone())));
+    Ok(())
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+    
+    #[test]
+    fn test_listing() {
+        let lists = IpLists::parse(r#"{"allow": ["192.0.2.0/24"], "deny": ["198.51.100.0/24", "192.0.2.66"]}"#).unwrap();
+        assert_eq!(lists.listing(ip("198.51.100.7")), Listing::Denied);
+        assert_eq!(lists.listing(ip("192.0.2.10")), Listing::Allowed);
+        // on both lists - deny wins
+        assert_eq!(lists.listing(ip("192.0.2.66")), Listing::Denied);
+        assert_eq!(lists.listing(ip("203.0.113.1")), Listing::Unlisted);
+        assert_eq!(IpLists::default().listing(ip("2001:db8::1")), Listing::Unlisted);
+    }
+    
+    #[test]
+    fn test_document() {
+        assert_eq!(IpLists::parse("{}").unwrap(), IpLists::default());
+        assert!(IpLists::parse(r#"{"deny": ["not an ip"]}"#).is_err());
+        
+        let entry: ListEntry = serde_json::from_str(r#"{"list": "deny", "entry": "2001:db8::/32"}"#).unwrap();
+        assert_eq!(entry.list, List::Deny);
+        assert!(serde_json::from_str::<ListEntry>(r#"{"list": "maybe", "entry": "192.0.2.1"}"#).is_err());
+        assert_eq!(List::parse("allow"), Some(List::Allow));
+        assert_eq!(List::parse("block"), None);
+    }
+    
+    #[test]
+    fn test_add_and_remove() {
+        let mut lists = IpLists::default();
+        let range: Cidr = "198.51.100.0/24".parse().unwrap();
+        assert!(lists.add(List::Deny, range));
+        // the same range written differently is a duplicate
+        assert!(!lists.add(List::Deny, "198.51.100.9/24".parse().unwrap()));
+        assert_eq!(lists.deny.len(), 1);
+        
+        assert!(!lists.remove(List::Allow, range));
+        assert!(lists.remove(List::Deny, range));
+        assert!(lists.deny.is_empty());
+        
+        let json = serde_json::to_string(&IpLists { allow: vec!["192.0.2.1".parse().unwrap()], deny: vec![range] }).unwrap();
+        assert_eq!(json, r#"{"allow":["192.0.2.1"],"deny":["198.51.100.0/24"]}"#);
+    }
+}
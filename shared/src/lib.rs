@@ -10,6 +10,29 @@
 //!     - used by: workers/url-shortener (ShortenRequest, ShortenResponse)
 //!     - used by: workers/rate-limiter (RateLimitConfig)
 //!     - used by: workers/capability-demo (CapabilityTest, CapabilityResult)
+//!     - used by: all workers (request id and log path helpers, problem details, cors, metrics, auth, turnstile)
+//!     - used by: url-shortener, rate-limiter (scheduled kv maintenance)
+//!     - used by: url-shortener, rate-limiter, analytics-consumer (queued analytics events)
+//!     - used by: url-shortener (link storage over kv or d1)
+//!     - used by: all fetch workers and the gateway (live event hub, feature flags, build info)
+//!     - used by: all fetch workers (dependency health checks), gateway (aggregated /health/all)
+//!     - used by: integration (wasm bundle size budgets)
+//!     - used by: gateway (alert emails for failed health checks)
+//!     - used by: gateway (canary routing between worker versions)
+//!     - used by: rate-limiter (exact sliding-window limits in a durable object)
+//!     - used by: rate-limiter (api key tiers)
+//!     - used by: rate-limiter (limits reloadable from kv)
+//!     - used by: rate-limiter (ip and cidr allow and deny lists)
+//!     - used by: rate-limiter (in-flight request caps in a durable object)
+//!     - used by: rate-limiter (turnstile challenges and bypass tokens on 429)
+//!     - used by: rate-limiter (allowed and blocked decisions in analytics engine)
+//!     - used by: rate-limiter (kv counters cached in the isolate)
+//!     - used by: rate-limiter (forwarding allowed requests to ORIGIN_URL)
+//!     - used by: rate-limiter (daily and monthly quotas)
+//!     - used by: rate-limiter (burst allowance - token buckets)
+//!     - used by: rate-limiter (shadow mode - decisions recorded, not enforced)
+//!     - used by: rate-limiter (abuse thresholds), any worker (webhook notifications)
+//!     - used by: rate-limiter (kv counters updated one request per client at a time)
 //!
 //! design rationale:
 //!     centralized types make api changes easier to manage across workers.
@@ -20,6 +43,44 @@
 
 use serde::{Deserialize, Serialize};
 
+pub mod abuse;
+pub mod alerting;
+pub mod analytics;
+pub mod auth;
+#[cfg(feature = "worker")]
+pub mod auth_guard;
+pub mod body_limit;
+pub mod build_info;
+pub mod canary;
+pub mod challenge;
+pub mod cidr;
+pub mod client_queue;
+pub mod concurrency;
+pub mod cors;
+pub mod counter_cache;
+pub mod decisions;
+pub mod etag;
+pub mod event_hub;
+pub mod flags;
+pub mod health;
+pub mod ip_lists;
+pub mod limiter;
+pub mod limits;
+pub mod maintenance;
+pub mod metrics;
+#[cfg(feature = "worker")]
+pub mod metrics_store;
+pub mod mode;
+pub mod notify;
+pub mod problem;
+pub mod proxy;
+pub mod quotas;
+pub mod size_budget;
+pub mod storage;
+pub mod tiers;
+pub mod token_bucket;
+pub mod turnstile;
+
 // ==============================================================================
 // url shortener types
 // ==============================================================================
@@ -28,6 +89,12 @@ use serde::{Deserialize, Serialize};
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ShortenRequest {
     pub url: String,
+    /// custom code instead of a generated one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub alias: Option<String>,
+    /// seconds until the link expires
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<u64>,
 }
 
 /// response from shortening a url
@@ -36,14 +103,22 @@ pub struct ShortenResponse {
     pub code: String,
     pub short_url: String,
     pub original_url: String,
+    /// expiry timestamp in ms, none = never
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<u64>,
 }
 
-/// stored url entry in kv
+/// stored url entry - kv json or a d1 row, see storage
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct UrlEntry {
     pub original_url: String,
     pub created_at: u64,
+    /// clicks counted before analytics moved to the queue, plus (from d1)
+    /// the consumer's count
     pub clicks: u64,
+    /// expiry timestamp in ms, none = never
+    #[serde(default)]
+    pub expires_at: Option<u64>,
 }
 
 // ==============================================================================
@@ -57,6 +132,10 @@ pub struct RateLimitConfig {
     pub limit: u32,
     /// window size in seconds
     pub window_seconds: u64,
+    /// requests a client may make at once - with it, limit per window is
+    /// the rate a token bucket of this size refills at (shared::token_bucket)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
 }
 
 impl Default for RateLimitConfig {
@@ -64,6 +143,7 @@ impl Default for RateLimitConfig {
         Self {
             limit: 10,
             window_seconds: 60,
+            burst: None,
         }
     }
 }
@@ -111,6 +191,57 @@ pub struct CapabilityResult {
     pub message: String,
 }
 
+// ==============================================================================
+// request ids
+// ==============================================================================
+
+/// header carrying the request id in both directions
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// longest client-supplied request id a worker will adopt
+pub const REQUEST_ID_MAX_LEN: usize = 64;
+
+/// adopt a client-supplied request id, if it is safe to log and echo back
+pub fn accept_request_id(incoming: &str) -> Option<String> {
+    let id = incoming.trim();
+    let valid = !id.is_empty()
+        && id.len() <= REQUEST_ID_MAX_LEN
+        && id.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+    valid.then(|| id.to_string())
+}
+
+/// build a new request id: ms timestamp then a random suffix, both hex,
+/// so ids sort by time and are easy to grep for in logs
+pub fn new_request_id(now_ms: u64, random: u32) -> String {
+    format!("{:011x}-{:08x}", now_ms, random)
+}
+
+/// a path safe to log - the api key in /admin/keys/<key> is masked
+pub fn log_path(path: &str) -> String {
+    match path.strip_prefix("/admin/keys/") {
+        Some(rest) => match rest.split_once('/') {
+            Some((_, tail)) => format!("/admin/keys/***/{}", tail),
+            None => "/admin/keys/***".to_string(),
+        },
+        None => path.to_string(),
+    }
+}
+
+// ==============================================================================
+// gateway
+// ==============================================================================
+
+/// header the gateway uses to tell a worker which path prefix it is mounted under
+pub const FORWARDED_PREFIX_HEADER: &str = "X-Forwarded-Prefix";
+
+/// accept a forwarded prefix only if it is a single path segment like "/shorten"
+pub fn accept_forwarded_prefix(prefix: &str) -> Option<&str> {
+    let valid = prefix
+        .strip_prefix('/')
+        .is_some_and(|p| !p.is_empty() && p.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+    valid.then_some(prefix)
+}
+
 // ==============================================================================
 // tests
 // ==============================================================================
@@ -123,9 +254,13 @@ mod tests {
     fn test_shorten_request_serialization() {
         let req = ShortenRequest {
             url: "https://example.com".to_string(),
+            alias: None,
+            expires_in: None,
         };
         let json = serde_json::to_string(&req).unwrap();
         assert!(json.contains("example.com"));
+        // optional fields stay out of the wire format when unset
+        assert!(!json.contains("alias"));
     }
 
     #[test]
@@ -134,4 +269,42 @@ mod tests {
         assert_eq!(config.limit, 10);
         assert_eq!(config.window_seconds, 60);
     }
+
+    #[test]
+    fn test_accept_request_id() {
+        assert_eq!(accept_request_id(" abc-123 "), Some("abc-123".to_string()));
+        assert_eq!(accept_request_id("8a1b2c3d4e5f6a7b-IAD"), Some("8a1b2c3d4e5f6a7b-IAD".to_string()));
+        // empty, oversized or log-unsafe ids are replaced with a fresh one
+        assert_eq!(accept_request_id(""), None);
+        assert_eq!(accept_request_id(&"a".repeat(REQUEST_ID_MAX_LEN + 1)), None);
+        assert_eq!(accept_request_id("id\nforged log line"), None);
+        assert_eq!(accept_request_id("<script>"), None);
+    }
+
+    #[test]
+    fn test_new_request_id_format() {
+        let id = new_request_id(1_700_000_000_000, 0xdeadbeef);
+        assert_eq!(id, "18bcfe56800-deadbeef");
+        // generated ids always pass validation
+        assert_eq!(accept_request_id(&id), Some(id));
+    }
+    
+    #[test]
+    fn test_log_path_masks_keys() {
+        assert_eq!(log_path("/admin/keys/rl_0123abcd"), "/admin/keys/***");
+        assert_eq!(log_path("/admin/keys/rl_0123abcd/usage"), "/admin/keys/***/usage");
+        assert_eq!(log_path("/admin/keys"), "/admin/keys");
+        assert_eq!(log_path("/api/status"), "/api/status");
+    }
+    
+    #[test]
+    fn test_accept_forwarded_prefix() {
+        assert_eq!(accept_forwarded_prefix("/shorten"), Some("/shorten"));
+        assert_eq!(accept_forwarded_prefix("/url_shortener-v2"), Some("/url_shortener-v2"));
+        assert_eq!(accept_forwarded_prefix(""), None);
+        assert_eq!(accept_forwarded_prefix("/"), None);
+        assert_eq!(accept_forwarded_prefix("shorten"), None);
+        assert_eq!(accept_forwarded_prefix("/a/b"), None);
+        assert_eq!(accept_forwarded_prefix("//evil.example"), None);
+    }
 }
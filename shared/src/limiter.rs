@@ -0,0 +1,413 @@
+//! ==============================================================================
+//! limiter.rs - exact sliding-window rate limiting in a durable object
+//! ==============================================================================
+//!
+//! purpose:
+//!     the rate limiter's kv counters are a fixed window read and written
+//!     without a lock between isolates (shared::client_queue only orders
+//!     requests within one): two requests can both read count 9 and pass,
+//!     and a client can spend its limit at the end of one window and again
+//!     at the start of the next. this is the exact alternative - one durable
+//!     object per client keeping a log of its recent hits.
+//!
+//! relationships:
+//!     - used by: rate-limiter (LIMITER_BACKEND = "durable_object"; Backend
+//!       also names the kv and kv_cached backends)
+//!     - binding: LIMITER -> class RateLimiterStore, exported by
+//!       workers/rate-limiter
+//!
+//! protocol (internal, worker -> object named by the client id):
+//!     POST   /?limit=10&window=60   count a hit if allowed; Decision json
+//!     GET    /?limit=10&window=60   the Decision without counting
+//!     either with &burst=20         a token bucket instead of the log
+//!                                   (shared::token_bucket)
+//!     DELETE /                      forget the client (admin reset)
+//!
+//! algorithm:
+//!     sliding log: the object stores the time of every allowed hit in the
+//!     last window and allows a request while fewer than limit remain, so
+//!     no stretch of window seconds holds more than limit hits. the object
+//!     handles one request at a time, so reading and writing the log can't
+//!     interleave. an alarm deletes the log once its newest hit is a window
+//!     old, so idle clients leave nothing behind.
+//!
+//! ==============================================================================
+
+use serde::{Deserialize, Serialize};
+
+/// binding name of the limiter durable object
+pub const LIMITER_BINDING: &str = "LIMITER";
+/// env var choosing the backend
+pub const BACKEND_VAR: &str = "LIMITER_BACKEND";
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// where the rate limiter keeps its counts
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// fixed windows in kv - cheap, approximate
+    #[default]
+    Kv,
+    /// the same windows, counted in the isolate and synced to kv in
+    /// batches (shared::counter_cache) - fewer kv operations, looser
+    KvCached,
+    /// a sliding log per client in a durable object - exact
+    DurableObject,
+}
+
+impl Backend {
+    /// LIMITER_BACKEND's value - anything unknown stays on kv
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "durable_object" | "do" => Self::DurableObject,
+            "kv_cached" => Self::KvCached,
+            _ => Self::Kv,
+        }
+    }
+    
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Kv => "kv",
+            Self::KvCached => "kv_cached",
+            Self::DurableObject => "durable_object",
+        }
+    }
+}
+
+/// how a limit is enforced - named in X-RateLimit-Policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    /// the kv backends
+    FixedWindow,
+    /// the durable object backend
+    SlidingWindow,
+    /// any backend with a burst set
+    TokenBucket { burst: u32 },
+}
+
+impl Algorithm {
+    pub fn of(backend: Backend, burst: Option<u32>) -> Self {
+        match (backend, burst) {
+            (_, Some(burst)) => Self::TokenBucket { burst },
+            (Backend::DurableObject, None) => Self::SlidingWindow,
+            _ => Self::FixedWindow,
+        }
+    }
+    
+    /// X-RateLimit-Policy for `limit` per window: the algorithm and its
+    /// parameters, e.g. "token-bucket;rate=10;w=60;burst=20"
+    pub fn policy(&self, limit: u32, window_seconds: u64) -> String {
+        match self {
+            Self::FixedWindow => format!("fixed-window;limit={};w={}", limit, window_seconds),
+            Self::SlidingWindow => format!("sliding-window;limit={};w={}", limit, window_seconds),
+            Self::TokenBucket { burst } => format!("token-bucket;rate={};w={};burst={}", limit, window_seconds, burst),
+        }
+    }
+    
+    /// RateLimit-Policy - the draft's "burst" parameter for a bucket
+    pub fn standard_policy(&self, limit: u32, window_seconds: u64) -> String {
+        match self {
+            Self::TokenBucket { burst } => format!("{};w={};burst={}", limit, window_seconds, burst),
+            _ => format!("{};w={}", limit, window_seconds),
+        }
+    }
+}
+
+/// the outcome of a check, whichever backend made it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Decision {
+    pub allowed: bool,
+    /// hits counted in the current window, this one included
+    pub count: u32,
+    pub limit: u32,
+    pub remaining: u32,
+    /// seconds until the counted hits start to expire - the fixed window's
+    /// end with kv, the oldest hit leaving with the sliding log. when
+    /// blocked, how long to wait
+    pub reset_in_seconds: u64,
+}
+
+impl Decision {
+    /// the RateLimit-* headers of draft-ietf-httpapi-ratelimit-headers for
+    /// this decision - RateLimit-Policy depends on the algorithm, see
+    /// Algorithm::standard_policy
+    pub fn standard_headers(&self) -> [(&'static str, String); 3] {
+        [
+            ("RateLimit-Limit", self.limit.to_string()),
+            ("RateLimit-Remaining", self.remaining.to_string()),
+            ("RateLimit-Reset", self.reset_in_seconds.to_string()),
+        ]
+    }
+}
+
+/// the hits one client made in the last window (unix ms, oldest first)
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SlidingLog {
+    hits: Vec<u64>,
+}
+
+impl SlidingLog {
+    /// count a hit at `now_ms` if the window has room
+    pub fn check(&mut self, now_ms: u64, limit: u32, window_seconds: u64) -> Decision {
+        self.prune(now_ms, window_seconds);
+        let allowed = (self.hits.len() as u64) < limit as u64;
+        if allowed {
+            self.hits.push(now_ms);
+        }
+        Decision { allowed, ..self.decision(now_ms, limit, window_seconds) }
+    }
+    
+    /// where the client stands at `now_ms`, without counting anything
+    pub fn peek(&mut self, now_ms: u64, limit: u32, window_seconds: u64) -> Decision {
+        self.prune(now_ms, window_seconds);
+        self.decision(now_ms, limit, window_seconds)
+    }
+    
+    pub fn is_empty(&self) -> bool {
+        self.hits.is_empty()
+    }
+    
+    /// when every hit has left the window (unix ms)
+    pub fn expires_at(&self, window_seconds: u64) -> Option<u64> {
+        self.hits.last().map(|last| last + window_seconds * 1000)
+    }
+    
+    fn prune(&mut self, now_ms: u64, window_seconds: u64) {
+        let window_ms = window_seconds * 1000;
+        self.hits.retain(|&at| now_ms.saturating_sub(at) < window_ms);
+    }
+    
+    fn decision(&self, now_ms: u64, limit: u32, window_seconds: u64) -> Decision {
+        let count = self.hits.len().min(u32::MAX as usize) as u32;
+        let remaining = limit.saturating_sub(count);
+        // the hit whose leaving makes room for one more - the oldest, unless
+        // the limit shrank below what's already counted
+        let freeing = self.hits.get(self.hits.len().saturating_sub(limit.max(1) as usize));
+        let reset_in_seconds = match freeing {
+            Some(at) => (at + window_seconds * 1000).saturating_sub(now_ms).div_ceil(1000),
+            // nothing counted: free now, or never with a zero limit
+            None if remaining > 0 => 0,
+            None => window_seconds,
+        };
+        Decision { allowed: remaining > 0, count, limit, remaining, reset_in_seconds }
+    }
+}
+
+// ==============================================================================
+// durable object
+// ==============================================================================
+
+#[cfg(feature = "worker")]
+const LOG_KEY: &str = "log";
+#[cfg(feature = "worker")]
+const BUCKET_KEY: &str = "bucket";
+/// object the health probe reads - never counted against
+#[cfg(feature = "worker")]
+const PROBE_NAME: &str = "health:probe";
+/// the limiter as a GET /health/deps dependency
+#[cfg(feature = "worker")]
+pub const HEALTH_DEPENDENCY: crate::health::Dependency = crate::health::Dependency::DurableObject(LIMITER_BINDING, PROBE_NAME);
+
+#[cfg(feature = "worker")]
+#[worker::durable_object]
+pub struct RateLimiterStore {
+    state: worker::State,
+}
+
+#[cfg(feature = "worker")]
+impl worker::DurableObject for RateLimiterStore {
+    fn new(state: worker::State, _env: worker::Env) -> Self {
+        Self { state }
+    }
+    
+    async fn fetch(&self, req: worker::Request) -> worker::Result<worker::Response> {
+        use worker::{Method, Response};
+        
+        let storage = self.state.storage();
+        if req.method() == Method::Delete {
+            storage.delete_all().await?;
+            return Response::empty();
+        }
+        
+        let url = req.url()?;
+        let param = |name: &str| url.query_pairs().find(|(k, _)| k == name).and_then(|(_, v)| v.parse::<u64>().ok());
+        let (Some(limit), Some(window_seconds)) = (param("limit"), param("window")) else {
+            return Response::error("missing limit or window", 400);
+        };
+        let limit = limit.min(u32::MAX as u64) as u32;
+        
+        let now = worker::js_sys::Date::now() as u64;
+        if let Some(burst) = param("burst").filter(|b| *b > 0) {
+            let shape = crate::token_bucket::Shape { rate: limit, window_seconds, burst: burst.min(u32::MAX as u64) as u32 };
+            let mut bucket = storage.get(BUCKET_KEY).await?.unwrap_or(crate::token_bucket::TokenBucket::full(shape, now));
+            let decision = if req.method() == Method::Post {
+                let decision = bucket.take(shape, now);
+                if decision.allowed {
+                    storage.put(BUCKET_KEY, &bucket).await?;
+                    // a full bucket is the same as none
+                    let after = std::time::Duration::from_millis(bucket.full_at(shape).saturating_sub(now));
+                    storage.set_alarm(after).await?;
+                }
+                decision
+            } else {
+                bucket.peek(shape, now)
+            };
+            return Response::from_json(&decision);
+        }
+        let mut log: SlidingLog = storage.get(LOG_KEY).await?.unwrap_or_default();
+        let decision = if req.method() == Method::Post {
+            let decision = log.check(now, limit, window_seconds);
+            if decision.allowed {
+                storage.put(LOG_KEY, &log).await?;
+                if let Some(expires_at) = log.expires_at(window_seconds) {
+                    let after = std::time::Duration::from_millis(expires_at.saturating_sub(now));
+                    storage.set_alarm(after).await?;
+                }
+            }
+            decision
+        } else {
+            log.peek(now, limit, window_seconds)
+        };
+        Response::from_json(&decision)
+    }
+    
+    /// the newest hit has left the window (or the bucket is full) - nothing
+    /// worth keeping
+    async fn alarm(&self) -> worker::Result<worker::Response> {
+        self.state.storage().delete_all().await?;
+        worker::Response::empty()
+    }
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// the backend LIMITER_BACKEND selects (kv when unset)
+#[cfg(feature = "worker")]
+pub fn backend(env: &worker::Env) -> Backend {
+    env.var(BACKEND_VAR).map(|v| Backend::parse(&v.to_string())).unwrap_or_default()
+}
+
+#[cfg(feature = "worker")]
+async fn call(
+    env: &worker::Env,
+    client_id: &str,
+    method: worker::Method,
+    limit: u32,
+    window_seconds: u64,
+    burst: Option<u32>,
+) -> worker::Result<worker::Response> {
+    let mut url = format!("https://limiter.internal/?limit={}&window={}", limit, window_seconds);
+    if let Some(burst) = burst {
+        url.push_str(&format!("&burst={}", burst));
+    }
+    let init = worker::RequestInit { method, ..worker::RequestInit::default() };
+    let req = worker::Request::new_with_init(&url, &init)?;
+    env.durable_object(LIMITER_BINDING)?.id_from_name(client_id)?.get_stub()?.fetch_with_request(req).await
+}
+
+/// count a request for `client_id` if its window (or bucket, with a burst) has room
+#[cfg(feature = "worker")]
+pub async fn check(env: &worker::Env, client_id: &str, limit: u32, window_seconds: u64, burst: Option<u32>) -> worker::Result<Decision> {
+    call(env, client_id, worker::Method::Post, limit, window_seconds, burst).await?.json().await
+}
+
+/// where `client_id` stands, without counting a request
+#[cfg(feature = "worker")]
+pub async fn status(env: &worker::Env, client_id: &str, limit: u32, window_seconds: u64, burst: Option<u32>) -> worker::Result<Decision> {
+    call(env, client_id, worker::Method::Get, limit, window_seconds, burst).await?.json().await
+}
+
+/// forget `client_id`'s hits
+#[cfg(feature = "worker")]
+pub async fn reset(env: &worker::Env, client_id: &str) -> worker::Result<()> {
+    call(env, client_id, worker::Method::Delete, 0, 0, None).await.map(|_| ())
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const S: u64 = 1000;
+    
+    #[test]
+    fn test_allows_up_to_limit_in_any_window() {
+        let mut log = SlidingLog::default();
+        for i in 0..3 {
+            let decision = log.check(10 * S + i, 3, 60);
+            assert!(decision.allowed);
+            assert_eq!(decision.remaining, 2 - i as u32);
+        }
+        let blocked = log.check(11 * S, 3, 60);
+        assert!(!blocked.allowed);
+        assert_eq!((blocked.count, blocked.remaining), (3, 0));
+        // the first hit leaves the window at 70s
+        assert_eq!(blocked.reset_in_seconds, 59);
+        assert!(log.check(70 * S, 3, 60).allowed);
+        assert!(!log.check(70 * S, 3, 60).allowed);
+    }
+    
+    #[test]
+    fn test_no_double_burst_at_window_boundary() {
+        // a fixed window would allow 3 at 59s and 3 more at 60s
+        let mut log = SlidingLog::default();
+        let allowed = (0..6).filter(|i| log.check(59 * S + i * 500, 3, 60).allowed).count();
+        assert_eq!(allowed, 3);
+        let status = log.peek(61 * S, 3, 60);
+        assert_eq!((status.count, status.allowed), (3, false));
+    }
+    
+    #[test]
+    fn test_peek_and_expiry() {
+        let mut log = SlidingLog::default();
+        assert_eq!(log.peek(0, 10, 60), Decision { allowed: true, count: 0, limit: 10, remaining: 10, reset_in_seconds: 0 });
+        assert_eq!(log.expires_at(60), None);
+        
+        log.check(5 * S, 10, 60);
+        log.check(20 * S, 10, 60);
+        let status = log.peek(21 * S, 10, 60);
+        assert_eq!((status.count, status.reset_in_seconds), (2, 44));
+        assert_eq!(log.expires_at(60), Some(80 * S));
+        assert_eq!(log.peek(80 * S, 10, 60).count, 0);
+        assert!(log.is_empty());
+        
+        // a zero limit blocks everything for a whole window
+        assert_eq!(SlidingLog::default().check(0, 0, 60).reset_in_seconds, 60);
+    }
+    
+    #[test]
+    fn test_standard_headers() {
+        let decision = Decision { allowed: true, count: 3, limit: 10, remaining: 7, reset_in_seconds: 42 };
+        let headers = decision.standard_headers();
+        assert_eq!(headers[0], ("RateLimit-Limit", "10".to_string()));
+        assert_eq!(headers[1], ("RateLimit-Remaining", "7".to_string()));
+        assert_eq!(headers[2], ("RateLimit-Reset", "42".to_string()));
+    }
+    
+    #[test]
+    fn test_algorithm_policy() {
+        assert_eq!(Algorithm::of(Backend::Kv, None).policy(10, 60), "fixed-window;limit=10;w=60");
+        assert_eq!(Algorithm::of(Backend::DurableObject, None).policy(10, 60), "sliding-window;limit=10;w=60");
+        let bucket = Algorithm::of(Backend::DurableObject, Some(20));
+        assert_eq!(bucket.policy(10, 60), "token-bucket;rate=10;w=60;burst=20");
+        assert_eq!(bucket.standard_policy(10, 60), "10;w=60;burst=20");
+        assert_eq!(Algorithm::FixedWindow.standard_policy(10, 60), "10;w=60");
+    }
+    
+    #[test]
+    fn test_backend_parse() {
+        assert_eq!(Backend::parse("durable_object"), Backend::DurableObject);
+        assert_eq!(Backend::parse(" DO "), Backend::DurableObject);
+        assert_eq!(Backend::parse("kv"), Backend::Kv);
+        assert_eq!(Backend::parse("kv_cached"), Backend::KvCached);
+        assert_eq!(Backend::parse(""), Backend::Kv);
+        assert_eq!(Backend::DurableObject.as_str(), "durable_object");
+    }
+}
@@ -0,0 +1,173 @@
+//! ==============================================================================
+//! limits.rs - the rate limiter's limits, reloadable from kv
+//! ==============================================================================
+//!
+//! purpose:
+//!     RATE_LIMIT and RATE_WINDOW_SECONDS only change with a deploy, which
+//!     is too slow for tightening limits during an attack. an operator
+//!     writes a document to kv instead and every isolate picks it up within
+//!     30 seconds; without one the env vars apply as before.
+//!
+//! relationships:
+//!     - used by: rate-limiter (the free tier's limits on every request,
+//!       GET/PUT/DELETE /admin/limits)
+//!     - uses: workers kv namespace AUTH, key "limits:config" - re-read
+//!       every 30 seconds, like shared::flags
+//!
+//! document (json, crate::RateLimitConfig):
+//!     {"limit": 5, "window_seconds": 60, "burst": 20}
+//!     limit and window_seconds are required; burst (at least limit) turns
+//!     the limit into a token bucket's rate. the limit is the free tier's -
+//!     pro and enterprise keep their multiples of it (shared::tiers). a
+//!     document that doesn't parse or is out of range is ignored with a
+//!     warning.
+//!
+//! ==============================================================================
+
+use serde::Serialize;
+
+use crate::RateLimitConfig;
+
+/// kv key of the limits document in the AUTH namespace
+pub const LIMITS_KV_KEY: &str = "limits:config";
+/// highest limit a document may set
+pub const MAX_LIMIT: u32 = 1_000_000;
+/// longest window a document may set - a day
+pub const MAX_WINDOW_SECONDS: u64 = 86_400;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// where the limits in force came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    Kv,
+    Env,
+}
+
+/// a document as stored or sent to PUT /admin/limits
+pub fn parse(json: &str) -> Result<RateLimitConfig, String> {
+    let config: RateLimitConfig = serde_json::from_str(json).map_err(|e| e.to_string())?;
+    validate(&config)?;
+    Ok(config)
+}
+
+/// limits outside these ranges are almost certainly typos
+pub fn validate(config: &RateLimitConfig) -> Result<(), String> {
+    if config.limit == 0 || config.limit > MAX_LIMIT {
+        return Err(format!("limit must be 1..={}", MAX_LIMIT));
+    }
+    if config.window_seconds == 0 || config.window_seconds > MAX_WINDOW_SECONDS {
+        return Err(format!("window_seconds must be 1..={}", MAX_WINDOW_SECONDS));
+    }
+    // a bucket smaller than the rate would hold the client below it
+    if let Some(burst) = config.burst {
+        if burst < config.limit || burst > MAX_LIMIT {
+            return Err(format!("burst must be limit..={}", MAX_LIMIT));
+        }
+    }
+    Ok(())
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// how long an isolate keeps the document before reading kv again
+#[cfg(feature = "worker")]
+const LIMITS_TTL_MS: f64 = 30_000.0;
+
+#[cfg(feature = "worker")]
+thread_local! {
+    // (read at ms, document) - None when kv has no usable document
+    static LIMITS: std::cell::RefCell<Option<(f64, Option<RateLimitConfig>)>> = const { std::cell::RefCell::new(None) };
+}
+
+#[cfg(feature = "worker")]
+fn remember(document: Option<RateLimitConfig>) {
+    let now = worker::js_sys::Date::now();
+    LIMITS.with(|l| *l.borrow_mut() = Some((now, document)));
+}
+
+/// the limits in force - the kv document, cached per isolate, else
+/// `fallback` (the env vars)
+#[cfg(feature = "worker")]
+pub async fn load(env: &worker::Env, fallback: RateLimitConfig) -> (RateLimitConfig, Source) {
+    let now = worker::js_sys::Date::now();
+    let cached = LIMITS.with(|l| l.borrow().as_ref().filter(|(at, _)| now - at < LIMITS_TTL_MS).map(|(_, d)| d.clone()));
+    let document = match cached {
+        Some(document) => document,
+        None => {
+            let document = stored(env).await.unwrap_or_else(|e| {
+                worker::console_warn!("ignoring {}: {}", LIMITS_KV_KEY, e);
+                None
+            });
+            remember(document.clone());
+            document
+        }
+    };
+    match document {
+        Some(config) => (config, Source::Kv),
+        None => (fallback, Source::Env),
+    }
+}
+
+/// the document in kv, read past the isolate cache. one that doesn't
+/// parse is an error
+#[cfg(feature = "worker")]
+pub async fn stored(env: &worker::Env) -> worker::Result<Option<RateLimitConfig>> {
+    crate::metrics::record_kv_op("get");
+    let text = env.kv(crate::auth_guard::AUTH_KV_BINDING)?.get(LIMITS_KV_KEY).text().await?;
+    text.as_deref().map(parse).transpose().map_err(worker::Error::from)
+}
+
+/// replace the document - this isolate applies it at once, others within
+/// the cache ttl (and kv's own propagation)
+#[cfg(feature = "worker")]
+pub async fn save(env: &worker::Env, config: &RateLimitConfig) -> worker::Result<()> {
+    crate::metrics::record_kv_op("put");
+    env.kv(crate::auth_guard::AUTH_KV_BINDING)?.put(LIMITS_KV_KEY, serde_json::to_string(config)?)?.execute().await?;
+    remember(Some(config.clone()));
+    Ok(())
+}
+
+/// delete the document, handing the limits back to the env vars
+#[cfg(feature = "worker")]
+pub async fn clear(env: &worker::Env) -> worker::Result<()> {
+    crate::metrics::record_kv_op("delete");
+    env.kv(crate::auth_guard::AUTH_KV_BINDING)?.delete(LIMITS_KV_KEY).await?;
+    remember(None);
+    Ok(())
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_document() {
+        let config = parse(r#"{"limit": 5, "window_seconds": 30}"#).unwrap();
+        assert_eq!((config.limit, config.window_seconds), (5, 30));
+        
+        // both fields are required
+        assert!(parse(r#"{"limit": 5}"#).is_err());
+        assert!(parse("not json").is_err());
+    }
+    
+    #[test]
+    fn test_rejects_out_of_range() {
+        assert!(parse(r#"{"limit": 0, "window_seconds": 60}"#).unwrap_err().contains("limit"));
+        assert!(parse(r#"{"limit": 2000000, "window_seconds": 60}"#).is_err());
+        assert!(parse(r#"{"limit": 10, "window_seconds": 0}"#).unwrap_err().contains("window_seconds"));
+        assert!(parse(r#"{"limit": 10, "window_seconds": 604800}"#).is_err());
+        assert!(validate(&RateLimitConfig::default()).is_ok());
+        assert!(parse(r#"{"limit": 10, "window_seconds": 60, "burst": 5}"#).unwrap_err().contains("burst"));
+        assert_eq!(parse(r#"{"limit": 10, "window_seconds": 60, "burst": 20}"#).unwrap().burst, Some(20));
+    }
+}
@@ -0,0 +1,218 @@
+//! ==============================================================================
+//! maintenance.rs - kv cleanup for the workers' scheduled handlers
+//! ==============================================================================
+//!
+//! purpose:
+//!     kv ttls expire most records on their own, but entries written without
+//!     one (older versions, or ones a worker can't give a ttl) stay forever.
+//!     each worker's cron handler sweeps its keys through here and deletes
+//!     what it no longer needs, so namespaces don't grow without bound.
+//!
+//! relationships:
+//!     - used by: url-shortener and rate-limiter #[event(scheduled)] handlers
+//!     - uses: metrics (kv operation counts)
+//!
+//! design:
+//!     a sweep lists keys page by page and asks the worker what to do with
+//!     each: keep it, delete it, or read the value first and decide. a run
+//!     stops after a fixed number of kv operations - well under the
+//!     per-invocation subrequest limit - and saves its list cursor under
+//!     "maintenance:cursor:<job>", so big namespaces are covered across runs.
+//!
+//! ==============================================================================
+
+use serde::Serialize;
+
+/// prefix of the saved list cursors
+pub const CURSOR_PREFIX: &str = "maintenance:cursor:";
+/// kv operations one run may spend, leaving headroom under the 1000 subrequest limit
+pub const DEFAULT_MAX_OPS: usize = 500;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// a key as kv lists it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ListedKey {
+    pub name: String,
+    /// unix seconds when kv drops the key itself, none = never
+    pub expiration: Option<u64>,
+}
+
+impl ListedKey {
+    /// kv will remove this key on its own
+    pub fn has_ttl(&self) -> bool {
+        self.expiration.is_some()
+    }
+}
+
+/// what a job wants done with one listed key
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeyAction {
+    Keep,
+    Delete,
+    /// read the value, then decide
+    Inspect,
+}
+
+/// kv operations left in this run
+#[derive(Debug, Clone, Copy)]
+pub struct Budget {
+    left: usize,
+}
+
+impl Budget {
+    pub fn new(ops: usize) -> Self {
+        Self { left: ops }
+    }
+    
+    /// spend `ops` if there's room, false once the run should stop
+    pub fn spend(&mut self, ops: usize) -> bool {
+        if ops > self.left {
+            return false;
+        }
+        self.left -= ops;
+        true
+    }
+}
+
+/// what one run did, logged by the cron handler
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct SweepReport {
+    pub job: String,
+    pub scanned: u64,
+    pub inspected: u64,
+    pub deleted: u64,
+    /// the whole keyspace was covered (the next run starts over)
+    pub complete: bool,
+}
+
+/// key the job's list cursor is saved under
+pub fn cursor_key(job: &str) -> String {
+    format!("{}{}", CURSOR_PREFIX, job)
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// keys per list page - a whole page (a read and a delete per key) fits in
+/// one run's budget, so every run gets past the page it resumed from
+#[cfg(feature = "worker")]
+const PAGE_SIZE: u64 = 100;
+
+/// sweep `kv` (optionally only keys under `prefix`), resuming from the cursor
+/// saved in `cursor_kv`. `classify` decides per key from the listing alone;
+/// keys it marks Inspect are read and deleted when `expired(key, value)` says so
+#[cfg(feature = "worker")]
+pub async fn sweep(
+    kv: &worker::kv::KvStore,
+    cursor_kv: &worker::kv::KvStore,
+    job: &str,
+    prefix: Option<&str>,
+    max_ops: usize,
+    classify: impl Fn(&ListedKey) -> KeyAction,
+    expired: impl Fn(&str, &str) -> bool,
+) -> worker::Result<SweepReport> {
+    use crate::metrics::record_kv_op;
+    
+    let mut report = SweepReport { job: job.to_string(), ..SweepReport::default() };
+    let mut budget = Budget::new(max_ops);
+    
+    // reading and later saving the cursor comes out of the budget too
+    budget.spend(2);
+    record_kv_op("get");
+    let mut cursor = cursor_kv.get(&cursor_key(job)).text().await?;
+    
+    'pages: while budget.spend(1) {
+        // a run cut off mid-page resumes from the start of that page -
+        // kept keys are cheap to list again and deleted ones are gone
+        let page_start = cursor.take();
+        let mut list = kv.list().limit(PAGE_SIZE);
+        if let Some(p) = prefix {
+            list = list.prefix(p.to_string());
+        }
+        if let Some(c) = &page_start {
+            list = list.cursor(c.clone());
+        }
+        record_kv_op("list");
+        let page = list.execute().await?;
+        
+        for key in &page.keys {
+            let listed = ListedKey { name: key.name.clone(), expiration: key.expiration };
+            report.scanned += 1;
+            
+            let delete = match classify(&listed) {
+                KeyAction::Keep => false,
+                KeyAction::Delete => true,
+                KeyAction::Inspect => {
+                    if !budget.spend(1) {
+                        cursor = page_start;
+                        break 'pages;
+                    }
+                    report.inspected += 1;
+                    record_kv_op("get");
+                    match kv.get(&listed.name).text().await? {
+                        Some(value) => expired(&listed.name, &value),
+                        None => false,
+                    }
+                }
+            };
+            if delete {
+                if !budget.spend(1) {
+                    cursor = page_start;
+                    break 'pages;
+                }
+                record_kv_op("delete");
+                kv.delete(&listed.name).await?;
+                report.deleted += 1;
+            }
+        }
+        
+        if page.list_complete {
+            report.complete = true;
+            break;
+        }
+        cursor = page.cursor.clone();
+    }
+    
+    record_kv_op("put");
+    match &cursor {
+        Some(c) if !report.complete => cursor_kv.put(&cursor_key(job), c)?.execute().await?,
+        _ => cursor_kv.delete(&cursor_key(job)).await?,
+    }
+    Ok(report)
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_budget_stops_at_zero() {
+        let mut budget = Budget::new(3);
+        assert!(budget.spend(2));
+        assert!(!budget.spend(2));
+        assert!(budget.spend(1));
+        assert!(!budget.spend(1));
+    }
+    
+    #[test]
+    fn test_cursor_key() {
+        assert_eq!(cursor_key("url-shortener-links"), "maintenance:cursor:url-shortener-links");
+    }
+    
+    #[test]
+    fn test_report_serializes_for_logs() {
+        let report = SweepReport { job: "j".to_string(), scanned: 3, inspected: 1, deleted: 1, complete: true };
+        assert_eq!(
+            serde_json::to_string(&report).unwrap(),
+            r#"{"job":"j","scanned":3,"inspected":1,"deleted":1,"complete":true}"#
+        );
+    }
+}
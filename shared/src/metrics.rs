@@ -0,0 +1,306 @@
+//! ==============================================================================
+//! metrics.rs - request metrics shared by all workers
+//! ==============================================================================
+//!
+//! purpose:
+//!     counts requests (by route and status), kv operations and request
+//!     latency, and renders them in the prometheus text format for the
+//!     workers' /metrics routes.
+//!
+//! relationships:
+//!     - used by: every worker's fetch entry point (record_request)
+//!     - used by: metrics_store (durable object holding the totals)
+//!     - used by: gateway (record_upstream - requests per backend version,
+//!       compared by shared::canary)
+//!
+//! design:
+//!     each isolate buffers a snapshot in memory and periodically merges it
+//!     into a durable object, so counting never adds a subrequest to the
+//!     request path. snapshots are plain serde types so they can travel
+//!     to the durable object as json.
+//!
+//! ==============================================================================
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+/// upper bounds of the latency histogram buckets, in ms
+pub const LATENCY_BUCKETS_MS: &[f64] = &[5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0];
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// latency histogram for one route
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Histogram {
+    /// per-bucket counts (not cumulative), one per LATENCY_BUCKETS_MS entry
+    pub buckets: Vec<u64>,
+    pub count: u64,
+    pub sum_ms: f64,
+}
+
+impl Histogram {
+    pub fn observe(&mut self, ms: f64) {
+        self.buckets.resize(LATENCY_BUCKETS_MS.len(), 0);
+        if let Some(i) = LATENCY_BUCKETS_MS.iter().position(|le| ms <= *le) {
+            self.buckets[i] += 1;
+        }
+        self.count += 1;
+        self.sum_ms += ms;
+    }
+    
+    pub fn merge(&mut self, other: &Histogram) {
+        self.buckets.resize(LATENCY_BUCKETS_MS.len(), 0);
+        for (total, n) in self.buckets.iter_mut().zip(&other.buckets) {
+            *total += n;
+        }
+        self.count += other.count;
+        self.sum_ms += other.sum_ms;
+    }
+}
+
+/// counters for one worker
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct MetricsSnapshot {
+    /// keyed by "<route> <status>", e.g. "/shorten 200"
+    pub requests: BTreeMap<String, u64>,
+    /// keyed by kv operation: "get", "put", "list", "delete"
+    pub kv_ops: BTreeMap<String, u64>,
+    /// keyed by route
+    pub latency: BTreeMap<String, Histogram>,
+    /// proxied requests keyed by "<upstream> <version> <status>", e.g.
+    /// "url-shortener canary 500" - only the gateway fills this in. totals
+    /// stored before it existed have no such field
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub upstream_requests: BTreeMap<String, u64>,
+}
+
+impl MetricsSnapshot {
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty() && self.kv_ops.is_empty() && self.upstream_requests.is_empty()
+    }
+    
+    pub fn record_request(&mut self, route: &str, status: u16, latency_ms: f64) {
+        *self.requests.entry(format!("{} {}", route, status)).or_default() += 1;
+        self.latency.entry(route.to_string()).or_default().observe(latency_ms);
+    }
+    
+    pub fn record_kv_op(&mut self, op: &str) {
+        *self.kv_ops.entry(op.to_string()).or_default() += 1;
+    }
+    
+    pub fn record_upstream(&mut self, upstream: &str, version: &str, status: u16) {
+        *self.upstream_requests.entry(format!("{} {} {}", upstream, version, status)).or_default() += 1;
+    }
+    
+    pub fn merge(&mut self, other: &MetricsSnapshot) {
+        for (key, n) in &other.requests {
+            *self.requests.entry(key.clone()).or_default() += n;
+        }
+        for (op, n) in &other.kv_ops {
+            *self.kv_ops.entry(op.clone()).or_default() += n;
+        }
+        for (route, h) in &other.latency {
+            self.latency.entry(route.clone()).or_default().merge(h);
+        }
+        for (key, n) in &other.upstream_requests {
+            *self.upstream_requests.entry(key.clone()).or_default() += n;
+        }
+    }
+    
+    /// render in the prometheus text exposition format
+    pub fn render_prometheus(&self, worker: &str) -> String {
+        let mut out = String::new();
+        let requests: Vec<(&str, &str, u64)> = self
+            .requests
+            .iter()
+            .filter_map(|(key, n)| key.rsplit_once(' ').map(|(route, status)| (route, status, *n)))
+            .collect();
+        
+        header(&mut out, "edge_requests_total", "counter", "Requests handled, by route and status.");
+        for (route, status, n) in &requests {
+            let _ = writeln!(out, "edge_requests_total{{worker=\"{}\",route=\"{}\",status=\"{}\"}} {}", worker, route, status, n);
+        }
+        
+        // errors and 429s are derivable from the above, but scrapers alerting
+        // on them shouldn't have to know which status codes count
+        header(&mut out, "edge_errors_total", "counter", "Requests answered with a 5xx status.");
+        let _ = writeln!(out, "edge_errors_total{{worker=\"{}\"}} {}", worker, count_status(&requests, |s| s.starts_with('5')));
+        header(&mut out, "edge_rate_limited_total", "counter", "Requests rejected with 429 Too Many Requests.");
+        let _ = writeln!(out, "edge_rate_limited_total{{worker=\"{}\"}} {}", worker, count_status(&requests, |s| s == "429"));
+        
+        header(&mut out, "edge_kv_operations_total", "counter", "Workers KV operations, by operation.");
+        for (op, n) in &self.kv_ops {
+            let _ = writeln!(out, "edge_kv_operations_total{{worker=\"{}\",op=\"{}\"}} {}", worker, op, n);
+        }
+        
+        header(&mut out, "edge_request_duration_ms", "histogram", "Request latency in milliseconds, by route.");
+        for (route, h) in &self.latency {
+            let mut cumulative = 0;
+            for (le, n) in LATENCY_BUCKETS_MS.iter().zip(h.buckets.iter().chain(std::iter::repeat(&0))) {
+                cumulative += n;
+                let _ = writeln!(out, "edge_request_duration_ms_bucket{{worker=\"{}\",route=\"{}\",le=\"{}\"}} {}", worker, route, le, cumulative);
+            }
+            let _ = writeln!(out, "edge_request_duration_ms_bucket{{worker=\"{}\",route=\"{}\",le=\"+Inf\"}} {}", worker, route, h.count);
+            let _ = writeln!(out, "edge_request_duration_ms_sum{{worker=\"{}\",route=\"{}\"}} {}", worker, route, h.sum_ms);
+            let _ = writeln!(out, "edge_request_duration_ms_count{{worker=\"{}\",route=\"{}\"}} {}", worker, route, h.count);
+        }
+        
+        if !self.upstream_requests.is_empty() {
+            header(&mut out, "edge_upstream_requests_total", "counter", "Proxied requests, by backend worker, version and status.");
+            for (key, n) in &self.upstream_requests {
+                let mut parts = key.splitn(3, ' ');
+                if let (Some(upstream), Some(version), Some(status)) = (parts.next(), parts.next(), parts.next()) {
+                    let _ = writeln!(
+                        out,
+                        "edge_upstream_requests_total{{worker=\"{}\",upstream=\"{}\",version=\"{}\",status=\"{}\"}} {}",
+                        worker, upstream, version, status, n
+                    );
+                }
+            }
+        }
+        
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} {}", name, kind);
+}
+
+fn count_status(requests: &[(&str, &str, u64)], matches: impl Fn(&str) -> bool) -> u64 {
+    requests.iter().filter(|(_, status, _)| matches(status)).map(|(_, _, n)| n).sum()
+}
+
+// ==============================================================================
+// per-isolate buffer
+// ==============================================================================
+
+thread_local! {
+    // wasm isolates are single-threaded, so a thread-local is the whole isolate
+    static PENDING: RefCell<MetricsSnapshot> = RefCell::new(MetricsSnapshot::default());
+}
+
+/// count a handled request in this isolate's buffer
+pub fn record_request(route: &str, status: u16, latency_ms: f64) {
+    PENDING.with(|p| p.borrow_mut().record_request(route, status, latency_ms));
+}
+
+/// count a kv operation in this isolate's buffer
+pub fn record_kv_op(op: &str) {
+    PENDING.with(|p| p.borrow_mut().record_kv_op(op));
+}
+
+/// count a request the gateway proxied to `version` of `upstream`
+pub fn record_upstream(upstream: &str, version: &str, status: u16) {
+    PENDING.with(|p| p.borrow_mut().record_upstream(upstream, version, status));
+}
+
+/// take everything buffered so far, leaving the buffer empty
+pub fn take_pending() -> MetricsSnapshot {
+    PENDING.with(|p| std::mem::take(&mut *p.borrow_mut()))
+}
+
+/// put a snapshot back after a failed flush, so it's retried next time
+pub fn restore_pending(snapshot: &MetricsSnapshot) {
+    PENDING.with(|p| p.borrow_mut().merge(snapshot));
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_histogram_buckets() {
+        let mut h = Histogram::default();
+        h.observe(3.0);
+        h.observe(10.0);
+        h.observe(9000.0);
+        assert_eq!(h.buckets[0], 1);
+        assert_eq!(h.buckets[1], 1);
+        // beyond the last bucket only shows up in +Inf
+        assert_eq!(h.buckets.iter().sum::<u64>(), 2);
+        assert_eq!(h.count, 3);
+    }
+    
+    #[test]
+    fn test_merge_snapshots() {
+        let mut a = MetricsSnapshot::default();
+        a.record_request("/shorten", 200, 12.0);
+        a.record_kv_op("get");
+        let mut b = MetricsSnapshot::default();
+        b.record_request("/shorten", 200, 40.0);
+        b.record_request("/shorten", 429, 2.0);
+        
+        a.merge(&b);
+        assert_eq!(a.requests["/shorten 200"], 2);
+        assert_eq!(a.requests["/shorten 429"], 1);
+        assert_eq!(a.kv_ops["get"], 1);
+        assert_eq!(a.latency["/shorten"].count, 3);
+        
+        b.record_upstream("url-shortener", "canary", 500);
+        a.merge(&b);
+        assert_eq!(a.upstream_requests["url-shortener canary 500"], 1);
+    }
+    
+    #[test]
+    fn test_render_prometheus() {
+        let mut m = MetricsSnapshot::default();
+        m.record_request("/api/protected", 200, 20.0);
+        m.record_request("/api/protected", 429, 4.0);
+        m.record_request("/api/protected", 500, 300.0);
+        m.record_kv_op("put");
+        
+        let text = m.render_prometheus("rate-limiter");
+        assert!(text.contains("# TYPE edge_requests_total counter"));
+        assert!(text.contains("edge_requests_total{worker=\"rate-limiter\",route=\"/api/protected\",status=\"429\"} 1"));
+        assert!(text.contains("edge_errors_total{worker=\"rate-limiter\"} 1"));
+        assert!(text.contains("edge_rate_limited_total{worker=\"rate-limiter\"} 1"));
+        assert!(text.contains("edge_kv_operations_total{worker=\"rate-limiter\",op=\"put\"} 1"));
+        // buckets are cumulative and end with +Inf
+        assert!(text.contains("le=\"5\"} 1"));
+        assert!(text.contains("le=\"25\"} 2"));
+        assert!(text.contains("le=\"+Inf\"} 3"));
+        assert!(text.contains("edge_request_duration_ms_count{worker=\"rate-limiter\",route=\"/api/protected\"} 3"));
+        // only the gateway has per-version series
+        assert!(!text.contains("edge_upstream_requests_total"));
+        
+        m.record_upstream("url-shortener", "canary", 502);
+        let text = m.render_prometheus("gateway");
+        assert!(text.contains("edge_upstream_requests_total{worker=\"gateway\",upstream=\"url-shortener\",version=\"canary\",status=\"502\"} 1"));
+    }
+    
+    #[test]
+    fn test_snapshot_round_trips_as_json() {
+        let mut m = MetricsSnapshot::default();
+        m.record_request("/health", 200, 1.0);
+        let json = serde_json::to_string(&m).unwrap();
+        assert_eq!(serde_json::from_str::<MetricsSnapshot>(&json).unwrap(), m);
+        assert!(!json.contains("upstream_requests"));
+        
+        m.record_upstream("gateway", "stable", 200);
+        let json = serde_json::to_string(&m).unwrap();
+        assert_eq!(serde_json::from_str::<MetricsSnapshot>(&json).unwrap(), m);
+    }
+    
+    #[test]
+    fn test_pending_buffer() {
+        record_request("/health", 200, 1.0);
+        record_kv_op("get");
+        let taken = take_pending();
+        assert_eq!(taken.requests["/health 200"], 1);
+        assert!(take_pending().is_empty());
+        
+        restore_pending(&taken);
+        assert_eq!(take_pending(), taken);
+    }
+}
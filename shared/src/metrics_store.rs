@@ -0,0 +1,136 @@
+//! ==============================================================================
+//! metrics_store.rs - durable object holding each worker's metric totals
+//! ==============================================================================
+//!
+//! purpose:
+//!     isolates come and go, so their in-memory counters (see metrics.rs)
+//!     are merged into one durable object per worker. /metrics reads the
+//!     totals back and renders them for prometheus.
+//!
+//! relationships:
+//!     - used by: every worker (flush_if_due in fetch, the /metrics route)
+//!     - used by: gateway (GET /canary reads the per-version totals)
+//!     - binding: METRICS -> class MetricsStore (declared in each wrangler.toml)
+//!
+//! protocol (internal, worker -> object):
+//!     POST /  body: MetricsSnapshot json, merged into the totals
+//!     GET  /  response: MetricsSnapshot json of the totals
+//!
+//! ==============================================================================
+
+use std::cell::Cell;
+
+use worker::*;
+
+use crate::metrics::{self, MetricsSnapshot};
+
+/// binding name every worker uses for the metrics durable object
+pub const METRICS_BINDING: &str = "METRICS";
+/// how often an isolate pushes its buffered counters
+const FLUSH_INTERVAL_MS: f64 = 10_000.0;
+/// storage key of the totals inside the object
+const TOTALS_KEY: &str = "totals";
+/// the worker's store as a GET /health/deps dependency
+pub const HEALTH_DEPENDENCY: crate::health::Dependency = crate::health::Dependency::DurableObject(METRICS_BINDING, TOTALS_KEY);
+
+thread_local! {
+    static LAST_FLUSH: Cell<f64> = const { Cell::new(0.0) };
+}
+
+// ==============================================================================
+// durable object
+// ==============================================================================
+
+#[durable_object]
+pub struct MetricsStore {
+    state: State,
+}
+
+impl DurableObject for MetricsStore {
+    fn new(state: State, _env: Env) -> Self {
+        Self { state }
+    }
+    
+    async fn fetch(&self, mut req: Request) -> Result<Response> {
+        let storage = self.state.storage();
+        let mut totals: MetricsSnapshot = storage.get(TOTALS_KEY).await?.unwrap_or_default();
+        
+        if req.method() == Method::Post {
+            let delta: MetricsSnapshot = req.json().await?;
+            totals.merge(&delta);
+            storage.put(TOTALS_KEY, &totals).await?;
+        }
+        Response::from_json(&totals)
+    }
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// push this isolate's buffered counters in the background, at most every
+/// FLUSH_INTERVAL_MS so counting never sits on the request path
+pub fn flush_if_due(env: &Env, ctx: &Context) {
+    let now = js_sys::Date::now();
+    if now - LAST_FLUSH.with(Cell::get) < FLUSH_INTERVAL_MS {
+        return;
+    }
+    LAST_FLUSH.with(|last| last.set(now));
+    
+    let env = env.clone();
+    ctx.wait_until(async move {
+        if let Err(e) = flush(&env).await {
+            console_warn!("metrics flush failed: {}", e);
+        }
+    });
+}
+
+/// merge buffered counters into the durable object; on failure they go
+/// back into the buffer for the next attempt
+pub async fn flush(env: &Env) -> Result<()> {
+    let pending = metrics::take_pending();
+    if pending.is_empty() {
+        return Ok(());
+    }
+    
+    let body = serde_json::to_string(&pending)?;
+    let init = RequestInit {
+        method: Method::Post,
+        body: Some(body.into()),
+        ..RequestInit::default()
+    };
+    let result = async {
+        let req = Request::new_with_init("https://metrics.internal/", &init)?;
+        stub(env)?.fetch_with_request(req).await
+    }
+    .await;
+    
+    if result.is_err() {
+        metrics::restore_pending(&pending);
+    }
+    result.map(|_| ())
+}
+
+/// flush this isolate, then read the worker's totals back
+pub async fn totals(env: &Env) -> Result<MetricsSnapshot> {
+    if let Err(e) = flush(env).await {
+        console_warn!("metrics flush failed: {}", e);
+    }
+    stub(env)?.fetch_with_str("https://metrics.internal/").await?.json().await
+}
+
+/// GET /metrics - the totals for prometheus
+pub async fn metrics_response(env: &Env, worker: &str) -> Result<Response> {
+    let totals = totals(env).await?;
+    
+    let headers = Headers::new();
+    headers.set("Content-Type", "text/plain; version=0.0.4; charset=utf-8")?;
+    headers.set("Cache-Control", "no-store")?;
+    Ok(Response::ok(totals.render_prometheus(worker))?.with_headers(headers))
+}
+
+fn stub(env: &Env) -> Result<Stub> {
+    env.durable_object(METRICS_BINDING)?
+        .id_from_name(TOTALS_KEY)?
+        .get_stub()
+}
@@ -0,0 +1,123 @@
+//! ==============================================================================
+//! mode.rs - enforcing limits or only watching them (shadow mode)
+//! ==============================================================================
+//!
+//! purpose:
+//!     turning a limiter on in front of real traffic with a guessed limit is
+//!     how legitimate clients find out the guess was wrong. with MODE=shadow
+//!     the rate limiter still evaluates every request - counts, headers,
+//!     analytics - but serves the ones it would have refused, so the
+//!     decisions dataset shows what enforcing would do before it does.
+//!
+//! relationships:
+//!     - used by: rate-limiter (/api/protected and, in proxy mode, every
+//!       proxied path), decisions (blob4 of every data point)
+//!
+//! shadow mode:
+//!     a request that would get a 429 - window, quota or concurrency - is
+//!     served instead, keeping the X-RateLimit-* headers the 429 had and
+//!     naming the held-back outcome in X-RateLimit-Shadow. nothing else is
+//!     counted for it, so counters match what enforcing would leave behind.
+//!     denied ips and revoked or unknown keys still get their 403 or 401.
+//!
+//! ==============================================================================
+
+use serde::{Deserialize, Serialize};
+
+use crate::decisions::Outcome;
+
+/// env var selecting the mode - unset or unknown enforces
+pub const MODE_VAR: &str = "MODE";
+/// response header naming the outcome shadow mode held back
+pub const SHADOW_HEADER: &str = "X-RateLimit-Shadow";
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// whether refusals are returned or only recorded
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    /// 429s go out
+    #[default]
+    Enforce,
+    /// 429s are recorded and the request is served anyway
+    Shadow,
+}
+
+impl Mode {
+    /// MODE's value - anything unknown enforces
+    pub fn parse(value: &str) -> Self {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "shadow" | "dry_run" | "dry-run" => Self::Shadow,
+            _ => Self::Enforce,
+        }
+    }
+    
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Enforce => "enforce",
+            Self::Shadow => "shadow",
+        }
+    }
+    
+    /// whether a request decided as `outcome` is served instead of refused
+    pub fn holds_back(&self, outcome: Outcome) -> bool {
+        *self == Self::Shadow && outcome.limited()
+    }
+}
+
+/// whether a held-back 429's header goes on the response served instead -
+/// the limit headers do, Retry-After and the problem's content type don't
+pub fn keeps_header(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name.starts_with("x-ratelimit-") || name.starts_with("ratelimit-")
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// the mode MODE selects (enforce when unset)
+#[cfg(feature = "worker")]
+pub fn mode(env: &worker::Env) -> Mode {
+    env.var(MODE_VAR).map(|v| Mode::parse(&v.to_string())).unwrap_or_default()
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse() {
+        assert_eq!(Mode::parse("shadow"), Mode::Shadow);
+        assert_eq!(Mode::parse(" Dry-Run "), Mode::Shadow);
+        assert_eq!(Mode::parse("enforce"), Mode::Enforce);
+        assert_eq!(Mode::parse(""), Mode::Enforce);
+        assert_eq!(Mode::parse("shdow"), Mode::Enforce);
+        assert_eq!(serde_json::to_value(Mode::Shadow).unwrap(), Mode::Shadow.as_str());
+    }
+    
+    #[test]
+    fn test_holds_back_only_limits_in_shadow() {
+        assert!(Mode::Shadow.holds_back(Outcome::RateLimited));
+        assert!(Mode::Shadow.holds_back(Outcome::QuotaExceeded));
+        assert!(Mode::Shadow.holds_back(Outcome::ConcurrencyLimited));
+        assert!(!Mode::Shadow.holds_back(Outcome::Denied));
+        assert!(!Mode::Shadow.holds_back(Outcome::Allowed));
+        assert!(!Mode::Enforce.holds_back(Outcome::RateLimited));
+    }
+    
+    #[test]
+    fn test_keeps_limit_headers() {
+        assert!(keeps_header("X-RateLimit-Remaining"));
+        assert!(keeps_header("ratelimit-policy"));
+        assert!(!keeps_header("Retry-After"));
+        assert!(!keeps_header("Content-Type"));
+    }
+}
@@ -0,0 +1,199 @@
+//! ==============================================================================
+//! notify.rs - security notifications posted to a webhook
+//! ==============================================================================
+//!
+//! purpose:
+//!     alert emails (shared::alerting) are for operators and throttled per
+//!     kind; security teams want every incident, with the details, in the
+//!     tool they already watch. a worker records a Notification and it is
+//!     posted as json to the webhook after the response is on its way.
+//!
+//! relationships:
+//!     - used by: rate-limiter (abuse thresholds, shared::abuse)
+//!     - uses: auth_guard (hmac_sha256_hex signs the body)
+//!     - calls: NOTIFY_WEBHOOK_URL
+//!
+//! configuration (per worker):
+//!     var NOTIFY_WEBHOOK_URL        https url notifications are POSTed to -
+//!                                   empty turns them off (they're only logged)
+//!     secret NOTIFY_WEBHOOK_SECRET  when set, every body is signed:
+//!                                   X-Notify-Signature: sha256=<hex hmac-sha256>
+//!
+//! delivery:
+//!     like analytics events, notifications go into a per-isolate buffer and
+//!     fetch sends them with ctx.wait_until. a failed post is logged and
+//!     dropped - whatever raised it decides when it is worth raising again.
+//!     the body carries "text", so a slack-style incoming webhook shows the
+//!     one-liner without a relay in between.
+//!
+//! ==============================================================================
+
+use std::cell::RefCell;
+
+use serde::{Deserialize, Serialize};
+
+/// env var with the webhook
+pub const WEBHOOK_VAR: &str = "NOTIFY_WEBHOOK_URL";
+/// secret the body is signed with
+pub const SECRET_NAME: &str = "NOTIFY_WEBHOOK_SECRET";
+/// header carrying the body's signature
+pub const SIGNATURE_HEADER: &str = "X-Notify-Signature";
+/// notifications an isolate holds between sends; older ones are dropped
+const MAX_PENDING: usize = 100;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// one notification, the webhook's json body
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Notification {
+    /// what happened, e.g. "abuse_threshold"
+    pub event: String,
+    pub worker: String,
+    /// one line for people
+    pub text: String,
+    /// the event's fields, for machines
+    pub details: serde_json::Value,
+    /// the request that raised it
+    pub request_id: String,
+    /// unix ms
+    pub at: u64,
+}
+
+/// the webhook NOTIFY_WEBHOOK_URL names - none when empty or not https
+pub fn parse_webhook(value: &str) -> Option<String> {
+    let value = value.trim();
+    let host = value.strip_prefix("https://")?;
+    (!host.is_empty() && !host.starts_with('/')).then(|| value.to_string())
+}
+
+/// X-Notify-Signature's value for a body with hmac `hex`
+pub fn signature_header(hex: &str) -> String {
+    format!("sha256={}", hex)
+}
+
+// ==============================================================================
+// per-isolate buffer
+// ==============================================================================
+
+thread_local! {
+    static PENDING: RefCell<Vec<Notification>> = const { RefCell::new(Vec::new()) };
+}
+
+/// buffer a notification for the next send
+pub fn record(notification: Notification) {
+    PENDING.with(|p| {
+        let mut pending = p.borrow_mut();
+        pending.push(notification);
+        if pending.len() > MAX_PENDING {
+            let excess = pending.len() - MAX_PENDING;
+            pending.drain(..excess);
+        }
+    });
+}
+
+/// take everything buffered so far, leaving the buffer empty
+pub fn take_pending() -> Vec<Notification> {
+    PENDING.with(|p| std::mem::take(&mut *p.borrow_mut()))
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// post this isolate's buffered notifications in the background. without a
+/// webhook they're logged and dropped
+#[cfg(feature = "worker")]
+pub fn flush(env: &worker::Env, ctx: &worker::Context) {
+    let notifications = take_pending();
+    if notifications.is_empty() {
+        return;
+    }
+    let webhook = env.var(WEBHOOK_VAR).ok().and_then(|v| parse_webhook(&v.to_string()));
+    let Some(webhook) = webhook else {
+        for n in &notifications {
+            worker::console_log!("notifications off - {}: {}", n.event, n.text);
+        }
+        return;
+    };
+    let secret = env.secret(SECRET_NAME).ok().map(|s| s.to_string());
+    
+    ctx.wait_until(async move {
+        for n in notifications {
+            if let Err(e) = post(&webhook, secret.as_deref(), &n).await {
+                worker::console_warn!("notification {} failed: {}", n.event, e);
+            }
+        }
+    });
+}
+
+#[cfg(feature = "worker")]
+async fn post(webhook: &str, secret: Option<&str>, notification: &Notification) -> worker::Result<()> {
+    use worker::{Fetch, Headers, Method, Request, RequestInit};
+    
+    let body = serde_json::to_string(notification)?;
+    let headers = Headers::new();
+    headers.set("Content-Type", "application/json")?;
+    if let Some(secret) = secret {
+        let hex = crate::auth_guard::hmac_sha256_hex(secret.as_bytes(), body.as_bytes()).await?;
+        headers.set(SIGNATURE_HEADER, &signature_header(&hex))?;
+    }
+    let init = RequestInit { method: Method::Post, headers, body: Some(body.into()), ..RequestInit::default() };
+    let mut resp = Fetch::Request(Request::new_with_init(webhook, &init)?).send().await?;
+    if !(200..300).contains(&resp.status_code()) {
+        let detail = resp.text().await.unwrap_or_default();
+        return Err(worker::Error::from(format!("webhook answered {}: {}", resp.status_code(), detail)));
+    }
+    Ok(())
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn notification(i: u64) -> Notification {
+        Notification {
+            event: "abuse_threshold".to_string(),
+            worker: "rate-limiter".to_string(),
+            text: "ip:192.0.2.1 blocked 100 times in 10 minutes".to_string(),
+            details: serde_json::json!({"client": "ip:192.0.2.1"}),
+            request_id: format!("18bcfe56800-{}", i),
+            at: i,
+        }
+    }
+    
+    #[test]
+    fn test_parse_webhook() {
+        assert_eq!(parse_webhook(" https://hooks.example.com/T00/B00 "), Some("https://hooks.example.com/T00/B00".to_string()));
+        assert_eq!(parse_webhook(""), None);
+        // bodies can name clients - never in the clear
+        assert_eq!(parse_webhook("http://hooks.example.com/x"), None);
+        assert_eq!(parse_webhook("https://"), None);
+        assert_eq!(signature_header("ab12"), "sha256=ab12");
+    }
+    
+    #[test]
+    fn test_buffer_keeps_the_newest() {
+        take_pending();
+        for i in 0..(MAX_PENDING as u64 + 5) {
+            record(notification(i));
+        }
+        let pending = take_pending();
+        assert_eq!(pending.len(), MAX_PENDING);
+        assert_eq!(pending[0].at, 5);
+        assert!(take_pending().is_empty());
+    }
+    
+    #[test]
+    fn test_body_shape() {
+        let json = serde_json::to_value(notification(1)).unwrap();
+        assert_eq!(json["event"], "abuse_threshold");
+        assert_eq!(json["text"], "ip:192.0.2.1 blocked 100 times in 10 minutes");
+        assert_eq!(json["details"]["client"], "ip:192.0.2.1");
+    }
+}
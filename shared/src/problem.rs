@@ -0,0 +1,256 @@
+//! ==============================================================================
+//! problem.rs - rfc 9457 problem details for every worker error
+//! ==============================================================================
+//!
+//! purpose:
+//!     one error format for all workers: an application/problem+json body
+//!     with type, title, status, detail and the request id. cors headers are
+//!     added to it like any other response (see cors.rs).
+//!
+//! relationships:
+//!     - used by: every worker's error helper (cors_error / problem)
+//!     - counterpart: dashboard api::ApiError, which decodes these bodies
+//!
+//! body:
+//!     {
+//!       "type": "tag:edge-protocol-demo,2025:problems/rate_limited",
+//!       "title": "Too Many Requests",
+//!       "status": 429,
+//!       "detail": "rate limit exceeded - try again later",
+//!       "instance": "urn:request-id:18bcfe56800-deadbeef",
+//!       "request_id": "18bcfe56800-deadbeef"
+//!     }
+//!     `type` is a tag uri (rfc 4151) - it names the problem but isn't meant
+//!     to be fetched. its last segment is a stable machine-readable code.
+//!     extra members (e.g. retry_after_seconds on 429s) sit alongside.
+//!
+//! ==============================================================================
+
+use serde::{Deserialize, Serialize};
+
+pub const CONTENT_TYPE: &str = "application/problem+json";
+/// prefix of every problem `type`; the code follows it
+pub const TYPE_PREFIX: &str = "tag:edge-protocol-demo,2025:problems/";
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// an rfc 9457 problem details object
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub instance: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    /// extension members, serialized next to the standard ones
+    #[serde(flatten)]
+    pub extensions: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Problem {
+    /// problem for `status`, typed with the status's default code
+    pub fn new(status: u16, detail: &str) -> Self {
+        Self {
+            kind: format!("{}{}", TYPE_PREFIX, default_code(status)),
+            title: title(status).to_string(),
+            status,
+            detail: detail.to_string(),
+            instance: None,
+            request_id: None,
+            extensions: serde_json::Map::new(),
+        }
+    }
+    
+    /// a more specific code than the status implies, e.g. "alias_taken"
+    pub fn with_code(mut self, code: &str) -> Self {
+        self.kind = format!("{}{}", TYPE_PREFIX, code);
+        self
+    }
+    
+    pub fn with_request_id(mut self, request_id: &str) -> Self {
+        self.instance = Some(format!("urn:request-id:{}", request_id));
+        self.request_id = Some(request_id.to_string());
+        self
+    }
+    
+    pub fn with_extension(mut self, key: &str, value: impl Into<serde_json::Value>) -> Self {
+        self.extensions.insert(key.to_string(), value.into());
+        self
+    }
+    
+    /// the code at the end of `type`
+    pub fn code(&self) -> &str {
+        self.kind.strip_prefix(TYPE_PREFIX).unwrap_or(&self.kind)
+    }
+    
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).unwrap_or_default()
+    }
+    
+    /// headers every problem response carries - errors must never be cached.
+    /// cors comes from shared::cors, which each worker applies to every response
+    pub fn headers(&self) -> [(&'static str, &'static str); 2] {
+        [
+            ("Content-Type", CONTENT_TYPE),
+            ("Cache-Control", "no-store"),
+        ]
+    }
+}
+
+/// machine-readable code for a status, matching the dashboard's fallbacks
+pub fn default_code(status: u16) -> &'static str {
+    match status {
+        400 => "bad_request",
+        401 => "unauthorized",
+        403 => "forbidden",
+        404 => "not_found",
+        405 => "method_not_allowed",
+        409 => "conflict",
+        413 => "payload_too_large",
+        426 => "upgrade_required",
+        429 => "rate_limited",
+        502 => "bad_gateway",
+        503 => "unavailable",
+        500..=599 => "internal_error",
+        _ => "http_error",
+    }
+}
+
+/// the status's reason phrase, used as the problem title
+pub fn title(status: u16) -> &'static str {
+    match status {
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        409 => "Conflict",
+        413 => "Content Too Large",
+        426 => "Upgrade Required",
+        429 => "Too Many Requests",
+        502 => "Bad Gateway",
+        503 => "Service Unavailable",
+        500..=599 => "Internal Server Error",
+        _ => "Request Failed",
+    }
+}
+
+/// whether a response must be rewritten as a problem - an error status whose
+/// body isn't one already (the router's plain-text 404/405s, upstream errors)
+pub fn needs_wrapping(status: u16, content_type: Option<&str>) -> bool {
+    status >= 400 && !content_type.is_some_and(|ct| ct.starts_with(CONTENT_TYPE))
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+#[cfg(feature = "worker")]
+impl Problem {
+    pub fn into_response(self) -> worker::Result<worker::Response> {
+        let headers = worker::Headers::new();
+        for (name, value) in self.headers() {
+            headers.set(name, value)?;
+        }
+        Ok(worker::Response::ok(self.to_json())?
+            .with_status(self.status)
+            .with_headers(headers))
+    }
+}
+
+/// make sure an error response is problem+json: anything else (the router's
+/// text 404s, a plain upstream failure) is rewrapped, keeping its text as detail
+#[cfg(feature = "worker")]
+pub async fn ensure_problem(mut resp: worker::Response, request_id: &str) -> worker::Result<worker::Response> {
+    let status = resp.status_code();
+    if !needs_wrapping(status, resp.headers().get("Content-Type")?.as_deref()) {
+        return Ok(resp);
+    }
+    let text = resp.text().await.unwrap_or_default();
+    let detail = match text.trim() {
+        "" => title(status).to_lowercase(),
+        t => t.to_lowercase(),
+    };
+    Problem::new(status, &detail).with_request_id(request_id).into_response()
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    /// every status a worker currently answers errors with
+    const ERROR_STATUSES: &[u16] = &[400, 401, 403, 404, 405, 409, 413, 429, 500, 502, 503];
+    
+    #[test]
+    fn test_problem_body_shape() {
+        let p = Problem::new(429, "rate limit exceeded - try again later").with_request_id("18bcfe56800-deadbeef");
+        let json: serde_json::Value = serde_json::from_str(&p.to_json()).unwrap();
+        assert_eq!(json["type"], "tag:edge-protocol-demo,2025:problems/rate_limited");
+        assert_eq!(json["title"], "Too Many Requests");
+        assert_eq!(json["status"], 429);
+        assert_eq!(json["detail"], "rate limit exceeded - try again later");
+        assert_eq!(json["instance"], "urn:request-id:18bcfe56800-deadbeef");
+        assert_eq!(json["request_id"], "18bcfe56800-deadbeef");
+    }
+    
+    #[test]
+    fn test_every_error_status_is_problem_json() {
+        for &status in ERROR_STATUSES {
+            let p = Problem::new(status, "x").with_request_id("id");
+            let headers = p.headers();
+            assert!(headers.contains(&("Content-Type", CONTENT_TYPE)), "{} content type", status);
+            // allow-origin is left to the cors policy, which must not be overridden here
+            assert!(!headers.iter().any(|(name, _)| name.starts_with("Access-Control")), "{} cors", status);
+            assert!(!headers.iter().any(|(_, v)| v.starts_with("text/plain")), "{} text/plain", status);
+            
+            // a real title and code, not the catch-alls
+            assert_ne!(p.title, "Request Failed", "{} title", status);
+            assert_ne!(p.code(), "http_error", "{} code", status);
+            assert_eq!(p.status, status);
+        }
+    }
+    
+    #[test]
+    fn test_custom_code_and_extensions() {
+        let p = Problem::new(409, "alias already in use")
+            .with_code("alias_taken")
+            .with_extension("retry_after_seconds", 45)
+            .with_extension("limit", 10);
+        assert_eq!(p.code(), "alias_taken");
+        
+        let json: serde_json::Value = serde_json::from_str(&p.to_json()).unwrap();
+        assert_eq!(json["retry_after_seconds"], 45);
+        assert_eq!(json["limit"], 10);
+        // no request id means no instance either
+        assert!(json.get("instance").is_none());
+    }
+    
+    #[test]
+    fn test_round_trips_as_json() {
+        let p = Problem::new(404, "short url not found").with_request_id("abc").with_extension("code", "abc123");
+        assert_eq!(serde_json::from_str::<Problem>(&p.to_json()).unwrap(), p);
+    }
+    
+    #[test]
+    fn test_needs_wrapping() {
+        // the router's own 404/405 bodies are text/plain
+        assert!(needs_wrapping(404, Some("text/plain;charset=UTF-8")));
+        assert!(needs_wrapping(500, None));
+        // the old json error shape is rewrapped too
+        assert!(needs_wrapping(400, Some("application/json")));
+        
+        assert!(!needs_wrapping(429, Some("application/problem+json")));
+        assert!(!needs_wrapping(200, Some("text/plain")));
+        assert!(!needs_wrapping(301, None));
+    }
+}
@@ -0,0 +1,182 @@
+//! ==============================================================================
+//! proxy.rs - forwarding allowed requests to an origin
+//! ==============================================================================
+//!
+//! purpose:
+//!     with ORIGIN_URL set, the rate limiter stops being a demo: every
+//!     request that passes its checks is sent on to the origin - method,
+//!     path, query, headers and body - and the origin's answer goes back
+//!     with the X-RateLimit-* headers added. put the worker on a route in
+//!     front of an api and it shields that api.
+//!
+//! relationships:
+//!     - used by: rate-limiter (/api/protected and, in proxy mode, every
+//!       path the worker doesn't serve itself)
+//!
+//! forwarding:
+//!     ORIGIN_URL is an http(s) base; its path is a prefix, so
+//!     "https://api.example.com/v1" gets /users?page=2 as /v1/users?page=2.
+//!     hop-by-hop headers and the limiter's own (bypass and turnstile
+//!     tokens) stay behind. X-Forwarded-For/-Host/-Proto and X-Request-Id
+//!     are set. redirects go back to the client, not through the worker.
+//!
+//! ==============================================================================
+
+/// env var with the origin - unset or empty keeps the demo payload
+pub const ORIGIN_VAR: &str = "ORIGIN_URL";
+/// request bodies the worker buffers for the origin
+pub const MAX_BODY_BYTES: usize = 1024 * 1024;
+
+/// headers that describe one connection, not the message
+const HOP_BY_HOP: &[&str] = &[
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "proxy-connection",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// the limiter's own request headers - meaningless to the origin
+const LIMITER_HEADERS: &[&str] = &["x-bypass-token", "x-turnstile-token"];
+
+/// ORIGIN_URL as a base without a trailing slash - none when empty,
+/// an error when it isn't an http(s) url without query or fragment
+pub fn parse_origin(value: &str) -> Result<Option<String>, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+    let Some(rest) = value.strip_prefix("https://").or_else(|| value.strip_prefix("http://")) else {
+        return Err(format!("{} must start with http:// or https://", ORIGIN_VAR));
+    };
+    if rest.is_empty() || rest.starts_with('/') || value.contains(['?', '#']) {
+        return Err(format!("{} must be a base url like https://api.example.com", ORIGIN_VAR));
+    }
+    Ok(Some(value.trim_end_matches('/').to_string()))
+}
+
+/// the origin url a request for `path` (and `query`) goes to
+pub fn target(origin: &str, path: &str, query: Option<&str>) -> String {
+    match query {
+        Some(q) if !q.is_empty() => format!("{}{}?{}", origin, path, q),
+        _ => format!("{}{}", origin, path),
+    }
+}
+
+/// whether a client's request header goes on to the origin
+pub fn forwards_request_header(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    name != "host" && !HOP_BY_HOP.contains(&name.as_str()) && !LIMITER_HEADERS.contains(&name.as_str())
+}
+
+/// whether an origin's response header goes back to the client
+pub fn forwards_response_header(name: &str) -> bool {
+    !HOP_BY_HOP.contains(&name.to_ascii_lowercase().as_str())
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// the origin ORIGIN_URL names - none when it's unset, empty or invalid
+#[cfg(feature = "worker")]
+pub fn origin(env: &worker::Env) -> Option<String> {
+    let value = env.var(ORIGIN_VAR).ok()?.to_string();
+    parse_origin(&value).unwrap_or_else(|e| {
+        worker::console_warn!("ignoring {}: {}", ORIGIN_VAR, e);
+        None
+    })
+}
+
+/// send `req` (its body already read into `body`) to `origin`, and return
+/// the origin's response with `extra` headers added
+#[cfg(feature = "worker")]
+pub async fn forward(
+    req: &worker::Request,
+    body: Option<&[u8]>,
+    origin: &str,
+    request_id: &str,
+    extra: &worker::Headers,
+) -> worker::Result<worker::Response> {
+    use worker::{Fetch, Headers, Request, RequestInit, RequestRedirect, Response};
+    
+    let url = req.url()?;
+    let headers = Headers::new();
+    for (name, value) in req.headers().entries() {
+        if forwards_request_header(&name) {
+            headers.append(&name, &value)?;
+        }
+    }
+    if let Some(ip) = req.headers().get("CF-Connecting-IP")? {
+        headers.set("X-Forwarded-For", &ip)?;
+    }
+    headers.set("X-Forwarded-Host", url.host_str().unwrap_or_default())?;
+    headers.set("X-Forwarded-Proto", url.scheme())?;
+    headers.set(crate::REQUEST_ID_HEADER, request_id)?;
+    
+    let mut init = RequestInit::new();
+    init.with_method(req.method())
+        .with_headers(headers)
+        .with_redirect(RequestRedirect::Manual);
+    if let Some(body) = body {
+        init.with_body(Some(worker::js_sys::Uint8Array::from(body).into()));
+    }
+    let forwarded = Request::new_with_init(&target(origin, url.path(), url.query()), &init)?;
+    let mut resp = Fetch::Request(forwarded).send().await?;
+    
+    // fetched responses have immutable headers - copy into a fresh one
+    let status = resp.status_code();
+    let headers = Headers::new();
+    for (name, value) in resp.headers().entries() {
+        if forwards_response_header(&name) {
+            headers.append(&name, &value)?;
+        }
+    }
+    for (name, value) in extra.entries() {
+        headers.set(&name, &value)?;
+    }
+    let body = resp.bytes().await?;
+    let out = if body.is_empty() { Response::empty()? } else { Response::from_bytes(body)? };
+    Ok(out.with_status(status).with_headers(headers))
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_parse_origin() {
+        assert_eq!(parse_origin(""), Ok(None));
+        assert_eq!(parse_origin(" https://api.example.com/ "), Ok(Some("https://api.example.com".to_string())));
+        assert_eq!(parse_origin("http://10.0.0.2:8080/v1"), Ok(Some("http://10.0.0.2:8080/v1".to_string())));
+        assert!(parse_origin("api.example.com").is_err());
+        assert!(parse_origin("https://").is_err());
+        assert!(parse_origin("https://api.example.com/?debug=1").is_err());
+    }
+    
+    #[test]
+    fn test_target_keeps_path_and_query() {
+        assert_eq!(target("https://api.example.com", "/users", Some("page=2")), "https://api.example.com/users?page=2");
+        assert_eq!(target("https://api.example.com/v1", "/users", None), "https://api.example.com/v1/users");
+        assert_eq!(target("https://api.example.com", "/", Some("")), "https://api.example.com/");
+    }
+    
+    #[test]
+    fn test_header_filters() {
+        assert!(forwards_request_header("Authorization"));
+        assert!(forwards_request_header("X-API-Key"));
+        assert!(!forwards_request_header("Host"));
+        assert!(!forwards_request_header("Connection"));
+        assert!(!forwards_request_header("X-Bypass-Token"));
+        assert!(forwards_response_header("Set-Cookie"));
+        assert!(!forwards_response_header("Transfer-Encoding"));
+    }
+}
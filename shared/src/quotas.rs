@@ -0,0 +1,251 @@
+//! ==============================================================================
+//! quotas.rs - daily and monthly request quotas
+//! ==============================================================================
+//!
+//! purpose:
+//!     a per-minute window stops bursts, not a client that stays just under
+//!     it all month. real api plans sell so many requests a day or a month;
+//!     these are those quotas, next to the window limit.
+//!
+//! relationships:
+//!     - used by: rate-limiter (/api/protected, /api/status)
+//!     - uses: tiers (pro and enterprise multiply the quotas like the limit),
+//!       the kv namespace the worker passes in (RATES)
+//!
+//! configuration (rate-limiter):
+//!     var DAILY_QUOTA     free-tier requests per utc day - 0 turns it off
+//!     var MONTHLY_QUOTA   free-tier requests per utc month - 0 turns it off
+//!
+//! storage:
+//!     one counter per client and calendar period, so a new day or month
+//!     starts from zero without a reset:
+//!         "quota:day:2026-10-16:key:<api key>"
+//!         "quota:month:2026-10:ip:192.0.2.1"
+//!     each expires EXPIRY_SLACK_SECS after its period ends. the rate
+//!     limiter reads and writes them within the client's turn
+//!     (shared::client_queue), but like the kv window, requests racing in
+//!     from other isolates can undercount.
+//!
+//! ==============================================================================
+
+use serde::{Deserialize, Serialize};
+
+use crate::tiers::KeyTier;
+
+/// env var with the free tier's daily quota
+pub const DAILY_VAR: &str = "DAILY_QUOTA";
+/// env var with the free tier's monthly quota
+pub const MONTHLY_VAR: &str = "MONTHLY_QUOTA";
+/// kv prefix of quota counters
+pub const QUOTA_KEY_PREFIX: &str = "quota:";
+/// how long kv keeps a counter past its period
+pub const EXPIRY_SLACK_SECS: u64 = 3600;
+
+const SECS_PER_DAY: u64 = 86_400;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Period {
+    Day,
+    Month,
+}
+
+impl Period {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Month => "month",
+        }
+    }
+    
+    /// the utc calendar period `now` (unix seconds) falls in - "2026-10-16"
+    /// or "2026-10"
+    pub fn label(&self, now: u64) -> String {
+        let (year, month, day) = civil_from_days((now / SECS_PER_DAY) as i64);
+        match self {
+            Self::Day => format!("{:04}-{:02}-{:02}", year, month, day),
+            Self::Month => format!("{:04}-{:02}", year, month),
+        }
+    }
+    
+    /// seconds from `now` until the period ends
+    pub fn resets_in(&self, now: u64) -> u64 {
+        let end_day = match self {
+            Self::Day => now / SECS_PER_DAY + 1,
+            Self::Month => {
+                let (year, month, _) = civil_from_days((now / SECS_PER_DAY) as i64);
+                let (year, month) = if month == 12 { (year + 1, 1) } else { (year, month + 1) };
+                days_from_civil(year, month, 1) as u64
+            }
+        };
+        end_day * SECS_PER_DAY - now
+    }
+}
+
+/// kv key of `client_id`'s counter for the period `now` falls in
+pub fn kv_key(period: Period, client_id: &str, now: u64) -> String {
+    format!("{}{}:{}:{}", QUOTA_KEY_PREFIX, period.as_str(), period.label(now), client_id)
+}
+
+/// requests per day and per month - 0 means no quota
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quotas {
+    pub daily: u32,
+    pub monthly: u32,
+}
+
+impl Quotas {
+    /// this tier's quotas, from the free tier's
+    pub fn for_tier(&self, tier: KeyTier) -> Self {
+        Self { daily: self.daily.saturating_mul(tier.multiplier()), monthly: self.monthly.saturating_mul(tier.multiplier()) }
+    }
+    
+    /// the periods with a quota, and their limits
+    pub fn periods(&self) -> Vec<(Period, u32)> {
+        [(Period::Day, self.daily), (Period::Month, self.monthly)].into_iter().filter(|(_, limit)| *limit > 0).collect()
+    }
+}
+
+/// where a client stands in one period - also the "quota" member of its 429
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct QuotaUsage {
+    pub period: Period,
+    pub limit: u32,
+    pub used: u32,
+    pub remaining: u32,
+    pub reset_in_seconds: u64,
+}
+
+impl QuotaUsage {
+    pub fn new(period: Period, limit: u32, used: u32, now: u64) -> Self {
+        Self { period, limit, used, remaining: limit.saturating_sub(used), reset_in_seconds: period.resets_in(now) }
+    }
+    
+    pub fn exhausted(&self) -> bool {
+        self.used >= self.limit
+    }
+}
+
+/// the first spent quota - the day's before the month's
+pub fn exhausted(usage: &[QuotaUsage]) -> Option<&QuotaUsage> {
+    usage.iter().find(|u| u.exhausted())
+}
+
+/// (year, month, day) of a day count since 1970-01-01 - howard hinnant's
+/// civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// days since 1970-01-01 of a date - the inverse of civil_from_days
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let doy = (153 * mp + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// DAILY_QUOTA and MONTHLY_QUOTA - unset or unparsable is 0, no quota
+#[cfg(feature = "worker")]
+pub fn configured(env: &worker::Env) -> Quotas {
+    let var = |name: &str| env.var(name).ok().and_then(|v| v.to_string().trim().parse::<u32>().ok()).unwrap_or(0);
+    Quotas { daily: var(DAILY_VAR), monthly: var(MONTHLY_VAR) }
+}
+
+/// where `client_id` stands in each period with a quota - no kv reads when
+/// there are none
+#[cfg(feature = "worker")]
+pub async fn usage(kv: &worker::kv::KvStore, client_id: &str, quotas: Quotas, now: u64) -> worker::Result<Vec<QuotaUsage>> {
+    let mut usage = Vec::new();
+    for (period, limit) in quotas.periods() {
+        crate::metrics::record_kv_op("get");
+        let used = kv.get(&kv_key(period, client_id, now)).text().await?.and_then(|v| v.parse::<u32>().ok()).unwrap_or(0);
+        usage.push(QuotaUsage::new(period, limit, used, now));
+    }
+    Ok(usage)
+}
+
+/// count one request against every quota in `usage` (as read by `usage`)
+#[cfg(feature = "worker")]
+pub async fn consume(kv: &worker::kv::KvStore, client_id: &str, usage: &[QuotaUsage], now: u64) -> worker::Result<()> {
+    for u in usage {
+        crate::metrics::record_kv_op("put");
+        kv.put(&kv_key(u.period, client_id, now), (u.used + 1).to_string())?
+            .expiration_ttl(u.reset_in_seconds + EXPIRY_SLACK_SECS)
+            .execute()
+            .await?;
+    }
+    Ok(())
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // 2026-10-16 13:30:00 utc
+    const NOW: u64 = 1_792_157_400;
+    
+    #[test]
+    fn test_calendar_periods() {
+        assert_eq!(Period::Day.label(NOW), "2026-10-16");
+        assert_eq!(Period::Month.label(NOW), "2026-10");
+        assert_eq!(Period::Day.resets_in(NOW), 37_800);
+        assert_eq!(Period::Month.resets_in(NOW), 15 * 86_400 + 37_800);
+        
+        // the last second of a year
+        assert_eq!(Period::Day.label(1_798_761_599), "2026-12-31");
+        assert_eq!(Period::Month.resets_in(1_798_761_599), 1);
+        assert_eq!(Period::Day.label(1_798_761_600), "2027-01-01");
+        
+        // 2028-02-28 12:00 - february has 29 days
+        assert_eq!(Period::Month.resets_in(1_835_352_000), 43_200 + 86_400);
+        assert_eq!(Period::Day.label(0), "1970-01-01");
+    }
+    
+    #[test]
+    fn test_kv_keys() {
+        assert_eq!(kv_key(Period::Day, "key:abc", NOW), "quota:day:2026-10-16:key:abc");
+        assert_eq!(kv_key(Period::Month, "ip:192.0.2.1", NOW), "quota:month:2026-10:ip:192.0.2.1");
+    }
+    
+    #[test]
+    fn test_quotas_and_usage() {
+        let base = Quotas { daily: 1000, monthly: 0 };
+        assert_eq!(base.periods(), vec![(Period::Day, 1000)]);
+        assert_eq!(base.for_tier(KeyTier::Pro), Quotas { daily: 10_000, monthly: 0 });
+        assert!(Quotas::default().periods().is_empty());
+        
+        let usage = vec![QuotaUsage::new(Period::Day, 5, 4, NOW), QuotaUsage::new(Period::Month, 100, 100, NOW)];
+        assert_eq!(usage[0].remaining, 1);
+        assert_eq!(exhausted(&usage).map(|u| u.period), Some(Period::Month));
+        assert_eq!(exhausted(&usage[..1]), None);
+        
+        let json = serde_json::to_value(&usage[1]).unwrap();
+        assert_eq!(json["period"], "month");
+        assert_eq!(json["remaining"], 0);
+    }
+}
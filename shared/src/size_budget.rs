@@ -0,0 +1,142 @@
+//! ==============================================================================
+//! size_budget.rs - how big each worker's wasm bundle may get
+//! ==============================================================================
+//!
+//! purpose:
+//!     a cold start downloads and compiles the whole wasm module, so bundle
+//!     size is start-up latency. every worker has a byte budget here; ci
+//!     measures the built bundles against it (integration/src/bin/wasm-size.rs)
+//!     and each worker's GET /version reports the budget it was built under.
+//!
+//! relationships:
+//!     - used by: build_info (size_budget_bytes in GET /version)
+//!     - used by: integration/src/bin/wasm-size.rs (the ci report)
+//!
+//! report:
+//!     {"within_budget": false,
+//!      "workers": [{"worker": "gateway", "bytes": 960512, "budget_bytes": 921600,
+//!                   "within_budget": false}]}
+//!
+//! design:
+//!     budgets are raw .wasm bytes as worker-build leaves them (after
+//!     wasm-opt), not the gzipped upload size cloudflare limits - raw is
+//!     what the isolate compiles. they sit a little above today's sizes,
+//!     so growth has to be a decision: raise the number in the same pr.
+//!
+//! ==============================================================================
+
+use serde::{Deserialize, Serialize};
+
+const KIB: u64 = 1024;
+
+/// (worker, max raw wasm bytes)
+pub const BUDGETS: &[(&str, u64)] = &[
+    ("url-shortener", 1_100 * KIB),
+    ("rate-limiter", 900 * KIB),
+    ("capability-demo", 700 * KIB),
+    ("log-collector", 700 * KIB),
+    ("analytics-consumer", 600 * KIB),
+    ("event-hub", 700 * KIB),
+    ("capture-archive", 800 * KIB),
+    ("gateway", 900 * KIB),
+];
+
+/// the budget for `worker`, if it has one
+pub fn budget(worker: &str) -> Option<u64> {
+    BUDGETS.iter().find(|(name, _)| *name == worker).map(|(_, bytes)| *bytes)
+}
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SizeCheck {
+    pub worker: String,
+    pub bytes: u64,
+    /// none for a worker without a budget - which fails the check
+    pub budget_bytes: Option<u64>,
+    pub within_budget: bool,
+}
+
+impl SizeCheck {
+    pub fn new(worker: &str, bytes: u64) -> Self {
+        let budget_bytes = budget(worker);
+        Self {
+            worker: worker.to_string(),
+            bytes,
+            budget_bytes,
+            within_budget: budget_bytes.is_some_and(|max| bytes <= max),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SizeReport {
+    pub within_budget: bool,
+    pub workers: Vec<SizeCheck>,
+}
+
+impl SizeReport {
+    /// `measured` is (worker, bundle bytes)
+    pub fn new(measured: &[(&str, u64)]) -> Self {
+        let workers: Vec<SizeCheck> = measured.iter().map(|(worker, bytes)| SizeCheck::new(worker, *bytes)).collect();
+        Self { within_budget: workers.iter().all(|w| w.within_budget), workers }
+    }
+    
+    /// one line per worker for ci logs
+    pub fn table(&self) -> String {
+        let mut lines = vec![format!("{:<20} {:>10} {:>10} {:>6}", "worker", "KiB", "budget", "used")];
+        for check in &self.workers {
+            let (budget, used) = match check.budget_bytes {
+                Some(max) => ((max / KIB).to_string(), format!("{}%", check.bytes * 100 / max.max(1))),
+                None => ("none".to_string(), "-".to_string()),
+            };
+            let flag = if check.within_budget { "" } else { "  OVER" };
+            lines.push(format!("{:<20} {:>10} {:>10} {:>6}{}", check.worker, check.bytes / KIB, budget, used, flag));
+        }
+        lines.join("\n")
+    }
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_every_worker_has_a_budget() {
+        for worker in ["url-shortener", "rate-limiter", "capability-demo", "log-collector",
+                       "analytics-consumer", "event-hub", "capture-archive", "gateway"] {
+            assert!(budget(worker).is_some_and(|b| b > 0), "{}", worker);
+        }
+        assert_eq!(budget("protocol-parser"), None);
+    }
+    
+    #[test]
+    fn test_report_flags_overages() {
+        let ok = SizeReport::new(&[("gateway", 100 * KIB), ("event-hub", 700 * KIB)]);
+        assert!(ok.within_budget);
+        
+        let over = SizeReport::new(&[("gateway", 100 * KIB), ("event-hub", 700 * KIB + 1)]);
+        assert!(!over.within_budget);
+        assert!(over.workers[0].within_budget && !over.workers[1].within_budget);
+        assert!(over.table().lines().nth(2).unwrap().ends_with("OVER"));
+        
+        // an unbudgeted bundle can't pass
+        let unknown = SizeReport::new(&[("new-worker", 1)]);
+        assert!(!unknown.within_budget);
+        assert!(unknown.table().contains("none"));
+    }
+    
+    #[test]
+    fn test_report_json_shape() {
+        let json = serde_json::to_value(SizeReport::new(&[("gateway", 2048)])).unwrap();
+        assert_eq!(json["within_budget"], true);
+        assert_eq!(json["workers"][0]["budget_bytes"], 900 * KIB);
+        assert_eq!(json["workers"][0]["bytes"], 2048);
+    }
+}
@@ -0,0 +1,232 @@
+//! ==============================================================================
+//! storage.rs - link storage over kv or d1
+//! ==============================================================================
+//!
+//! purpose:
+//!     one interface for the url shortener's link entries with two backends:
+//!     workers kv (the default) and d1. d1 keeps links in a relational table
+//!     next to the analytics events, so questions kv can't answer - click
+//!     totals joined with their links, aggregates across every link - are a
+//!     single query.
+//!
+//! relationships:
+//!     - used by: url-shortener (every link read and write, the cron purge)
+//!     - uses: d1 table link_clicks (kept by analytics-consumer) for stats
+//!     - schema: /migrations/0002_links.sql
+//!
+//! backend selection (per worker, by binding):
+//!     LINKS_DB bound      d1 - the links table (unless the "d1-links" flag is off)
+//!     otherwise           kv namespace URLS, as before
+//!
+//! design rationale:
+//!     a plain enum rather than a trait object: there are exactly two
+//!     backends and the worker never needs to add its own. switching a
+//!     deployment to d1 starts with an empty table - existing kv links are
+//!     not copied over.
+//!
+//! ==============================================================================
+
+use serde::Deserialize;
+
+use crate::UrlEntry;
+
+/// d1 binding that switches the link store to d1
+pub const LINKS_DB_BINDING: &str = "LINKS_DB";
+/// kv binding used when LINKS_DB isn't bound
+pub const LINKS_KV_BINDING: &str = "URLS";
+
+/// a link with its clicks from the analytics pipeline, in one query
+pub const SELECT_LINK_SQL: &str = "SELECT l.original_url, l.created_at, l.clicks, l.expires_at, \
+     COALESCE(c.clicks, 0) AS counted_clicks \
+     FROM links l LEFT JOIN link_clicks c ON c.code = l.code WHERE l.code = ?1";
+pub const UPSERT_LINK_SQL: &str = "INSERT INTO links (code, original_url, created_at, clicks, expires_at) \
+     VALUES (?1, ?2, ?3, ?4, ?5) ON CONFLICT (code) DO UPDATE SET original_url = excluded.original_url, \
+     created_at = excluded.created_at, clicks = excluded.clicks, expires_at = excluded.expires_at";
+pub const DELETE_LINK_SQL: &str = "DELETE FROM links WHERE code = ?1";
+/// d1 rows don't expire on their own - the cron handler runs this instead of a kv sweep
+pub const PURGE_EXPIRED_SQL: &str = "DELETE FROM links WHERE expires_at IS NOT NULL AND expires_at <= ?1";
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Kv,
+    D1,
+}
+
+impl Backend {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Kv => "kv",
+            Self::D1 => "d1",
+        }
+    }
+}
+
+/// a row of SELECT_LINK_SQL
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct LinkRow {
+    pub original_url: String,
+    pub created_at: u64,
+    pub clicks: u64,
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+    /// clicks counted by the analytics consumer
+    #[serde(default)]
+    pub counted_clicks: u64,
+}
+
+impl LinkRow {
+    /// the entry, with both click counts folded into `clicks`
+    pub fn into_entry(self) -> UrlEntry {
+        UrlEntry {
+            original_url: self.original_url,
+            created_at: self.created_at,
+            clicks: self.clicks + self.counted_clicks,
+            expires_at: self.expires_at,
+        }
+    }
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// where the worker's links live
+#[cfg(feature = "d1")]
+pub enum LinkStore {
+    Kv(worker::kv::KvStore),
+    D1(worker::D1Database),
+}
+
+#[cfg(feature = "d1")]
+impl LinkStore {
+    /// pick the backend from the worker's bindings: d1 if LINKS_DB is bound
+    /// and `allow_d1` (the d1-links flag) says so
+    pub fn from_env(env: &worker::Env, allow_d1: bool) -> worker::Result<Self> {
+        match env.d1(LINKS_DB_BINDING) {
+            Ok(db) if allow_d1 => Ok(Self::D1(db)),
+            _ => Ok(Self::Kv(env.kv(LINKS_KV_BINDING)?)),
+        }
+    }
+    
+    pub fn backend(&self) -> Backend {
+        match self {
+            Self::Kv(_) => Backend::Kv,
+            Self::D1(_) => Backend::D1,
+        }
+    }
+    
+    /// the stored entry for `code`. on d1 its clicks already include the
+    /// analytics consumer's count; on kv they're only what the entry holds
+    pub async fn get(&self, code: &str) -> worker::Result<Option<UrlEntry>> {
+        match self {
+            Self::Kv(kv) => {
+                crate::metrics::record_kv_op("get");
+                match kv.get(code).text().await? {
+                    Some(json) => serde_json::from_str(&json)
+                        .map(Some)
+                        .map_err(|_| worker::Error::from("invalid stored data")),
+                    None => Ok(None),
+                }
+            }
+            Self::D1(db) => {
+                let row: Option<LinkRow> = db.prepare(SELECT_LINK_SQL).bind(&[code.into()])?.first(None).await?;
+                Ok(row.map(LinkRow::into_entry))
+            }
+        }
+    }
+    
+    /// the entry with every click counted - d1 joins them in, kv asks the
+    /// analytics database separately
+    pub async fn get_with_clicks(&self, env: &worker::Env, code: &str) -> worker::Result<Option<UrlEntry>> {
+        let mut entry = self.get(code).await?;
+        if let (Self::Kv(_), Some(entry)) = (self, entry.as_mut()) {
+            entry.clicks += crate::analytics::click_count(env, code).await.unwrap_or(0);
+        }
+        Ok(entry)
+    }
+    
+    /// store an entry; kv drops it after `ttl_seconds`, d1 leaves that to the cron purge
+    pub async fn put(&self, code: &str, entry: &UrlEntry, ttl_seconds: Option<u64>) -> worker::Result<()> {
+        match self {
+            Self::Kv(kv) => {
+                crate::metrics::record_kv_op("put");
+                let mut put = kv.put(code, serde_json::to_string(entry)?)?;
+                if let Some(ttl) = ttl_seconds {
+                    put = put.expiration_ttl(ttl);
+                }
+                put.execute().await?;
+            }
+            Self::D1(db) => {
+                use worker::wasm_bindgen::JsValue;
+                
+                let params = [
+                    code.into(),
+                    entry.original_url.as_str().into(),
+                    JsValue::from_f64(entry.created_at as f64),
+                    JsValue::from_f64(entry.clicks as f64),
+                    entry.expires_at.map_or(JsValue::NULL, |at| JsValue::from_f64(at as f64)),
+                ];
+                db.prepare(UPSERT_LINK_SQL).bind(&params)?.run().await?;
+            }
+        }
+        Ok(())
+    }
+    
+    pub async fn delete(&self, code: &str) -> worker::Result<()> {
+        match self {
+            Self::Kv(kv) => {
+                crate::metrics::record_kv_op("delete");
+                kv.delete(code).await?;
+            }
+            Self::D1(db) => {
+                db.prepare(DELETE_LINK_SQL).bind(&[code.into()])?.run().await?;
+            }
+        }
+        Ok(())
+    }
+    
+    /// delete every expired link in one statement (d1 only - kv links are
+    /// swept key by key, see shared::maintenance)
+    pub async fn purge_expired(&self, now_ms: u64) -> worker::Result<()> {
+        if let Self::D1(db) = self {
+            let now = worker::wasm_bindgen::JsValue::from_f64(now_ms as f64);
+            db.prepare(PURGE_EXPIRED_SQL).bind(&[now])?.run().await?;
+        }
+        Ok(())
+    }
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_row_folds_in_counted_clicks() {
+        let row: LinkRow = serde_json::from_value(serde_json::json!({
+            "original_url": "https://example.com",
+            "created_at": 1_700_000_000_000u64,
+            "clicks": 3,
+            "expires_at": null,
+            "counted_clicks": 39
+        }))
+        .unwrap();
+        let entry = row.into_entry();
+        assert_eq!(entry.clicks, 42);
+        assert_eq!(entry.expires_at, None);
+    }
+    
+    #[test]
+    fn test_sql_placeholders() {
+        assert_eq!(UPSERT_LINK_SQL.matches('?').count(), 5);
+        assert_eq!(SELECT_LINK_SQL.matches('?').count(), 1);
+        assert!(SELECT_LINK_SQL.contains("LEFT JOIN link_clicks"));
+    }
+}
@@ -0,0 +1,285 @@
+//! ==============================================================================
+//! tiers.rs - the rate limiter's api keys and their tiers
+//! ==============================================================================
+//!
+//! purpose:
+//!     clients of the rate limiter identify themselves with X-API-Key. an
+//!     operator issues keys, moves them between tiers - pro and enterprise
+//!     multiply the limit - and revokes them. the worker looks each key up
+//!     on every request, so a change applies without a redeploy.
+//!
+//! relationships:
+//!     - used by: rate-limiter (limits per request, /admin/tier, /admin/keys)
+//!     - uses: workers kv namespace AUTH, keys "tier:key:<sha-256 hex>" -
+//!       hashed like shared::auth's api keys, never stored in the clear
+//!
+//! keys:
+//!     issued keys are "rl_" and 32 random hex bytes, shown once when
+//!     created. a revoked key keeps its record (with revoked_at) and is
+//!     refused with 403. a key without a record is a free-tier client unless
+//!     the worker requires registered keys, when it's refused with 401.
+//!
+//! tiers (base = shared::limits - RATE_LIMIT per RATE_WINDOW_SECONDS by default):
+//!     free        base limit (also ip clients and keys without a record)
+//!     pro         10x base
+//!     enterprise  100x base
+//!     the window stays the same, so a tier only changes how many requests fit.
+//!
+//! ==============================================================================
+
+use serde::{Deserialize, Serialize};
+
+/// kv prefix of tier records in the AUTH namespace
+pub const TIER_KEY_PREFIX: &str = "tier:key:";
+/// prefix of issued keys
+pub const ISSUED_KEY_PREFIX: &str = "rl_";
+/// random bytes in an issued key
+#[cfg(feature = "worker")]
+const ISSUED_KEY_BYTES: usize = 32;
+/// seconds kv may serve a cached key record - how long a change takes to land
+#[cfg(feature = "worker")]
+const KEY_CACHE_TTL_SECS: u64 = 60;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum KeyTier {
+    #[default]
+    Free,
+    Pro,
+    Enterprise,
+}
+
+impl KeyTier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Free => "free",
+            Self::Pro => "pro",
+            Self::Enterprise => "enterprise",
+        }
+    }
+    
+    /// how many times the base limit this tier gets
+    pub fn multiplier(&self) -> u32 {
+        match self {
+            Self::Free => 1,
+            Self::Pro => 10,
+            Self::Enterprise => 100,
+        }
+    }
+    
+    /// this tier's limits, from the worker's base (free) ones - the burst
+    /// grows with the rate
+    pub fn limits(&self, base: TierLimits) -> TierLimits {
+        TierLimits {
+            limit: base.limit.saturating_mul(self.multiplier()),
+            burst: base.burst.map(|b| b.saturating_mul(self.multiplier())),
+            ..base
+        }
+    }
+}
+
+/// requests allowed per window
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TierLimits {
+    pub limit: u32,
+    pub window_seconds: u64,
+    /// token bucket size, when the limit is a sustained rate
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub burst: Option<u32>,
+}
+
+impl From<crate::RateLimitConfig> for TierLimits {
+    fn from(config: crate::RateLimitConfig) -> Self {
+        Self { limit: config.limit, window_seconds: config.window_seconds, burst: config.burst }
+    }
+}
+
+/// what kv stores for a known key - issued, given a tier, or revoked
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct KeyRecord {
+    /// who the key is for - empty for keys only ever given a tier
+    #[serde(default)]
+    pub name: String,
+    pub tier: KeyTier,
+    /// unix ms
+    #[serde(default)]
+    pub created_at: u64,
+    /// unix ms of the last tier change
+    #[serde(default)]
+    pub assigned_at: u64,
+    /// unix ms
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<u64>,
+}
+
+/// what a key on a request amounts to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAccess {
+    /// counted on this tier
+    Allowed(KeyTier),
+    /// the key was revoked - 403
+    Revoked,
+    /// no record while registered keys are required - 401
+    Unknown,
+}
+
+impl KeyAccess {
+    /// `record` is the key's, if kv has one
+    pub fn of(record: Option<&KeyRecord>, require_registered: bool) -> Self {
+        match record {
+            Some(r) if r.revoked_at.is_some() => Self::Revoked,
+            Some(r) => Self::Allowed(r.tier),
+            None if require_registered => Self::Unknown,
+            None => Self::Allowed(KeyTier::Free),
+        }
+    }
+}
+
+/// POST /admin/keys body
+#[derive(Debug, Clone, Deserialize)]
+pub struct CreateKey {
+    pub name: String,
+    #[serde(default)]
+    pub tier: KeyTier,
+}
+
+/// POST /admin/tier body
+#[derive(Debug, Clone, Deserialize)]
+pub struct AssignTier {
+    /// the api key as clients send it in X-API-Key
+    pub key: String,
+    pub tier: KeyTier,
+}
+
+/// kv key of the tier record for an api key's sha-256 hex
+pub fn tier_kv_key(key_hash: &str) -> String {
+    format!("{}{}", TIER_KEY_PREFIX, key_hash)
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+#[cfg(feature = "worker")]
+async fn kv_key(api_key: &str) -> worker::Result<String> {
+    Ok(tier_kv_key(&crate::auth_guard::sha256_hex(api_key.as_bytes()).await?))
+}
+
+/// the record of `api_key`, if it has one. `fresh` skips kv's edge cache -
+/// admin routes read their own writes, request checks may lag a minute
+#[cfg(feature = "worker")]
+pub async fn record(env: &worker::Env, api_key: &str, fresh: bool) -> worker::Result<Option<KeyRecord>> {
+    let kv_key = kv_key(api_key).await?;
+    crate::metrics::record_kv_op("get");
+    let get = env.kv(crate::auth_guard::AUTH_KV_BINDING)?.get(&kv_key);
+    let get = if fresh { get } else { get.cache_ttl(KEY_CACHE_TTL_SECS) };
+    Ok(get.json::<KeyRecord>().await?)
+}
+
+#[cfg(feature = "worker")]
+async fn store(env: &worker::Env, api_key: &str, record: &KeyRecord) -> worker::Result<()> {
+    let kv_key = kv_key(api_key).await?;
+    crate::metrics::record_kv_op("put");
+    env.kv(crate::auth_guard::AUTH_KV_BINDING)?.put(&kv_key, serde_json::to_string(record)?)?.execute().await?;
+    Ok(())
+}
+
+/// what `api_key` may do - an unreadable record counts as no record
+#[cfg(feature = "worker")]
+pub async fn access(env: &worker::Env, api_key: &str, require_registered: bool) -> KeyAccess {
+    let record = record(env, api_key, false).await.unwrap_or_else(|e| {
+        worker::console_warn!("key lookup failed: {}", e);
+        None
+    });
+    KeyAccess::of(record.as_ref(), require_registered)
+}
+
+/// issue a new key; returns it with its record - the only time it's readable
+#[cfg(feature = "worker")]
+pub async fn create(env: &worker::Env, request: &CreateKey) -> worker::Result<(String, KeyRecord)> {
+    let api_key = format!("{}{}", ISSUED_KEY_PREFIX, crate::auth_guard::random_hex(ISSUED_KEY_BYTES)?);
+    let now = worker::js_sys::Date::now() as u64;
+    let record = KeyRecord { name: request.name.clone(), tier: request.tier, created_at: now, assigned_at: now, revoked_at: None };
+    store(env, &api_key, &record).await?;
+    Ok((api_key, record))
+}
+
+/// put `api_key` on `tier`, keeping the rest of its record; a key without
+/// one going back to free needs none
+#[cfg(feature = "worker")]
+pub async fn assign(env: &worker::Env, api_key: &str, tier: KeyTier) -> worker::Result<()> {
+    let now = worker::js_sys::Date::now() as u64;
+    let record = match record(env, api_key, true).await? {
+        Some(record) => KeyRecord { tier, assigned_at: now, ..record },
+        None if tier == KeyTier::Free => return Ok(()),
+        None => KeyRecord { tier, created_at: now, assigned_at: now, ..KeyRecord::default() },
+    };
+    store(env, api_key, &record).await
+}
+
+/// revoke `api_key` - a key without a record gets one, so it stays refused
+#[cfg(feature = "worker")]
+pub async fn revoke(env: &worker::Env, api_key: &str) -> worker::Result<KeyRecord> {
+    let now = worker::js_sys::Date::now() as u64;
+    let record = record(env, api_key, true).await?.unwrap_or_default();
+    let record = KeyRecord { revoked_at: record.revoked_at.or(Some(now)), ..record };
+    store(env, api_key, &record).await?;
+    Ok(record)
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const BASE: TierLimits = TierLimits { limit: 10, window_seconds: 60, burst: None };
+    
+    #[test]
+    fn test_tier_limits_scale_base() {
+        assert_eq!(KeyTier::Free.limits(BASE), BASE);
+        assert_eq!(KeyTier::Pro.limits(BASE), TierLimits { limit: 100, window_seconds: 60, burst: None });
+        assert_eq!(KeyTier::Enterprise.limits(BASE).limit, 1000);
+        let huge = TierLimits { limit: u32::MAX / 2, window_seconds: 1, burst: None };
+        assert_eq!(KeyTier::Enterprise.limits(huge).limit, u32::MAX);
+        let bursty = TierLimits { burst: Some(20), ..BASE };
+        assert_eq!(KeyTier::Pro.limits(bursty).burst, Some(200));
+    }
+    
+    #[test]
+    fn test_key_access() {
+        let pro = KeyRecord { name: "acme".to_string(), tier: KeyTier::Pro, ..KeyRecord::default() };
+        assert_eq!(KeyAccess::of(Some(&pro), false), KeyAccess::Allowed(KeyTier::Pro));
+        assert_eq!(KeyAccess::of(Some(&pro), true), KeyAccess::Allowed(KeyTier::Pro));
+        
+        let revoked = KeyRecord { revoked_at: Some(1), ..pro };
+        assert_eq!(KeyAccess::of(Some(&revoked), false), KeyAccess::Revoked);
+        
+        assert_eq!(KeyAccess::of(None, false), KeyAccess::Allowed(KeyTier::Free));
+        assert_eq!(KeyAccess::of(None, true), KeyAccess::Unknown);
+    }
+    
+    #[test]
+    fn test_records_and_requests() {
+        // records written before keys had names still read
+        let record: KeyRecord = serde_json::from_str(r#"{"tier": "enterprise", "assigned_at": 5}"#).unwrap();
+        assert_eq!(record, KeyRecord { tier: KeyTier::Enterprise, assigned_at: 5, ..KeyRecord::default() });
+        assert!(serde_json::to_value(&record).unwrap().get("revoked_at").is_none());
+        
+        let create: CreateKey = serde_json::from_str(r#"{"name": "acme"}"#).unwrap();
+        assert_eq!((create.name.as_str(), create.tier), ("acme", KeyTier::Free));
+        assert_eq!(serde_json::to_value(KeyTier::Pro).unwrap(), KeyTier::Pro.as_str());
+        
+        let assign: AssignTier = serde_json::from_str(r#"{"key": "k-123", "tier": "pro"}"#).unwrap();
+        assert_eq!((assign.key.as_str(), assign.tier), ("k-123", KeyTier::Pro));
+        assert!(serde_json::from_str::<AssignTier>(r#"{"key": "k", "tier": "platinum"}"#).is_err());
+        
+        assert_eq!(tier_kv_key("ab12"), "tier:key:ab12");
+    }
+}
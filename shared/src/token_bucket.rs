@@ -0,0 +1,184 @@
+//! ==============================================================================
+//! token_bucket.rs - a burst allowance on top of the sustained rate
+//! ==============================================================================
+//!
+//! purpose:
+//!     a window of 10 per minute turns away the 11th request even from a
+//!     client that was idle for an hour. production limiters let a client
+//!     spend saved-up capacity in a burst and hold it to the rate after.
+//!     with a burst set (RATE_BURST, or "burst" in the limits document),
+//!     each client gets a bucket of that many tokens refilling at
+//!     limit per window.
+//!
+//! relationships:
+//!     - used by: rate-limiter (kv backends, key "bucket:<client id>" in
+//!       RATES), limiter (the durable object backend keeps its bucket in
+//!       the object)
+//!
+//! algorithm:
+//!     a request takes a token if there is one. tokens come back
+//!     continuously - 10 per 60 seconds is one every 6 seconds - up to the
+//!     burst. so "10/min, burst 20" allows 20 at once after a quiet spell,
+//!     then one every 6 seconds. in a Decision the limit is the burst,
+//!     remaining the whole tokens left, and reset_in_seconds the time until
+//!     the bucket is full again - or, when empty, until the next token.
+//!
+//! ==============================================================================
+
+use serde::{Deserialize, Serialize};
+
+use crate::limiter::Decision;
+
+/// env var with the free tier's burst - unset or 0 keeps plain windows
+pub const BURST_VAR: &str = "RATE_BURST";
+/// kv prefix of buckets in RATES
+pub const BUCKET_KEY_PREFIX: &str = "bucket:";
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// tokens refilling at `rate` per window, up to `burst`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shape {
+    pub rate: u32,
+    pub window_seconds: u64,
+    pub burst: u32,
+}
+
+impl Shape {
+    fn per_ms(&self) -> f64 {
+        self.rate as f64 / (self.window_seconds.max(1) * 1000) as f64
+    }
+    
+    /// ms until `missing` tokens are back - none at a zero rate
+    fn ms_for(&self, missing: f64) -> Option<u64> {
+        let per_ms = self.per_ms();
+        (per_ms > 0.0).then(|| (missing.max(0.0) / per_ms).ceil() as u64)
+    }
+}
+
+/// one client's bucket
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TokenBucket {
+    tokens: f64,
+    /// unix ms the tokens were counted at
+    updated_ms: u64,
+}
+
+impl TokenBucket {
+    /// what a client without a stored bucket has
+    pub fn full(shape: Shape, now_ms: u64) -> Self {
+        Self { tokens: shape.burst as f64, updated_ms: now_ms }
+    }
+    
+    /// take a token at `now_ms` if there is one
+    pub fn take(&mut self, shape: Shape, now_ms: u64) -> Decision {
+        self.refill(shape, now_ms);
+        let allowed = self.tokens >= 1.0;
+        if allowed {
+            self.tokens -= 1.0;
+        }
+        Decision { allowed, ..self.decision(shape) }
+    }
+    
+    /// where the client stands at `now_ms`, without taking anything
+    pub fn peek(&mut self, shape: Shape, now_ms: u64) -> Decision {
+        self.refill(shape, now_ms);
+        self.decision(shape)
+    }
+    
+    /// when the bucket is full again (unix ms) - past that, storing it is
+    /// no different from having none
+    pub fn full_at(&self, shape: Shape) -> u64 {
+        let missing = shape.burst as f64 - self.tokens;
+        self.updated_ms + shape.ms_for(missing).unwrap_or(shape.window_seconds * 1000)
+    }
+    
+    fn refill(&mut self, shape: Shape, now_ms: u64) {
+        let elapsed = now_ms.saturating_sub(self.updated_ms) as f64;
+        self.tokens = (self.tokens + elapsed * shape.per_ms()).min(shape.burst as f64);
+        self.updated_ms = self.updated_ms.max(now_ms);
+    }
+    
+    fn decision(&self, shape: Shape) -> Decision {
+        let remaining = (self.tokens.floor().max(0.0) as u32).min(shape.burst);
+        let wait_ms = if remaining == 0 {
+            shape.ms_for(1.0 - self.tokens)
+        } else {
+            shape.ms_for(shape.burst as f64 - self.tokens)
+        };
+        Decision {
+            allowed: remaining > 0,
+            count: shape.burst - remaining,
+            limit: shape.burst,
+            remaining,
+            reset_in_seconds: wait_ms.map_or(shape.window_seconds, |ms| ms.div_ceil(1000)),
+        }
+    }
+}
+
+/// kv key of `client_id`'s bucket
+pub fn kv_key(client_id: &str) -> String {
+    format!("{}{}", BUCKET_KEY_PREFIX, client_id)
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const S: u64 = 1000;
+    // 10 a minute, 20 at once
+    const SHAPE: Shape = Shape { rate: 10, window_seconds: 60, burst: 20 };
+    
+    #[test]
+    fn test_burst_then_sustained_rate() {
+        let mut bucket = TokenBucket::full(SHAPE, 0);
+        let allowed = (0..25).filter(|i| bucket.take(SHAPE, i * 10).allowed).count();
+        assert_eq!(allowed, 20);
+        
+        // empty: the next token is 6 seconds out
+        let blocked = bucket.take(SHAPE, S);
+        assert_eq!((blocked.allowed, blocked.remaining, blocked.limit), (false, 0, 20));
+        assert_eq!(blocked.reset_in_seconds, 6);
+        assert!(bucket.take(SHAPE, 7 * S).allowed);
+        
+        // held to the rate from there: 10 over the next minute
+        let later = (8..68).filter(|s| bucket.take(SHAPE, s * S).allowed).count();
+        assert_eq!(later, 10);
+    }
+    
+    #[test]
+    fn test_refills_to_burst() {
+        let mut bucket = TokenBucket::full(SHAPE, 0);
+        for _ in 0..5 {
+            bucket.take(SHAPE, 0);
+        }
+        let status = bucket.peek(SHAPE, 0);
+        assert_eq!((status.count, status.remaining), (5, 15));
+        // 5 tokens back takes 30 seconds
+        assert_eq!(status.reset_in_seconds, 30);
+        assert_eq!(bucket.full_at(SHAPE), 30 * S);
+        
+        // an hour idle doesn't save more than the burst
+        assert_eq!(bucket.peek(SHAPE, 3600 * S).remaining, 20);
+        assert_eq!(TokenBucket::full(SHAPE, 0).peek(SHAPE, 0).reset_in_seconds, 0);
+    }
+    
+    #[test]
+    fn test_zero_rate_never_refills() {
+        let shape = Shape { rate: 0, ..SHAPE };
+        let mut bucket = TokenBucket::full(shape, 0);
+        for _ in 0..20 {
+            assert!(bucket.take(shape, 0).allowed);
+        }
+        let blocked = bucket.take(shape, 3600 * S);
+        assert!(!blocked.allowed);
+        assert_eq!(blocked.reset_in_seconds, 60);
+        assert_eq!(kv_key("ip:192.0.2.1"), "bucket:ip:192.0.2.1");
+    }
+}
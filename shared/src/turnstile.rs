@@ -0,0 +1,151 @@
+//! ==============================================================================
+//! turnstile.rs - cloudflare turnstile verification for write endpoints
+//! ==============================================================================
+//!
+//! purpose:
+//!     the public demo lets anyone write to kv, and ip rate limits only slow
+//!     a bot down. write endpoints ask the browser for a turnstile token and
+//!     check it with cloudflare's siteverify api before accepting.
+//!
+//! relationships:
+//!     - used by: url-shortener (POST /shorten), rate-limiter (POST /api/verify)
+//!     - counterpart: dashboard turnstile widget, which sends TOKEN_HEADER
+//!
+//! configuration (per worker):
+//!     secret TURNSTILE_SECRET   the widget's secret key. when unset the check
+//!                               is skipped, so `wrangler dev` works without one
+//!
+//! ==============================================================================
+
+use serde::{Deserialize, Serialize};
+
+/// header the dashboard puts the widget's token in
+pub const TOKEN_HEADER: &str = "X-Turnstile-Token";
+pub const SITEVERIFY_URL: &str = "https://challenges.cloudflare.com/turnstile/v0/siteverify";
+/// tokens are at most 2048 chars - anything longer isn't worth a subrequest
+const MAX_TOKEN_LEN: usize = 2048;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// body posted to siteverify
+#[derive(Debug, Serialize)]
+pub struct SiteverifyRequest<'a> {
+    pub secret: &'a str,
+    pub response: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remoteip: Option<&'a str>,
+}
+
+/// siteverify's answer (only the fields we look at)
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct SiteverifyResponse {
+    pub success: bool,
+    #[serde(default, rename = "error-codes")]
+    pub error_codes: Vec<String>,
+    #[serde(default)]
+    pub hostname: Option<String>,
+}
+
+impl SiteverifyResponse {
+    /// short reason for logs and the 403 body
+    pub fn failure_reason(&self) -> &str {
+        self.error_codes.first().map(String::as_str).unwrap_or("verification failed")
+    }
+}
+
+/// the token from the header, if it's plausibly one
+pub fn token(header: Option<&str>) -> Option<&str> {
+    header
+        .map(str::trim)
+        .filter(|t| !t.is_empty() && t.len() <= MAX_TOKEN_LEN)
+}
+
+// ==============================================================================
+// worker side
+// ==============================================================================
+
+/// check the request's turnstile token with siteverify.
+/// the outer error is infrastructure failure, the inner one the verdict.
+#[cfg(feature = "worker")]
+pub async fn verify(
+    req: &worker::Request,
+    env: &worker::Env,
+) -> worker::Result<std::result::Result<(), String>> {
+    use worker::*;
+    
+    let Ok(secret) = env.secret("TURNSTILE_SECRET") else {
+        return Ok(Ok(()));
+    };
+    let headers = req.headers();
+    let header = headers.get(TOKEN_HEADER)?;
+    let Some(token) = token(header.as_deref()) else {
+        return Ok(Err("missing turnstile token".to_string()));
+    };
+    let remote_ip = headers.get("CF-Connecting-IP")?;
+    
+    let body = serde_json::to_string(&SiteverifyRequest {
+        secret: &secret.to_string(),
+        response: token,
+        remoteip: remote_ip.as_deref(),
+    })?;
+    let verify_headers = Headers::new();
+    verify_headers.set("Content-Type", "application/json")?;
+    let init = RequestInit {
+        method: Method::Post,
+        headers: verify_headers,
+        body: Some(body.into()),
+        ..RequestInit::default()
+    };
+    
+    let outcome: SiteverifyResponse = Fetch::Request(Request::new_with_init(SITEVERIFY_URL, &init)?)
+        .send()
+        .await?
+        .json()
+        .await?;
+    Ok(if outcome.success {
+        Ok(())
+    } else {
+        Err(outcome.failure_reason().to_string())
+    })
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_token_from_header() {
+        assert_eq!(token(Some(" abc.def ")), Some("abc.def"));
+        assert_eq!(token(Some("")), None);
+        assert_eq!(token(None), None);
+        assert_eq!(token(Some(&"x".repeat(MAX_TOKEN_LEN + 1))), None);
+    }
+    
+    #[test]
+    fn test_parse_siteverify_response() {
+        let ok: SiteverifyResponse = serde_json::from_str(
+            r#"{"success":true,"error-codes":[],"hostname":"example.com","challenge_ts":"2024-01-01T00:00:00Z"}"#,
+        )
+        .unwrap();
+        assert!(ok.success);
+        assert_eq!(ok.hostname.as_deref(), Some("example.com"));
+        
+        let bad: SiteverifyResponse =
+            serde_json::from_str(r#"{"success":false,"error-codes":["timeout-or-duplicate"]}"#).unwrap();
+        assert!(!bad.success);
+        assert_eq!(bad.failure_reason(), "timeout-or-duplicate");
+        assert_eq!(SiteverifyResponse::default().failure_reason(), "verification failed");
+    }
+    
+    #[test]
+    fn test_request_omits_missing_ip() {
+        let body = SiteverifyRequest { secret: "s", response: "t", remoteip: None };
+        assert_eq!(serde_json::to_string(&body).unwrap(), r#"{"secret":"s","response":"t"}"#);
+    }
+}
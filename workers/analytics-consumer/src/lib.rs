@@ -0,0 +1,145 @@
+//! ==============================================================================
+//! lib.rs - analytics queue consumer
+//! ==============================================================================
+//!
+//! purpose:
+//!     drains the "edge-analytics" queue the other workers send clicks,
+//!     rate-limit denials and faults to, and writes each batch into d1 in a
+//!     single round trip. nothing here is on a user's request path.
+//!
+//! relationships:
+//!     - consumes: queue "edge-analytics" (producers: url-shortener, rate-limiter)
+//!     - uses: d1 database "ANALYTICS_DB" (schema in /migrations)
+//!     - uses: shared::analytics (event type and sql)
+//!     - read by: url-shortener GET /stats/:code (link_clicks)
+//!
+//! cloudflare features demonstrated:
+//!     - queues consumer (batched, retried delivery)
+//!     - d1 (sqlite at the edge) with migrations
+//!
+//! delivery:
+//!     queues deliver at least once. every event is inserted with INSERT OR
+//!     IGNORE keyed by (request_id, kind), so a retried batch doesn't count
+//!     twice; click totals come from a trigger on that insert. if d1 is
+//!     unavailable the whole batch is retried (up to max_retries).
+//!
+//! ==============================================================================
+
+use std::collections::BTreeMap;
+
+use worker::*;
+use worker::wasm_bindgen::JsValue;
+
+use shared::analytics::{AnalyticsEvent, DB_BINDING, INSERT_EVENT_SQL};
+
+// ==============================================================================
+// queue entry point
+// ==============================================================================
+
+#[event(queue)]
+async fn queue(batch: MessageBatch<AnalyticsEvent>, env: Env, _ctx: Context) -> Result<()> {
+    let events: Vec<AnalyticsEvent> = batch.messages()?.iter().map(|m| m.body().clone()).collect();
+    if events.is_empty() {
+        return Ok(());
+    }
+    
+    match store(&env, &events).await {
+        Ok(()) => {
+            console_log!("stored {} analytics events {:?}", events.len(), summarize(&events));
+            batch.ack_all();
+        }
+        Err(e) => {
+            console_error!("storing {} analytics events failed, retrying: {}", events.len(), e);
+            batch.retry_all();
+        }
+    }
+    Ok(())
+}
+
+/// insert a batch in one d1 round trip
+async fn store(env: &Env, events: &[AnalyticsEvent]) -> Result<()> {
+    let db = env.d1(DB_BINDING)?;
+    let statements = events
+        .iter()
+        .map(|event| {
+            let params: Vec<JsValue> = insert_params(event).iter().map(to_js).collect();
+            db.prepare(INSERT_EVENT_SQL).bind(&params)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    db.batch(statements).await?;
+    Ok(())
+}
+
+// ==============================================================================
+// helpers
+// ==============================================================================
+
+/// INSERT_EVENT_SQL's parameters for one event, in order
+fn insert_params(event: &AnalyticsEvent) -> [serde_json::Value; 6] {
+    [
+        event.request_id.clone().into(),
+        event.kind.as_str().into(),
+        event.worker.clone().into(),
+        event.subject.clone().into(),
+        event.status.into(),
+        event.at.into(),
+    ]
+}
+
+/// d1 takes strings and numbers as js values
+fn to_js(value: &serde_json::Value) -> JsValue {
+    match value {
+        serde_json::Value::String(s) => JsValue::from_str(s),
+        serde_json::Value::Number(n) => JsValue::from_f64(n.as_f64().unwrap_or_default()),
+        _ => JsValue::NULL,
+    }
+}
+
+/// events per kind, for the batch log line
+fn summarize(events: &[AnalyticsEvent]) -> BTreeMap<&'static str, usize> {
+    let mut counts = BTreeMap::new();
+    for event in events {
+        *counts.entry(event.kind.as_str()).or_insert(0) += 1;
+    }
+    counts
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn test_insert_params_follow_the_sql() {
+        let event = AnalyticsEvent::fault("rate-limiter", "/api/status", 503, "18bcfe56800-deadbeef", 1_700_000_000_000);
+        assert_eq!(
+            insert_params(&event),
+            [
+                serde_json::json!("18bcfe56800-deadbeef"),
+                serde_json::json!("fault"),
+                serde_json::json!("rate-limiter"),
+                serde_json::json!("/api/status"),
+                serde_json::json!(503),
+                serde_json::json!(1_700_000_000_000u64),
+            ]
+        );
+        // one placeholder per parameter
+        assert_eq!(INSERT_EVENT_SQL.matches('?').count(), insert_params(&event).len());
+    }
+    
+    #[test]
+    fn test_summarize_counts_kinds() {
+        let events = vec![
+            AnalyticsEvent::click("url-shortener", "abc123", "a", 0),
+            AnalyticsEvent::click("url-shortener", "abc123", "b", 0),
+            AnalyticsEvent::denial("rate-limiter", "ip:203.0.113.7", "c", 0),
+        ];
+        let counts = summarize(&events);
+        assert_eq!(counts.get("click"), Some(&2));
+        assert_eq!(counts.get("denial"), Some(&1));
+        assert_eq!(counts.get("fault"), None);
+    }
+}
@@ -8,8 +8,14 @@
 //!     cloudflare context where fetch/kv are allowed but filesystem is blocked.
 //!
 //! relationships:
-//!     - uses: shared (CapabilityTest, CapabilityResult, CapabilityType)
+//!     - uses: shared (CapabilityTest, CapabilityResult, CapabilityType, request ids)
 //!     - called by: dashboard (capability explorer tab)
+//!     - uses: durable object "METRICS" for /metrics totals (shared::metrics_store)
+//!     - uses: workers kv namespace "AUTH" for api keys (shared::auth_guard)
+//!     - uses: durable object "EVENTS" in the event-hub worker for the live
+//!       feed - blocks, faults (shared::event_hub)
+//!     - uses: shared::flags to switch the live feed and durable metrics off
+//!       (or roll them out) without a redeploy
 //!     - deployed to: cloudflare workers
 //!
 //! cloudflare context:
@@ -22,6 +28,17 @@
 //!     this mirrors wasi's capability model where the host decides
 //!     what the sandboxed code can access - not the code itself.
 //!
+//! metrics:
+//!     GET /metrics serves prometheus text: request counts by route and
+//!     status, 5xx and 429 totals, kv operations and latency histograms.
+//!     isolates buffer counters and merge them into the durable object
+//!     every few seconds, so totals lag live traffic slightly.
+//!
+//! request ids:
+//!     every response carries X-Request-Id (adopted from the request when
+//!     the client sent a sane one) and error bodies repeat it as
+//!     "request_id", so a user-reported failure maps to a log line.
+//!
 //! api:
 //!     GET /api/capability?test=fetch
 //!     response: { "capability": "Fetch", "allowed": true, "message": "..." }
@@ -29,6 +46,15 @@
 //!     GET /api/capability?test=filesystem
 //!     response: { "capability": "Filesystem", "allowed": false, "message": "..." }
 //!
+//!     GET /api/capabilities
+//!     response: [["fetch", true, "..."], ...] with an ETag (If-None-Match gets a 304)
+//!
+//!     GET /version
+//!     response: { "worker": "capability-demo", "version": "0.1.0", "git_sha": "...", ... }
+//!
+//!     GET /health/deps
+//!     response: shared::health report over RATES, AUTH, METRICS and EVENTS
+//!
 //! security parallel:
 //!     cloudflare workers : fetch/kv = your wasi host : gpio-provider
 //!     both are capabilities granted by the runtime, not inherent to the code.
@@ -36,13 +62,38 @@
 //! ==============================================================================
 
 use shared::{CapabilityType, CapabilityResult};
+use shared::build_info::BuildInfo;
+use shared::event_hub::LiveEvent;
+use shared::flags::{self, ClientFlags};
+use shared::health::Dependency;
 use worker::*;
 use serde::{Deserialize, Serialize};
 
+// the metrics durable object class has to be exported by the worker itself
+pub use shared::metrics_store::MetricsStore;
+
 // ==============================================================================
 // types
 // ==============================================================================
 
+/// per-request data the router hands to every handler
+struct RequestMeta {
+    request_id: String,
+    /// feature flags for this request's client
+    flags: ClientFlags,
+}
+
+/// worker label on exported metrics
+const WORKER_NAME: &str = "capability-demo";
+
+/// bindings probed by GET /health/deps
+const HEALTH_DEPS: &[Dependency] = &[
+    Dependency::Kv("RATES"),
+    Dependency::Kv(shared::auth_guard::AUTH_KV_BINDING),
+    shared::metrics_store::HEALTH_DEPENDENCY,
+    shared::event_hub::HEALTH_DEPENDENCY,
+];
+
 #[derive(Debug, Serialize, Deserialize)]
 struct RateInfo {
     count: u32,
@@ -54,24 +105,77 @@ struct RateInfo {
 // ==============================================================================
 
 #[event(fetch)]
-async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
-    let router = Router::new();
+async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
+    let started = js_sys::Date::now();
+    let route = route_label(&req.path());
+    let request_id = request_id(&req);
+    let origin = req.headers().get("Origin").ok().flatten();
+    console_log!("[{}] {:?} {}", request_id, req.method(), req.path());
     
-    router
+    let client_flags = flags::load(&env).await.for_client(&get_client_id(&req));
+    let router = Router::with_data(RequestMeta { request_id: request_id.clone(), flags: client_flags.clone() });
+    
+    // the router consumes env - keep a handle for cors and the metrics flush
+    let metrics_env = env.clone();
+    let result = router
         .get_async("/api/capability", |req, ctx| handle_capability(req, ctx))
         .get("/api/capabilities", handle_list_capabilities)
         .get("/health", |_, _| Response::ok("ok"))
+        // which build is deployed
+        .get("/version", |_, _| Response::from_json(&BuildInfo::new(WORKER_NAME, env!("CARGO_PKG_VERSION"))))
+        // this worker's bindings, collected by the gateway's /health/all
+        .get_async("/health/deps", handle_health_deps)
+        // prometheus scrape target
+        .get_async("/metrics", handle_metrics)
         .options("/api/capability", handle_cors)
         .run(req, env)
-        .await
+        .await;
+    
+    // unhandled errors and the router's own text 404/405s become problem+json too
+    let resp = match result {
+        Ok(resp) => resp,
+        Err(e) => {
+            console_error!("[{}] unhandled error: {}", request_id, e);
+            cors_error(&request_id, "internal error", 500)?
+        }
+    };
+    let mut resp = shared::problem::ensure_problem(resp, &request_id).await?;
+    stamp_request_id(&mut resp, &request_id);
+    let cors = shared::cors::policy(&metrics_env).await;
+    shared::cors::apply(&cors, origin.as_deref(), resp.headers_mut());
+    
+    let status = resp.status_code();
+    shared::metrics::record_request(route, status, js_sys::Date::now() - started);
+    if status >= 500 && client_flags.on(flags::LIVE_EVENTS) {
+        shared::event_hub::record(LiveEvent::fault(WORKER_NAME, route, status, &request_id, started as u64));
+    }
+    // with durable metrics off, requests leave their counters in the isolate - only a scrape merges them
+    if client_flags.on(flags::DURABLE_METRICS) {
+        shared::metrics_store::flush_if_due(&metrics_env, &ctx);
+    }
+    shared::event_hub::flush(&metrics_env, &ctx);
+    Ok(resp)
 }
 
 // ==============================================================================
 // request handlers
 // ==============================================================================
 
+/// prometheus text format metrics for this worker (needs the "metrics" scope)
+async fn handle_metrics(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    if let Err(e) = shared::auth_guard::require(&req, &ctx.env, &[], shared::auth::METRICS_SCOPE).await? {
+        return cors_error(&ctx.data.request_id, &e.to_string(), e.status());
+    }
+    shared::metrics_store::metrics_response(&ctx.env, WORKER_NAME).await
+}
+
+/// probe this worker's bindings
+async fn handle_health_deps(_req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    shared::health::deps_response(&ctx.env, HEALTH_DEPS).await
+}
+
 /// handle capability test request
-async fn handle_capability(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+async fn handle_capability(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
     // check rate limit first
     let limit: u32 = ctx.env.var("RATE_LIMIT")
         .map(|v| v.to_string().parse().unwrap_or(30))
@@ -84,12 +188,15 @@ async fn handle_capability(req: Request, ctx: RouteContext<()>) -> Result<Respon
     let (allowed, _) = check_rate_limit(&ctx, &client_id, limit, window_seconds).await?;
     
     if !allowed {
-        let headers = Headers::new();
-        headers.set("Content-Type", "application/json")?;
-        headers.set("Access-Control-Allow-Origin", "*")?;
-        let mut resp = Response::ok("{\"error\": \"rate limit exceeded - try again later\"}")?;
-        resp = resp.with_status(429);
-        return Ok(resp.with_headers(headers));
+        if ctx.data.flags.on(flags::LIVE_EVENTS) {
+            shared::event_hub::record(LiveEvent::block(
+                WORKER_NAME,
+                &client_id,
+                &ctx.data.request_id,
+                js_sys::Date::now() as u64,
+            ));
+        }
+        return cors_error(&ctx.data.request_id, "rate limit exceeded - try again later", 429);
     }
     
     // parse query parameter
@@ -106,7 +213,7 @@ async fn handle_capability(req: Request, ctx: RouteContext<()>) -> Result<Respon
         "filesystem" | "fs" => CapabilityType::Filesystem,
         "sockets" | "raw_sockets" => CapabilityType::RawSockets,
         "subprocess" | "exec" => CapabilityType::Subprocess,
-        _ => return Response::error("unknown capability. use: fetch, kv, filesystem, sockets, subprocess", 400),
+        _ => return cors_error(&ctx.data.request_id, "unknown capability. use: fetch, kv, filesystem, sockets, subprocess", 400),
     };
     
     // test the capability
@@ -116,13 +223,12 @@ async fn handle_capability(req: Request, ctx: RouteContext<()>) -> Result<Respon
     let json = serde_json::to_string(&result).unwrap();
     let headers = Headers::new();
     headers.set("Content-Type", "application/json")?;
-    headers.set("Access-Control-Allow-Origin", "*")?;
     
     Ok(Response::ok(json)?.with_headers(headers))
 }
 
 /// list all capabilities and their status
-fn handle_list_capabilities(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
+fn handle_list_capabilities(req: Request, _ctx: RouteContext<RequestMeta>) -> Result<Response> {
     let capabilities = vec![
         ("fetch", true, "HTTP requests via fetch() API"),
         ("kv_storage", true, "Workers KV key-value storage"),
@@ -131,21 +237,16 @@ fn handle_list_capabilities(_req: Request, _ctx: RouteContext<()>) -> Result<Res
         ("subprocess", false, "No subprocess/exec - no shell access"),
     ];
     
+    // cache for 60 seconds (static data), then revalidate against the etag
     let json = serde_json::to_string(&capabilities).unwrap();
-    let headers = Headers::new();
-    headers.set("Content-Type", "application/json")?;
-    headers.set("Access-Control-Allow-Origin", "*")?;
-    headers.set("Cache-Control", "public, max-age=60")?; // Cache for 60 seconds (static data)
-    
-    Ok(Response::ok(json)?.with_headers(headers))
+    shared::etag::json_response(&req, json, "public, max-age=60")
 }
 
-/// handle cors preflight
-fn handle_cors(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
+/// handle cors preflight (allow-origin is added in fetch from the shared policy)
+fn handle_cors(_req: Request, _ctx: RouteContext<RequestMeta>) -> Result<Response> {
     let headers = Headers::new();
-    headers.set("Access-Control-Allow-Origin", "*")?;
     headers.set("Access-Control-Allow-Methods", "GET, OPTIONS")?;
-    headers.set("Access-Control-Allow-Headers", "Content-Type")?;
+    headers.set("Access-Control-Allow-Headers", "Content-Type, X-Request-Id")?;
     
     Ok(Response::empty()?.with_headers(headers))
 }
@@ -155,7 +256,7 @@ fn handle_cors(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
 // ==============================================================================
 
 /// test a specific capability and return the result
-async fn test_capability(capability: CapabilityType, _ctx: &RouteContext<()>) -> CapabilityResult {
+async fn test_capability(capability: CapabilityType, _ctx: &RouteContext<RequestMeta>) -> CapabilityResult {
     match capability {
         CapabilityType::Fetch => test_fetch().await,
         CapabilityType::KvStorage => test_kv().await,
@@ -239,7 +340,7 @@ fn test_subprocess() -> CapabilityResult {
 
 /// check if request is allowed and update counter
 async fn check_rate_limit(
-    ctx: &RouteContext<()>,
+    ctx: &RouteContext<RequestMeta>,
     client_id: &str,
     limit: u32,
     window_seconds: u64,
@@ -251,6 +352,7 @@ async fn check_rate_limit(
     let key = format!("capability-demo:{}", client_id);
     
     // get current rate info
+    shared::metrics::record_kv_op("get");
     let mut rate_info = match kv.get(&key).text().await? {
         Some(json) => {
             let info: RateInfo = serde_json::from_str(&json).unwrap_or(RateInfo {
@@ -277,6 +379,7 @@ async fn check_rate_limit(
     
     // store updated info with ttl
     let json = serde_json::to_string(&rate_info).unwrap();
+    shared::metrics::record_kv_op("put");
     kv.put(&key, json)?
         .expiration_ttl(window_seconds)
         .execute()
@@ -285,6 +388,45 @@ async fn check_rate_limit(
     Ok((true, rate_info))
 }
 
+/// route label for metrics - unknown paths share one series
+fn route_label(path: &str) -> &'static str {
+    match path {
+        "/api/capability" => "/api/capability",
+        "/api/capabilities" => "/api/capabilities",
+        "/health" => "/health",
+        "/version" => "/version",
+        "/health/deps" => "/health/deps",
+        "/metrics" => "/metrics",
+        _ => "other",
+    }
+}
+
+/// adopt the caller's X-Request-Id, or mint a new one
+fn request_id(req: &Request) -> String {
+    req.headers()
+        .get(shared::REQUEST_ID_HEADER)
+        .ok()
+        .flatten()
+        .and_then(|id| shared::accept_request_id(&id))
+        .unwrap_or_else(|| {
+            let random = (js_sys::Math::random() * u32::MAX as f64) as u32;
+            shared::new_request_id(js_sys::Date::now() as u64, random)
+        })
+}
+
+/// put the request id on the response and let browsers read it
+fn stamp_request_id(resp: &mut Response, request_id: &str) {
+    let headers = resp.headers_mut();
+    let _ = headers.set(shared::REQUEST_ID_HEADER, request_id);
+    let _ = headers.append("Access-Control-Expose-Headers", shared::REQUEST_ID_HEADER);
+}
+
+/// problem+json error response with cors headers, carrying the request id
+fn cors_error(request_id: &str, msg: &str, status: u16) -> Result<Response> {
+    console_warn!("[{}] {} {}", request_id, status, msg);
+    shared::problem::Problem::new(status, msg).with_request_id(request_id).into_response()
+}
+
 /// get client identifier from ip address
 fn get_client_id(req: &Request) -> String {
     let headers = req.headers();
@@ -0,0 +1,464 @@
+//! ==============================================================================
+//! lib.rs - capture archive cloudflare worker
+//! ==============================================================================
+//!
+//! purpose:
+//!     stores blobs too big for kv - protocol captures, bulk exports - in an
+//!     r2 bucket, with a small metadata record per blob in kv. blobs are
+//!     content addressed: the id is the sha-256 of the bytes, so uploading
+//!     the same file twice stores it once and downloads can be verified.
+//!
+//! relationships:
+//!     - uses: r2 bucket "CAPTURES" for the blobs (key = id)
+//!     - uses: workers kv namespace "CAPTURES_META" for metadata ("capture:<id>")
+//!     - uses: shared::auth_guard for the upload scope and sha-256
+//!     - uses: shared::body_limit to cap uploads
+//!     - uses: durable object "METRICS" for /metrics totals (shared::metrics_store)
+//!     - uses: workers kv namespace "AUTH" for api keys (shared::auth_guard)
+//!     - uses: durable object "EVENTS" in the event-hub worker for the live
+//!       feed - faults (shared::event_hub)
+//!     - uses: shared::flags to switch the live feed and durable metrics off
+//!       (or roll them out) without a redeploy
+//!     - deployed to: cloudflare workers edge network
+//!
+//! cloudflare features demonstrated:
+//!     - r2 object storage (streamed downloads)
+//!     - workers kv as an index next to r2
+//!     - webcrypto sha-256
+//!
+//! metrics:
+//!     GET /metrics serves prometheus text: request counts by route and
+//!     status, 5xx and 429 totals, kv operations and latency histograms.
+//!     isolates buffer counters and merge them into the durable object
+//!     every few seconds, so totals lag live traffic slightly.
+//!
+//! request ids:
+//!     every response carries X-Request-Id (adopted from the request when
+//!     the client sent a sane one) and error bodies repeat it as
+//!     "request_id", so a user-reported failure maps to a log line.
+//!
+//! api:
+//!     POST /api/captures   ("captures" scope - see shared::auth)
+//!         body: the raw bytes, up to 32 MiB (hashed in memory)
+//!         headers: Content-Type (stored), X-Capture-Name: capture.pcap (optional)
+//!         response: 201 {"id": "<sha-256 hex>", "size": 1234, "content_type": "...",
+//!                   "name": "capture.pcap", "created_at": ..., "url": "/api/captures/<id>"}
+//!         (200 with the existing record when the same bytes were stored before)
+//!
+//!     GET /api/captures/:id
+//!         response: the bytes as an attachment, ETag "<id>" and an immutable
+//!         cache lifetime - If-None-Match with it gets a 304
+//!
+//!     GET /version
+//!         response: {"worker": "...", "version": "0.1.0", "git_sha": "...", "built_at": ..., "features": [...]}
+//!
+//!     GET /health/deps
+//!         response: shared::health report over CAPTURES, CAPTURES_META, AUTH, METRICS
+//!         and EVENTS
+//!
+//! ==============================================================================
+
+use worker::*;
+use serde::{Deserialize, Serialize};
+
+// the metrics durable object class has to be exported by the worker itself
+pub use shared::metrics_store::MetricsStore;
+
+use shared::build_info::BuildInfo;
+use shared::event_hub::LiveEvent;
+use shared::flags;
+use shared::health::Dependency;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// per-request data the router hands to every handler
+struct RequestMeta {
+    request_id: String,
+}
+
+/// worker label on exported metrics
+const WORKER_NAME: &str = "capture-archive";
+
+/// bindings probed by GET /health/deps
+const HEALTH_DEPS: &[Dependency] = &[
+    Dependency::R2("CAPTURES"),
+    Dependency::Kv("CAPTURES_META"),
+    Dependency::Kv(shared::auth_guard::AUTH_KV_BINDING),
+    shared::metrics_store::HEALTH_DEPENDENCY,
+    shared::event_hub::HEALTH_DEPENDENCY,
+];
+
+/// blobs are hashed in memory, so this stays well under the isolate's 128 MB
+const MAX_CAPTURE_BYTES: usize = 32 * 1024 * 1024;
+const MAX_NAME_LEN: usize = 100;
+const MAX_CONTENT_TYPE_LEN: usize = 127;
+const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+/// kv key prefix of the metadata records
+const META_PREFIX: &str = "capture:";
+
+/// what kv holds for each stored blob
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct CaptureMeta {
+    /// sha-256 of the bytes, lowercase hex - also the r2 key
+    id: String,
+    size: u64,
+    content_type: String,
+    name: String,
+    /// unix ms
+    created_at: u64,
+    /// principal that uploaded it
+    uploaded_by: String,
+}
+
+#[derive(Debug, Serialize)]
+struct CaptureResponse<'a> {
+    #[serde(flatten)]
+    meta: &'a CaptureMeta,
+    url: String,
+}
+
+// ==============================================================================
+// worker entry point
+// ==============================================================================
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
+    let started = js_sys::Date::now();
+    let route = route_label(&req.path());
+    let request_id = request_id(&req);
+    let origin = req.headers().get("Origin").ok().flatten();
+    console_log!("[{}] {:?} {}", request_id, req.method(), req.path());
+    
+    let client_flags = flags::load(&env).await.for_client(&get_client_id(&req));
+    let router = Router::with_data(RequestMeta { request_id: request_id.clone() });
+    
+    // the router consumes env - keep a handle for cors and the metrics flush
+    let metrics_env = env.clone();
+    let result = router
+        // upload a blob
+        .post_async("/api/captures", handle_upload)
+        // download a blob
+        .get_async("/api/captures/:id", handle_download)
+        .get("/health", |_, _| Response::ok("ok"))
+        // which build is deployed
+        .get("/version", |_, _| Response::from_json(&BuildInfo::new(WORKER_NAME, env!("CARGO_PKG_VERSION"))))
+        // this worker's bindings, collected by the gateway's /health/all
+        .get_async("/health/deps", handle_health_deps)
+        // prometheus scrape target
+        .get_async("/metrics", handle_metrics)
+        .options("/api/captures", handle_cors)
+        .options("/api/captures/:id", handle_cors)
+        .run(req, env)
+        .await;
+    
+    // unhandled errors and the router's own text 404/405s become problem+json too
+    let resp = match result {
+        Ok(resp) => resp,
+        Err(e) => {
+            console_error!("[{}] unhandled error: {}", request_id, e);
+            cors_error(&request_id, "internal error", 500)?
+        }
+    };
+    let mut resp = shared::problem::ensure_problem(resp, &request_id).await?;
+    stamp_request_id(&mut resp, &request_id);
+    let cors = shared::cors::policy(&metrics_env).await;
+    shared::cors::apply(&cors, origin.as_deref(), resp.headers_mut());
+    
+    let status = resp.status_code();
+    shared::metrics::record_request(route, status, js_sys::Date::now() - started);
+    if status >= 500 && client_flags.on(flags::LIVE_EVENTS) {
+        shared::event_hub::record(LiveEvent::fault(WORKER_NAME, route, status, &request_id, started as u64));
+    }
+    // with durable metrics off, requests leave their counters in the isolate - only a scrape merges them
+    if client_flags.on(flags::DURABLE_METRICS) {
+        shared::metrics_store::flush_if_due(&metrics_env, &ctx);
+    }
+    shared::event_hub::flush(&metrics_env, &ctx);
+    Ok(resp)
+}
+
+// ==============================================================================
+// request handlers
+// ==============================================================================
+
+/// prometheus text format metrics for this worker (needs the "metrics" scope)
+async fn handle_metrics(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    if let Err(e) = shared::auth_guard::require(&req, &ctx.env, &[], shared::auth::METRICS_SCOPE).await? {
+        return cors_error(&ctx.data.request_id, &e.to_string(), e.status());
+    }
+    shared::metrics_store::metrics_response(&ctx.env, WORKER_NAME).await
+}
+
+/// probe this worker's bindings
+async fn handle_health_deps(_req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    shared::health::deps_response(&ctx.env, HEALTH_DEPS).await
+}
+
+/// store a blob in r2 under its sha-256 (needs the "captures" scope)
+async fn handle_upload(mut req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    // signed requests cover the body, so it's read before auth
+    let body = match shared::body_limit::read_body(&mut req, MAX_CAPTURE_BYTES).await? {
+        Ok(body) => body,
+        Err(e) => {
+            console_warn!("[{}] 413 capture over {} bytes", ctx.data.request_id, e.limit);
+            let problem = e.problem().with_request_id(&ctx.data.request_id);
+            return problem.into_response();
+        }
+    };
+    let principal = match shared::auth_guard::require(&req, &ctx.env, &body, shared::auth::CAPTURES_SCOPE).await? {
+        Ok(p) => p,
+        Err(e) => return cors_error(&ctx.data.request_id, &e.to_string(), e.status()),
+    };
+    if body.is_empty() {
+        return cors_error(&ctx.data.request_id, "capture body is empty", 400);
+    }
+    
+    let id = shared::auth_guard::sha256_hex(&body).await?;
+    let meta_kv = ctx.env.kv("CAPTURES_META")?;
+    
+    // same bytes, same id - hand back the record that's already there
+    shared::metrics::record_kv_op("get");
+    if let Some(existing) = meta_kv.get(&meta_key(&id)).json::<CaptureMeta>().await? {
+        return capture_response(&existing, 200);
+    }
+    
+    let headers = req.headers();
+    let content_type = content_type(headers.get("Content-Type")?.as_deref());
+    let name = capture_name(headers.get("X-Capture-Name")?.as_deref(), &id);
+    let meta = CaptureMeta {
+        id: id.clone(),
+        size: body.len() as u64,
+        content_type: content_type.clone(),
+        name,
+        created_at: js_sys::Date::now() as u64,
+        uploaded_by: principal.subject,
+    };
+    
+    // blob first, then the record - a record never points at a missing blob
+    ctx.env
+        .bucket("CAPTURES")?
+        .put(&id, body)
+        .http_metadata(HttpMetadata { content_type: Some(content_type), ..HttpMetadata::default() })
+        .execute()
+        .await?;
+    shared::metrics::record_kv_op("put");
+    meta_kv.put(&meta_key(&id), serde_json::to_string(&meta)?)?.execute().await?;
+    
+    console_log!("[{}] {} stored capture {} ({} bytes)", ctx.data.request_id, meta.uploaded_by, id, meta.size);
+    capture_response(&meta, 201)
+}
+
+/// stream a stored blob back as an attachment
+async fn handle_download(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    let id = match ctx.param("id") {
+        Some(id) if valid_capture_id(id) => id.to_string(),
+        Some(_) => return cors_error(&ctx.data.request_id, "capture ids are 64 hex characters", 400),
+        None => return cors_error(&ctx.data.request_id, "missing capture id", 400),
+    };
+    
+    shared::metrics::record_kv_op("get");
+    let meta = match ctx.env.kv("CAPTURES_META")?.get(&meta_key(&id)).json::<CaptureMeta>().await? {
+        Some(meta) => meta,
+        None => return cors_error(&ctx.data.request_id, "capture not found", 404),
+    };
+    
+    // the id is the content hash, so it never changes and makes a perfect etag
+    let headers = Headers::new();
+    let etag = format!("\"{}\"", id);
+    headers.set("ETag", &etag)?;
+    headers.set("Cache-Control", "public, max-age=31536000, immutable")?;
+    if shared::etag::not_modified(req.headers().get("If-None-Match")?.as_deref(), &etag) {
+        return Ok(Response::empty()?.with_status(304).with_headers(headers));
+    }
+    
+    let object = ctx.env.bucket("CAPTURES")?.get(&id).execute().await?;
+    let body = match object.as_ref().and_then(|object| object.body()) {
+        Some(body) => body,
+        None => {
+            console_error!("[{}] capture {} has a record but no blob", ctx.data.request_id, id);
+            return cors_error(&ctx.data.request_id, "capture not found", 404);
+        }
+    };
+    
+    // uploads are untrusted - always a download, never rendered by the browser
+    headers.set("Content-Type", &meta.content_type)?;
+    headers.set("Content-Length", &meta.size.to_string())?;
+    headers.set("Content-Disposition", &content_disposition(&meta.name))?;
+    headers.set("X-Content-Type-Options", "nosniff")?;
+    Ok(Response::from_stream(body.stream()?)?.with_headers(headers))
+}
+
+/// handle cors preflight (allow-origin is added in fetch from the shared policy)
+fn handle_cors(_req: Request, _ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    let headers = Headers::new();
+    headers.set("Access-Control-Allow-Methods", "POST, GET, OPTIONS")?;
+    headers.set(
+        "Access-Control-Allow-Headers",
+        "Content-Type, X-Request-Id, X-Capture-Name, Authorization, X-API-Key, X-Auth-Timestamp, X-Auth-Signature",
+    )?;
+    
+    Ok(Response::empty()?.with_headers(headers))
+}
+
+// ==============================================================================
+// helpers
+// ==============================================================================
+
+fn capture_response(meta: &CaptureMeta, status: u16) -> Result<Response> {
+    let url = format!("/api/captures/{}", meta.id);
+    let json = serde_json::to_string(&CaptureResponse { meta, url: url.clone() })?;
+    let headers = Headers::new();
+    headers.set("Content-Type", "application/json")?;
+    headers.set("Location", &url)?;
+    Ok(Response::ok(json)?.with_status(status).with_headers(headers))
+}
+
+fn meta_key(id: &str) -> String {
+    format!("{}{}", META_PREFIX, id)
+}
+
+/// a sha-256 in lowercase hex
+fn valid_capture_id(id: &str) -> bool {
+    id.len() == 64 && id.chars().all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c))
+}
+
+/// the declared content type if it's a sane header value, else octet-stream
+fn content_type(declared: Option<&str>) -> String {
+    match declared.map(str::trim) {
+        Some(ct) if !ct.is_empty()
+            && ct.len() <= MAX_CONTENT_TYPE_LEN
+            && ct.contains('/')
+            && ct.chars().all(|c| c.is_ascii_graphic() || c == ' ') => ct.to_string(),
+        _ => DEFAULT_CONTENT_TYPE.to_string(),
+    }
+}
+
+/// a download file name: the client's, reduced to safe characters, or one
+/// made from the id
+fn capture_name(declared: Option<&str>, id: &str) -> String {
+    let cleaned: String = declared
+        .unwrap_or_default()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_'))
+        .take(MAX_NAME_LEN)
+        .collect();
+    match cleaned.trim_matches('.') {
+        "" => format!("capture-{}.bin", &id[..12.min(id.len())]),
+        name => name.to_string(),
+    }
+}
+
+fn content_disposition(name: &str) -> String {
+    format!("attachment; filename=\"{}\"", name)
+}
+
+/// route label for metrics - unknown paths share one series
+fn route_label(path: &str) -> &'static str {
+    match path {
+        "/api/captures" => "/api/captures",
+        "/health" => "/health",
+        "/version" => "/version",
+        "/health/deps" => "/health/deps",
+        "/metrics" => "/metrics",
+        p if p.starts_with("/api/captures/") => "/api/captures/:id",
+        _ => "other",
+    }
+}
+
+/// adopt the caller's X-Request-Id, or mint a new one
+fn request_id(req: &Request) -> String {
+    req.headers()
+        .get(shared::REQUEST_ID_HEADER)
+        .ok()
+        .flatten()
+        .and_then(|id| shared::accept_request_id(&id))
+        .unwrap_or_else(|| {
+            let random = (js_sys::Math::random() * u32::MAX as f64) as u32;
+            shared::new_request_id(js_sys::Date::now() as u64, random)
+        })
+}
+
+/// put the request id on the response and let browsers read it
+fn stamp_request_id(resp: &mut Response, request_id: &str) {
+    let headers = resp.headers_mut();
+    let _ = headers.set(shared::REQUEST_ID_HEADER, request_id);
+    let _ = headers.append("Access-Control-Expose-Headers", shared::REQUEST_ID_HEADER);
+}
+
+/// problem+json error response with cors headers, carrying the request id
+fn cors_error(request_id: &str, msg: &str, status: u16) -> Result<Response> {
+    console_warn!("[{}] {} {}", request_id, status, msg);
+    shared::problem::Problem::new(status, msg).with_request_id(request_id).into_response()
+}
+
+/// client identifier for flag rollouts - the connecting ip
+fn get_client_id(req: &Request) -> String {
+    match req.headers().get("CF-Connecting-IP") {
+        Ok(Some(ip)) => format!("ip:{}", ip),
+        _ => "unknown".to_string(),
+    }
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const ID: &str = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08";
+    
+    #[test]
+    fn test_capture_ids() {
+        assert!(valid_capture_id(ID));
+        assert!(!valid_capture_id(&ID.to_uppercase()));
+        assert!(!valid_capture_id(&ID[..63]));
+        assert!(!valid_capture_id("../../etc/passwd"));
+    }
+    
+    #[test]
+    fn test_content_type_falls_back() {
+        assert_eq!(content_type(Some("application/vnd.tcpdump.pcap")), "application/vnd.tcpdump.pcap");
+        assert_eq!(content_type(Some("text/csv; charset=utf-8")), "text/csv; charset=utf-8");
+        assert_eq!(content_type(None), DEFAULT_CONTENT_TYPE);
+        assert_eq!(content_type(Some("nonsense")), DEFAULT_CONTENT_TYPE);
+        assert_eq!(content_type(Some("text/html\r\nSet-Cookie: x=1")), DEFAULT_CONTENT_TYPE);
+    }
+    
+    #[test]
+    fn test_capture_names_are_safe() {
+        assert_eq!(capture_name(Some("modbus-2024-01.pcap"), ID), "modbus-2024-01.pcap");
+        // no paths, quotes or header tricks survive
+        assert_eq!(capture_name(Some("../\"evil\".pcap"), ID), "evil.pcap");
+        assert_eq!(capture_name(Some("..."), ID), "capture-9f86d081884c.bin");
+        assert_eq!(capture_name(None, ID), "capture-9f86d081884c.bin");
+        assert_eq!(content_disposition("a.pcap"), "attachment; filename=\"a.pcap\"");
+    }
+    
+    #[test]
+    fn test_response_flattens_meta() {
+        let meta = CaptureMeta {
+            id: ID.to_string(),
+            size: 1234,
+            content_type: DEFAULT_CONTENT_TYPE.to_string(),
+            name: "a.bin".to_string(),
+            created_at: 1,
+            uploaded_by: "ci".to_string(),
+        };
+        let json = serde_json::to_value(CaptureResponse { meta: &meta, url: format!("/api/captures/{}", ID) }).unwrap();
+        assert_eq!(json["id"], ID);
+        assert_eq!(json["size"], 1234);
+        assert_eq!(json["url"], format!("/api/captures/{}", ID));
+        assert_eq!(meta_key(ID), format!("capture:{}", ID));
+    }
+    
+    #[test]
+    fn test_route_labels() {
+        assert_eq!(route_label("/api/captures"), "/api/captures");
+        assert_eq!(route_label(&format!("/api/captures/{}", ID)), "/api/captures/:id");
+        assert_eq!(route_label("/nope"), "other");
+    }
+}
@@ -0,0 +1,88 @@
+//! ==============================================================================
+//! lib.rs - event hub cloudflare worker
+//! ==============================================================================
+//!
+//! purpose:
+//!     home of the EventHub durable object (shared::event_hub). the other
+//!     workers publish new links, rate-limit blocks and 5xx faults to it, and
+//!     dashboards hold a websocket open here (or through the gateway) for
+//!     one live feed across every demo.
+//!
+//! relationships:
+//!     - exports: durable object class EventHub, bound as "EVENTS" here and
+//!       by url-shortener, rate-limiter, capability-demo, capture-archive
+//!       and gateway (script_name = "event-hub")
+//!     - uses: workers kv namespace "AUTH" for the cors origin override
+//!     - called by: dashboard live feed (GET /events)
+//!
+//! cloudflare features demonstrated:
+//!     - durable objects shared across workers (script_name bindings)
+//!     - hibernatable websockets - idle subscribers cost nothing
+//!
+//! api:
+//!     GET /events   websocket upgrade; up to 25 recent events, then each new
+//!                   one as a json text frame:
+//!                   {"kind": "link" | "block" | "fault", "worker": "...",
+//!                    "subject": "...", "status": 429, "request_id": "...", "at": ...}
+//!     GET /health   health check
+//!     GET /version  build info (shared::build_info)
+//!     GET /health/deps  shared::health report over AUTH and EVENTS
+//!
+//! ==============================================================================
+
+use worker::*;
+
+// the hub class has to be exported by the worker that declares its migration
+pub use shared::event_hub::EventHub;
+
+use shared::build_info::BuildInfo;
+use shared::health::Dependency;
+
+/// bindings probed by GET /health/deps
+const HEALTH_DEPS: &[Dependency] = &[
+    Dependency::Kv(shared::auth_guard::AUTH_KV_BINDING),
+    shared::event_hub::HEALTH_DEPENDENCY,
+];
+
+// ==============================================================================
+// worker entry point
+// ==============================================================================
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    let request_id = request_id(&req);
+    console_log!("[{}] {:?} {}", request_id, req.method(), req.path());
+    
+    let result = match (req.method(), req.path().as_str()) {
+        (Method::Get, "/events") => shared::event_hub::subscribe(req, &env, &request_id).await,
+        (Method::Get, "/health") => Response::ok("ok"),
+        (Method::Get, "/version") => Response::from_json(&BuildInfo::new("event-hub", env!("CARGO_PKG_VERSION"))),
+        (Method::Get, shared::health::DEPS_PATH) => shared::health::deps_response(&env, HEALTH_DEPS).await,
+        _ => shared::problem::Problem::new(404, "not found").with_request_id(&request_id).into_response(),
+    };
+    
+    match result {
+        Ok(resp) => Ok(resp),
+        Err(e) => {
+            console_error!("[{}] unhandled error: {}", request_id, e);
+            shared::problem::Problem::new(500, "internal error").with_request_id(&request_id).into_response()
+        }
+    }
+}
+
+// ==============================================================================
+// helpers
+// ==============================================================================
+
+/// adopt the caller's X-Request-Id, or mint a new one
+fn request_id(req: &Request) -> String {
+    req.headers()
+        .get(shared::REQUEST_ID_HEADER)
+        .ok()
+        .flatten()
+        .and_then(|id| shared::accept_request_id(&id))
+        .unwrap_or_else(|| {
+            let random = (js_sys::Math::random() * u32::MAX as f64) as u32;
+            shared::new_request_id(js_sys::Date::now() as u64, random)
+        })
+}
@@ -0,0 +1,518 @@
+//! ==============================================================================
+//! lib.rs - api gateway cloudflare worker
+//! ==============================================================================
+//!
+//! purpose:
+//!     one hostname in front of all demo workers. requests are routed by
+//!     path prefix to the individual workers over service bindings, so
+//!     the dashboard only ever talks to a single origin.
+//!
+//! relationships:
+//!     - calls: url-shortener, rate-limiter, capability-demo (service bindings)
+//!     - calls: capture-archive, event-hub, log-collector (service bindings,
+//!       only for GET /health/all)
+//!     - uses: workers kv namespace "RATES" for the gateway-wide limit
+//!     - uses: durable object "METRICS" for /metrics totals (shared::metrics_store)
+//!     - uses: workers kv namespace "AUTH" for api keys (shared::auth_guard)
+//!     - uses: shared (request ids, key-masked log paths)
+//!     - uses: durable object "EVENTS" in the event-hub worker for the
+//!       live feed (shared::event_hub) - publishes, and GET /events subscribes
+//!     - uses: shared::flags to switch the live feed and durable metrics off
+//!       (or roll them out) without a redeploy
+//!     - uses: shared::alerting - a cron runs the /health/all checks and
+//!       emails failures through mailchannels
+//!     - uses: shared::canary - splits each worker's traffic between its
+//!       stable binding and an optional <BINDING>_CANARY one
+//!     - called by: dashboard (when gateway mode is on)
+//!
+//! cloudflare features demonstrated:
+//!     - service bindings (worker-to-worker calls without a public hop)
+//!     - edge routing and header rewriting
+//!
+//! centralized here:
+//!     - cors: preflights are answered by the gateway, and every proxied
+//!       response gets the gateway's cors headers
+//!     - request ids: adopted or minted once and forwarded, so the worker
+//!       behind the gateway logs the same id the client sees
+//!     - rate limiting: a generous per-client limit across all services,
+//!       in front of each worker's own limit
+//!
+//! routing:
+//!     /shorten/*     -> url-shortener    (/shorten alone -> POST /shorten)
+//!     /limit/*       -> rate-limiter     (/limit alone -> /api/protected)
+//!     /capability/*  -> capability-demo  (/capability alone -> /api/capabilities)
+//!     the prefix is stripped before forwarding: /limit/api/status -> /api/status.
+//!     /vote and /parse from the original protocol demo have no workers in
+//!     this repo, so they 404 like any other unknown prefix.
+//!     a worker with a canary bound gets the share of clients set in kv
+//!     "canary:config" sent to it, or any request with X-Canary: canary;
+//!     X-Upstream-Version on the response says which one answered.
+//!
+//! api:
+//!     GET /health   gateway health (not proxied)
+//!     GET /health/all  every worker's /health/deps and the gateway's own
+//!                   bindings in one status document (shared::health)
+//!     GET /version  the gateway's own build info (each worker has its own /version)
+//!     GET /metrics  prometheus metrics for the gateway itself (metrics scope)
+//!     GET /canary   stable vs canary error rates per worker (metrics scope)
+//!     GET /events   websocket upgrade - live link/block/fault feed from all workers
+//!     auth headers pass through untouched - each worker checks its own admin routes
+//!
+//! ==============================================================================
+
+use worker::*;
+use serde::{Deserialize, Serialize};
+
+// the metrics durable object class has to be exported by the worker itself
+pub use shared::metrics_store::MetricsStore;
+
+use shared::alerting::Alert;
+use shared::build_info::BuildInfo;
+use shared::canary::{CanaryReport, Version};
+use shared::event_hub::LiveEvent;
+use shared::flags::{self, ClientFlags};
+use shared::health::Dependency;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// a worker reachable through the gateway
+#[derive(Debug, PartialEq)]
+struct Upstream {
+    /// worker name - the label on per-version metrics and in canary:config
+    name: &'static str,
+    /// path prefix on the gateway
+    prefix: &'static str,
+    /// service binding name in wrangler.toml
+    binding: &'static str,
+    /// upstream path for a bare prefix
+    root: &'static str,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct RateInfo {
+    count: u32,
+    window_start: u64,
+}
+
+/// worker label on exported metrics
+const WORKER_NAME: &str = "gateway";
+
+/// largest body the gateway will buffer and forward - the workers apply
+/// tighter per-route limits of their own
+const MAX_PROXY_BODY_BYTES: usize = 64 * 1024;
+
+const UPSTREAMS: &[Upstream] = &[
+    Upstream { name: "url-shortener", prefix: "/shorten", binding: "URL_SHORTENER", root: "/shorten" },
+    Upstream { name: "rate-limiter", prefix: "/limit", binding: "RATE_LIMITER", root: "/api/protected" },
+    Upstream { name: "capability-demo", prefix: "/capability", binding: "CAPABILITY_DEMO", root: "/api/capabilities" },
+];
+
+/// workers checked by GET /health/all: (name, service binding)
+const HEALTH_SERVICES: &[(&str, &str)] = &[
+    ("url-shortener", "URL_SHORTENER"),
+    ("rate-limiter", "RATE_LIMITER"),
+    ("capability-demo", "CAPABILITY_DEMO"),
+    ("capture-archive", "CAPTURE_ARCHIVE"),
+    ("event-hub", "EVENT_HUB"),
+    ("log-collector", "LOG_COLLECTOR"),
+];
+
+/// the gateway's own bindings in GET /health/all
+const HEALTH_DEPS: &[Dependency] = &[
+    Dependency::Kv("RATES"),
+    Dependency::Kv(shared::auth_guard::AUTH_KV_BINDING),
+    shared::metrics_store::HEALTH_DEPENDENCY,
+    shared::event_hub::HEALTH_DEPENDENCY,
+];
+
+/// response headers the dashboard needs to read
+const EXPOSED_HEADERS: &str = "X-Request-Id, X-RateLimit-Limit, X-RateLimit-Remaining, X-RateLimit-Reset, \
+    X-RateLimit-Policy, X-RateLimit-Shadow, RateLimit-Limit, RateLimit-Remaining, RateLimit-Reset, RateLimit-Policy, \
+    Retry-After, X-Upstream-Version";
+
+// ==============================================================================
+// worker entry point
+// ==============================================================================
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
+    let started = js_sys::Date::now();
+    let path = req.path();
+    let request_id = request_id(&req);
+    let origin = req.headers().get("Origin").ok().flatten();
+    console_log!("[{}] {:?} {}", request_id, req.method(), log_path(&path));
+    
+    let client_flags = flags::load(&env).await.for_client(&get_client_id(&req));
+    let route = resolve(&path);
+    let label = route.as_ref().map(|(u, _)| u.prefix).unwrap_or(match path.as_str() {
+        "/health" => "/health",
+        "/health/all" => "/health/all",
+        "/metrics" => "/metrics",
+        "/canary" => "/canary",
+        "/events" => "/events",
+        "/version" => "/version",
+        _ => "other",
+    });
+    
+    let result = match route {
+        // preflights never reach the workers
+        _ if req.method() == Method::Options => Response::empty(),
+        Some((upstream, upstream_path)) => {
+            handle_proxy(req, &env, &client_flags, upstream, &upstream_path, &request_id).await
+        }
+        None => match path.as_str() {
+            "/health" => Response::ok("ok"),
+            "/health/all" => handle_health_all(&env).await,
+            "/version" => Response::from_json(&BuildInfo::new(WORKER_NAME, env!("CARGO_PKG_VERSION"))),
+            "/metrics" => handle_metrics(&req, &env, &request_id).await,
+            "/canary" => handle_canary(&req, &env, &request_id).await,
+            "/events" => shared::event_hub::subscribe(req, &env, &request_id).await,
+            _ => cors_error(&request_id, "unknown service", 404),
+        },
+    };
+    
+    // unhandled errors and plain-text upstream failures become problem+json too
+    let resp = match result {
+        Ok(resp) => resp,
+        Err(e) => {
+            console_error!("[{}] unhandled error: {}", request_id, e);
+            cors_error(&request_id, "internal error", 500)?
+        }
+    };
+    // an accepted websocket goes back untouched
+    if resp.status_code() == 101 {
+        shared::metrics::record_request(label, 101, js_sys::Date::now() - started);
+        return Ok(resp);
+    }
+    let mut resp = shared::problem::ensure_problem(resp, &request_id).await?;
+    let headers = resp.headers_mut();
+    let _ = headers.set(shared::REQUEST_ID_HEADER, &request_id);
+    let cors = shared::cors::policy(&env).await;
+    apply_cors(&cors, origin.as_deref(), headers);
+    
+    let status = resp.status_code();
+    shared::metrics::record_request(label, status, js_sys::Date::now() - started);
+    if status >= 500 && client_flags.on(flags::LIVE_EVENTS) {
+        shared::event_hub::record(LiveEvent::fault(WORKER_NAME, label, status, &request_id, started as u64));
+    }
+    // with durable metrics off, requests leave their counters in the isolate - only a scrape merges them
+    if client_flags.on(flags::DURABLE_METRICS) {
+        shared::metrics_store::flush_if_due(&env, &ctx);
+    }
+    shared::event_hub::flush(&env, &ctx);
+    Ok(resp)
+}
+
+// ==============================================================================
+// scheduled health alerts
+// ==============================================================================
+
+/// every few minutes: run the /health/all checks and email the operators
+/// when something is down (shared::alerting throttles repeats)
+#[event(scheduled)]
+async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    let report = shared::health::aggregate(&env, WORKER_NAME, HEALTH_DEPS, HEALTH_SERVICES).await;
+    let Some(alert) = Alert::from_health(&report) else {
+        return;
+    };
+    console_warn!("health {:?}: {}", report.status, alert.summary);
+    if let Err(e) = shared::alerting::send(&env, &alert).await {
+        console_error!("alert email failed: {}", e);
+    }
+}
+
+// ==============================================================================
+// request handlers
+// ==============================================================================
+
+/// prometheus text format metrics for the gateway (needs the "metrics" scope)
+async fn handle_metrics(req: &Request, env: &Env, request_id: &str) -> Result<Response> {
+    if let Err(e) = shared::auth_guard::require(req, env, &[], shared::auth::METRICS_SCOPE).await? {
+        return cors_error(request_id, &e.to_string(), e.status());
+    }
+    shared::metrics_store::metrics_response(env, WORKER_NAME).await
+}
+
+/// per-version request and error counts of every proxied worker (needs the
+/// "metrics" scope, like /metrics)
+async fn handle_canary(req: &Request, env: &Env, request_id: &str) -> Result<Response> {
+    if let Err(e) = shared::auth_guard::require(req, env, &[], shared::auth::METRICS_SCOPE).await? {
+        return cors_error(request_id, &e.to_string(), e.status());
+    }
+    let config = shared::canary::load(env).await;
+    let totals = shared::metrics_store::totals(env).await?;
+    let names: Vec<&str> = UPSTREAMS.iter().map(|u| u.name).collect();
+    let report = CanaryReport {
+        checked_at: js_sys::Date::now() as u64,
+        upstreams: shared::canary::compare(&names, &config, &totals.upstream_requests),
+    };
+    let resp = Response::from_json(&report)?;
+    resp.headers().set("Cache-Control", "no-store")?;
+    Ok(resp)
+}
+
+/// consolidated status of every worker and its bindings
+async fn handle_health_all(env: &Env) -> Result<Response> {
+    let report = shared::health::aggregate(env, WORKER_NAME, HEALTH_DEPS, HEALTH_SERVICES).await;
+    shared::health::report_response(&report)
+}
+
+/// forward a request to its worker over the service binding
+async fn handle_proxy(
+    mut req: Request,
+    env: &Env,
+    client_flags: &ClientFlags,
+    upstream: &Upstream,
+    path: &str,
+    request_id: &str,
+) -> Result<Response> {
+    let limit: u32 = env.var("RATE_LIMIT")
+        .map(|v| v.to_string().parse().unwrap_or(120))
+        .unwrap_or(120);
+    let window_seconds: u64 = env.var("RATE_WINDOW_SECONDS")
+        .map(|v| v.to_string().parse().unwrap_or(60))
+        .unwrap_or(60);
+    
+    let client_id = get_client_id(&req);
+    let (allowed, reset_in) = check_rate_limit(env, &client_id, limit, window_seconds).await?;
+    if !allowed {
+        if client_flags.on(flags::LIVE_EVENTS) {
+            shared::event_hub::record(LiveEvent::block(WORKER_NAME, &client_id, request_id, js_sys::Date::now() as u64));
+        }
+        let resp = cors_error(request_id, "gateway rate limit exceeded - try again later", 429)?;
+        let _ = resp.headers().set("Retry-After", &reset_in.to_string());
+        return Ok(resp);
+    }
+    
+    // same url with the prefix stripped - keeping the host lets the worker
+    // see which hostname the client used
+    let mut url = req.url()?;
+    url.set_path(path);
+    
+    let headers = Headers::new();
+    for (name, value) in req.headers().entries() {
+        headers.append(&name, &value)?;
+    }
+    headers.set(shared::REQUEST_ID_HEADER, request_id)?;
+    // links the worker builds (short urls) and signed paths include the prefix
+    headers.set(shared::FORWARDED_PREFIX_HEADER, upstream.prefix)?;
+    
+    let mut init = RequestInit::new();
+    init.with_method(req.method())
+        .with_headers(headers)
+        // redirects (short links) go back to the client, not through us
+        .with_redirect(RequestRedirect::Manual);
+    if !matches!(req.method(), Method::Get | Method::Head) {
+        let body = match shared::body_limit::read_body(&mut req, MAX_PROXY_BODY_BYTES).await? {
+            Ok(body) => body,
+            Err(e) => return e.problem().with_request_id(request_id).into_response(),
+        };
+        init.with_body(Some(js_sys::Uint8Array::from(body.as_slice()).into()));
+    }
+    
+    // stable unless the worker has a canary bound and this client (or the
+    // X-Canary header) lands on it
+    let canary_binding = shared::canary::binding(upstream.binding, Version::Canary);
+    let forced = req.headers().get(shared::canary::FORCE_HEADER).ok().flatten();
+    let version = shared::canary::load(env).await
+        .choose(upstream.name, &client_id, forced.as_deref(), env.service(&canary_binding).is_ok());
+    
+    let forwarded = Request::new_with_init(url.as_str(), &init)?;
+    let service = env.service(&shared::canary::binding(upstream.binding, version))?;
+    let mut resp = match service.fetch_request(forwarded).await {
+        Ok(resp) => resp,
+        Err(e) => {
+            shared::metrics::record_upstream(upstream.name, version.as_str(), 502);
+            return Err(e);
+        }
+    };
+    
+    // fetched responses have immutable headers - copy into a fresh one
+    let status = resp.status_code();
+    shared::metrics::record_upstream(upstream.name, version.as_str(), status);
+    let headers = Headers::new();
+    for (name, value) in resp.headers().entries() {
+        headers.append(&name, &value)?;
+    }
+    headers.set(shared::canary::VERSION_HEADER, version.as_str())?;
+    let body = resp.bytes().await?;
+    let out = if body.is_empty() { Response::empty()? } else { Response::from_bytes(body)? };
+    Ok(out.with_status(status).with_headers(headers))
+}
+
+/// the gateway's cors headers, replacing whatever the worker sent
+fn apply_cors(policy: &shared::cors::CorsPolicy, origin: Option<&str>, headers: &Headers) {
+    shared::cors::apply(policy, origin, headers);
+    let _ = headers.set("Access-Control-Allow-Methods", "GET, POST, OPTIONS");
+    let _ = headers.set("Access-Control-Allow-Headers", "Authorization, Content-Type, X-API-Key, X-Request-Id, X-Turnstile-Token, X-Bypass-Token, X-Canary");
+    let _ = headers.set("Access-Control-Expose-Headers", EXPOSED_HEADERS);
+}
+
+// ==============================================================================
+// rate limiting
+// ==============================================================================
+
+/// count a request against the gateway-wide limit; returns (allowed, reset in seconds)
+async fn check_rate_limit(
+    env: &Env,
+    client_id: &str,
+    limit: u32,
+    window_seconds: u64,
+) -> Result<(bool, u64)> {
+    let kv = env.kv("RATES")?;
+    let now = js_sys::Date::now() as u64 / 1000;
+    
+    // prefix with worker name to avoid collisions
+    let key = format!("gateway:{}", client_id);
+    
+    shared::metrics::record_kv_op("get");
+    let mut rate_info = match kv.get(&key).text().await? {
+        Some(json) => {
+            let info: RateInfo = serde_json::from_str(&json).unwrap_or(RateInfo {
+                count: 0,
+                window_start: now,
+            });
+            // check if window has expired
+            if now - info.window_start >= window_seconds {
+                RateInfo { count: 0, window_start: now }
+            } else {
+                info
+            }
+        }
+        None => RateInfo { count: 0, window_start: now },
+    };
+    let reset_in = window_seconds.saturating_sub(now.saturating_sub(rate_info.window_start));
+    
+    if rate_info.count >= limit {
+        return Ok((false, reset_in));
+    }
+    
+    rate_info.count += 1;
+    let json = serde_json::to_string(&rate_info).unwrap();
+    shared::metrics::record_kv_op("put");
+    // windows under a minute still expire after one - kv refuses shorter ttls,
+    // and a counter from a finished window is reset above anyway
+    kv.put(&key, json)?
+        .expiration_ttl(window_seconds.max(60))
+        .execute()
+        .await?;
+    
+    Ok((true, reset_in))
+}
+
+// ==============================================================================
+// helpers
+// ==============================================================================
+
+/// find the worker for a gateway path, and the path to forward
+fn resolve(path: &str) -> Option<(&'static Upstream, String)> {
+    UPSTREAMS.iter().find_map(|upstream| {
+        match path.strip_prefix(upstream.prefix)? {
+            "" | "/" => Some((upstream, upstream.root.to_string())),
+            // "/shortened" is not under "/shorten"
+            rest if rest.starts_with('/') => Some((upstream, rest.to_string())),
+            _ => None,
+        }
+    })
+}
+
+/// a gateway path safe to log - masked the way its worker masks it
+fn log_path(path: &str) -> String {
+    match resolve(path) {
+        Some((upstream, _)) => format!("{}{}", upstream.prefix, shared::log_path(&path[upstream.prefix.len()..])),
+        None => shared::log_path(path),
+    }
+}
+
+/// adopt the caller's X-Request-Id, or mint a new one
+fn request_id(req: &Request) -> String {
+    req.headers()
+        .get(shared::REQUEST_ID_HEADER)
+        .ok()
+        .flatten()
+        .and_then(|id| shared::accept_request_id(&id))
+        .unwrap_or_else(|| {
+            let random = (js_sys::Math::random() * u32::MAX as f64) as u32;
+            shared::new_request_id(js_sys::Date::now() as u64, random)
+        })
+}
+
+/// problem+json error response with cors headers, carrying the request id
+fn cors_error(request_id: &str, msg: &str, status: u16) -> Result<Response> {
+    console_warn!("[{}] {} {}", request_id, status, msg);
+    shared::problem::Problem::new(status, msg).with_request_id(request_id).into_response()
+}
+
+/// get client identifier from api key header or ip address
+fn get_client_id(req: &Request) -> String {
+    let headers = req.headers();
+    
+    // prefer api key if provided
+    if let Ok(Some(key)) = headers.get("X-API-Key") {
+        return format!("key:{}", key);
+    }
+    
+    // fall back to cf-connecting-ip (cloudflare provides this)
+    if let Ok(Some(ip)) = headers.get("CF-Connecting-IP") {
+        return format!("ip:{}", ip);
+    }
+    
+    // last resort
+    "unknown".to_string()
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn binding(path: &str) -> Option<(&'static str, String)> {
+        resolve(path).map(|(u, p)| (u.binding, p))
+    }
+    
+    #[test]
+    fn test_resolve_strips_prefix() {
+        assert_eq!(binding("/shorten/abc123"), Some(("URL_SHORTENER", "/abc123".to_string())));
+        assert_eq!(binding("/shorten/stats/abc123"), Some(("URL_SHORTENER", "/stats/abc123".to_string())));
+        assert_eq!(binding("/limit/api/status"), Some(("RATE_LIMITER", "/api/status".to_string())));
+        assert_eq!(binding("/capability/api/capability"), Some(("CAPABILITY_DEMO", "/api/capability".to_string())));
+    }
+    
+    #[test]
+    fn test_resolve_bare_prefix_uses_root() {
+        assert_eq!(binding("/shorten"), Some(("URL_SHORTENER", "/shorten".to_string())));
+        assert_eq!(binding("/limit/"), Some(("RATE_LIMITER", "/api/protected".to_string())));
+        assert_eq!(binding("/capability"), Some(("CAPABILITY_DEMO", "/api/capabilities".to_string())));
+    }
+    
+    #[test]
+    fn test_resolve_unknown_paths() {
+        assert_eq!(binding("/shortened"), None);
+        assert_eq!(binding("/vote/results"), None);
+        assert_eq!(binding("/parse"), None);
+        assert_eq!(binding("/health"), None);
+        assert_eq!(binding("/"), None);
+    }
+    
+    #[test]
+    fn test_log_path_masks_proxied_keys() {
+        assert_eq!(log_path("/limit/admin/keys/rl_0123abcd"), "/limit/admin/keys/***");
+        assert_eq!(log_path("/limit/admin/keys/rl_0123abcd/usage"), "/limit/admin/keys/***/usage");
+        assert_eq!(log_path("/limit"), "/limit");
+        assert_eq!(log_path("/shorten/abc123"), "/shorten/abc123");
+        assert_eq!(log_path("/health"), "/health");
+    }
+    
+    #[test]
+    fn test_upstream_names_match_health_services() {
+        // canary:config and the per-version metrics use the names /health/all reports
+        for upstream in UPSTREAMS {
+            assert!(HEALTH_SERVICES.contains(&(upstream.name, upstream.binding)), "{}", upstream.name);
+        }
+    }
+}
@@ -0,0 +1,36 @@
+// ==============================================================================
+// entry.mjs - tail handler wrapper for the log collector
+// ==============================================================================
+//
+// workers-rs has no tail() event, so this module re-exports the generated
+// rust shim and adds one: trace events are forwarded to the rust fetch
+// handler as POST /ingest on an internal host the public can't reach.
+//
+// ==============================================================================
+
+import Shim from "../build/worker/shim.mjs";
+
+const INGEST_URL = "https://log-collector.internal/ingest";
+
+// the shim is a WorkerEntrypoint class in newer worker-build versions
+// and a plain { fetch } object in older ones
+function forward(request, env, ctx) {
+    return typeof Shim === "function"
+        ? new Shim(ctx, env).fetch(request)
+        : Shim.fetch(request, env, ctx);
+}
+
+export default {
+    fetch(request, env, ctx) {
+        return forward(request, env, ctx);
+    },
+
+    tail(events, env, ctx) {
+        const request = new Request(INGEST_URL, {
+            method: "POST",
+            headers: { "Content-Type": "application/json" },
+            body: JSON.stringify(events),
+        });
+        ctx.waitUntil(forward(request, env, ctx));
+    },
+};
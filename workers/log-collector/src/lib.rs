@@ -0,0 +1,460 @@
+//! ==============================================================================
+//! lib.rs - log collector tail worker
+//! ==============================================================================
+//!
+//! purpose:
+//!     central log collection for the demo workers. cloudflare delivers each
+//!     producer's trace events (outcome, status, colo, exceptions) to this
+//!     tail worker, which rolls them up into hourly error-rate counters per
+//!     worker and colo and serves them to the dashboard's ops view.
+//!
+//! relationships:
+//!     - consumes: trace events from url-shortener, rate-limiter and
+//!       capability-demo (tail_consumers in their wrangler.toml)
+//!     - uses: workers kv namespace "LOGS" for hourly buckets
+//!     - uses: shared (problem+json error bodies)
+//!     - called by: dashboard (ops view)
+//!     - src/entry.mjs adds the tail() handler and forwards events here
+//!
+//! cloudflare features demonstrated:
+//!     - tail workers (trace event consumers)
+//!     - workers kv with ttl-based retention
+//!
+//! storage:
+//!     one kv key per hour: "log-collector:<hours since epoch>"
+//!     value: {"<worker>|<colo>": {"requests": n, "errors": n, ...}}
+//!     kv is eventually consistent and allows ~1 write/sec per key, so
+//!     counts are approximate under heavy traffic - fine for an ops view.
+//!
+//! api:
+//!     GET /api/errors?hours=24
+//!         response: {"hours": 24, "rows": [{"worker": "rate-limiter",
+//!                    "colo": "IAD", "requests": 120, "errors": 2, ...}]}
+//!
+//!     POST /ingest (internal - only reachable from entry.mjs)
+//!         body: array of trace events
+//!
+//!     GET /version
+//!         response: {"worker": "...", "version": "0.1.0", "git_sha": "...", "built_at": ..., "features": [...]}
+//!
+//!     GET /health/deps
+//!         response: shared::health report over LOGS
+//!
+//! ==============================================================================
+
+use std::collections::BTreeMap;
+
+use worker::*;
+use serde::{Deserialize, Serialize};
+
+use shared::build_info::BuildInfo;
+use shared::health::Dependency;
+
+// ==============================================================================
+// types
+// ==============================================================================
+
+/// one trace event as delivered to tail()
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+struct TraceItem {
+    script_name: Option<String>,
+    /// "ok", "exception", "exceededCpu", "canceled", ...
+    outcome: String,
+    event_timestamp: Option<u64>,
+    event: Option<TraceEvent>,
+    exceptions: Vec<TraceException>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TraceEvent {
+    request: Option<TraceRequest>,
+    response: Option<TraceResponse>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TraceRequest {
+    cf: Option<TraceCf>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TraceCf {
+    colo: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TraceResponse {
+    status: u16,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct TraceException {
+    name: String,
+    message: String,
+}
+
+/// counters for one worker + colo
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize, Deserialize)]
+struct Counters {
+    requests: u64,
+    /// 5xx responses and failed invocations
+    errors: u64,
+    /// 4xx responses (rate limits, bad input)
+    client_errors: u64,
+    /// uncaught exceptions
+    exceptions: u64,
+}
+
+/// one hour of counters, keyed by "worker|colo"
+type Bucket = BTreeMap<String, Counters>;
+
+#[derive(Debug, Serialize)]
+struct ErrorRateRow {
+    worker: String,
+    colo: String,
+    requests: u64,
+    errors: u64,
+    client_errors: u64,
+    exceptions: u64,
+    /// errors / requests, 0 when idle
+    error_rate: f64,
+}
+
+#[derive(Debug, Serialize)]
+struct ErrorsResponse {
+    hours: u64,
+    rows: Vec<ErrorRateRow>,
+}
+
+// ==============================================================================
+// limits
+// ==============================================================================
+
+/// host entry.mjs uses when forwarding trace events
+const INTERNAL_HOST: &str = "log-collector.internal";
+
+const KEY_PREFIX: &str = "log-collector:";
+const HOUR_MS: u64 = 60 * 60 * 1000;
+
+/// buckets outlive the longest query window by a day
+const MAX_QUERY_HOURS: u64 = 7 * 24;
+const DEFAULT_QUERY_HOURS: u64 = 24;
+const BUCKET_TTL_SECONDS: u64 = (MAX_QUERY_HOURS + 24) * 60 * 60;
+
+/// bindings probed by GET /health/deps
+const HEALTH_DEPS: &[Dependency] = &[Dependency::Kv("LOGS")];
+
+// ==============================================================================
+// worker entry point
+// ==============================================================================
+
+#[event(fetch)]
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    let origin = req.headers().get("Origin").ok().flatten();
+    // no kv override here - the log collector doesn't bind AUTH
+    let cors = env
+        .var(shared::cors::ALLOWED_ORIGINS_VAR)
+        .map(|v| shared::cors::CorsPolicy::parse(&v.to_string()).0)
+        .ok()
+        .filter(|p| !p.patterns.is_empty())
+        .unwrap_or_default();
+    let router = Router::new();
+    
+    let result = router
+        // trace events forwarded by the tail() handler
+        .post_async("/ingest", handle_ingest)
+        // error rates for the dashboard
+        .get_async("/api/errors", handle_errors)
+        // health check
+        .get("/health", |_, _| Response::ok("ok"))
+        // which build is deployed
+        .get("/version", |_, _| Response::from_json(&BuildInfo::new("log-collector", env!("CARGO_PKG_VERSION"))))
+        // this worker's bindings, collected by the gateway's /health/all
+        .get_async("/health/deps", |_, ctx| async move { shared::health::deps_response(&ctx.env, HEALTH_DEPS).await })
+        // cors preflight
+        .options("/api/errors", handle_cors)
+        .run(req, env)
+        .await;
+    
+    // unhandled errors and the router's own text 404/405s become problem+json too
+    let mut resp = match result {
+        Ok(resp) => resp,
+        Err(e) => {
+            console_error!("unhandled error: {}", e);
+            problem(500, "internal error")?
+        }
+    };
+    let status = resp.status_code();
+    if shared::problem::needs_wrapping(status, resp.headers().get("Content-Type")?.as_deref()) {
+        resp = problem(status, &shared::problem::title(status).to_lowercase())?;
+    }
+    
+    let headers = resp.headers_mut();
+    let _ = headers.delete("Access-Control-Allow-Origin");
+    for (name, value) in cors.headers(origin.as_deref()) {
+        let _ = headers.append(name, &value);
+    }
+    Ok(resp)
+}
+
+// ==============================================================================
+// request handlers
+// ==============================================================================
+
+/// fold a batch of trace events into the hourly buckets
+async fn handle_ingest(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    // only entry.mjs can address the internal host
+    if req.url()?.host_str() != Some(INTERNAL_HOST) {
+        return problem(404, "not found");
+    }
+    
+    let items: Vec<TraceItem> = match req.json().await {
+        Ok(items) => items,
+        Err(_) => return problem(400, "invalid trace events"),
+    };
+    
+    // surface uncaught exceptions in this worker's own logs too
+    for item in &items {
+        for e in &item.exceptions {
+            console_error!("[{}] {}: {}", worker_name(item), e.name, e.message);
+        }
+    }
+    
+    let now = js_sys::Date::now() as u64;
+    let kv = ctx.env.kv("LOGS")?;
+    for (hour, batch) in aggregate(&items, now) {
+        let key = format!("{}{}", KEY_PREFIX, hour);
+        
+        let mut bucket: Bucket = match kv.get(&key).text().await? {
+            Some(json) => serde_json::from_str(&json).unwrap_or_default(),
+            None => Bucket::new(),
+        };
+        merge(&mut bucket, &batch);
+        
+        // a failed write only loses this batch - never fail the tail
+        let json = serde_json::to_string(&bucket).unwrap();
+        if let Err(e) = async { kv.put(&key, json)?.expiration_ttl(BUCKET_TTL_SECONDS).execute().await }.await {
+            console_warn!("dropping trace batch for hour {}: {}", hour, e);
+        }
+    }
+    
+    Response::empty()
+}
+
+/// error rates per worker and colo over the last `hours`
+async fn handle_errors(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+    let hours = req.url()?
+        .query_pairs()
+        .find(|(k, _)| k == "hours")
+        .and_then(|(_, v)| v.parse().ok())
+        .unwrap_or(DEFAULT_QUERY_HOURS)
+        .clamp(1, MAX_QUERY_HOURS);
+    
+    let kv = ctx.env.kv("LOGS")?;
+    let current_hour = js_sys::Date::now() as u64 / HOUR_MS;
+    
+    let mut total = Bucket::new();
+    for hour in (current_hour + 1 - hours)..=current_hour {
+        if let Some(json) = kv.get(&format!("{}{}", KEY_PREFIX, hour)).text().await? {
+            if let Ok(bucket) = serde_json::from_str::<Bucket>(&json) {
+                merge(&mut total, &bucket);
+            }
+        }
+    }
+    
+    let response = ErrorsResponse { hours, rows: rows(&total) };
+    let json = serde_json::to_string(&response).unwrap();
+    let headers = Headers::new();
+    headers.set("Content-Type", "application/json")?;
+    headers.set("Cache-Control", "public, max-age=30")?; // Cache for 30 seconds
+    
+    Ok(Response::ok(json)?.with_headers(headers))
+}
+
+/// handle cors preflight (allow-origin is added in fetch from the shared policy)
+fn handle_cors(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
+    let headers = Headers::new();
+    headers.set("Access-Control-Allow-Methods", "GET, OPTIONS")?;
+    headers.set("Access-Control-Allow-Headers", "Content-Type")?;
+    
+    Ok(Response::empty()?.with_headers(headers))
+}
+
+/// problem+json error response with cors headers (see shared::problem)
+fn problem(status: u16, msg: &str) -> Result<Response> {
+    let problem = shared::problem::Problem::new(status, msg);
+    let headers = Headers::new();
+    for (name, value) in problem.headers() {
+        headers.set(name, value)?;
+    }
+    Ok(Response::ok(problem.to_json())?.with_status(status).with_headers(headers))
+}
+
+// ==============================================================================
+// aggregation
+// ==============================================================================
+
+/// worker name, without the "-preview" suffix so both envs group together
+fn worker_name(item: &TraceItem) -> String {
+    let name = item.script_name.as_deref().unwrap_or("unknown");
+    name.strip_suffix("-preview").unwrap_or(name).to_string()
+}
+
+fn colo(item: &TraceItem) -> String {
+    item.event
+        .as_ref()
+        .and_then(|e| e.request.as_ref())
+        .and_then(|r| r.cf.as_ref())
+        .and_then(|cf| cf.colo.clone())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+/// count one trace event
+fn count(counters: &mut Counters, item: &TraceItem) {
+    let status = item.event.as_ref().and_then(|e| e.response.as_ref()).map(|r| r.status);
+    
+    counters.requests += 1;
+    counters.exceptions += item.exceptions.len() as u64;
+    if item.outcome != "ok" || status.is_some_and(|s| s >= 500) {
+        counters.errors += 1;
+    } else if status.is_some_and(|s| s >= 400) {
+        counters.client_errors += 1;
+    }
+}
+
+/// group trace events into per-hour buckets (now in ms, for events without a timestamp)
+fn aggregate(items: &[TraceItem], now: u64) -> BTreeMap<u64, Bucket> {
+    let mut hours: BTreeMap<u64, Bucket> = BTreeMap::new();
+    for item in items {
+        let hour = item.event_timestamp.unwrap_or(now) / HOUR_MS;
+        let key = format!("{}|{}", worker_name(item), colo(item));
+        count(hours.entry(hour).or_default().entry(key).or_default(), item);
+    }
+    hours
+}
+
+/// add `from` into `into`
+fn merge(into: &mut Bucket, from: &Bucket) {
+    for (key, c) in from {
+        let total = into.entry(key.clone()).or_default();
+        total.requests += c.requests;
+        total.errors += c.errors;
+        total.client_errors += c.client_errors;
+        total.exceptions += c.exceptions;
+    }
+}
+
+/// flatten a bucket into rows, worst error rate first
+fn rows(bucket: &Bucket) -> Vec<ErrorRateRow> {
+    let mut rows: Vec<ErrorRateRow> = bucket
+        .iter()
+        .map(|(key, c)| {
+            let (worker, colo) = key.split_once('|').unwrap_or((key.as_str(), "unknown"));
+            ErrorRateRow {
+                worker: worker.to_string(),
+                colo: colo.to_string(),
+                requests: c.requests,
+                errors: c.errors,
+                client_errors: c.client_errors,
+                exceptions: c.exceptions,
+                error_rate: if c.requests == 0 { 0.0 } else { c.errors as f64 / c.requests as f64 },
+            }
+        })
+        .collect();
+    rows.sort_by(|a, b| b.error_rate.total_cmp(&a.error_rate).then(b.requests.cmp(&a.requests)));
+    rows
+}
+
+// ==============================================================================
+// tests
+// ==============================================================================
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn trace(script: &str, colo: &str, outcome: &str, status: u16) -> TraceItem {
+        serde_json::from_value(serde_json::json!({
+            "scriptName": script,
+            "outcome": outcome,
+            "eventTimestamp": 3 * HOUR_MS + 5,
+            "event": {
+                "request": { "url": "https://example.com/", "cf": { "colo": colo } },
+                "response": { "status": status }
+            },
+            "logs": [],
+            "exceptions": []
+        }))
+        .unwrap()
+    }
+    
+    #[test]
+    fn test_parse_minimal_trace_event() {
+        // scheduled/alarm events have no request or response
+        let item: TraceItem = serde_json::from_str(r#"{"scriptName":"rate-limiter","outcome":"ok"}"#).unwrap();
+        assert_eq!(worker_name(&item), "rate-limiter");
+        assert_eq!(colo(&item), "unknown");
+    }
+    
+    #[test]
+    fn test_preview_suffix_grouped() {
+        let item = trace("url-shortener-preview", "IAD", "ok", 200);
+        assert_eq!(worker_name(&item), "url-shortener");
+    }
+    
+    #[test]
+    fn test_count_classifies_outcomes() {
+        let items = [
+            trace("rate-limiter", "IAD", "ok", 200),
+            trace("rate-limiter", "IAD", "ok", 429),
+            trace("rate-limiter", "IAD", "ok", 500),
+            trace("rate-limiter", "IAD", "exceededCpu", 0),
+        ];
+        let hours = aggregate(&items, 0);
+        let counters = hours[&3]["rate-limiter|IAD"];
+        assert_eq!(counters.requests, 4);
+        assert_eq!(counters.client_errors, 1);
+        assert_eq!(counters.errors, 2);
+    }
+    
+    #[test]
+    fn test_aggregate_groups_by_worker_and_colo() {
+        let items = [
+            trace("rate-limiter", "IAD", "ok", 200),
+            trace("rate-limiter", "LHR", "ok", 200),
+            trace("url-shortener", "IAD", "ok", 200),
+        ];
+        let hours = aggregate(&items, 0);
+        assert_eq!(hours.len(), 1);
+        assert_eq!(hours[&3].len(), 3);
+    }
+    
+    #[test]
+    fn test_merge_adds_counters() {
+        let items = [trace("capability-demo", "SJC", "ok", 500)];
+        let batch = aggregate(&items, 0).remove(&3).unwrap();
+        let mut total = Bucket::new();
+        merge(&mut total, &batch);
+        merge(&mut total, &batch);
+        assert_eq!(total["capability-demo|SJC"].requests, 2);
+        assert_eq!(total["capability-demo|SJC"].errors, 2);
+    }
+    
+    #[test]
+    fn test_rows_sorted_by_error_rate() {
+        let items = [
+            trace("rate-limiter", "IAD", "ok", 200),
+            trace("url-shortener", "IAD", "ok", 503),
+        ];
+        let bucket = aggregate(&items, 0).remove(&3).unwrap();
+        let rows = rows(&bucket);
+        assert_eq!(rows[0].worker, "url-shortener");
+        assert_eq!(rows[0].error_rate, 1.0);
+        assert_eq!(rows[1].error_rate, 0.0);
+    }
+}
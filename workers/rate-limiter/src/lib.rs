@@ -6,45 +6,285 @@
 //!     demonstrates edge-based rate limiting using workers kv.
 //!     this is a core cloudflare use case - protecting apis from abuse
 //!     at the edge before requests reach origin servers.
+//!     with ORIGIN_URL set it is that shield: requests that pass are
+//!     proxied to the origin instead of getting the demo payload.
 //!
 //! relationships:
 //!     - uses: workers kv namespace "RATES" for storing request counters
+//!     - uses: shared (request ids)
+//!     - uses: durable object "METRICS" for /metrics totals (shared::metrics_store)
+//!     - uses: workers kv namespace "AUTH" for api keys (shared::auth_guard)
+//!     - uses: shared::maintenance for the daily stale-counter sweep
+//!     - uses: queue "ANALYTICS" for denial/fault events (shared::analytics)
+//!     - uses: durable object "EVENTS" in the event-hub worker for the live
+//!       feed - blocks, faults (shared::event_hub)
+//!     - uses: shared::flags to switch the queue, live feed and durable
+//!       metrics off (or roll them out) without a redeploy
+//!     - exports: durable object class RateLimiterStore, bound as "LIMITER"
+//!       (shared::limiter) - the exact backend
+//!     - exports: durable object class ConcurrencyLimiter, bound as
+//!       "CONCURRENCY" (shared::concurrency) - in-flight caps per client
+//!     - uses: shared::tiers - issued X-API-Key keys, their tiers (pro and
+//!       enterprise get a multiple of RATE_LIMIT) and revocations
+//!     - uses: shared::limits - a kv document that overrides RATE_LIMIT and
+//!       RATE_WINDOW_SECONDS without a redeploy
+//!     - uses: shared::ip_lists - addresses and cidr ranges that are denied
+//!       outright or let past the limit, checked before anything is counted
+//!     - uses: shared::challenge - a turnstile challenge on 429 whose answer
+//!       buys a temporary bypass (TURNSTILE_SITE_KEY, secret TURNSTILE_SECRET)
+//!     - uses: analytics engine dataset "DECISIONS" - every /api/protected
+//!       outcome, summarized over time (shared::decisions)
+//!     - calls: ORIGIN_URL in proxy mode (shared::proxy)
+//!     - uses: shared::quotas - daily and monthly quotas per client
+//!       (DAILY_QUOTA, MONTHLY_QUOTA), counted in RATES next to the window
+//!     - uses: shared::mode - MODE=shadow records refusals without
+//!       returning them, for calibrating limits against real traffic
+//!     - uses: shared::client_queue - parallel requests for one client in
+//!       an isolate update its quota and window counters one at a time
+//!     - uses: shared::abuse - counts each client's 429s and, past
+//!       ABUSE_THRESHOLD in ABUSE_WINDOW_SECONDS, posts a notification to
+//!       NOTIFY_WEBHOOK_URL (shared::notify)
 //!     - deployed to: cloudflare workers edge network
 //!
 //! cloudflare features demonstrated:
 //!     - workers kv (for distributed rate counters)
-//!     - environment variables (RATE_LIMIT, RATE_WINDOW_SECONDS)
+//!     - environment variables (RATE_LIMIT, RATE_WINDOW_SECONDS, LIMITER_BACKEND,
+//!       RATE_BURST, REQUIRE_REGISTERED_KEYS, MAX_CONCURRENT, ORIGIN_URL,
+//!       DAILY_QUOTA, MONTHLY_QUOTA, MODE, ABUSE_THRESHOLD,
+//!       ABUSE_WINDOW_SECONDS, NOTIFY_WEBHOOK_URL)
+//!     - durable objects (one per client for exact limits)
 //!     - edge compute for api protection
-//!     - custom response headers (X-RateLimit-*)
+//!     - custom response headers (X-RateLimit-*, and the ietf draft's
+//!       RateLimit-* with the "ratelimit-headers" flag on)
+//!     - cron triggers (scheduled kv cleanup)
+//!     - queues producer (analytics events off the request path)
+//!     - workers analytics engine (per-decision data points, read back
+//!       through the sql api)
+//!
+//! metrics:
+//!     GET /metrics serves prometheus text: request counts by route and
+//!     status, 5xx and 429 totals, kv operations and latency histograms.
+//!     isolates buffer counters and merge them into the durable object
+//!     every few seconds, so totals lag live traffic slightly.
+//!
+//! request ids:
+//!     every response carries X-Request-Id (adopted from the request when
+//!     the client sent a sane one) and error bodies repeat it as
+//!     "request_id", so a user-reported failure maps to a log line.
 //!
 //! algorithm:
-//!     sliding window rate limiting using fixed window approximation.
-//!     each client (identified by ip or api key) gets a counter in kv.
-//!     counter expires after window_seconds using kv ttl.
+//!     LIMITER_BACKEND picks how clients (identified by ip or api key) are
+//!     counted:
+//!     kv (default)    fixed window approximation. each client gets a
+//!                     counter in kv that expires after window_seconds using
+//!                     kv ttl. a daily cron deletes counters left without a
+//!                     ttl once their window has passed. requests racing in
+//!                     from different isolates can both pass, and a client
+//!                     can spend its limit twice around a window boundary.
+//!     kv_cached       the same counters, kept in the isolate for a second
+//!                     and written back in batches (shared::counter_cache).
+//!                     blocked clients cost no kv operations while cached;
+//!                     each isolate can let a few extra requests through.
+//!     durable_object  a sliding log per client in a durable object
+//!                     (shared::limiter) - exact, at one subrequest per call.
+//!
+//!     on every backend an isolate counts one request per client at a time
+//!     (shared::client_queue): quotas, window or bucket are read and written
+//!     before the next request for that client starts, then it is served.
+//!
+//!     with RATE_BURST (or "burst" in the limits document) the limit becomes
+//!     a sustained rate: every backend keeps a token bucket of burst tokens
+//!     refilling at limit per window instead (shared::token_bucket), so an
+//!     idle client can fire off up to burst requests at once.
+//!     X-RateLimit-Policy says which algorithm answered, e.g.
+//!     "token-bucket;rate=10;w=60;burst=20" or "fixed-window;limit=10;w=60".
+//!
+//!     with MAX_CONCURRENT > 0 a client may also only have that many
+//!     requests in flight (shared::concurrency). the slot is taken before
+//!     the window counts anything, so a request refused for parallelism
+//!     doesn't use up the client's limit.
 //!
 //! api:
 //!     GET /api/protected
 //!         headers: X-API-Key: <key> (optional, uses ip if not provided)
 //!         response: {"data": "..."} or 429 Too Many Requests
+//!         a denied ip gets 403 whatever its key; an allowed one is never
+//!         counted (and gets no X-RateLimit-* headers). over MAX_CONCURRENT
+//!         in flight: 429 with code "too_many_concurrent_requests"
+//!         a revoked key gets 403; with REQUIRE_REGISTERED_KEYS = "true" a
+//!         key that was never issued gets 401 (both on /api/status too)
+//!         with TURNSTILE_SITE_KEY set, a 429 body carries
+//!         "challenge": {"provider": "turnstile", "site_key": "...", "verify_url": "/api/verify"}
+//!         and X-Bypass-Token: <token from /api/verify> skips the window
+//!         with DAILY_QUOTA or MONTHLY_QUOTA set, a spent quota gets 429 with
+//!         code "quota_exceeded" and "quota": {"period": "day", "limit": 1000,
+//!         "used": 1000, "remaining": 0, "reset_in_seconds": 37800}, before the
+//!         window counts anything
+//!         with MODE = "shadow" none of those 429s go out: the request is
+//!         served with the 429's X-RateLimit-* headers and
+//!         X-RateLimit-Shadow: rate_limited (or quota_exceeded,
+//!         concurrency_limited), and the decision is recorded as shadow
+//!         with ABUSE_THRESHOLD set, the 429 that brings a client to that many
+//!         in ABUSE_WINDOW_SECONDS posts {"event": "abuse_threshold", "text": "...",
+//!         "details": {"client": "ip:192.0.2.1", "refusals": 100, ...}} to
+//!         NOTIFY_WEBHOOK_URL
+//!
+//!     ANY /*   (only with ORIGIN_URL set)
+//!         every path and method the worker doesn't serve itself goes through
+//!         the same checks as /api/protected, then to ORIGIN_URL + path + query
+//!         with the headers and body (up to 1 MiB). the origin's response comes
+//!         back as it is, plus X-RateLimit-*; 502 when the origin can't be
+//!         reached. GET /api/protected is proxied the same way
+//!
+//!     GET /api/analytics/summary?hours=24   (metrics scope)
+//!         response: {"hours": 24, "timeline": [{"hour": "...", "outcome": "rate_limited",
+//!                    "mode": "enforce", "requests": 420}, ...], "top_blocked_clients":
+//!                    [{"client": "<hash>", "colo": "LAX", "mode": "enforce", "blocked": 380}, ...]}
+//!         needs CF_ACCOUNT_ID and the ANALYTICS_API_TOKEN secret (503 without)
+//!
+//!     POST /api/verify
+//!         headers: X-Turnstile-Token: <the solved widget's token>
+//!         response: {"bypass_token": "bp_...", "expires_in_seconds": 600} - only
+//!         for the same api key or ip; 403 when siteverify says no, 404 with
+//!         challenges off
 //!
 //!     GET /api/status
-//!         response: {"requests_remaining": 8, "reset_in_seconds": 45}
+//!         response: {"requests_remaining": 8, "reset_in_seconds": 45, "tier": "free",
+//!                    "quotas": [{"period": "day", "remaining": 990, ...}]}   (quotas when configured)
+//!         carries an ETag - If-None-Match with it gets a 304 while nothing changed
+//!
+//!     POST /admin/reset?client=ip:1.2.3.4   (admin scope - see shared::auth)
+//!         response: {"reset": "ip:1.2.3.4"}
+//!
+//!     POST /admin/tier   (admin scope)
+//!         body: {"key": "<X-API-Key value>", "tier": "free" | "pro" | "enterprise"}
+//!         response: {"tier": "pro", "limit": 100, "window_seconds": 60}
+//!
+//!     POST /admin/keys   (admin scope)
+//!         body: {"name": "acme", "tier": "pro"}   (tier optional, free by default)
+//!         response: 201 {"key": "rl_...", "name": "acme", "tier": "pro", ...} -
+//!         the only response that ever contains the key
+//!
+//!     DELETE /admin/keys/:key   (admin scope)
+//!         response: {"revoked": true, "revoked_at": 1767225600000}
+//!
+//!     GET /admin/keys/:key/usage   (admin scope)
+//!         response: {"name": "acme", "tier": "pro", "revoked": false, "limit": 100,
+//!                    "requests_made": 12, "requests_remaining": 88, "reset_in_seconds": 41, ...}
+//!
+//!     GET | PUT | DELETE /admin/limits   (admin scope)
+//!         body (PUT): {"limit": 5, "window_seconds": 60, "burst": 20}   (burst optional)
+//!         response: {"limit": 5, "window_seconds": 60, "source": "kv",
+//!                    "env": {"limit": 10, "window_seconds": 60}}
+//!         PUT replaces the free tier's limits for every isolate within 30
+//!         seconds, DELETE goes back to the env vars
+//!
+//!     GET /admin/ip-lists   (admin scope)
+//!         response: {"allow": ["192.0.2.10"], "deny": ["198.51.100.0/24"]}
+//!
+//!     POST /admin/ip-lists   (admin scope)
+//!         body: {"list": "allow" | "deny", "entry": "198.51.100.0/24"}
+//!         response: 201 with the lists (200 when the entry was already there)
+//!
+//!     DELETE /admin/ip-lists?list=deny&entry=198.51.100.0/24   (admin scope)
+//!         response: the lists, or 404 when the entry wasn't on the list
+//!
+//!     GET /version
+//!         response: {"worker": "...", "version": "0.1.0", "git_sha": "...", "built_at": ..., "features": [...]}
+//!
+//!     GET /health/deps
+//!         response: shared::health report over RATES, AUTH, METRICS, EVENTS, LIMITER
+//!         and CONCURRENCY
+//!         (the analytics queue can't be probed without sending)
 //!
 //! ==============================================================================
 
 use worker::*;
 use serde::{Deserialize, Serialize};
 
+use shared::abuse::Incident;
+use shared::analytics::{client_label, AnalyticsEvent};
+use shared::build_info::BuildInfo;
+use shared::counter_cache::LocalCounter;
+use shared::decisions::Outcome;
+use shared::event_hub::LiveEvent;
+use shared::flags::{self, ClientFlags};
+use shared::health::Dependency;
+use shared::ip_lists::{List, ListEntry, Listing};
+use shared::limiter::{Algorithm, Backend, Decision};
+use shared::limits::Source;
+use shared::mode::Mode;
+use shared::quotas::QuotaUsage;
+use shared::tiers::{AssignTier, CreateKey, KeyAccess, KeyTier, TierLimits};
+use shared::token_bucket::{Shape, TokenBucket};
+use shared::RateLimitConfig;
+
+// the metrics durable object class has to be exported by the worker itself
+pub use shared::metrics_store::MetricsStore;
+// and so does the limiter's, which only this worker binds
+pub use shared::limiter::RateLimiterStore;
+/// durable object class for the CONCURRENCY binding (shared::concurrency)
+pub use shared::concurrency::ConcurrencyLimiter;
+
 // ==============================================================================
 // types
 // ==============================================================================
 
+/// per-request data the router hands to every handler
+struct RequestMeta {
+    request_id: String,
+    /// feature flags for this request's client
+    flags: ClientFlags,
+    /// enforcing or shadow (MODE)
+    mode: Mode,
+}
+
+/// worker label on exported metrics
+const WORKER_NAME: &str = "rate-limiter";
+
+/// bindings probed by GET /health/deps
+const HEALTH_DEPS: &[Dependency] = &[
+    Dependency::Kv("RATES"),
+    Dependency::Kv(shared::auth_guard::AUTH_KV_BINDING),
+    shared::metrics_store::HEALTH_DEPENDENCY,
+    shared::event_hub::HEALTH_DEPENDENCY,
+    shared::limiter::HEALTH_DEPENDENCY,
+    shared::concurrency::HEALTH_DEPENDENCY,
+];
+
+/// admin requests carry at most a small json body - anything bigger is not ours
+const MAX_ADMIN_BODY_BYTES: usize = 1024;
+
 #[derive(Debug, Serialize, Deserialize)]
 struct RateInfo {
     count: u32,
     window_start: u64,
 }
 
+impl RateInfo {
+    /// where this counter leaves the client (now in seconds) - the window
+    /// resets all at once
+    fn decision(&self, limit: u32, window_seconds: u64, now: u64) -> Decision {
+        let remaining = limit.saturating_sub(self.count);
+        Decision {
+            allowed: remaining > 0,
+            count: self.count,
+            limit,
+            remaining,
+            reset_in_seconds: window_seconds.saturating_sub(now.saturating_sub(self.window_start)),
+        }
+    }
+}
+
+/// what a request that gets through is answered with
+enum Serve {
+    /// the demo payload
+    Demo,
+    /// the origin's answer (ORIGIN_URL) - the body is read before anything
+    /// is counted
+    Origin { origin: String, body: Option<Vec<u8>> },
+}
+
 #[derive(Debug, Serialize)]
 struct ProtectedResponse {
     message: String,
@@ -52,20 +292,33 @@ struct ProtectedResponse {
     edge_location: String,
 }
 
+/// GET /admin/keys/:key/usage
 #[derive(Debug, Serialize)]
-struct StatusResponse {
-    client_id: String,
+struct KeyUsage {
+    name: String,
+    tier: KeyTier,
+    revoked: bool,
+    limit: u32,
+    window_seconds: u64,
     requests_made: u32,
     requests_remaining: u32,
-    limit: u32,
     reset_in_seconds: u64,
 }
 
 #[derive(Debug, Serialize)]
-struct RateLimitedResponse {
-    error: String,
-    retry_after_seconds: u64,
+struct StatusResponse {
+    client_id: String,
+    requests_made: u32,
+    requests_remaining: u32,
     limit: u32,
+    /// with a burst, limit is the rate and the requests the bucket's tokens
+    #[serde(skip_serializing_if = "Option::is_none")]
+    burst: Option<u32>,
+    reset_in_seconds: u64,
+    tier: KeyTier,
+    /// daily and monthly quotas, when configured
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    quotas: Vec<QuotaUsage>,
 }
 
 // ==============================================================================
@@ -73,127 +326,712 @@ struct RateLimitedResponse {
 // ==============================================================================
 
 #[event(fetch)]
-async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
-    let router = Router::new();
+async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
+    let started = js_sys::Date::now();
+    let route = route_label(&req.path());
+    let request_id = request_id(&req);
+    let origin = req.headers().get("Origin").ok().flatten();
+    console_log!("[{}] {:?} {}", request_id, req.method(), shared::log_path(&req.path()));
     
-    router
+    let client_flags = flags::load(&env).await.for_client(&get_client_id(&req));
+    let proxying = shared::proxy::origin(&env).is_some();
+    let meta = RequestMeta { request_id: request_id.clone(), flags: client_flags.clone(), mode: shared::mode::mode(&env) };
+    let router = Router::with_data(meta);
+    
+    // the router consumes env - keep a handle for cors and the metrics flush
+    let metrics_env = env.clone();
+    let router = router
         // protected endpoint (rate limited)
         .get_async("/api/protected", handle_protected)
         // check rate limit status
         .get_async("/api/status", handle_status)
+        // allowed and blocked requests over time (analytics engine)
+        .get_async("/api/analytics/summary", handle_analytics_summary)
+        // trade a solved challenge for a bypass token
+        .post_async("/api/verify", handle_verify)
         // health check (not rate limited)
         .get("/health", |_, _| Response::ok("ok"))
+        // which build is deployed
+        .get("/version", |_, _| Response::from_json(&BuildInfo::new(WORKER_NAME, env!("CARGO_PKG_VERSION"))))
+        // this worker's bindings, collected by the gateway's /health/all
+        .get_async("/health/deps", handle_health_deps)
+        // prometheus scrape target
+        .get_async("/metrics", handle_metrics)
+        // admin: clear a client's counter
+        .post_async("/admin/reset", handle_admin_reset)
+        // admin: move an api key to another tier
+        .post_async("/admin/tier", handle_admin_tier)
+        // admin: issue, revoke and inspect api keys
+        .post_async("/admin/keys", handle_create_key)
+        .delete_async("/admin/keys/:key", handle_revoke_key)
+        .get_async("/admin/keys/:key/usage", handle_key_usage)
+        // admin: the limits in force, changed without a redeploy
+        .get_async("/admin/limits", handle_get_limits)
+        .put_async("/admin/limits", handle_set_limits)
+        .delete_async("/admin/limits", handle_clear_limits)
+        // admin: ip addresses and ranges that skip or never reach the limit
+        .get_async("/admin/ip-lists", handle_get_ip_lists)
+        .post_async("/admin/ip-lists", handle_add_ip_list_entry)
+        .delete_async("/admin/ip-lists", handle_remove_ip_list_entry)
         // cors
         .options("/api/protected", handle_cors)
         .options("/api/status", handle_cors)
-        .run(req, env)
-        .await
+        .options("/api/verify", handle_cors);
+    // proxy mode: every other path and method is limited like
+    // /api/protected and sent on to ORIGIN_URL
+    let router = if proxying {
+        router
+            .or_else_any_method_async("/", handle_protected)
+            .or_else_any_method_async("/*path", handle_protected)
+    } else {
+        router
+    };
+    let result = router.run(req, env).await;
+    
+    // unhandled errors and the router's own text 404/405s become problem+json too
+    let resp = match result {
+        Ok(resp) => resp,
+        Err(e) => {
+            console_error!("[{}] unhandled error: {}", request_id, e);
+            cors_error(&request_id, "internal error", 500)?
+        }
+    };
+    // ...except the origin's, which go back as it wrote them (ours are problems already)
+    let mut resp = if proxying && matches!(route, "/api/protected" | "other") {
+        resp
+    } else {
+        shared::problem::ensure_problem(resp, &request_id).await?
+    };
+    stamp_request_id(&mut resp, &request_id);
+    let cors = shared::cors::policy(&metrics_env).await;
+    shared::cors::apply(&cors, origin.as_deref(), resp.headers_mut());
+    
+    let status = resp.status_code();
+    shared::metrics::record_request(route, status, js_sys::Date::now() - started);
+    if status >= 500 && client_flags.on(flags::ANALYTICS_QUEUE) {
+        shared::analytics::record(AnalyticsEvent::fault(WORKER_NAME, route, status, &request_id, started as u64));
+    }
+    if status >= 500 && client_flags.on(flags::LIVE_EVENTS) {
+        shared::event_hub::record(LiveEvent::fault(WORKER_NAME, route, status, &request_id, started as u64));
+    }
+    // with durable metrics off, requests leave their counters in the isolate - only a scrape merges them
+    if client_flags.on(flags::DURABLE_METRICS) {
+        shared::metrics_store::flush_if_due(&metrics_env, &ctx);
+    }
+    shared::analytics::flush(&metrics_env, &ctx);
+    shared::event_hub::flush(&metrics_env, &ctx);
+    shared::notify::flush(&metrics_env, &ctx);
+    Ok(resp)
+}
+
+// ==============================================================================
+// scheduled maintenance
+// ==============================================================================
+
+/// client counter prefixes - see get_client_id
+const CLIENT_PREFIXES: &[&str] = &["ip:", "key:"];
+
+/// daily cron: delete counters whose window is over but that kv still holds
+/// (written without a ttl, e.g. by older versions). every counter is a
+/// single record, so there's nothing to compact beyond that
+#[event(scheduled)]
+async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    if let Err(e) = run_maintenance(&env).await {
+        console_error!("maintenance failed: {}", e);
+    }
+}
+
+async fn run_maintenance(env: &Env) -> Result<()> {
+    use shared::maintenance::{self, KeyAction};
+    
+    let kv = env.kv("RATES")?;
+    let window_seconds = base_limits(env).await.window_seconds;
+    let now = js_sys::Date::now() as u64 / 1000;
+    
+    // RATES shares its namespace with AUTH and the other workers' counters,
+    // so only this worker's prefixes are swept - each with half the budget
+    for prefix in CLIENT_PREFIXES {
+        let job = format!("rate-limiter-{}", prefix.trim_end_matches(':'));
+        let report = maintenance::sweep(
+            &kv,
+            &kv,
+            &job,
+            Some(*prefix),
+            maintenance::DEFAULT_MAX_OPS / CLIENT_PREFIXES.len(),
+            |key| if key.has_ttl() { KeyAction::Keep } else { KeyAction::Inspect },
+            |_, value| stale_counter(value, now, window_seconds),
+        )
+        .await?;
+        console_log!("maintenance {}", serde_json::to_string(&report).unwrap_or_default());
+    }
+    shared::metrics_store::flush(env).await
 }
 
 // ==============================================================================
 // request handlers
 // ==============================================================================
 
-/// protected endpoint - applies rate limiting
-async fn handle_protected(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // get rate limit config from env
-    let limit: u32 = ctx.env.var("RATE_LIMIT")
-        .map(|v| v.to_string().parse().unwrap_or(10))
-        .unwrap_or(10);
-    let window_seconds: u64 = ctx.env.var("RATE_WINDOW_SECONDS")
-        .map(|v| v.to_string().parse().unwrap_or(60))
-        .unwrap_or(60);
+/// prometheus text format metrics for this worker (needs the "metrics" scope)
+async fn handle_metrics(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    if let Err(e) = shared::auth_guard::require(&req, &ctx.env, &[], shared::auth::METRICS_SCOPE).await? {
+        return cors_error(&ctx.data.request_id, &e.to_string(), e.status());
+    }
+    shared::metrics_store::metrics_response(&ctx.env, WORKER_NAME).await
+}
+
+/// probe this worker's bindings
+async fn handle_health_deps(_req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    shared::health::deps_response(&ctx.env, HEALTH_DEPS).await
+}
+
+/// protected endpoint - applies rate limiting. in proxy mode this is also
+/// every path the worker doesn't serve itself
+async fn handle_protected(mut req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    let serve = match shared::proxy::origin(&ctx.env) {
+        None => Serve::Demo,
+        Some(origin) => {
+            let body = if matches!(req.method(), Method::Get | Method::Head) {
+                None
+            } else {
+                match shared::body_limit::read_body(&mut req, shared::proxy::MAX_BODY_BYTES).await? {
+                    Ok(body) => Some(body),
+                    Err(e) => return e.problem().with_request_id(&ctx.data.request_id).into_response(),
+                }
+            };
+            Serve::Origin { origin, body }
+        }
+    };
     
     // identify client by api key or ip
     let client_id = get_client_id(&req);
+    let (resp, outcome) = decide(&req, &ctx, &client_id, &serve).await?;
+    let mode = ctx.data.mode;
+    let (route, colo) = (req.path(), get_edge_location(&req));
+    // the labelled route - proxied paths would give the column one value per url
+    shared::decisions::record(&ctx.env, &client_id, route_label(&route), &colo, outcome, mode).await;
+    if !mode.holds_back(outcome) {
+        if outcome.limited() {
+            note_refusal(&ctx, &client_id, outcome, &route, &colo).await;
+        }
+        return Ok(resp);
+    }
+    
+    // shadow mode: serve it anyway, with the limit headers the 429 carried
+    console_log!("[{}] shadow {} {}", ctx.data.request_id, outcome.as_str(), client_label(client_id));
+    let headers = Headers::new();
+    for (name, value) in resp.headers().entries() {
+        if shared::mode::keeps_header(&name) {
+            headers.set(&name, &value)?;
+        }
+    }
+    headers.set(shared::mode::SHADOW_HEADER, outcome.as_str())?;
+    respond(&req, &ctx, &serve, headers).await
+}
+
+/// count an enforced 429 towards the client's abuse threshold, and raise
+/// the notification on the one that reaches it - failures are only logged
+async fn note_refusal(ctx: &RouteContext<RequestMeta>, client_id: &str, outcome: Outcome, route: &str, colo: &str) {
+    let Some(threshold) = shared::abuse::configured(&ctx.env) else {
+        return;
+    };
+    let now = js_sys::Date::now() as u64;
+    let crossed = async { shared::abuse::strike(&ctx.env.kv("RATES")?, client_id, threshold, now / 1000).await }.await;
+    match crossed {
+        Ok(true) => {
+            console_warn!("[{}] abuse threshold reached by {}", ctx.data.request_id, client_label(client_id));
+            let incident = Incident::new(client_id, threshold, outcome, route, colo);
+            shared::notify::record(incident.notification(WORKER_NAME, &ctx.data.request_id, now));
+        }
+        Ok(false) => {}
+        Err(e) => console_warn!("[{}] counting refusal failed: {}", ctx.data.request_id, e),
+    }
+}
+
+/// let the request through or turn it away, and say which
+async fn decide(req: &Request, ctx: &RouteContext<RequestMeta>, client_id: &str, serve: &Serve) -> Result<(Response, Outcome)> {
+    // the ip lists come before keys and counters
+    let listing = match client_ip(req) {
+        Some(ip) => shared::ip_lists::load(&ctx.env).await.listing(ip),
+        None => Listing::Unlisted,
+    };
+    match listing {
+        Listing::Denied => {
+            console_warn!("[{}] 403 denied ip {}", ctx.data.request_id, client_label(client_id));
+            return Ok((cors_error(&ctx.data.request_id, "ip address denied", 403)?, Outcome::Denied));
+        }
+        Listing::Allowed => return Ok((respond(req, ctx, serve, Headers::new()).await?, Outcome::Bypassed)),
+        Listing::Unlisted => {}
+    }
     
-    // check/update rate limit
-    let (allowed, rate_info) = check_rate_limit(&ctx, &client_id, limit, window_seconds).await?;
+    // the caller's tier decides its limit
+    let (tier, limits) = match client_limits(req, &ctx.env).await {
+        Ok(limits) => limits,
+        Err((status, msg)) => return Ok((cors_error(&ctx.data.request_id, msg, status)?, Outcome::Denied)),
+    };
+    
+    // a solved challenge skips the window until the bypass expires
+    if shared::challenge::offer(&ctx.env).is_some() && shared::challenge::bypassed(req, &ctx.env.kv("RATES")?, client_id).await? {
+        return Ok((respond(req, ctx, serve, Headers::new()).await?, Outcome::Bypassed));
+    }
+    
+    // a slot first, so a request turned away for parallelism isn't counted
+    let Some(cap) = shared::concurrency::cap(&ctx.env) else {
+        return handle_counted(req, ctx, client_id, tier, limits, serve).await;
+    };
+    let Some(lease) = shared::concurrency::acquire(&ctx.env, client_id, cap).await?.lease else {
+        console_warn!("[{}] 429 over {} in flight {}", ctx.data.request_id, cap, client_label(client_id));
+        let resp = shared::problem::Problem::new(429, "too many concurrent requests - wait for one to finish")
+            .with_code("too_many_concurrent_requests")
+            .with_request_id(&ctx.data.request_id)
+            .with_extension("max_concurrent", cap)
+            .into_response()?;
+        resp.headers().set("Retry-After", "1")?;
+        return Ok((resp, Outcome::ConcurrencyLimited));
+    };
+    let result = handle_counted(req, ctx, client_id, tier, limits, serve).await;
+    // a lost release only holds the slot until its lease expires
+    if let Err(e) = shared::concurrency::release(&ctx.env, client_id, lease).await {
+        console_warn!("[{}] releasing slot failed: {}", ctx.data.request_id, e);
+    }
+    result
+}
+
+/// count the request against the client's quotas and window, and serve it
+/// if there's room in all of them
+async fn handle_counted(
+    req: &Request,
+    ctx: &RouteContext<RequestMeta>,
+    client_id: &str,
+    tier: KeyTier,
+    limits: TierLimits,
+    serve: &Serve,
+) -> Result<(Response, Outcome)> {
+    let TierLimits { limit, window_seconds, burst } = limits;
     
-    // calculate remaining
-    let remaining = if rate_info.count >= limit { 0 } else { limit - rate_info.count };
+    // parallel requests for the client in this isolate wait here, so each
+    // reads the quota and window counts the one before wrote. the turn is
+    // given up before the request is served
+    let turn = shared::client_queue::turn(client_id).await;
+    
+    // a spent quota refuses before the window counts anything
+    let rates = ctx.env.kv("RATES")?;
     let now = js_sys::Date::now() as u64 / 1000;
-    let reset_in = window_seconds.saturating_sub(now.saturating_sub(rate_info.window_start));
+    let quotas = shared::quotas::configured(&ctx.env).for_tier(tier);
+    let quota_usage = shared::quotas::usage(&rates, client_id, quotas, now).await?;
+    if let Some(spent) = shared::quotas::exhausted(&quota_usage) {
+        console_warn!("[{}] 429 {} quota spent {}", ctx.data.request_id, spent.period.as_str(), client_label(client_id));
+        let resp = quota_exceeded_problem(&ctx.data.request_id, spent)?.into_response()?;
+        resp.headers().set("Retry-After", &spent.reset_in_seconds.to_string())?;
+        return Ok((resp, Outcome::QuotaExceeded));
+    }
     
-    if !allowed {
+    // check/update rate limit - a burst makes it a token bucket on any backend
+    let backend = shared::limiter::backend(&ctx.env);
+    let algorithm = Algorithm::of(backend, burst);
+    let decision = match (backend, burst) {
+        (Backend::DurableObject, _) => shared::limiter::check(&ctx.env, client_id, limit, window_seconds, burst).await?,
+        (_, Some(burst)) => take_token(ctx, client_id, Shape { rate: limit, window_seconds, burst }).await?,
+        (Backend::Kv, None) => check_rate_limit(ctx, client_id, limit, window_seconds).await?,
+        (Backend::KvCached, None) => check_rate_limit_cached(ctx, client_id, limit, window_seconds).await?,
+    };
+    let reset_in = decision.reset_in_seconds;
+    
+    if !decision.allowed {
         // rate limited - return 429
-        let response = RateLimitedResponse {
-            error: "Too Many Requests".to_string(),
-            retry_after_seconds: reset_in,
-            limit,
-        };
-        
-        let json = serde_json::to_string(&response).unwrap();
-        let headers = Headers::new();
-        headers.set("Content-Type", "application/json")?;
-        headers.set("X-RateLimit-Limit", &limit.to_string())?;
-        headers.set("X-RateLimit-Remaining", "0")?;
-        headers.set("X-RateLimit-Reset", &reset_in.to_string())?;
-        headers.set("Retry-After", &reset_in.to_string())?;
-        headers.set("Access-Control-Allow-Origin", "*")?;
+        console_warn!("[{}] 429 rate limited {}", ctx.data.request_id, client_label(client_id));
+        // a shadow refusal serves the request - it's no denial or block
+        let at = js_sys::Date::now() as u64;
+        let enforced = !ctx.data.mode.holds_back(Outcome::RateLimited);
+        if enforced && ctx.data.flags.on(flags::ANALYTICS_QUEUE) {
+            shared::analytics::record(AnalyticsEvent::denial(WORKER_NAME, client_id, &ctx.data.request_id, at));
+        }
+        if enforced && ctx.data.flags.on(flags::LIVE_EVENTS) {
+            shared::event_hub::record(LiveEvent::block(WORKER_NAME, client_id, &ctx.data.request_id, at));
+        }
         
-        let mut resp = Response::ok(json)?;
-        resp = resp.with_status(429);
-        return Ok(resp.with_headers(headers));
+        let mut problem = rate_limited_problem(&ctx.data.request_id, reset_in, limit);
+        if let Some(challenge) = shared::challenge::offer(&ctx.env) {
+            problem = problem.with_extension("challenge", serde_json::to_value(challenge)?);
+        }
+        let resp = problem.into_response()?;
+        set_limit_headers(resp.headers(), &decision, &limits, algorithm, &ctx.data.flags)?;
+        resp.headers().set("Retry-After", &reset_in.to_string())?;
+        return Ok((resp, Outcome::RateLimited));
     }
     
     // allowed - return protected data
+    shared::quotas::consume(&rates, client_id, &quota_usage, now).await?;
+    drop(turn);
+    let headers = Headers::new();
+    set_limit_headers(&headers, &decision, &limits, algorithm, &ctx.data.flags)?;
+    Ok((respond(req, ctx, serve, headers).await?, Outcome::Allowed))
+}
+
+/// X-RateLimit-* (X-RateLimit-Policy naming the algorithm), plus the
+/// standard RateLimit-* when the flag is on for the client
+fn set_limit_headers(headers: &Headers, decision: &Decision, limits: &TierLimits, algorithm: Algorithm, flags: &ClientFlags) -> Result<()> {
+    headers.set("X-RateLimit-Limit", &decision.limit.to_string())?;
+    headers.set("X-RateLimit-Remaining", &decision.remaining.to_string())?;
+    headers.set("X-RateLimit-Reset", &decision.reset_in_seconds.to_string())?;
+    headers.set("X-RateLimit-Policy", &algorithm.policy(limits.limit, limits.window_seconds))?;
+    if flags.on(flags::RATELIMIT_HEADERS) {
+        for (name, value) in decision.standard_headers() {
+            headers.set(name, &value)?;
+        }
+        headers.set("RateLimit-Policy", &algorithm.standard_policy(limits.limit, limits.window_seconds))?;
+    }
+    Ok(())
+}
+
+/// the protected resource, with `headers` added - the origin's answer in
+/// proxy mode, the demo payload otherwise
+async fn respond(req: &Request, ctx: &RouteContext<RequestMeta>, serve: &Serve, headers: Headers) -> Result<Response> {
+    let Serve::Origin { origin, body } = serve else {
+        return protected_response(req, headers);
+    };
+    match shared::proxy::forward(req, body.as_deref(), origin, &ctx.data.request_id, &headers).await {
+        Ok(resp) => Ok(resp),
+        Err(e) => {
+            console_error!("[{}] origin fetch failed: {}", ctx.data.request_id, e);
+            cors_error(&ctx.data.request_id, "origin unreachable", 502)
+        }
+    }
+}
+
+/// the demo payload, with `headers` added
+fn protected_response(req: &Request, headers: Headers) -> Result<Response> {
     let response = ProtectedResponse {
         message: "You have accessed the protected resource!".to_string(),
-        timestamp: now,
-        edge_location: get_edge_location(&req),
+        timestamp: js_sys::Date::now() as u64 / 1000,
+        edge_location: get_edge_location(req),
     };
     
     let json = serde_json::to_string(&response).unwrap();
+    headers.set("Content-Type", "application/json")?;
+    Ok(Response::ok(json)?.with_headers(headers))
+}
+
+/// reset one client's window (needs the "admin" scope)
+async fn handle_admin_reset(mut req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    // signed requests cover the body, so read it before checking
+    let body = match shared::body_limit::read_body(&mut req, MAX_ADMIN_BODY_BYTES).await? {
+        Ok(body) => body,
+        Err(e) => return e.problem().with_request_id(&ctx.data.request_id).into_response(),
+    };
+    let principal = match shared::auth_guard::require(&req, &ctx.env, &body, shared::auth::ADMIN_SCOPE).await? {
+        Ok(p) => p,
+        Err(e) => return cors_error(&ctx.data.request_id, &e.to_string(), e.status()),
+    };
+    
+    // client ids look like "ip:1.2.3.4" or "key:abc", as in /api/status
+    let client_id = match req.url()?.query_pairs().find(|(k, _)| k == "client") {
+        Some((_, v)) if !v.is_empty() => v.into_owned(),
+        _ => return cors_error(&ctx.data.request_id, "missing ?client=", 400),
+    };
+    
+    match shared::limiter::backend(&ctx.env) {
+        Backend::Kv | Backend::KvCached => {
+            shared::metrics::record_kv_op("delete");
+            ctx.env.kv("RATES")?.delete(&client_id).await?;
+            shared::metrics::record_kv_op("delete");
+            ctx.env.kv("RATES")?.delete(&shared::token_bucket::kv_key(&client_id)).await?;
+            // other isolates drop their copy within counter_cache::MAX_AGE_MS
+            shared::counter_cache::forget(&client_id);
+        }
+        Backend::DurableObject => shared::limiter::reset(&ctx.env, &client_id).await?,
+    }
+    console_log!("[{}] {} reset {}", ctx.data.request_id, principal.subject, client_label(&client_id));
+    
     let headers = Headers::new();
     headers.set("Content-Type", "application/json")?;
-    headers.set("X-RateLimit-Limit", &limit.to_string())?;
-    headers.set("X-RateLimit-Remaining", &remaining.to_string())?;
-    headers.set("X-RateLimit-Reset", &reset_in.to_string())?;
-    headers.set("Access-Control-Allow-Origin", "*")?;
+    let body = serde_json::json!({ "reset": client_id }).to_string();
+    Ok(Response::ok(body)?.with_headers(headers))
+}
+
+/// put an api key on a tier (needs the "admin" scope)
+async fn handle_admin_tier(mut req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    let body = match shared::body_limit::read_body(&mut req, MAX_ADMIN_BODY_BYTES).await? {
+        Ok(body) => body,
+        Err(e) => return e.problem().with_request_id(&ctx.data.request_id).into_response(),
+    };
+    let principal = match shared::auth_guard::require(&req, &ctx.env, &body, shared::auth::ADMIN_SCOPE).await? {
+        Ok(p) => p,
+        Err(e) => return cors_error(&ctx.data.request_id, &e.to_string(), e.status()),
+    };
     
-    Ok(Response::ok(json)?.with_headers(headers))
+    let assign: AssignTier = match serde_json::from_slice(&body) {
+        Ok(assign) if !assign.key.is_empty() => assign,
+        _ => return cors_error(&ctx.data.request_id, "expected {\"key\": \"...\", \"tier\": \"free\" | \"pro\" | \"enterprise\"}", 400),
+    };
+    shared::tiers::assign(&ctx.env, &assign.key, assign.tier).await?;
+    // the key itself stays out of the logs
+    console_log!("[{}] {} set a key to {}", ctx.data.request_id, principal.subject, assign.tier.as_str());
+    
+    let limits = assign.tier.limits(base_limits(&ctx.env).await);
+    let body = serde_json::json!({ "tier": assign.tier, "limit": limits.limit, "window_seconds": limits.window_seconds });
+    Response::from_json(&body)
 }
 
-/// get rate limit status without consuming a request
-async fn handle_status(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let limit: u32 = ctx.env.var("RATE_LIMIT")
-        .map(|v| v.to_string().parse().unwrap_or(10))
-        .unwrap_or(10);
-    let window_seconds: u64 = ctx.env.var("RATE_WINDOW_SECONDS")
-        .map(|v| v.to_string().parse().unwrap_or(60))
-        .unwrap_or(60);
+/// issue a new api key (needs the "admin" scope)
+async fn handle_create_key(mut req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    let body = match shared::body_limit::read_body(&mut req, MAX_ADMIN_BODY_BYTES).await? {
+        Ok(body) => body,
+        Err(e) => return e.problem().with_request_id(&ctx.data.request_id).into_response(),
+    };
+    let principal = match shared::auth_guard::require(&req, &ctx.env, &body, shared::auth::ADMIN_SCOPE).await? {
+        Ok(p) => p,
+        Err(e) => return cors_error(&ctx.data.request_id, &e.to_string(), e.status()),
+    };
+    
+    let create: CreateKey = match serde_json::from_slice(&body) {
+        Ok(create) if !create.name.trim().is_empty() => create,
+        _ => return cors_error(&ctx.data.request_id, "expected {\"name\": \"...\", \"tier\": \"free\" | \"pro\" | \"enterprise\"}", 400),
+    };
+    let (key, record) = shared::tiers::create(&ctx.env, &create).await?;
+    console_log!("[{}] {} issued a {} key for {}", ctx.data.request_id, principal.subject, record.tier.as_str(), record.name);
+    
+    let limits = record.tier.limits(base_limits(&ctx.env).await);
+    let body = serde_json::json!({
+        "key": key,
+        "name": record.name,
+        "tier": record.tier,
+        "created_at": record.created_at,
+        "limit": limits.limit,
+        "window_seconds": limits.window_seconds,
+    });
+    Ok(Response::from_json(&body)?.with_status(201))
+}
+
+/// revoke an api key (needs the "admin" scope)
+async fn handle_revoke_key(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    let principal = match shared::auth_guard::require(&req, &ctx.env, &[], shared::auth::ADMIN_SCOPE).await? {
+        Ok(p) => p,
+        Err(e) => return cors_error(&ctx.data.request_id, &e.to_string(), e.status()),
+    };
+    let Some(key) = ctx.param("key").cloned() else {
+        return cors_error(&ctx.data.request_id, "missing key", 400);
+    };
+    
+    let record = shared::tiers::revoke(&ctx.env, &key).await?;
+    console_log!("[{}] {} revoked a key of {:?}", ctx.data.request_id, principal.subject, record.name);
+    Response::from_json(&serde_json::json!({ "revoked": true, "revoked_at": record.revoked_at }))
+}
+
+/// a key's record and where it stands in its window (needs the "admin" scope)
+async fn handle_key_usage(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    if let Err(e) = shared::auth_guard::require(&req, &ctx.env, &[], shared::auth::ADMIN_SCOPE).await? {
+        return cors_error(&ctx.data.request_id, &e.to_string(), e.status());
+    }
+    let Some(key) = ctx.param("key").cloned() else {
+        return cors_error(&ctx.data.request_id, "missing key", 400);
+    };
+    
+    // a key with no record is a free client that may still have a counter
+    let record = shared::tiers::record(&ctx.env, &key, true).await?.unwrap_or_default();
+    let limits = record.tier.limits(base_limits(&ctx.env).await);
+    let decision = current_usage(&ctx, &format!("key:{}", key), limits).await?;
+    let usage = KeyUsage {
+        name: record.name,
+        tier: record.tier,
+        revoked: record.revoked_at.is_some(),
+        limit: limits.limit,
+        window_seconds: limits.window_seconds,
+        requests_made: decision.count,
+        requests_remaining: decision.remaining,
+        reset_in_seconds: decision.reset_in_seconds,
+    };
+    let resp = Response::from_json(&usage)?;
+    resp.headers().set("Cache-Control", "no-store")?;
+    Ok(resp)
+}
+
+/// the limits in force and where they came from (needs the "admin" scope)
+async fn handle_get_limits(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    if let Err(e) = shared::auth_guard::require(&req, &ctx.env, &[], shared::auth::ADMIN_SCOPE).await? {
+        return cors_error(&ctx.data.request_id, &e.to_string(), e.status());
+    }
+    
+    // read past the isolate cache - an admin wants what kv holds now
+    match shared::limits::stored(&ctx.env).await {
+        Ok(stored) => Response::from_json(&limits_body(stored, &ctx.env)),
+        Err(e) => cors_error(&ctx.data.request_id, &format!("invalid {}: {}", shared::limits::LIMITS_KV_KEY, e), 500),
+    }
+}
+
+/// replace the free tier's limits (needs the "admin" scope)
+async fn handle_set_limits(mut req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    let body = match shared::body_limit::read_body(&mut req, MAX_ADMIN_BODY_BYTES).await? {
+        Ok(body) => body,
+        Err(e) => return e.problem().with_request_id(&ctx.data.request_id).into_response(),
+    };
+    let principal = match shared::auth_guard::require(&req, &ctx.env, &body, shared::auth::ADMIN_SCOPE).await? {
+        Ok(p) => p,
+        Err(e) => return cors_error(&ctx.data.request_id, &e.to_string(), e.status()),
+    };
+    
+    let config = match shared::limits::parse(&String::from_utf8_lossy(&body)) {
+        Ok(config) => config,
+        Err(e) => return cors_error(&ctx.data.request_id, &format!("expected {{\"limit\": n, \"window_seconds\": n}}: {}", e), 400),
+    };
+    shared::limits::save(&ctx.env, &config).await?;
+    console_log!("[{}] {} set limits to {} per {}s", ctx.data.request_id, principal.subject, config.limit, config.window_seconds);
+    Response::from_json(&limits_body(Some(config), &ctx.env))
+}
+
+/// hand the limits back to the env vars (needs the "admin" scope)
+async fn handle_clear_limits(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    let principal = match shared::auth_guard::require(&req, &ctx.env, &[], shared::auth::ADMIN_SCOPE).await? {
+        Ok(p) => p,
+        Err(e) => return cors_error(&ctx.data.request_id, &e.to_string(), e.status()),
+    };
+    
+    shared::limits::clear(&ctx.env).await?;
+    console_log!("[{}] {} cleared the limits document", ctx.data.request_id, principal.subject);
+    Response::from_json(&limits_body(None, &ctx.env))
+}
+
+/// the allow and deny lists (needs the "admin" scope)
+async fn handle_get_ip_lists(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    if let Err(e) = shared::auth_guard::require(&req, &ctx.env, &[], shared::auth::ADMIN_SCOPE).await? {
+        return cors_error(&ctx.data.request_id, &e.to_string(), e.status());
+    }
+    
+    let lists = shared::ip_lists::stored(&ctx.env).await?;
+    let resp = Response::from_json(&lists)?;
+    resp.headers().set("Cache-Control", "no-store")?;
+    Ok(resp)
+}
+
+/// put an address or range on a list (needs the "admin" scope)
+async fn handle_add_ip_list_entry(mut req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    let body = match shared::body_limit::read_body(&mut req, MAX_ADMIN_BODY_BYTES).await? {
+        Ok(body) => body,
+        Err(e) => return e.problem().with_request_id(&ctx.data.request_id).into_response(),
+    };
+    let principal = match shared::auth_guard::require(&req, &ctx.env, &body, shared::auth::ADMIN_SCOPE).await? {
+        Ok(p) => p,
+        Err(e) => return cors_error(&ctx.data.request_id, &e.to_string(), e.status()),
+    };
+    
+    let add: ListEntry = match serde_json::from_slice(&body) {
+        Ok(add) => add,
+        Err(e) => return cors_error(&ctx.data.request_id, &format!("expected {{\"list\": \"allow\" | \"deny\", \"entry\": \"<ip or cidr>\"}}: {}", e), 400),
+    };
+    // read-modify-write: two admins adding at once can lose one entry
+    let mut lists = shared::ip_lists::stored(&ctx.env).await?;
+    if !lists.add(add.list, add.entry) {
+        return Response::from_json(&lists);
+    }
+    shared::ip_lists::save(&ctx.env, &lists).await?;
+    console_log!("[{}] {} added {} to the {:?} list", ctx.data.request_id, principal.subject, add.entry, add.list);
+    Ok(Response::from_json(&lists)?.with_status(201))
+}
+
+/// take an address or range off a list (needs the "admin" scope)
+async fn handle_remove_ip_list_entry(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    let principal = match shared::auth_guard::require(&req, &ctx.env, &[], shared::auth::ADMIN_SCOPE).await? {
+        Ok(p) => p,
+        Err(e) => return cors_error(&ctx.data.request_id, &e.to_string(), e.status()),
+    };
+    
+    let url = req.url()?;
+    let param = |name: &str| url.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned());
+    let list = param("list").as_deref().and_then(List::parse);
+    let entry = param("entry").and_then(|e| e.parse::<shared::cidr::Cidr>().ok());
+    let (Some(list), Some(entry)) = (list, entry) else {
+        return cors_error(&ctx.data.request_id, "expected ?list=allow|deny&entry=<ip or cidr>", 400);
+    };
+    
+    let mut lists = shared::ip_lists::stored(&ctx.env).await?;
+    if !lists.remove(list, entry) {
+        return cors_error(&ctx.data.request_id, "entry not on the list", 404);
+    }
+    shared::ip_lists::save(&ctx.env, &lists).await?;
+    console_log!("[{}] {} removed {} from the {:?} list", ctx.data.request_id, principal.subject, entry, list);
+    Response::from_json(&lists)
+}
+
+/// allowed and blocked requests per hour, and who was blocked most (needs the "metrics" scope)
+async fn handle_analytics_summary(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    if let Err(e) = shared::auth_guard::require(&req, &ctx.env, &[], shared::auth::METRICS_SCOPE).await? {
+        return cors_error(&ctx.data.request_id, &e.to_string(), e.status());
+    }
+    let account = ctx.env.var("CF_ACCOUNT_ID").map(|v| v.to_string()).unwrap_or_default();
+    if account.is_empty() || ctx.env.secret("ANALYTICS_API_TOKEN").is_err() {
+        return cors_error(&ctx.data.request_id, "analytics engine queries are not configured", 503);
+    }
+    
+    let url = req.url()?;
+    let hours = shared::decisions::hours(url.query_pairs().find(|(k, _)| k == "hours").map(|(_, v)| v).as_deref());
+    let summary = match shared::decisions::summary(&ctx.env, hours).await {
+        Ok(summary) => summary,
+        Err(e) => {
+            console_error!("[{}] analytics summary failed: {}", ctx.data.request_id, e);
+            return cors_error(&ctx.data.request_id, "analytics engine query failed", 502);
+        }
+    };
+    let resp = Response::from_json(&summary)?;
+    resp.headers().set("Cache-Control", "no-store")?;
+    Ok(resp)
+}
+
+/// a turnstile token that passes siteverify buys this client a bypass token
+async fn handle_verify(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    // without the secret turnstile::verify passes everything - no bypasses then
+    if shared::challenge::offer(&ctx.env).is_none() || ctx.env.secret("TURNSTILE_SECRET").is_err() {
+        return cors_error(&ctx.data.request_id, "challenges are not enabled", 404);
+    }
+    if let Err(reason) = shared::turnstile::verify(&req, &ctx.env).await? {
+        return cors_error(&ctx.data.request_id, &format!("turnstile check failed: {}", reason), 403);
+    }
     
+    // the same identity /api/protected counts, so the token only helps this client
     let client_id = get_client_id(&req);
-    let rate_info = get_rate_info(&ctx, &client_id).await?;
+    let bypass = shared::challenge::issue(&ctx.env.kv("RATES")?, &client_id).await?;
+    console_log!("[{}] bypass issued to {}", ctx.data.request_id, client_label(&client_id));
+    let resp = Response::from_json(&bypass)?;
+    resp.headers().set("Cache-Control", "no-store")?;
+    Ok(resp)
+}
+
+/// get rate limit status without consuming a request
+async fn handle_status(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    let (tier, limits) = match client_limits(&req, &ctx.env).await {
+        Ok(limits) => limits,
+        Err((status, msg)) => return cors_error(&ctx.data.request_id, msg, status),
+    };
     
+    let client_id = get_client_id(&req);
+    let decision = current_usage(&ctx, &client_id, limits).await?;
     let now = js_sys::Date::now() as u64 / 1000;
-    let reset_in = window_seconds.saturating_sub(now.saturating_sub(rate_info.window_start));
-    let remaining = if rate_info.count >= limit { 0 } else { limit - rate_info.count };
+    let quotas = shared::quotas::configured(&ctx.env).for_tier(tier);
+    let quota_usage = shared::quotas::usage(&ctx.env.kv("RATES")?, &client_id, quotas, now).await?;
     
     let response = StatusResponse {
         client_id: format!("{}...", &client_id[..8.min(client_id.len())]),
-        requests_made: rate_info.count,
-        requests_remaining: remaining,
-        limit,
-        reset_in_seconds: reset_in,
+        requests_made: decision.count,
+        requests_remaining: decision.remaining,
+        limit: limits.limit,
+        burst: limits.burst,
+        reset_in_seconds: decision.reset_in_seconds,
+        tier,
+        quotas: quota_usage,
     };
     
+    // cache for 2 seconds, then revalidate - reset_in ticks every second,
+    // so 304s only land within the same second or on an idle window
     let json = serde_json::to_string(&response).unwrap();
-    let headers = Headers::new();
-    headers.set("Content-Type", "application/json")?;
-    headers.set("Access-Control-Allow-Origin", "*")?;
-    headers.set("Cache-Control", "public, max-age=2")?; // Cache for 2 seconds
-    
-    Ok(Response::ok(json)?.with_headers(headers))
+    shared::etag::json_response(&req, json, "public, max-age=2")
 }
 
-fn handle_cors(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
+fn handle_cors(_req: Request, _ctx: RouteContext<RequestMeta>) -> Result<Response> {
     let headers = Headers::new();
-    headers.set("Access-Control-Allow-Origin", "*")?;
-    headers.set("Access-Control-Allow-Methods", "GET, OPTIONS")?;
-    headers.set("Access-Control-Allow-Headers", "Content-Type, X-API-Key")?;
-    headers.set("Access-Control-Expose-Headers", "X-RateLimit-Limit, X-RateLimit-Remaining, X-RateLimit-Reset")?;
+    headers.set("Access-Control-Allow-Methods", "GET, POST, OPTIONS")?;
+    headers.set("Access-Control-Allow-Headers", "Content-Type, X-API-Key, X-Request-Id, X-Turnstile-Token, X-Bypass-Token")?;
+    headers.set(
+        "Access-Control-Expose-Headers",
+        "X-RateLimit-Limit, X-RateLimit-Remaining, X-RateLimit-Reset, X-RateLimit-Policy, X-RateLimit-Shadow, RateLimit-Limit, RateLimit-Remaining, RateLimit-Reset, RateLimit-Policy",
+    )?;
     
     Ok(Response::empty()?.with_headers(headers))
 }
@@ -202,17 +1040,19 @@ fn handle_cors(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
 // rate limiting logic
 // ==============================================================================
 
-/// check if request is allowed and update counter
+/// check if request is allowed and update counter (the kv backend) - the
+/// caller holds the client's turn (shared::client_queue)
 async fn check_rate_limit(
-    ctx: &RouteContext<()>,
+    ctx: &RouteContext<RequestMeta>,
     client_id: &str,
     limit: u32,
     window_seconds: u64,
-) -> Result<(bool, RateInfo)> {
+) -> Result<Decision> {
     let kv = ctx.env.kv("RATES")?;
     let now = js_sys::Date::now() as u64 / 1000;
     
     // get current rate info
+    shared::metrics::record_kv_op("get");
     let mut rate_info = match kv.get(client_id).text().await? {
         Some(json) => {
             let info: RateInfo = serde_json::from_str(&json).unwrap_or(RateInfo {
@@ -231,7 +1071,7 @@ async fn check_rate_limit(
     
     // check if over limit
     if rate_info.count >= limit {
-        return Ok((false, rate_info));
+        return Ok(Decision { allowed: false, ..rate_info.decision(limit, window_seconds, now) });
     }
     
     // increment counter
@@ -239,19 +1079,119 @@ async fn check_rate_limit(
     
     // store updated info with ttl
     let json = serde_json::to_string(&rate_info).unwrap();
+    shared::metrics::record_kv_op("put");
+    // windows under a minute still expire after one - kv refuses shorter ttls,
+    // and a counter from a finished window is reset above anyway
     kv.put(client_id, json)?
-        .expiration_ttl(window_seconds)
+        .expiration_ttl(window_seconds.max(60))
         .execute()
         .await?;
     
-    Ok((true, rate_info))
+    Ok(Decision { allowed: true, ..rate_info.decision(limit, window_seconds, now) })
+}
+
+/// the kv backend with counters cached in the isolate - only reads kv
+/// when the cached counter is stale and only writes batches of hits. the
+/// caller holds the client's turn, so two syncs can't overwrite each other
+async fn check_rate_limit_cached(
+    ctx: &RouteContext<RequestMeta>,
+    client_id: &str,
+    limit: u32,
+    window_seconds: u64,
+) -> Result<Decision> {
+    let kv = ctx.env.kv("RATES")?;
+    let now_ms = js_sys::Date::now() as u64;
+    
+    let cached = shared::counter_cache::get(client_id);
+    let mut counter = match cached {
+        Some(c) if c.fresh(now_ms, window_seconds) => c,
+        stale => sync_counter(&kv, client_id, stale.as_ref(), now_ms, window_seconds).await?,
+    };
+    let allowed = counter.hit(limit);
+    let info = RateInfo { count: counter.count, window_start: counter.window_start };
+    let decision = Decision { allowed, ..info.decision(limit, window_seconds, now_ms / 1000) };
+    
+    if counter.needs_flush(now_ms) {
+        counter = sync_counter(&kv, client_id, Some(&counter), now_ms, window_seconds).await?;
+    }
+    shared::counter_cache::put(client_id, counter);
+    Ok(decision)
+}
+
+/// read the client's counter from kv and write this isolate's pending hits
+/// onto it
+async fn sync_counter(
+    kv: &worker::kv::KvStore,
+    client_id: &str,
+    local: Option<&LocalCounter>,
+    now_ms: u64,
+    window_seconds: u64,
+) -> Result<LocalCounter> {
+    shared::metrics::record_kv_op("get");
+    let stored = kv.get(client_id).text().await?
+        .and_then(|json| serde_json::from_str::<RateInfo>(&json).ok())
+        .map(|info| (info.count, info.window_start));
+    let counter = LocalCounter::synced(stored, local, now_ms, window_seconds);
+    
+    if local.is_some_and(|l| l.pending > 0) {
+        let info = RateInfo { count: counter.count, window_start: counter.window_start };
+        shared::metrics::record_kv_op("put");
+        kv.put(client_id, serde_json::to_string(&info)?)?
+            .expiration_ttl(window_seconds.max(60))
+            .execute()
+            .await?;
+    }
+    Ok(counter)
+}
+
+/// where a client stands in its window, without counting a request
+async fn current_usage(ctx: &RouteContext<RequestMeta>, client_id: &str, limits: TierLimits) -> Result<Decision> {
+    let TierLimits { limit, window_seconds, burst } = limits;
+    match (shared::limiter::backend(&ctx.env), burst) {
+        (Backend::DurableObject, _) => shared::limiter::status(&ctx.env, client_id, limit, window_seconds, burst).await,
+        (_, Some(burst)) => {
+            let shape = Shape { rate: limit, window_seconds, burst };
+            let now_ms = js_sys::Date::now() as u64;
+            Ok(stored_bucket(&ctx.env.kv("RATES")?, client_id, shape, now_ms).await?.peek(shape, now_ms))
+        }
+        _ => Ok(get_rate_info(ctx, client_id).await?.decision(limit, window_seconds, js_sys::Date::now() as u64 / 1000)),
+    }
+}
+
+/// take a token from the client's bucket in kv (kv backends with a burst).
+/// the caller holds the client's turn; other isolates race, like the kv window
+async fn take_token(ctx: &RouteContext<RequestMeta>, client_id: &str, shape: Shape) -> Result<Decision> {
+    let kv = ctx.env.kv("RATES")?;
+    let now_ms = js_sys::Date::now() as u64;
+    let mut bucket = stored_bucket(&kv, client_id, shape, now_ms).await?;
+    let decision = bucket.take(shape, now_ms);
+    
+    if decision.allowed {
+        // kept until it's full again - kv won't expire anything sooner than a minute
+        let ttl = bucket.full_at(shape).saturating_sub(now_ms).div_ceil(1000).max(60);
+        shared::metrics::record_kv_op("put");
+        kv.put(&shared::token_bucket::kv_key(client_id), serde_json::to_string(&bucket)?)?
+            .expiration_ttl(ttl)
+            .execute()
+            .await?;
+    }
+    Ok(decision)
+}
+
+/// the client's bucket - full when kv has none
+async fn stored_bucket(kv: &worker::kv::KvStore, client_id: &str, shape: Shape, now_ms: u64) -> Result<TokenBucket> {
+    shared::metrics::record_kv_op("get");
+    Ok(kv.get(&shared::token_bucket::kv_key(client_id)).text().await?
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or(TokenBucket::full(shape, now_ms)))
 }
 
 /// get rate info without incrementing
-async fn get_rate_info(ctx: &RouteContext<()>, client_id: &str) -> Result<RateInfo> {
+async fn get_rate_info(ctx: &RouteContext<RequestMeta>, client_id: &str) -> Result<RateInfo> {
     let kv = ctx.env.kv("RATES")?;
     let now = js_sys::Date::now() as u64 / 1000;
     
+    shared::metrics::record_kv_op("get");
     match kv.get(client_id).text().await? {
         Some(json) => {
             let info: RateInfo = serde_json::from_str(&json).unwrap_or(RateInfo {
@@ -268,6 +1208,141 @@ async fn get_rate_info(ctx: &RouteContext<()>, client_id: &str) -> Result<RateIn
 // helpers
 // ==============================================================================
 
+/// whether a stored counter can go (now in seconds): its window is over,
+/// or it doesn't parse - check_rate_limit would start afresh either way
+fn stale_counter(value: &str, now: u64, window_seconds: u64) -> bool {
+    match serde_json::from_str::<RateInfo>(value) {
+        Ok(info) => now.saturating_sub(info.window_start) >= window_seconds,
+        Err(_) => true,
+    }
+}
+
+/// the free tier's limits - the kv document when there is one, else the env vars
+async fn base_limits(env: &Env) -> TierLimits {
+    shared::limits::load(env, env_limits(env)).await.0.into()
+}
+
+/// RATE_LIMIT and RATE_WINDOW_SECONDS
+fn env_limits(env: &Env) -> RateLimitConfig {
+    let limit: u32 = env.var("RATE_LIMIT")
+        .map(|v| v.to_string().parse().unwrap_or(10))
+        .unwrap_or(10);
+    let window_seconds: u64 = env.var("RATE_WINDOW_SECONDS")
+        .map(|v| v.to_string().parse().unwrap_or(60))
+        .unwrap_or(60);
+    // 0 or unset keeps plain windows; a bucket smaller than the rate can't be
+    let burst = env.var(shared::token_bucket::BURST_VAR).ok()
+        .and_then(|v| v.to_string().parse::<u32>().ok())
+        .filter(|b| *b > 0)
+        .map(|b| b.max(limit));
+    RateLimitConfig { limit, window_seconds, burst }
+}
+
+/// GET/PUT/DELETE /admin/limits - `stored` is the kv document, if any
+fn limits_body(stored: Option<RateLimitConfig>, env: &Env) -> serde_json::Value {
+    let fallback = env_limits(env);
+    let (config, source) = match stored {
+        Some(config) => (config, Source::Kv),
+        None => (fallback.clone(), Source::Env),
+    };
+    let mut body = serde_json::json!({
+        "limit": config.limit,
+        "window_seconds": config.window_seconds,
+        "source": source,
+        "env": fallback,
+    });
+    if let Some(burst) = config.burst {
+        body["burst"] = burst.into();
+    }
+    body
+}
+
+/// the caller's tier and its limits - ip clients are always free. a key
+/// that may not be used is the (status, message) to refuse it with
+async fn client_limits(req: &Request, env: &Env) -> std::result::Result<(KeyTier, TierLimits), (u16, &'static str)> {
+    let access = match req.headers().get("X-API-Key").ok().flatten() {
+        Some(key) => shared::tiers::access(env, &key, require_registered_keys(env)).await,
+        None => KeyAccess::Allowed(KeyTier::Free),
+    };
+    match access {
+        KeyAccess::Allowed(tier) => Ok((tier, tier.limits(base_limits(env).await))),
+        KeyAccess::Revoked => Err((403, "api key revoked")),
+        KeyAccess::Unknown => Err((401, "unknown api key")),
+    }
+}
+
+/// REQUIRE_REGISTERED_KEYS = "true" refuses keys that were never issued
+fn require_registered_keys(env: &Env) -> bool {
+    env.var("REQUIRE_REGISTERED_KEYS").is_ok_and(|v| v.to_string() == "true")
+}
+
+/// route label for metrics - unknown paths share one series
+fn route_label(path: &str) -> &'static str {
+    match path {
+        "/api/protected" => "/api/protected",
+        "/api/status" => "/api/status",
+        "/api/verify" => "/api/verify",
+        "/api/analytics/summary" => "/api/analytics/summary",
+        "/health" => "/health",
+        "/version" => "/version",
+        "/health/deps" => "/health/deps",
+        "/metrics" => "/metrics",
+        "/admin/reset" => "/admin/reset",
+        "/admin/tier" => "/admin/tier",
+        "/admin/keys" => "/admin/keys",
+        "/admin/limits" => "/admin/limits",
+        "/admin/ip-lists" => "/admin/ip-lists",
+        p if p.starts_with("/admin/keys/") && p.ends_with("/usage") => "/admin/keys/:key/usage",
+        p if p.starts_with("/admin/keys/") => "/admin/keys/:key",
+        _ => "other",
+    }
+}
+
+/// adopt the caller's X-Request-Id, or mint a new one
+fn request_id(req: &Request) -> String {
+    req.headers()
+        .get(shared::REQUEST_ID_HEADER)
+        .ok()
+        .flatten()
+        .and_then(|id| shared::accept_request_id(&id))
+        .unwrap_or_else(|| {
+            let random = (js_sys::Math::random() * u32::MAX as f64) as u32;
+            shared::new_request_id(js_sys::Date::now() as u64, random)
+        })
+}
+
+/// put the request id on the response and let browsers read it
+fn stamp_request_id(resp: &mut Response, request_id: &str) {
+    let headers = resp.headers_mut();
+    let _ = headers.set(shared::REQUEST_ID_HEADER, request_id);
+    let _ = headers.append("Access-Control-Expose-Headers", shared::REQUEST_ID_HEADER);
+}
+
+/// problem+json error response with cors headers, carrying the request id
+fn cors_error(request_id: &str, msg: &str, status: u16) -> Result<Response> {
+    console_warn!("[{}] {} {}", request_id, status, msg);
+    shared::problem::Problem::new(status, msg).with_request_id(request_id).into_response()
+}
+
+/// 429 for a spent daily or monthly quota - apart from a full window by its
+/// code and "quota" member
+fn quota_exceeded_problem(request_id: &str, spent: &QuotaUsage) -> Result<shared::problem::Problem> {
+    let detail = format!("{} quota of {} requests used up - try again after it resets", spent.period.as_str(), spent.limit);
+    Ok(shared::problem::Problem::new(429, &detail)
+        .with_code("quota_exceeded")
+        .with_request_id(request_id)
+        .with_extension("retry_after_seconds", spent.reset_in_seconds)
+        .with_extension("quota", serde_json::to_value(spent)?))
+}
+
+/// 429 body - the retry hint and limit ride along as problem extensions
+fn rate_limited_problem(request_id: &str, retry_after_seconds: u64, limit: u32) -> shared::problem::Problem {
+    shared::problem::Problem::new(429, "too many requests - wait for the window to reset")
+        .with_request_id(request_id)
+        .with_extension("retry_after_seconds", retry_after_seconds)
+        .with_extension("limit", limit)
+}
+
 /// get client identifier from api key header or ip address
 fn get_client_id(req: &Request) -> String {
     let headers = req.headers();
@@ -286,6 +1361,11 @@ fn get_client_id(req: &Request) -> String {
     "unknown".to_string()
 }
 
+/// the caller's address, as cloudflare saw it
+fn client_ip(req: &Request) -> Option<std::net::IpAddr> {
+    req.headers().get("CF-Connecting-IP").ok().flatten()?.parse().ok()
+}
+
 /// get cloudflare edge location from headers
 fn get_edge_location(req: &Request) -> String {
     let headers = req.headers();
@@ -306,7 +1386,7 @@ fn get_edge_location(req: &Request) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
-
+    
     // ===========================================================================
     // RateInfo serialization tests
     // ===========================================================================
@@ -368,22 +1448,38 @@ mod tests {
             requests_made: 5,
             requests_remaining: 5,
             limit: 10,
+            burst: None,
             reset_in_seconds: 30,
+            tier: KeyTier::Pro,
+            quotas: Vec::new(),
         };
         let json = serde_json::to_string(&resp).unwrap();
         assert!(json.contains("\"requests_remaining\":5"));
         assert!(json.contains("\"limit\":10"));
+        assert!(json.contains("\"tier\":\"pro\""));
+        // no quotas configured - no quotas member
+        assert!(!json.contains("quotas"));
     }
     
     #[test]
     fn test_rate_limited_response_serialization() {
-        let resp = RateLimitedResponse {
-            error: "rate limited".to_string(),
-            retry_after_seconds: 45,
-            limit: 10,
-        };
-        let json = serde_json::to_string(&resp).unwrap();
+        let json = rate_limited_problem("18bcfe56800-deadbeef", 45, 10).to_json();
         assert!(json.contains("\"retry_after_seconds\":45"));
+        assert!(json.contains("\"limit\":10"));
+        assert!(json.contains("\"status\":429"));
+        assert!(json.contains("\"request_id\":\"18bcfe56800-deadbeef\""));
+    }
+    
+    #[test]
+    fn test_quota_exceeded_response_serialization() {
+        let spent = QuotaUsage::new(shared::quotas::Period::Day, 1000, 1000, 1_792_157_400);
+        let json: serde_json::Value = serde_json::from_str(&quota_exceeded_problem("18bcfe56800-deadbeef", &spent).unwrap().to_json()).unwrap();
+        // told apart from a full window by its type
+        assert_eq!(json["type"], "tag:edge-protocol-demo,2025:problems/quota_exceeded");
+        assert_eq!(json["status"], 429);
+        assert_eq!(json["quota"]["period"], "day");
+        assert_eq!(json["quota"]["remaining"], 0);
+        assert_eq!(json["retry_after_seconds"], 37_800);
     }
     
     // ===========================================================================
@@ -413,4 +1509,30 @@ mod tests {
             }
         }
     }
+    
+    #[test]
+    fn test_rate_info_decision() {
+        let info = RateInfo { count: 10, window_start: 1000 };
+        let decision = info.decision(10, 60, 1_015);
+        assert!(!decision.allowed);
+        assert_eq!((decision.remaining, decision.reset_in_seconds), (0, 45));
+        
+        let decision = RateInfo { count: 3, window_start: 1000 }.decision(10, 60, 1_075);
+        assert_eq!((decision.remaining, decision.reset_in_seconds), (7, 0));
+    }
+    
+    #[test]
+    fn test_route_label_hides_keys() {
+        assert_eq!(route_label("/admin/keys/rl_0123abcd/usage"), "/admin/keys/:key/usage");
+        assert_eq!(route_label("/admin/keys/rl_0123abcd"), "/admin/keys/:key");
+    }
+    
+    #[test]
+    fn test_stale_counter() {
+        let counter = r#"{"count":10,"window_start":1000}"#;
+        assert!(!stale_counter(counter, 1_059, 60));
+        assert!(stale_counter(counter, 1_060, 60));
+        // unreadable counters are reset on next use anyway
+        assert!(stale_counter("garbage", 1_000, 60));
+    }
 }
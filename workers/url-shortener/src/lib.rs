@@ -8,8 +8,20 @@
 //!     showing real-world patterns for edge key-value operations.
 //!
 //! relationships:
-//!     - uses: shared (ShortenRequest, ShortenResponse types)
-//!     - uses: workers kv namespace "URLS" for persistent storage
+//!     - uses: shared (ShortenRequest, ShortenResponse types, request ids)
+//!     - uses: workers kv namespace "URLS" for persistent storage, or d1
+//!       "LINKS_DB" when that's bound (shared::storage)
+//!     - uses: durable object "METRICS" for /metrics totals (shared::metrics_store)
+//!     - uses: workers kv namespace "AUTH" for api keys (shared::auth_guard)
+//!     - uses: cloudflare turnstile siteverify for POST /shorten (shared::turnstile)
+//!     - uses: shared::body_limit to cap POST /shorten bodies before parsing
+//!     - uses: shared::maintenance for the daily expired-link sweep
+//!     - uses: queue "ANALYTICS" for click/denial/fault events and d1
+//!       "ANALYTICS_DB" for click totals (shared::analytics)
+//!     - uses: durable object "EVENTS" in the event-hub worker for the live
+//!       feed - new links, blocks, faults (shared::event_hub)
+//!     - uses: shared::flags to switch the queue, live feed, durable metrics
+//!       and d1 links off (or roll them out) without a redeploy
 //!     - deployed to: cloudflare workers edge network
 //!
 //! cloudflare features demonstrated:
@@ -17,17 +29,48 @@
 //!     - json api handling
 //!     - http redirects (301)
 //!     - cors headers for browser access
+//!     - cron triggers (scheduled kv cleanup)
+//!     - queues producer (analytics events off the request path)
+//!
+//! metrics:
+//!     GET /metrics serves prometheus text: request counts by route and
+//!     status, 5xx and 429 totals, kv operations and latency histograms.
+//!     isolates buffer counters and merge them into the durable object
+//!     every few seconds, so totals lag live traffic slightly.
+//!
+//! request ids:
+//!     every response carries X-Request-Id (adopted from the request when
+//!     the client sent a sane one) and error bodies repeat it as
+//!     "request_id", so a user-reported failure maps to a log line.
 //!
 //! api:
 //!     POST /shorten
 //!         body: {"url": "https://example.com/long/path"}
+//!         optional: "alias": "my-link" (custom code), "expires_in": 3600 (seconds)
+//!         header: X-Turnstile-Token (required once TURNSTILE_SECRET is set)
+//!         machine callers can sign the request instead (X-Auth-Timestamp,
+//!         X-Auth-Signature, optional X-Auth-Key-Id - shorten scope, see
+//!         shared::auth); a bad signature gets a 401, not the turnstile check
+//!         bodies over 4 KiB get a 413
 //!         response: {"code": "abc123", "short_url": "https://.../abc123"}
 //!
 //!     GET /:code
-//!         response: 301 redirect to original url
+//!         response: 301 redirect to original url (404 once expired)
 //!
 //!     GET /stats/:code
-//!         response: {"code": "abc123", "original_url": "...", "clicks": 42}
+//!         response: {"code": "abc123", "original_url": "...", "clicks": 42, "expires_at": null}
+//!         clicks arrive through the analytics queue, so they lag by a few seconds
+//!         carries an ETag - If-None-Match with it gets a 304 while nothing changed
+//!
+//!     DELETE /admin/links/:code   (admin scope - see shared::auth)
+//!         response: {"deleted": "abc123"}
+//!
+//!     GET /version
+//!         response: {"worker": "...", "version": "0.1.0", "git_sha": "...", "built_at": ..., "features": [...]}
+//!
+//!     GET /health/deps
+//!         response: shared::health report over URLS, RATES, AUTH, ANALYTICS_DB,
+//!         LINKS_DB (when bound), METRICS and EVENTS
 //!
 //! ==============================================================================
 
@@ -35,6 +78,17 @@ use worker::*;
 use serde::{Deserialize, Serialize};
 use url::Url;
 
+use shared::analytics::AnalyticsEvent;
+use shared::event_hub::LiveEvent;
+use shared::flags::{self, ClientFlags};
+use shared::health::Dependency;
+use shared::storage::{Backend, LinkStore};
+use shared::build_info::BuildInfo;
+use shared::UrlEntry;
+
+// the metrics durable object class has to be exported by the worker itself
+pub use shared::metrics_store::MetricsStore;
+
 // ==============================================================================
 // types
 // ==============================================================================
@@ -42,6 +96,12 @@ use url::Url;
 #[derive(Debug, Deserialize)]
 struct ShortenRequest {
     url: String,
+    /// custom code instead of a generated one
+    #[serde(default)]
+    alias: Option<String>,
+    /// seconds until the link expires
+    #[serde(default)]
+    expires_in: Option<u64>,
 }
 
 #[derive(Debug, Serialize)]
@@ -49,50 +109,213 @@ struct ShortenResponse {
     code: String,
     short_url: String,
     original_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct UrlEntry {
-    original_url: String,
-    created_at: u64,
-    clicks: u64,
+
+/// per-request data the router hands to every handler
+struct RequestMeta {
+    request_id: String,
+    /// feature flags for this request's client
+    flags: ClientFlags,
 }
 
+/// worker label on exported metrics
+const WORKER_NAME: &str = "url-shortener";
+
 #[derive(Debug, Serialize, Deserialize)]
 struct RateInfo {
     count: u32,
     window_start: u64,
 }
 
+// ==============================================================================
+// limits
+// ==============================================================================
+
+const ALIAS_MIN_LEN: usize = 3;
+const ALIAS_MAX_LEN: usize = 32;
+
+/// bindings probed by GET /health/deps - LINKS_DB is added when bound
+const HEALTH_DEPS: &[Dependency] = &[
+    Dependency::Kv(shared::storage::LINKS_KV_BINDING),
+    Dependency::Kv("RATES"),
+    Dependency::Kv(shared::auth_guard::AUTH_KV_BINDING),
+    Dependency::D1(shared::analytics::DB_BINDING),
+    shared::metrics_store::HEALTH_DEPENDENCY,
+    shared::event_hub::HEALTH_DEPENDENCY,
+];
+
+/// aliases that would shadow the worker's own routes
+const RESERVED_ALIASES: &[&str] = &["shorten", "stats", "health", "metrics", "admin", "version"];
+
+/// kv rejects ttls under 60 seconds
+const EXPIRY_MIN_SECONDS: u64 = 60;
+const EXPIRY_MAX_SECONDS: u64 = 30 * 24 * 60 * 60;
+
+/// a url plus alias and expiry - 2k urls fit with plenty to spare
+const MAX_SHORTEN_BODY_BYTES: usize = 4 * 1024;
+
 // ==============================================================================
 // worker entry point
 // ==============================================================================
 
 #[event(fetch)]
-async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
-    let router = Router::new();
-    
-    router
+async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
+    let started = js_sys::Date::now();
+    let route = route_label(&req.path());
+    let request_id = request_id(&req);
+    let origin = req.headers().get("Origin").ok().flatten();
+    console_log!("[{}] {:?} {}", request_id, req.method(), req.path());
+    
+    let client_flags = flags::load(&env).await.for_client(&get_client_id(&req));
+    let router = Router::with_data(RequestMeta { request_id: request_id.clone(), flags: client_flags.clone() });
+    
+    // the router consumes env - keep a handle for cors and the metrics flush
+    let metrics_env = env.clone();
+    let result = router
         // shorten a url
         .post_async("/shorten", handle_shorten)
         // get stats for a code
         .get_async("/stats/:code", handle_stats)
         // health check
         .get("/health", |_, _| Response::ok("ok"))
+        // which build is deployed
+        .get("/version", |_, _| Response::from_json(&BuildInfo::new(WORKER_NAME, env!("CARGO_PKG_VERSION"))))
+        // this worker's bindings, collected by the gateway's /health/all
+        .get_async("/health/deps", handle_health_deps)
+        // prometheus scrape target
+        .get_async("/metrics", handle_metrics)
+        // admin: take a link down
+        .delete_async("/admin/links/:code", handle_admin_delete)
         // cors preflight
         .options("/shorten", handle_cors)
         // redirect short url to original (must be last - catches all)
         .get_async("/:code", handle_redirect)
         .run(req, env)
-        .await
+        .await;
+    
+    // unhandled errors and the router's own text 404/405s become problem+json too
+    let resp = match result {
+        Ok(resp) => resp,
+        Err(e) => {
+            console_error!("[{}] unhandled error: {}", request_id, e);
+            cors_error(&request_id, "internal error", 500)?
+        }
+    };
+    let mut resp = shared::problem::ensure_problem(resp, &request_id).await?;
+    stamp_request_id(&mut resp, &request_id);
+    let cors = shared::cors::policy(&metrics_env).await;
+    shared::cors::apply(&cors, origin.as_deref(), resp.headers_mut());
+    
+    let status = resp.status_code();
+    shared::metrics::record_request(route, status, js_sys::Date::now() - started);
+    if status >= 500 && client_flags.on(flags::ANALYTICS_QUEUE) {
+        shared::analytics::record(AnalyticsEvent::fault(WORKER_NAME, route, status, &request_id, started as u64));
+    }
+    if status >= 500 && client_flags.on(flags::LIVE_EVENTS) {
+        shared::event_hub::record(LiveEvent::fault(WORKER_NAME, route, status, &request_id, started as u64));
+    }
+    // with durable metrics off, requests leave their counters in the isolate - only a scrape merges them
+    if client_flags.on(flags::DURABLE_METRICS) {
+        shared::metrics_store::flush_if_due(&metrics_env, &ctx);
+    }
+    shared::analytics::flush(&metrics_env, &ctx);
+    shared::event_hub::flush(&metrics_env, &ctx);
+    Ok(resp)
+}
+
+// ==============================================================================
+// scheduled maintenance
+// ==============================================================================
+
+/// daily cron: delete expired links the store still holds. on kv, links
+/// shortened with expires_in carry a ttl and vanish on their own, so the
+/// sweep catches entries stored without one; the list cursor lives in RATES
+/// so it's never listed (or served) as a link. on d1 one statement purges
+/// them all. admin deletes remove links outright, so there are no
+/// tombstones to clear
+#[event(scheduled)]
+async fn scheduled(_event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    if let Err(e) = run_maintenance(&env).await {
+        console_error!("maintenance failed: {}", e);
+    }
+}
+
+async fn run_maintenance(env: &Env) -> Result<()> {
+    use shared::maintenance::{self, KeyAction};
+    
+    let now = js_sys::Date::now() as u64;
+    let store = LinkStore::from_env(env, flags::load(env).await.enabled(flags::D1_LINKS))?;
+    if store.backend() == Backend::D1 {
+        store.purge_expired(now).await?;
+        console_log!("maintenance purged expired d1 links");
+        return Ok(());
+    }
+    
+    let report = maintenance::sweep(
+        &env.kv(shared::storage::LINKS_KV_BINDING)?,
+        &env.kv("RATES")?,
+        "url-shortener-links",
+        None,
+        maintenance::DEFAULT_MAX_OPS,
+        |key| if key.has_ttl() { KeyAction::Keep } else { KeyAction::Inspect },
+        |_, value| stale_link(value, now),
+    )
+    .await?;
+    console_log!("maintenance {}", serde_json::to_string(&report).unwrap_or_default());
+    shared::metrics_store::flush(env).await
 }
 
 // ==============================================================================
 // request handlers
 // ==============================================================================
 
+/// prometheus text format metrics for this worker (needs the "metrics" scope)
+async fn handle_metrics(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    if let Err(e) = shared::auth_guard::require(&req, &ctx.env, &[], shared::auth::METRICS_SCOPE).await? {
+        return cors_error(&ctx.data.request_id, &e.to_string(), e.status());
+    }
+    shared::metrics_store::metrics_response(&ctx.env, WORKER_NAME).await
+}
+
+/// probe this worker's bindings
+async fn handle_health_deps(_req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    let mut deps = HEALTH_DEPS.to_vec();
+    if ctx.env.d1(shared::storage::LINKS_DB_BINDING).is_ok() {
+        deps.push(Dependency::D1(shared::storage::LINKS_DB_BINDING));
+    }
+    shared::health::deps_response(&ctx.env, &deps).await
+}
+
+/// delete a short link (needs the "admin" scope)
+async fn handle_admin_delete(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
+    let principal = match shared::auth_guard::require(&req, &ctx.env, &[], shared::auth::ADMIN_SCOPE).await? {
+        Ok(p) => p,
+        Err(e) => return cors_error(&ctx.data.request_id, &e.to_string(), e.status()),
+    };
+    
+    let code = match ctx.param("code") {
+        Some(c) => c.to_string(),
+        None => return cors_error(&ctx.data.request_id, "missing code", 400),
+    };
+    let store = LinkStore::from_env(&ctx.env, ctx.data.flags.enabled(flags::D1_LINKS))?;
+    
+    if store.get(&code).await?.is_none() {
+        return cors_error(&ctx.data.request_id, "short url not found", 404);
+    }
+    store.delete(&code).await?;
+    console_log!("[{}] {} deleted /{}", ctx.data.request_id, principal.subject, code);
+    
+    let headers = Headers::new();
+    headers.set("Content-Type", "application/json")?;
+    let body = serde_json::json!({ "deleted": code }).to_string();
+    Ok(Response::ok(body)?.with_headers(headers))
+}
+
 /// create a short url
-async fn handle_shorten(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
+async fn handle_shorten(mut req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
     // check rate limit first
     let limit: u32 = ctx.env.var("RATE_LIMIT")
         .map(|v| v.to_string().parse().unwrap_or(20))
@@ -105,168 +328,267 @@ async fn handle_shorten(mut req: Request, ctx: RouteContext<()>) -> Result<Respo
     let (allowed, _) = check_rate_limit(&ctx, &client_id, limit, window_seconds).await?;
     
     if !allowed {
-        return cors_error("rate limit exceeded - try again later", 429);
+        let now = js_sys::Date::now() as u64;
+        if ctx.data.flags.on(flags::ANALYTICS_QUEUE) {
+            shared::analytics::record(AnalyticsEvent::denial(WORKER_NAME, &client_id, &ctx.data.request_id, now));
+        }
+        if ctx.data.flags.on(flags::LIVE_EVENTS) {
+            shared::event_hub::record(LiveEvent::block(WORKER_NAME, &client_id, &ctx.data.request_id, now));
+        }
+        return cors_error(&ctx.data.request_id, "rate limit exceeded - try again later", 429);
+    }
+    
+    // size check before anything touches the body
+    let bytes = match shared::body_limit::read_body(&mut req, MAX_SHORTEN_BODY_BYTES).await? {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            console_warn!("[{}] 413 body over {} bytes", ctx.data.request_id, e.limit);
+            return e.problem().with_request_id(&ctx.data.request_id).into_response();
+        }
+    };
+    
+    // signed machine callers skip turnstile - they can't solve a challenge
+    let signer = match shared::auth_guard::verify_signature(&req, &ctx.env, &bytes, shared::auth::SHORTEN_SCOPE).await? {
+        Some(Ok(principal)) => Some(principal),
+        Some(Err(e)) => return cors_error(&ctx.data.request_id, &e.to_string(), e.status()),
+        None => None,
+    };
+    
+    // bots get past ip limits by rotating addresses, so writes need a turnstile pass too
+    if signer.is_none() {
+        if let Err(reason) = shared::turnstile::verify(&req, &ctx.env).await? {
+            return cors_error(&ctx.data.request_id, &format!("turnstile check failed: {}", reason), 403);
+        }
     }
     
     // parse request
-    let body: ShortenRequest = match req.json().await {
+    let body: ShortenRequest = match serde_json::from_slice(&bytes) {
         Ok(b) => b,
-        Err(_) => return cors_error("invalid json body", 400),
+        Err(_) => return cors_error(&ctx.data.request_id, "invalid json body", 400),
     };
     
     // validate url (must be valid and use http/https)
     let parsed_url = match Url::parse(&body.url) {
         Ok(u) => u,
-        Err(_) => return cors_error("invalid url format", 400),
+        Err(_) => return cors_error(&ctx.data.request_id, "invalid url format", 400),
     };
     
     // only allow http and https schemes
     match parsed_url.scheme() {
         "http" | "https" => {},
-        _ => return cors_error("url must use http:// or https://", 400),
+        _ => return cors_error(&ctx.data.request_id, "url must use http:// or https://", 400),
     }
     
     // ensure it has a host
     if parsed_url.host_str().is_none() {
-        return cors_error("url must have a valid host", 400);
+        return cors_error(&ctx.data.request_id, "url must have a valid host", 400);
+    }
+    
+    // validate optional alias and expiry
+    if let Some(alias) = &body.alias {
+        if let Err(msg) = validate_alias(alias) {
+            return cors_error(&ctx.data.request_id, msg, 400);
+        }
+    }
+    if let Some(expires_in) = body.expires_in {
+        if let Err(msg) = validate_expiry(expires_in) {
+            return cors_error(&ctx.data.request_id, msg, 400);
+        }
     }
     
-    // generate short code (6 characters)
-    let code = generate_code();
+    // use the custom alias, or generate a short code (6 characters)
+    let code = body.alias.clone().unwrap_or_else(generate_code);
     
-    // get kv namespace
-    let kv = match ctx.env.kv("URLS") {
-        Ok(kv) => kv,
-        Err(_) => return cors_error("kv namespace not configured", 500),
+    // kv or d1, whichever is bound
+    let store = match LinkStore::from_env(&ctx.env, ctx.data.flags.enabled(flags::D1_LINKS)) {
+        Ok(store) => store,
+        Err(_) => return cors_error(&ctx.data.request_id, "link store not configured", 500),
     };
     
+    // custom aliases must never overwrite an existing link
+    if body.alias.is_some() && store.get(&code).await?.is_some() {
+        return cors_error(&ctx.data.request_id, "alias already in use", 409);
+    }
+    
     // create entry
+    let now = js_sys::Date::now() as u64;
     let entry = UrlEntry {
         original_url: body.url.clone(),
-        created_at: js_sys::Date::now() as u64,
+        created_at: now,
         clicks: 0,
+        expires_at: body.expires_in.map(|secs| now + secs * 1000),
     };
     
-    // store in kv
-    let entry_json = serde_json::to_string(&entry).unwrap();
-    kv.put(&code, entry_json)?.execute().await?;
+    // store it (kv deletes expiring links for us, d1 leaves them to the cron)
+    store.put(&code, &entry, body.expires_in).await?;
+    if let Some(signer) = &signer {
+        console_log!("[{}] {} (signed) created /{}", ctx.data.request_id, signer.subject, code);
+    }
+    if ctx.data.flags.on(flags::LIVE_EVENTS) {
+        shared::event_hub::record(LiveEvent::link(WORKER_NAME, &code, &ctx.data.request_id, now));
+    }
     
     // build response
     let host = req.url()?.host_str().unwrap_or("localhost").to_string();
     let protocol = if host.contains("localhost") { "http" } else { "https" };
+    // behind the gateway, links live under the prefix it forwarded
+    let prefix = forwarded_prefix(&req);
     
     let response = ShortenResponse {
         code: code.clone(),
-        short_url: format!("{}://{}/{}", protocol, host, code),
+        short_url: format!("{}://{}{}/{}", protocol, host, prefix, code),
         original_url: body.url,
+        expires_at: entry.expires_at,
     };
     
     // return json with cors
     let json = serde_json::to_string(&response).unwrap();
     let headers = Headers::new();
     headers.set("Content-Type", "application/json")?;
-    headers.set("Access-Control-Allow-Origin", "*")?;
     
     Ok(Response::ok(json)?.with_headers(headers))
 }
 
 /// redirect short url to original
-async fn handle_redirect(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
+async fn handle_redirect(_req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
     let code = match ctx.param("code") {
         Some(c) => c,
-        None => return cors_error("missing code", 400),
+        None => return cors_error(&ctx.data.request_id, "missing code", 400),
     };
     
-    // get kv namespace
-    let kv = match ctx.env.kv("URLS") {
-        Ok(kv) => kv,
-        Err(_) => return cors_error("kv namespace not configured", 500),
+    // kv or d1, whichever is bound
+    let store = match LinkStore::from_env(&ctx.env, ctx.data.flags.enabled(flags::D1_LINKS)) {
+        Ok(store) => store,
+        Err(_) => return cors_error(&ctx.data.request_id, "link store not configured", 500),
     };
     
     // look up the code
-    let entry_json = match kv.get(code).text().await? {
-        Some(json) => json,
-        None => return cors_error("short url not found", 404),
+    let mut entry = match store.get(code).await? {
+        Some(entry) => entry,
+        None => return cors_error(&ctx.data.request_id, "short url not found", 404),
     };
     
-    // parse entry
-    let mut entry: UrlEntry = serde_json::from_str(&entry_json)
-        .map_err(|_| Error::from("invalid stored data"))?;
+    // kv expiry is eventually consistent and d1 rows wait for the cron -
+    // don't redirect stale reads
+    let now = js_sys::Date::now() as u64;
+    if is_expired(&entry, now) {
+        return cors_error(&ctx.data.request_id, "short url has expired", 404);
+    }
     
-    // increment click counter
-    entry.clicks += 1;
-    let updated_json = serde_json::to_string(&entry).unwrap();
-    kv.put(code, updated_json)?.execute().await?;
+    // the click is counted by the analytics consumer - with the queue
+    // flagged off it's written back to the entry, as before the pipeline
+    if ctx.data.flags.on(flags::ANALYTICS_QUEUE) {
+        shared::analytics::record(AnalyticsEvent::click(WORKER_NAME, code, &ctx.data.request_id, now));
+    } else {
+        entry.clicks += 1;
+        let ttl = entry.expires_at.map(|at| (at.saturating_sub(now) / 1000).max(EXPIRY_MIN_SECONDS));
+        store.put(code, &entry, ttl).await?;
+    }
     
     // redirect to original url
     Response::redirect(entry.original_url.parse()?)
 }
 
 /// get stats for a short url
-async fn handle_stats(_req: Request, ctx: RouteContext<()>) -> Result<Response> {
+async fn handle_stats(req: Request, ctx: RouteContext<RequestMeta>) -> Result<Response> {
     let code = match ctx.param("code") {
         Some(c) => c,
-        None => return cors_error("missing code", 400),
+        None => return cors_error(&ctx.data.request_id, "missing code", 400),
     };
     
-    // get kv namespace
-    let kv = match ctx.env.kv("URLS") {
-        Ok(kv) => kv,
-        Err(_) => return cors_error("kv namespace not configured", 500),
+    let store = match LinkStore::from_env(&ctx.env, ctx.data.flags.enabled(flags::D1_LINKS)) {
+        Ok(store) => store,
+        Err(_) => return cors_error(&ctx.data.request_id, "link store not configured", 500),
     };
     
-    // look up the code
-    let entry_json = match kv.get(code).text().await? {
-        Some(json) => json,
-        None => return cors_error("short url not found", 404),
+    // look up the code - clicks stored with the entry predate the analytics
+    // pipeline, newer ones come from d1 (joined in on the d1 store). if d1 is
+    // unreachable the count is just low for a while
+    let entry = match store.get_with_clicks(&ctx.env, code).await? {
+        Some(entry) => entry,
+        None => return cors_error(&ctx.data.request_id, "short url not found", 404),
     };
     
-    // parse and return
-    let entry: UrlEntry = serde_json::from_str(&entry_json)
-        .map_err(|_| Error::from("invalid stored data"))?;
+    if is_expired(&entry, js_sys::Date::now() as u64) {
+        return cors_error(&ctx.data.request_id, "short url has expired", 404);
+    }
     
     let response = serde_json::json!({
         "code": code,
         "original_url": entry.original_url,
         "created_at": entry.created_at,
         "clicks": entry.clicks,
+        "expires_at": entry.expires_at,
     });
     
-    let headers = Headers::new();
-    headers.set("Content-Type", "application/json")?;
-    headers.set("Access-Control-Allow-Origin", "*")?;
-    headers.set("Cache-Control", "public, max-age=5")?; // Cache for 5 seconds
-    
-    Ok(Response::ok(response.to_string())?.with_headers(headers))
+    // cache for 5 seconds, then revalidate - unchanged stats come back as a 304
+    shared::etag::json_response(&req, response.to_string(), "public, max-age=5")
 }
 
-/// handle cors preflight
-fn handle_cors(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
+/// handle cors preflight (allow-origin is added in fetch from the shared policy)
+fn handle_cors(_req: Request, _ctx: RouteContext<RequestMeta>) -> Result<Response> {
     let headers = Headers::new();
-    headers.set("Access-Control-Allow-Origin", "*")?;
     headers.set("Access-Control-Allow-Methods", "POST, GET, OPTIONS")?;
-    headers.set("Access-Control-Allow-Headers", "Content-Type")?;
+    headers.set("Access-Control-Allow-Headers", "Content-Type, X-Request-Id, X-Turnstile-Token")?;
     
     Ok(Response::empty()?.with_headers(headers))
 }
 
-/// helper to create error responses with cors headers
-fn cors_error(msg: &str, status: u16) -> Result<Response> {
-    let headers = Headers::new();
-    headers.set("Access-Control-Allow-Origin", "*")?;
-    headers.set("Content-Type", "application/json")?;
-    
-    let body = serde_json::json!({ "error": msg }).to_string();
-    
-    let mut resp = Response::ok(body)?;
-    resp = resp.with_status(status);
-    Ok(resp.with_headers(headers))
+/// problem+json error response with cors headers, carrying the request id
+fn cors_error(request_id: &str, msg: &str, status: u16) -> Result<Response> {
+    console_warn!("[{}] {} {}", request_id, status, msg);
+    shared::problem::Problem::new(status, msg).with_request_id(request_id).into_response()
 }
 
 // ==============================================================================
 // helpers
 // ==============================================================================
 
+/// route label for metrics - short codes collapse into one series
+fn route_label(path: &str) -> &'static str {
+    match path {
+        "/shorten" => "/shorten",
+        "/health" => "/health",
+        "/version" => "/version",
+        "/health/deps" => "/health/deps",
+        "/metrics" => "/metrics",
+        p if p.starts_with("/stats/") => "/stats/:code",
+        p if p.starts_with("/admin/links/") => "/admin/links/:code",
+        _ => "/:code",
+    }
+}
+
+/// path prefix the gateway mounted us under ("" when called directly)
+fn forwarded_prefix(req: &Request) -> String {
+    req.headers()
+        .get(shared::FORWARDED_PREFIX_HEADER)
+        .ok()
+        .flatten()
+        .and_then(|p| shared::accept_forwarded_prefix(&p).map(str::to_string))
+        .unwrap_or_default()
+}
+
+/// adopt the caller's X-Request-Id, or mint a new one
+fn request_id(req: &Request) -> String {
+    req.headers()
+        .get(shared::REQUEST_ID_HEADER)
+        .ok()
+        .flatten()
+        .and_then(|id| shared::accept_request_id(&id))
+        .unwrap_or_else(|| {
+            let random = (js_sys::Math::random() * u32::MAX as f64) as u32;
+            shared::new_request_id(js_sys::Date::now() as u64, random)
+        })
+}
+
+/// put the request id on the response and let browsers read it
+fn stamp_request_id(resp: &mut Response, request_id: &str) {
+    // redirects have immutable headers - skip them rather than fail the request
+    let headers = resp.headers_mut();
+    let _ = headers.set(shared::REQUEST_ID_HEADER, request_id);
+    let _ = headers.append("Access-Control-Expose-Headers", shared::REQUEST_ID_HEADER);
+}
+
 /// generate a random 6-character code for short urls
 fn generate_code() -> String {
     use std::collections::hash_map::DefaultHasher;
@@ -291,9 +613,42 @@ fn generate_code() -> String {
     code
 }
 
+/// validate a custom alias: 3-32 url-safe characters, not a reserved route
+fn validate_alias(alias: &str) -> std::result::Result<(), &'static str> {
+    if alias.len() < ALIAS_MIN_LEN || alias.len() > ALIAS_MAX_LEN {
+        return Err("alias must be 3-32 characters");
+    }
+    if !alias.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("alias may only contain letters, digits, '-' and '_'");
+    }
+    if RESERVED_ALIASES.contains(&alias.to_ascii_lowercase().as_str()) {
+        return Err("alias is reserved");
+    }
+    Ok(())
+}
+
+/// validate an expiry duration in seconds
+fn validate_expiry(expires_in: u64) -> std::result::Result<(), &'static str> {
+    if !(EXPIRY_MIN_SECONDS..=EXPIRY_MAX_SECONDS).contains(&expires_in) {
+        return Err("expires_in must be between 60 seconds and 30 days");
+    }
+    Ok(())
+}
+
+/// whether an entry is past its expiry (now in ms)
+fn is_expired(entry: &UrlEntry, now: u64) -> bool {
+    entry.expires_at.is_some_and(|expires_at| expires_at <= now)
+}
+
+/// whether a stored link should be purged (now in ms). values that don't
+/// parse are left alone - the sweep never deletes what it can't read
+fn stale_link(value: &str, now: u64) -> bool {
+    serde_json::from_str::<UrlEntry>(value).is_ok_and(|entry| is_expired(&entry, now))
+}
+
 /// check if request is allowed and update counter
 async fn check_rate_limit(
-    ctx: &RouteContext<()>,
+    ctx: &RouteContext<RequestMeta>,
     client_id: &str,
     limit: u32,
     window_seconds: u64,
@@ -305,6 +660,7 @@ async fn check_rate_limit(
     let key = format!("url-shortener:{}", client_id);
     
     // get current rate info
+    shared::metrics::record_kv_op("get");
     let mut rate_info = match kv.get(&key).text().await? {
         Some(json) => {
             let info: RateInfo = serde_json::from_str(&json).unwrap_or(RateInfo {
@@ -331,6 +687,7 @@ async fn check_rate_limit(
     
     // store updated info with ttl
     let json = serde_json::to_string(&rate_info).unwrap();
+    shared::metrics::record_kv_op("put");
     kv.put(&key, json)?
         .expiration_ttl(window_seconds)
         .execute()
@@ -426,4 +783,78 @@ mod tests {
         assert!(validate_url("not a url at all").is_err());
         assert!(validate_url("   ").is_err());
     }
+    
+    // ===========================================================================
+    // Alias / expiry tests
+    // ===========================================================================
+    
+    #[test]
+    fn test_valid_aliases() {
+        assert!(validate_alias("my-link").is_ok());
+        assert!(validate_alias("abc").is_ok());
+        assert!(validate_alias("Release_2026").is_ok());
+    }
+    
+    #[test]
+    fn test_alias_length_limits() {
+        assert!(validate_alias("ab").is_err());
+        assert!(validate_alias(&"a".repeat(33)).is_err());
+        assert!(validate_alias(&"a".repeat(32)).is_ok());
+    }
+    
+    #[test]
+    fn test_alias_rejects_unsafe_chars() {
+        assert!(validate_alias("has space").is_err());
+        assert!(validate_alias("slash/path").is_err());
+        assert!(validate_alias("query?x").is_err());
+    }
+    
+    #[test]
+    fn test_alias_rejects_reserved_routes() {
+        assert!(validate_alias("health").is_err());
+        assert!(validate_alias("STATS").is_err());
+        assert!(validate_alias("metrics").is_err());
+        assert!(validate_alias("admin").is_err());
+    }
+    
+    #[test]
+    fn test_expiry_bounds() {
+        assert!(validate_expiry(59).is_err());
+        assert!(validate_expiry(60).is_ok());
+        assert!(validate_expiry(30 * 24 * 60 * 60).is_ok());
+        assert!(validate_expiry(30 * 24 * 60 * 60 + 1).is_err());
+    }
+    
+    #[test]
+    fn test_is_expired() {
+        let entry = UrlEntry {
+            original_url: "https://example.com".to_string(),
+            created_at: 1_000,
+            clicks: 0,
+            expires_at: Some(5_000),
+        };
+        assert!(!is_expired(&entry, 4_999));
+        assert!(is_expired(&entry, 5_000));
+        
+        let forever = UrlEntry { expires_at: None, ..entry };
+        assert!(!is_expired(&forever, u64::MAX));
+    }
+    
+    #[test]
+    fn test_url_entry_without_expiry_field() {
+        // entries written before expiry support must still parse
+        let json = r#"{"original_url":"https://example.com","created_at":1,"clicks":3}"#;
+        let entry: UrlEntry = serde_json::from_str(json).unwrap();
+        assert_eq!(entry.expires_at, None);
+    }
+    
+    #[test]
+    fn test_stale_link() {
+        let expired = r#"{"original_url":"https://example.com","created_at":1,"clicks":0,"expires_at":5000}"#;
+        assert!(stale_link(expired, 5_000));
+        assert!(!stale_link(expired, 4_999));
+        // permanent links and unreadable values are kept
+        assert!(!stale_link(r#"{"original_url":"https://example.com","created_at":1,"clicks":3}"#, u64::MAX));
+        assert!(!stale_link("not json", u64::MAX));
+    }
 }